@@ -4,20 +4,30 @@
 -- Users table
 CREATE TABLE IF NOT EXISTS users (
     id TEXT PRIMARY KEY,
+    -- OAuth provider: 'github' or 'gitlab'
+    provider TEXT NOT NULL DEFAULT 'github',
+    -- Provider account ID (GitLab IDs are stored negated to stay unique)
     github_id INTEGER UNIQUE NOT NULL,
     github_login TEXT NOT NULL,
     email TEXT,
     created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
     last_login DATETIME
 );
+-- Existing databases: ALTER TABLE users ADD COLUMN provider TEXT NOT NULL DEFAULT 'github';
 
 -- Sessions table
 CREATE TABLE IF NOT EXISTS sessions (
     id TEXT PRIMARY KEY,
     user_id TEXT NOT NULL REFERENCES users(id),
     expires_at DATETIME NOT NULL,
-    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    last_activity DATETIME,
+    created_ip TEXT,
+    user_agent TEXT
 );
+-- Existing databases: ALTER TABLE sessions ADD COLUMN last_activity DATETIME;
+-- Existing databases: ALTER TABLE sessions ADD COLUMN created_ip TEXT;
+-- Existing databases: ALTER TABLE sessions ADD COLUMN user_agent TEXT;
 
 -- Allowed entities (orgs, users, teams) for access control
 CREATE TABLE IF NOT EXISTS allowed_entities (
@@ -36,8 +46,14 @@ CREATE TABLE IF NOT EXISTS client_tokens (
     token_hash TEXT NOT NULL UNIQUE,
     created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
     last_used DATETIME,
-    revoked_at DATETIME
+    last_used_ip TEXT,
+    last_used_ua TEXT,
+    revoked_at DATETIME,
+    expires_at DATETIME
 );
+-- Existing databases: ALTER TABLE client_tokens ADD COLUMN expires_at DATETIME;
+-- Existing databases: ALTER TABLE client_tokens ADD COLUMN last_used_ip TEXT;
+-- Existing databases: ALTER TABLE client_tokens ADD COLUMN last_used_ua TEXT;
 
 -- Connected clients (for public path routing without session auth)
 -- Populated when clients register via WebSocket, removed on disconnect
@@ -50,6 +66,36 @@ CREATE TABLE IF NOT EXISTS clients (
     last_seen DATETIME DEFAULT CURRENT_TIMESTAMP
 );
 
+-- Recent uses of each client token (WebSocket authentications), capped per token
+CREATE TABLE IF NOT EXISTS token_usage (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    token_id TEXT NOT NULL REFERENCES client_tokens(id) ON DELETE CASCADE,
+    used_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    ip TEXT,
+    user_agent TEXT,
+    client_id TEXT
+);
+
+-- Read-only access to a user's hub: the account viewer_account_id (users.github_id,
+-- resolved from the login when access is granted) may list owner_id's clients.
+-- viewer_login is that login as in ALLOWED_USERS ("gitlab:name" for GitLab), kept for display.
+-- Keyed by account rather than login so a renamed and re-registered login gains nothing;
+-- this replaces the login-keyed hub_viewers table, whose grants are not carried over.
+CREATE TABLE IF NOT EXISTS hub_shares (
+    owner_id TEXT NOT NULL REFERENCES users(id),
+    viewer_account_id INTEGER NOT NULL,
+    viewer_login TEXT NOT NULL COLLATE NOCASE,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (owner_id, viewer_account_id)
+);
+
+-- Fixed-window request counters (see src/rate_limit.rs), one row per key
+CREATE TABLE IF NOT EXISTS rate_limits (
+    key TEXT PRIMARY KEY,
+    window_start INTEGER NOT NULL,
+    hits INTEGER NOT NULL DEFAULT 0
+);
+
 -- Indexes for performance
 CREATE INDEX IF NOT EXISTS idx_sessions_user ON sessions(user_id);
 CREATE INDEX IF NOT EXISTS idx_sessions_expires ON sessions(expires_at);
@@ -58,3 +104,5 @@ CREATE INDEX IF NOT EXISTS idx_users_github_id ON users(github_id);
 CREATE INDEX IF NOT EXISTS idx_tokens_user ON client_tokens(user_id);
 CREATE INDEX IF NOT EXISTS idx_tokens_hash ON client_tokens(token_hash);
 CREATE INDEX IF NOT EXISTS idx_clients_user ON clients(user_id);
+CREATE INDEX IF NOT EXISTS idx_token_usage_token ON token_usage(token_id, id);
+CREATE INDEX IF NOT EXISTS idx_hub_shares_viewer ON hub_shares(viewer_account_id);
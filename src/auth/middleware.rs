@@ -5,11 +5,15 @@ use crate::models::User;
 
 /// Row returned from D1 session query
 #[derive(Debug, Deserialize)]
-#[allow(dead_code)]
 struct SessionRow {
     session_id: String,
     user_id: String,
     expires_at: String,
+    created_at: String,
+    last_activity: Option<String>,
+    /// Non-zero when `last_activity` is stale enough to be refreshed
+    needs_touch: i64,
+    provider: String,
     github_id: i64,
     github_login: String,
     email: Option<String>,
@@ -27,23 +31,56 @@ impl AuthMiddleware {
         };
 
         // Look up session in D1
-        let db = env.d1("DB")?;
-        let result = db.prepare(
-            "SELECT s.id as session_id, s.user_id, s.expires_at,
-                    u.github_id, u.github_login, u.email
+        // last_activity is only refreshed once per minute to avoid a write on every request.
+        // Expiry is checked in Rust: expires_at is written in JS ISO format, which does not
+        // compare correctly against SQLite's datetime('now') as a string.
+        let query =
+            "SELECT s.id as session_id, s.user_id, s.expires_at, s.created_at, s.last_activity,
+                    (s.last_activity IS NULL OR s.last_activity < datetime('now', '-60 seconds')) AS needs_touch,
+                    u.provider, u.github_id, u.github_login, u.email
              FROM sessions s
              JOIN users u ON s.user_id = u.id
-             WHERE s.id = ?1 AND s.expires_at > datetime('now')"
-        )
-        .bind(&[session_id.into()])?
-        .first::<SessionRow>(None)
-        .await?;
+             WHERE s.id = ?1";
+
+        let db = env.d1("DB")?;
+        let result = db
+            .prepare(query)
+            .bind(&[session_id.into()])?
+            .first::<SessionRow>(None)
+            .await?;
 
         match result {
             Some(row) => {
+                if crate::time::is_past(&row.expires_at) {
+                    return Ok(None);
+                }
+
+                // Optional idle timeout (minutes) on top of the absolute expiry
+                let idle_minutes = crate::config::var_u64(env, "SESSION_IDLE_TIMEOUT", 0);
+                if idle_minutes > 0 {
+                    let last_active = row.last_activity.as_deref().unwrap_or(&row.created_at);
+                    let idle_ms = idle_minutes as i64 * 60_000;
+                    let idle_expired = crate::time::epoch_ms_of(last_active)
+                        .is_none_or(|ms| ms + idle_ms <= crate::time::now_ms() as i64);
+                    if idle_expired {
+                        return Ok(None);
+                    }
+                }
+
+                if row.needs_touch != 0 {
+                    let _ = db
+                        .prepare(
+                            "UPDATE sessions SET last_activity = CURRENT_TIMESTAMP WHERE id = ?1",
+                        )
+                        .bind(&[row.session_id.clone().into()])?
+                        .run()
+                        .await;
+                }
+
                 // Session is valid, create user
                 Ok(Some(User::from_db(
                     row.user_id,
+                    row.provider,
                     row.github_id,
                     row.github_login,
                     row.email,
@@ -57,19 +94,72 @@ impl AuthMiddleware {
     pub async fn require_auth(
         req: &Request,
         env: &Env,
+    ) -> Result<std::result::Result<User, Response>> {
+        match Self::get_user(req, env).await? {
+            Some(user) => Ok(Ok(user)),
+            None => Ok(Err(Self::sign_in_redirect(req, env)?)),
+        }
+    }
+
+    /// Redirect to the sign-in page (not straight to the OAuth provider),
+    /// coming back to the requested page after signing in
+    pub fn sign_in_redirect(req: &Request, env: &Env) -> Result<Response> {
+        let url = req.url()?;
+        let next = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        let headers = Headers::new();
+        let login = crate::config::app_path(env, "/");
+        headers.set(
+            "Location",
+            &format!("{}?next={}", login, super::url_encode(&next)),
+        )?;
+        Ok(Response::empty()?.with_status(302).with_headers(headers))
+    }
+
+    /// Require authentication for `/api/*` routes, returning a 401 JSON error
+    /// instead of a login redirect if not authenticated
+    pub async fn require_auth_json(
+        req: &Request,
+        env: &Env,
     ) -> Result<std::result::Result<User, Response>> {
         match Self::get_user(req, env).await? {
             Some(user) => Ok(Ok(user)),
             None => {
-                // Return redirect to login
-                let headers = Headers::new();
-                headers.set("Location", "/auth/github")?;
-                let response = Response::empty()?.with_status(302).with_headers(headers);
+                let response = Response::from_json(&serde_json::json!({
+                    "error": "unauthorized"
+                }))?
+                .with_status(401);
                 Ok(Err(response))
             }
         }
     }
 
+    /// Require an authenticated admin (see `ADMIN_USERS`) for `/api/admin/*`
+    /// routes, returning 401 or 403 JSON errors otherwise
+    pub async fn require_admin_json(
+        req: &Request,
+        env: &Env,
+    ) -> Result<std::result::Result<User, Response>> {
+        match Self::require_auth_json(req, env).await? {
+            Ok(user) if super::is_admin(env, &user) => Ok(Ok(user)),
+            Ok(_) => {
+                let response = Response::from_json(&serde_json::json!({
+                    "error": "forbidden"
+                }))?
+                .with_status(403);
+                Ok(Err(response))
+            }
+            Err(response) => Ok(Err(response)),
+        }
+    }
+
+    /// ID of the session cookie sent with the request, if any
+    pub fn session_id(req: &Request) -> Option<String> {
+        Self::get_session_cookie(req)
+    }
+
     fn get_session_cookie(req: &Request) -> Option<String> {
         let cookie_header = req.headers().get("Cookie").ok()??;
         for part in cookie_header.split(';') {
@@ -1,70 +1,121 @@
 mod middleware;
+mod provider;
 
 pub use middleware::AuthMiddleware;
 
+pub(crate) use provider::{qualified_name, split_qualified_name};
+use provider::{OAuthProvider, Provider};
+
 use serde::{Deserialize, Serialize};
 use worker::*;
 
-const GITHUB_AUTHORIZE_URL: &str = "https://github.com/login/oauth/authorize";
-const GITHUB_TOKEN_URL: &str = "https://github.com/login/oauth/access_token";
-const GITHUB_USER_URL: &str = "https://api.github.com/user";
-const GITHUB_ORGS_URL: &str = "https://api.github.com/user/orgs";
+/// How long org/team lookups stay in `GH_CACHE` when `GH_CACHE_TTL_SECONDS` is unset
+const DEFAULT_GH_CACHE_TTL_SECONDS: u64 = 300;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct GitHubUser {
-    id: i64,
-    login: String,
-    email: Option<String>,
-}
+/// Sign-in callbacks allowed per client IP per window when `AUTH_CALLBACK_RATE_LIMIT` is unset
+pub(crate) const DEFAULT_AUTH_CALLBACK_RATE_LIMIT: u64 = 30;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct GitHubOrg {
-    id: i64,
-    login: String,
-}
+/// Length of the callback rate limit window when `AUTH_CALLBACK_RATE_WINDOW_SECONDS` is unset
+pub(crate) const DEFAULT_AUTH_CALLBACK_RATE_WINDOW_SECONDS: u64 = 60;
+
+/// Shortest expiration KV accepts
+const MIN_KV_TTL_SECONDS: u64 = 60;
+
+/// Cookie carrying a token auto-created at first login to the dashboard, which shows it once
+pub(crate) const WELCOME_TOKEN_COOKIE: &str = "welcome_token";
 
 #[derive(Debug, Serialize, Deserialize)]
 struct TokenResponse {
     access_token: String,
+    #[serde(default)]
     token_type: String,
+    #[serde(default)]
     scope: String,
 }
 
-/// Start GitHub OAuth flow
+/// Start the OAuth flow for the provider named in the path (`/auth/:provider`)
 pub async fn start_oauth(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let client_id = ctx.env.var("GITHUB_CLIENT_ID")?.to_string();
-    let redirect_uri = get_redirect_uri(&req)?;
+    let provider = ctx
+        .param("provider")
+        .and_then(|name| Provider::from_name(&ctx.env, name));
+    match provider {
+        Some(Provider::GitHub(github)) => start_with(&github, &req, &ctx.env),
+        Some(Provider::GitLab(gitlab)) => start_with(&gitlab, &req, &ctx.env),
+        None => Response::error("Unknown sign-in provider", 404),
+    }
+}
+
+fn start_with<P: OAuthProvider>(provider: &P, req: &Request, env: &Env) -> Result<Response> {
+    let redirect_uri = match get_redirect_uri(req, env, P::NAME)? {
+        Some(uri) => uri,
+        None => return disallowed_host_response(),
+    };
 
     // Generate state for CSRF protection
-    let state = generate_state();
+    let state = match generate_state() {
+        Ok(state) => state,
+        Err(e) => {
+            console_error!("Failed to generate OAuth state: {:?}", e);
+            return Ok(Response::from_html(sign_in_unavailable_page())?.with_status(500));
+        }
+    };
+
+    // Remember where to send the user after login
+    let next = login_redirect_target(req, env)?;
 
     // Store state in cookie for validation
-    let auth_url = format!(
-        "{}?client_id={}&redirect_uri={}&scope=read:org%20read:user%20user:email&state={}",
-        GITHUB_AUTHORIZE_URL,
-        client_id,
-        url_encode(&redirect_uri),
-        state
-    );
+    let auth_url = provider.authorize_url(&redirect_uri, &state);
 
     let headers = Headers::new();
     headers.set("Location", &auth_url)?;
     headers.set(
         "Set-Cookie",
-        &format!(
-            "oauth_state={}; Path=/; HttpOnly; Secure; SameSite=Lax; Max-Age=600",
-            state
-        ),
+        &crate::config::sign_in_cookie(env, "oauth_state", &state, 600),
     )?;
+    if let Some(next) = next {
+        headers.append(
+            "Set-Cookie",
+            &crate::config::sign_in_cookie(env, "oauth_next", &url_encode(&next), 600),
+        )?;
+    }
 
     Response::empty()
         .map(|r| r.with_status(302))
         .map(|r| r.with_headers(headers))
 }
 
-/// Handle GitHub OAuth callback
+/// Handle the OAuth callback for the provider named in the path
 pub async fn handle_callback(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    match handle_callback_inner(req, ctx).await {
+    // Throttle per client IP so the state and code can't be brute-forced
+    if let Some(ip) = crate::client_info::client_ip(&req, &ctx.env) {
+        let max = crate::config::var_u64(
+            &ctx.env,
+            "AUTH_CALLBACK_RATE_LIMIT",
+            DEFAULT_AUTH_CALLBACK_RATE_LIMIT,
+        );
+        let window = crate::config::var_u64(
+            &ctx.env,
+            "AUTH_CALLBACK_RATE_WINDOW_SECONDS",
+            DEFAULT_AUTH_CALLBACK_RATE_WINDOW_SECONDS,
+        );
+        let key = format!("auth_callback:{}", ip);
+        if let Some(retry_after) =
+            crate::rate_limit::check_rate_limit(&ctx.env, &key, max, window).await
+        {
+            console_warn!("Rate limited sign-in callbacks from {}", ip);
+            return crate::rate_limit::too_many_requests(retry_after);
+        }
+    }
+
+    let provider = ctx
+        .param("provider")
+        .and_then(|name| Provider::from_name(&ctx.env, name));
+    let result = match provider {
+        Some(Provider::GitHub(github)) => handle_callback_inner(&github, req, &ctx.env).await,
+        Some(Provider::GitLab(gitlab)) => handle_callback_inner(&gitlab, req, &ctx.env).await,
+        None => return Response::error("Unknown sign-in provider", 404),
+    };
+    match result {
         Ok(response) => Ok(response),
         Err(e) => {
             console_log!("OAuth callback error: {:?}", e);
@@ -73,19 +124,19 @@ pub async fn handle_callback(req: Request, ctx: RouteContext<()>) -> Result<Resp
     }
 }
 
-async fn handle_callback_inner(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+async fn handle_callback_inner<P: OAuthProvider>(
+    provider: &P,
+    req: Request,
+    env: &Env,
+) -> Result<Response> {
     console_log!("OAuth callback started");
 
-    let url = req.url()?;
-    let params: std::collections::HashMap<String, String> = url
-        .query_pairs()
-        .map(|(k, v)| (k.to_string(), v.to_string()))
-        .collect();
+    let params = crate::query::Query::from_request(&req)?;
 
     console_log!("Params parsed, verifying state");
 
     // Verify state matches
-    let state = params.get("state").ok_or("Missing state parameter")?;
+    let state = params.get_str("state").ok_or("Missing state parameter")?;
     let cookie_state = match get_cookie(&req, "oauth_state") {
         Ok(s) => s,
         Err(e) => {
@@ -94,7 +145,7 @@ async fn handle_callback_inner(req: Request, ctx: RouteContext<()>) -> Result<Re
         }
     };
 
-    if state != &cookie_state {
+    if state != cookie_state {
         console_log!("State mismatch: {} vs {}", state, cookie_state);
         return Response::error("Invalid state parameter", 400);
     }
@@ -102,12 +153,14 @@ async fn handle_callback_inner(req: Request, ctx: RouteContext<()>) -> Result<Re
     console_log!("State verified, exchanging code for token");
 
     // Exchange code for token
-    let code = params.get("code").ok_or("Missing code parameter")?;
-    let client_id = ctx.env.var("GITHUB_CLIENT_ID")?.to_string();
-    let client_secret = ctx.env.secret("GITHUB_CLIENT_SECRET")?.to_string();
-    let redirect_uri = get_redirect_uri(&req)?;
+    let code = params.get_str("code").ok_or("Missing code parameter")?;
+    let client_secret = env.secret(P::CLIENT_SECRET)?.to_string();
+    let redirect_uri = match get_redirect_uri(&req, env, P::NAME)? {
+        Some(uri) => uri,
+        None => return disallowed_host_response(),
+    };
 
-    let token = match exchange_code_for_token(&client_id, &client_secret, code, &redirect_uri).await {
+    let token = match exchange_code_for_token(provider, &client_secret, code, &redirect_uri).await {
         Ok(t) => t,
         Err(e) => {
             console_log!("Token exchange failed: {:?}", e);
@@ -118,64 +171,92 @@ async fn handle_callback_inner(req: Request, ctx: RouteContext<()>) -> Result<Re
     console_log!("Token obtained, getting user info");
 
     // Get user info
-    let github_user = match get_github_user(&token).await {
+    let provider_user = match provider.fetch_user(&token).await {
         Ok(u) => u,
         Err(e) => {
-            console_log!("Failed to get GitHub user: {:?}", e);
-            return Response::error(format!("GitHub user fetch failed: {:?}", e), 500);
+            console_log!("Failed to get {} user: {:?}", P::NAME, e);
+            return Response::error(format!("User fetch failed: {:?}", e), 500);
         }
     };
-
-    console_log!("GitHub user: {}", github_user.login);
-
-    // Verify org/user/team restrictions
-    let allowed_orgs: Vec<String> = ctx
-        .env
-        .var("ALLOWED_ORGS")?
-        .to_string()
-        .split(',')
-        .filter(|s| !s.is_empty())
-        .map(|s| s.trim().to_string())
-        .collect();
-
-    let allowed_users: Vec<String> = ctx
-        .env
-        .var("ALLOWED_USERS")?
-        .to_string()
-        .split(',')
-        .filter(|s| !s.is_empty())
-        .map(|s| s.trim().to_string())
-        .collect();
+    let account_id = provider.account_id(provider_user.id);
+    // Allowlists name non-GitHub accounts and groups as "provider:name"
+    let login = provider::qualified_name(P::NAME, &provider_user.login);
+
+    console_log!("{} user: {}", P::NAME, provider_user.login);
+
+    // Verify org/user/team restrictions (unset vars count as empty)
+    let allowed_orgs = allowlist(env, "ALLOWED_ORGS");
+    let allowed_users = allowlist(env, "ALLOWED_USERS");
+    let allowed_teams = allowlist(env, "ALLOWED_TEAMS");
+    let allow_all = crate::config::var_bool(env, "ALLOW_ALL_USERS", false);
+    if allowed_orgs.is_empty() && allowed_users.is_empty() && allowed_teams.is_empty() && !allow_all
+    {
+        console_error!(
+            "None of ALLOWED_USERS, ALLOWED_ORGS or ALLOWED_TEAMS is configured; denying all sign-ins (set ALLOW_ALL_USERS=true to allow any user)"
+        );
+    }
 
     // Check if user is allowed
-    let is_allowed = if allowed_users.is_empty() && allowed_orgs.is_empty() {
-        true // No restrictions configured
+    let user_orgs = if allowed_orgs.is_empty() {
+        Vec::new()
     } else {
-        let user_allowed = allowed_users.contains(&github_user.login);
-        let org_allowed = if !allowed_orgs.is_empty() {
-            check_org_membership(&token, &allowed_orgs).await?
-        } else {
-            false
-        };
-        user_allowed || org_allowed
+        check_org_membership(provider, env, account_id, &token).await?
     };
+    let user_teams = if allowed_teams.is_empty() {
+        Vec::new()
+    } else {
+        check_team_membership(provider, env, account_id, &token, &allowed_teams).await?
+    };
+    let (is_allowed, reason) = access_decision(
+        &login,
+        &Allowlists {
+            users: &allowed_users,
+            orgs: &allowed_orgs,
+            teams: &allowed_teams,
+        },
+        &user_orgs,
+        &user_teams,
+        allow_all,
+    );
+
+    // Structured record of the decision for debugging access issues (no tokens)
+    console_log!(
+        "{}",
+        serde_json::json!({
+            "event": "auth_decision",
+            "provider": P::NAME,
+            "github_login": login,
+            "allowed": is_allowed,
+            "reason": reason,
+            "allowed_users": allowed_users,
+            "allowed_orgs": allowed_orgs,
+            "user_orgs": user_orgs,
+            "allowed_teams": allowed_teams,
+            "user_teams": user_teams,
+        })
+    );
 
     if !is_allowed {
         return Response::error("Access denied: not authorized", 403);
     }
 
     // Create user record (for new users)
-    let new_user = crate::models::User::new(github_user.id, github_user.login.clone(), github_user.email.clone());
+    let new_user = crate::models::User::new(
+        P::NAME.to_string(),
+        account_id,
+        provider_user.login.clone(),
+        provider_user.email.clone(),
+    )?;
 
     console_log!("Processing user: {} (github_id: {})", new_user.github_login, new_user.github_id);
 
     // Store user and session in D1
-    let db = ctx.env.d1("DB")?;
+    let db = env.d1("DB")?;
 
     // Upsert user
     let user_result = db.prepare(
-        "INSERT INTO users (id, github_id, github_login, email, last_login)
-         VALUES (?1, ?2, ?3, ?4, CURRENT_TIMESTAMP)
+        "INSERT INTO users (id, github_id, github_login, email, provider, last_login)
+         VALUES (?1, ?2, ?3, ?4, ?5, CURRENT_TIMESTAMP)
          ON CONFLICT(github_id) DO UPDATE SET
          github_login = excluded.github_login,
          email = excluded.email,
@@ -185,7 +266,8 @@ async fn handle_callback_inner(req: Request, ctx: RouteContext<()>) -> Result<Re
         new_user.id.clone().into(),
         wasm_bindgen::JsValue::from_f64(new_user.github_id as f64),  // D1 doesn't support bigint
         new_user.github_login.clone().into(),
-        github_user.email.clone().map(|e| e.into()).unwrap_or(wasm_bindgen::JsValue::NULL),
+        provider_user.email.clone().map(|e| e.into()).unwrap_or(wasm_bindgen::JsValue::NULL),
+        new_user.provider.clone().into(),
     ])?
     .run()
     .await;
@@ -204,7 +286,7 @@ async fn handle_callback_inner(req: Request, ctx: RouteContext<()>) -> Result<Re
     let user_id_result = db.prepare(
         "SELECT id FROM users WHERE github_id = ?1"
     )
-    .bind(&[wasm_bindgen::JsValue::from_f64(github_user.id as f64)])?
+    .bind(&[wasm_bindgen::JsValue::from_f64(account_id as f64)])?
     .first::<UserIdRow>(None)
     .await?;
 
@@ -218,16 +300,23 @@ async fn handle_callback_inner(req: Request, ctx: RouteContext<()>) -> Result<Re
     console_log!("Using user_id: {}", actual_user_id);
 
     // Create session with the actual user ID
-    let session = crate::models::Session::new(actual_user_id.clone(), 24 * 7); // 1 week
+    let session = crate::models::Session::new(actual_user_id.clone(), 24 * 7)?; // 1 week
+
+    // Record where the session was created so it can be recognized in the session list
+    let created_ip = crate::client_info::client_ip(&req, env);
+    let user_agent = crate::client_info::user_agent(&req);
 
     // Insert session
     let session_result = db.prepare(
-        "INSERT INTO sessions (id, user_id, expires_at) VALUES (?1, ?2, ?3)"
+        "INSERT INTO sessions (id, user_id, expires_at, created_ip, user_agent)
+         VALUES (?1, ?2, ?3, ?4, ?5)"
     )
     .bind(&[
         session.id.clone().into(),
         actual_user_id.into(),
         session.expires_at.clone().into(),
+        created_ip.map(|ip| ip.into()).unwrap_or(wasm_bindgen::JsValue::NULL),
+        user_agent.map(|ua| ua.into()).unwrap_or(wasm_bindgen::JsValue::NULL),
     ])?
     .run()
     .await;
@@ -238,17 +327,45 @@ async fn handle_callback_inner(req: Request, ctx: RouteContext<()>) -> Result<Re
     }
     console_log!("Session created successfully");
 
-    // Redirect to dashboard with session ID cookie (just the ID, not full data)
+    // Onboarding: hand a brand-new user their first token (AUTO_CREATE_FIRST_TOKEN)
+    let first_token = crate::handlers::create_first_token(env, &session.user_id).await;
+    let welcome_token = match first_token {
+        Ok(token) => token,
+        Err(e) => {
+            console_log!("Failed to auto-create first token: {:?}", e);
+            None
+        }
+    };
+
+    // Return to the page that required login, if one was captured
+    let next = get_cookie(&req, "oauth_next")
+        .ok()
+        .and_then(|value| url_decode(&value))
+        .unwrap_or_default();
+    let location = safe_redirect_path(env, &next);
+
+    // Redirect with session ID cookie (just the ID, not full data)
     let headers = Headers::new();
-    headers.set("Location", "/dashboard")?;
+    headers.set("Location", &location)?;
     headers.set(
         "Set-Cookie",
-        &format!(
-            "session={}; Path=/; HttpOnly; Secure; SameSite=Lax; Max-Age={}",
-            session.id,
-            7 * 24 * 60 * 60 // 1 week in seconds
+        &crate::config::cookie(
+            env,
+            "session",
+            &session.id,
+            7 * 24 * 60 * 60, // 1 week in seconds
         ),
     )?;
+    headers.append(
+        "Set-Cookie",
+        &crate::config::sign_in_cookie(env, "oauth_next", "", 0),
+    )?;
+    if let Some(token) = welcome_token {
+        headers.append(
+            "Set-Cookie",
+            &crate::config::cookie(env, WELCOME_TOKEN_COOKIE, &token, 300),
+        )?;
+    }
 
     Response::empty()
         .map(|r| r.with_status(302))
@@ -256,29 +373,78 @@ async fn handle_callback_inner(req: Request, ctx: RouteContext<()>) -> Result<Re
 }
 
 /// Logout and clear session
-pub async fn logout(_req: Request, _ctx: RouteContext<()>) -> Result<Response> {
+pub async fn logout(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Some(session_id) = AuthMiddleware::session_id(&req) {
+        crate::user_cache::invalidate_session(&session_id);
+    }
+
     // Clear session cookie
     let headers = Headers::new();
-    headers.set("Location", "/")?;
-    headers.set(
-        "Set-Cookie",
-        "session=; Path=/; HttpOnly; Secure; SameSite=Lax; Max-Age=0",
-    )?;
+    headers.set("Location", &crate::config::app_path(&ctx.env, "/"))?;
+    headers.set("Set-Cookie", &clear_session_cookie(&ctx.env))?;
 
     Response::empty()
         .map(|r| r.with_status(302))
         .map(|r| r.with_headers(headers))
 }
 
-async fn exchange_code_for_token(
-    client_id: &str,
+/// Sign out of every device by deleting all of the user's sessions
+///
+/// Browsers are redirected to the sign-in page. Callers sending
+/// `Accept: application/json` get the number of sessions removed instead.
+pub async fn logout_all(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let wants_json = crate::handlers::wants_json(&req);
+
+    let auth = if wants_json {
+        AuthMiddleware::require_auth_json(&req, &ctx.env).await?
+    } else {
+        AuthMiddleware::require_auth(&req, &ctx.env).await?
+    };
+    let user = match auth {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    let db = ctx.env.d1("DB")?;
+    let result = db
+        .prepare("DELETE FROM sessions WHERE user_id = ?1")
+        .bind(&[user.id.clone().into()])?
+        .run()
+        .await?;
+    let revoked = result.meta()?.and_then(|meta| meta.changes).unwrap_or(0);
+    crate::user_cache::invalidate_user(&user.id);
+    console_log!("Signed {} out of {} sessions", user.github_login, revoked);
+
+    let headers = Headers::new();
+    headers.set("Set-Cookie", &clear_session_cookie(&ctx.env))?;
+    if wants_json {
+        return Ok(Response::from_json(&serde_json::json!({
+            "sessions_revoked": revoked
+        }))?
+        .with_headers(headers));
+    }
+
+    headers.set("Location", &crate::config::app_path(&ctx.env, "/"))?;
+    Ok(Response::empty()?.with_status(302).with_headers(headers))
+}
+
+/// `Set-Cookie` value that removes the session cookie
+pub(crate) fn clear_session_cookie(env: &Env) -> String {
+    crate::config::cookie(env, "session", "", 0)
+}
+
+async fn exchange_code_for_token<P: OAuthProvider>(
+    provider: &P,
     client_secret: &str,
     code: &str,
     redirect_uri: &str,
 ) -> Result<String> {
     let body = format!(
-        "client_id={}&client_secret={}&code={}&redirect_uri={}",
-        client_id, client_secret, code, redirect_uri
+        "client_id={}&client_secret={}&code={}&grant_type=authorization_code&redirect_uri={}",
+        url_encode(provider.client_id()),
+        url_encode(client_secret),
+        url_encode(code),
+        url_encode(redirect_uri)
     );
 
     let mut init = RequestInit::new();
@@ -290,48 +456,213 @@ async fn exchange_code_for_token(
     headers.set("Content-Type", "application/x-www-form-urlencoded")?;
     init.with_headers(headers);
 
-    let request = Request::new_with_init(GITHUB_TOKEN_URL, &init)?;
+    let request = Request::new_with_init(&provider.token_url(), &init)?;
     let mut response = Fetch::Request(request).send().await?;
     let token_response: TokenResponse = response.json().await?;
 
     Ok(token_response.access_token)
 }
 
-async fn get_github_user(token: &str) -> Result<GitHubUser> {
-    let headers = Headers::new();
-    headers.set("Authorization", &format!("Bearer {}", token))?;
-    headers.set("User-Agent", "AI-Orchestrator")?;
-    headers.set("Accept", "application/json")?;
+/// Comma-separated allowlist from an env var; unset and empty both mean no entries
+pub(crate) fn allowlist(env: &Env, name: &str) -> Vec<String> {
+    parse_allowlist(crate::config::var_string(env, name).as_deref())
+}
 
-    let mut init = RequestInit::new();
-    init.with_method(Method::Get);
-    init.with_headers(headers);
+/// Entries of a comma-separated allowlist value, trimmed, skipping blanks
+fn parse_allowlist(raw: Option<&str>) -> Vec<String> {
+    raw.unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
 
-    let request = Request::new_with_init(GITHUB_USER_URL, &init)?;
-    let mut response = Fetch::Request(request).send().await?;
-    response.json().await
+/// Whether a user is a deployment admin (listed in `ADMIN_USERS`)
+///
+/// Non-GitHub admins are listed with their provider prefix, e.g. `gitlab:alice`.
+pub fn is_admin(env: &Env, user: &crate::models::User) -> bool {
+    let login = account_name(user);
+    allowlist(env, "ADMIN_USERS")
+        .iter()
+        .any(|entry| entry.eq_ignore_ascii_case(&login))
 }
 
-async fn check_org_membership(token: &str, allowed_orgs: &[String]) -> Result<bool> {
-    let headers = Headers::new();
-    headers.set("Authorization", &format!("Bearer {}", token))?;
-    headers.set("User-Agent", "AI-Orchestrator")?;
-    headers.set("Accept", "application/json")?;
+/// A user's login as written in allowlists and viewer grants (`gitlab:` prefixed for GitLab)
+pub(crate) fn account_name(user: &crate::models::User) -> String {
+    provider::qualified_name(&user.provider, &user.github_login)
+}
 
-    let mut init = RequestInit::new();
-    init.with_method(Method::Get);
-    init.with_headers(headers);
+/// Sign-in allowlists from `ALLOWED_USERS`, `ALLOWED_ORGS` and `ALLOWED_TEAMS`
+struct Allowlists<'a> {
+    users: &'a [String],
+    orgs: &'a [String],
+    /// Entries of the form "org/team-slug"
+    teams: &'a [String],
+}
 
-    let request = Request::new_with_init(GITHUB_ORGS_URL, &init)?;
-    let mut response = Fetch::Request(request).send().await?;
-    let orgs: Vec<GitHubOrg> = response.json().await?;
+/// Decide whether a user may sign in, returning the reason for the outcome
+///
+/// `user_teams` holds the allowed teams the user was found in. With no
+/// allowlist configured, everyone is denied unless `allow_all` is set.
+fn access_decision(
+    login: &str,
+    allowed: &Allowlists,
+    user_orgs: &[String],
+    user_teams: &[String],
+    allow_all: bool,
+) -> (bool, &'static str) {
+    let (allowed_users, allowed_orgs) = (allowed.users, allowed.orgs);
+    if allowed_users.is_empty() && allowed_orgs.is_empty() && allowed.teams.is_empty() {
+        if allow_all {
+            (true, "allow_all_users")
+        } else {
+            (false, "no_allowlist_configured")
+        }
+    } else if allowed_users.iter().any(|u| u == login) {
+        (true, "in_allowed_users")
+    } else if user_orgs.iter().any(|org| allowed_orgs.contains(org)) {
+        (true, "member_of_allowed_org")
+    } else if !user_teams.is_empty() {
+        (true, "member_of_allowed_team")
+    } else if !allowed.teams.is_empty() {
+        (false, "not_in_allowed_users_orgs_or_teams")
+    } else if allowed_orgs.is_empty() {
+        (false, "not_in_allowed_users")
+    } else if allowed_users.is_empty() {
+        (false, "not_in_allowed_orgs")
+    } else {
+        (false, "not_in_allowed_users_or_orgs")
+    }
+}
+
+/// Organizations (or GitLab groups) a user belongs to, served from `GH_CACHE` when fresh
+///
+/// Non-GitHub names are qualified like logins (`gitlab:group`).
+async fn check_org_membership<P: OAuthProvider>(
+    provider: &P,
+    env: &Env,
+    account_id: i64,
+    token: &str,
+) -> Result<Vec<String>> {
+    let key = format!("{}:orgs:{}", P::NAME, account_id);
+    let orgs = cached_memberships(env, &key, provider.fetch_orgs(token)).await?;
+    Ok(orgs
+        .iter()
+        .map(|org| provider::qualified_name(P::NAME, org))
+        .collect())
+}
+
+/// Teams from `allowed_teams` ("org/team-slug") that a user belongs to
+///
+/// The user's full team list is cached in `GH_CACHE` like their orgs.
+async fn check_team_membership<P: OAuthProvider>(
+    provider: &P,
+    env: &Env,
+    account_id: i64,
+    token: &str,
+    allowed_teams: &[String],
+) -> Result<Vec<String>> {
+    let key = format!("{}:teams:{}", P::NAME, account_id);
+    let teams = cached_memberships(env, &key, provider.fetch_teams(token)).await?;
+    Ok(allowed_teams_of(allowed_teams, &teams))
+}
+
+/// The entries of `allowed_teams` found among a user's `teams` ("org/team-slug", any case)
+fn allowed_teams_of(allowed_teams: &[String], teams: &[String]) -> Vec<String> {
+    allowed_teams
+        .iter()
+        .filter(|allowed| teams.iter().any(|team| team.eq_ignore_ascii_case(allowed)))
+        .cloned()
+        .collect()
+}
+
+/// A membership list from the `GH_CACHE` KV namespace, or from `fetch` on a miss
+///
+/// Entries live for `GH_CACHE_TTL_SECONDS` (default 300, at least 60; 0
+/// disables caching), so a user removed from an org or team can still sign in
+/// until theirs expires. Without the binding, or when KV fails, the live
+/// lookup is used and sign-in carries on.
+async fn cached_memberships(
+    env: &Env,
+    key: &str,
+    fetch: impl std::future::Future<Output = Result<Vec<String>>>,
+) -> Result<Vec<String>> {
+    let ttl = crate::config::var_u64(env, "GH_CACHE_TTL_SECONDS", DEFAULT_GH_CACHE_TTL_SECONDS);
+    let cache = match env.kv("GH_CACHE") {
+        Ok(cache) if ttl > 0 => cache,
+        _ => return fetch.await,
+    };
+
+    match cache.get(key).json::<Vec<String>>().await {
+        Ok(Some(cached)) => return Ok(cached),
+        Ok(None) => {}
+        Err(e) => console_warn!("GH_CACHE read failed for {}: {:?}", key, e),
+    }
 
-    Ok(orgs.iter().any(|org| allowed_orgs.contains(&org.login)))
+    let memberships = fetch.await?;
+    let expiration_ttl = ttl.max(MIN_KV_TTL_SECONDS);
+    let stored = match cache.put(key, &memberships) {
+        Ok(put) => put.expiration_ttl(expiration_ttl).execute().await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = stored {
+        console_warn!("GH_CACHE write failed for {}: {:?}", key, e);
+    }
+    Ok(memberships)
+}
+
+/// Hosts allowed to start OAuth when ALLOWED_REDIRECT_HOSTS is unset
+const DEFAULT_REDIRECT_HOSTS: &str = "*.workers.dev,*.pages.dev,localhost,127.0.0.1";
+
+/// Whether OAuth may redirect back to `host`, per ALLOWED_REDIRECT_HOSTS
+/// (comma-separated; `*.example.com` matches any subdomain of example.com)
+fn is_allowed_redirect_host(env: &Env, host: &str) -> bool {
+    host_matches(&allowed_redirect_hosts(env), host)
+}
+
+/// Whether `host` (without a port) matches one of the `allowed` entries
+fn host_matches(allowed: &[String], host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+
+    allowed.iter().any(|entry| match entry.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+        None => host == *entry,
+    })
+}
+
+/// Lowercased ALLOWED_REDIRECT_HOSTS entries, or the defaults when unset
+pub(crate) fn allowed_redirect_hosts(env: &Env) -> Vec<String> {
+    parse_redirect_hosts(crate::config::var_string(env, "ALLOWED_REDIRECT_HOSTS").as_deref())
 }
 
-fn get_redirect_uri(req: &Request) -> Result<String> {
+/// Lowercased entries of an ALLOWED_REDIRECT_HOSTS value, or the defaults when unset
+fn parse_redirect_hosts(raw: Option<&str>) -> Vec<String> {
+    raw.unwrap_or(DEFAULT_REDIRECT_HOSTS)
+        .split(',')
+        .map(|entry| entry.trim().to_ascii_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .collect()
+}
+
+/// Error page for sign-in attempts made through a host that isn't allowlisted
+fn disallowed_host_response() -> Result<Response> {
+    Ok(Response::from_html(crate::templates::render_error(
+        "Sign-in unavailable",
+        "Sign-in is not available on this address. Please use the orchestrator's usual URL.",
+    ))?
+    .with_status(400))
+}
+
+/// OAuth callback URL for the request's host, or `None` if the host isn't allowlisted
+fn get_redirect_uri(req: &Request, env: &Env, provider: &str) -> Result<Option<String>> {
     let url = req.url()?;
     let host = url.host_str().ok_or("Missing host")?;
+    if !is_allowed_redirect_host(env, host) {
+        console_error!("Rejected OAuth on non-allowlisted host: {}", host);
+        return Ok(None);
+    }
     // Always use HTTPS for Cloudflare Workers (required for Secure cookies)
     let scheme = if host.ends_with(".workers.dev") || host.ends_with(".pages.dev") {
         "https"
@@ -339,20 +670,80 @@ fn get_redirect_uri(req: &Request) -> Result<String> {
         url.scheme()
     };
     let port = url.port().map(|p| format!(":{}", p)).unwrap_or_default();
-    Ok(format!(
-        "{}://{}{}/auth/github/callback",
-        scheme, host, port
-    ))
+    Ok(Some(format!(
+        "{}://{}{}{}",
+        scheme,
+        host,
+        port,
+        crate::config::app_path(env, &format!("/auth/{}/callback", provider))
+    )))
 }
 
-fn generate_state() -> String {
-    use getrandom::getrandom;
-    let mut bytes = [0u8; 16];
-    getrandom(&mut bytes).expect("Failed to generate random bytes");
-    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+/// Pick the post-login target from `?next=` or a same-origin `Referer`
+fn login_redirect_target(req: &Request, env: &Env) -> Result<Option<String>> {
+    let url = req.url()?;
+    let params = crate::query::Query::from_url(&url);
+    if let Some(next) = params.get_str("next") {
+        return Ok(Some(next.to_string()).filter(|path| is_safe_redirect_path(path)));
+    }
+
+    // Fall back to the referring page, but only from this origin and not
+    // from the landing or auth pages (those should still go to the dashboard)
+    let home = crate::config::app_path(env, "/");
+    let auth_prefix = crate::config::app_path(env, "/auth/");
+    let referer = req
+        .headers()
+        .get("Referer")?
+        .and_then(|r| Url::parse(&r).ok())
+        .filter(|r| r.origin() == url.origin());
+    Ok(referer
+        .map(|r| match r.query() {
+            Some(query) => format!("{}?{}", r.path(), query),
+            None => r.path().to_string(),
+        })
+        .filter(|p| *p != home && !p.starts_with(&auth_prefix) && is_safe_redirect_path(p)))
 }
 
-fn get_cookie(req: &Request, name: &str) -> Result<String> {
+/// Default post-login destination when no safe target is available
+const DEFAULT_REDIRECT_PATH: &str = "/dashboard";
+
+/// Return `candidate` if it is a same-origin absolute path, else `/dashboard`
+///
+/// Use this for every redirect target that comes from user input.
+pub(crate) fn safe_redirect_path(env: &Env, candidate: &str) -> String {
+    if is_safe_redirect_path(candidate) {
+        candidate.to_string()
+    } else {
+        crate::config::app_path(env, DEFAULT_REDIRECT_PATH)
+    }
+}
+
+/// Whether a redirect target is a path on this origin
+///
+/// Browsers treat `//host` and `/\host` as protocol-relative URLs and strip
+/// tabs/newlines before resolving, so those are rejected along with anything
+/// that is not rooted at a single `/` (e.g. `https://`, `javascript:`).
+pub(crate) fn is_safe_redirect_path(path: &str) -> bool {
+    let mut chars = path.chars();
+    chars.next() == Some('/')
+        && !matches!(chars.next(), Some('/') | Some('\\'))
+        && !path.contains('\\')
+        && !path.chars().any(|c| c.is_control() || c.is_whitespace())
+}
+
+/// Error page shown when the OAuth flow can't start (e.g. the RNG failed)
+fn sign_in_unavailable_page() -> String {
+    crate::templates::render_error(
+        "Sign-in unavailable",
+        "We couldn't start the sign-in flow. Please try again in a moment.",
+    )
+}
+
+fn generate_state() -> Result<String> {
+    crate::random::random_hex(16)
+}
+
+pub(crate) fn get_cookie(req: &Request, name: &str) -> Result<String> {
     let cookie_header = req.headers().get("Cookie")?.unwrap_or_default();
     for part in cookie_header.split(';') {
         let part = part.trim();
@@ -363,11 +754,214 @@ fn get_cookie(req: &Request, name: &str) -> Result<String> {
     Err("Cookie not found".into())
 }
 
-fn url_encode(s: &str) -> String {
-    s.chars()
-        .map(|c| match c {
-            'A'..='Z' | 'a'..='z' | '0'..='9' | '-' | '_' | '.' | '~' => c.to_string(),
-            _ => format!("%{:02X}", c as u8),
+pub(crate) fn url_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
         })
         .collect()
 }
+
+/// Reverse of `url_encode` (returns None for malformed input)
+pub(crate) fn url_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::tests::with_rng_failure;
+
+    /// Whether sign-in may start from `url` under ALLOWED_REDIRECT_HOSTS `raw`
+    fn allows(raw: Option<&str>, url: &str) -> bool {
+        let url = Url::parse(url).unwrap();
+        host_matches(&parse_redirect_hosts(raw), url.host_str().unwrap())
+    }
+
+    #[test]
+    fn default_redirect_hosts_cover_workers_pages_and_localhost() {
+        for url in [
+            "https://orchestrator.acme.workers.dev/",
+            "https://preview.orchestrator.pages.dev/",
+            "http://localhost:8787/",
+            "http://127.0.0.1:8787/",
+        ] {
+            assert!(allows(None, url), "{url}");
+        }
+        assert!(!allows(None, "https://orchestrator.example.com/"));
+    }
+
+    #[test]
+    fn wildcards_only_match_subdomains() {
+        assert!(!allows(None, "https://evilworkers.dev/"));
+        assert!(!allows(None, "https://x.evilworkers.dev/"));
+        assert!(!allows(None, "https://workers.dev/"));
+        assert!(!allows(None, "https://workers.dev.evil.com/"));
+        assert!(!allows(None, "https://localhost.evil.com/"));
+    }
+
+    #[test]
+    fn configured_redirect_hosts_replace_the_defaults() {
+        let raw = Some(" Orchestrator.Example.com ,, *.internal.example.com ");
+        assert!(allows(raw, "https://orchestrator.example.com/"));
+        assert!(allows(raw, "https://ORCHESTRATOR.example.com:8443/"));
+        assert!(allows(raw, "https://a.b.internal.example.com/"));
+        assert!(!allows(raw, "https://internal.example.com/"));
+        assert!(!allows(raw, "https://orchestrator.acme.workers.dev/"));
+        assert!(!allows(raw, "http://localhost:8787/"));
+    }
+
+    #[test]
+    fn rng_failure_shows_the_sign_in_error_page() {
+        assert!(with_rng_failure(generate_state).is_err());
+
+        let page = sign_in_unavailable_page();
+        assert!(page.contains("<h1>Sign-in unavailable</h1>"));
+        assert!(page.contains("We couldn&#39;t start the sign-in flow."));
+        assert!(page.contains(r#"<a href="/" class="btn btn-secondary">Back to home</a>"#));
+    }
+
+    #[test]
+    fn redirects_stay_on_this_origin() {
+        assert!(is_safe_redirect_path("/dashboard?x=1"));
+        assert!(is_safe_redirect_path("/"));
+
+        for unsafe_path in [
+            "//evil.example",
+            "/\\evil.example",
+            "/\t/evil.example",
+            "/\n/evil.example",
+            "https://evil.example",
+            "javascript:alert(1)",
+            "dashboard",
+            "",
+        ] {
+            assert!(!is_safe_redirect_path(unsafe_path), "{:?}", unsafe_path);
+        }
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    /// Decision for "octocat" with only ALLOWED_TEAMS set
+    fn team_decision(allowed_teams: &[&str], user_teams: &[&str]) -> (bool, &'static str) {
+        let allowed_teams = strings(allowed_teams);
+        let user_teams = allowed_teams_of(&allowed_teams, &strings(user_teams));
+        let allowed = Allowlists {
+            users: &[],
+            orgs: &[],
+            teams: &allowed_teams,
+        };
+        access_decision("octocat", &allowed, &[], &user_teams, false)
+    }
+
+    #[test]
+    fn member_of_allowed_team_passes() {
+        let decision = team_decision(&["acme/platform"], &["acme/web", "ACME/Platform"]);
+        assert_eq!(decision, (true, "member_of_allowed_team"));
+    }
+
+    #[test]
+    fn member_of_another_team_is_denied() {
+        // A denial is answered with 403 by the callback
+        let decision = team_decision(&["acme/platform"], &["acme/web", "other/platform"]);
+        assert_eq!(decision, (false, "not_in_allowed_users_orgs_or_teams"));
+    }
+
+    #[test]
+    fn no_allowlist_denies_unless_allow_all() {
+        let none = Allowlists {
+            users: &[],
+            orgs: &[],
+            teams: &[],
+        };
+        assert_eq!(
+            access_decision("octocat", &none, &[], &[], false),
+            (false, "no_allowlist_configured")
+        );
+        assert_eq!(
+            access_decision("octocat", &none, &[], &[], true),
+            (true, "allow_all_users")
+        );
+    }
+
+    /// Decision for "octocat" (a member of the "acme" org) with ALLOW_ALL_USERS on,
+    /// which only matters while no allowlist is configured
+    fn decision(users: &[&str], orgs: &[&str]) -> (bool, &'static str) {
+        let (users, orgs) = (strings(users), strings(orgs));
+        let allowed = Allowlists {
+            users: &users,
+            orgs: &orgs,
+            teams: &[],
+        };
+        access_decision("octocat", &allowed, &strings(&["acme"]), &[], true)
+    }
+
+    #[test]
+    fn blank_allowlists_have_no_entries() {
+        for raw in [None, Some(""), Some("   "), Some(" , ,")] {
+            assert!(parse_allowlist(raw).is_empty(), "{raw:?}");
+        }
+        assert_eq!(
+            parse_allowlist(Some(" octocat,, gitlab:alice ,")),
+            ["octocat", "gitlab:alice"]
+        );
+    }
+
+    #[test]
+    fn allow_all_users_applies_only_without_entries() {
+        let allow_all = crate::config::parse_bool(Some("true"), false);
+        let blank = parse_allowlist(Some(" , "));
+        let allowed = Allowlists {
+            users: &blank,
+            orgs: &blank,
+            teams: &blank,
+        };
+        let decision = access_decision("octocat", &allowed, &[], &[], allow_all);
+        assert_eq!(decision, (true, "allow_all_users"));
+
+        let users = parse_allowlist(Some("hubot"));
+        let allowed = Allowlists {
+            users: &users,
+            ..allowed
+        };
+        let decision = access_decision("octocat", &allowed, &[], &[], allow_all);
+        assert_eq!(decision, (false, "not_in_allowed_users"));
+    }
+
+    #[test]
+    fn allowed_users_and_orgs_pass() {
+        assert_eq!(decision(&["octocat"], &[]), (true, "in_allowed_users"));
+        assert_eq!(decision(&[], &["acme"]), (true, "member_of_allowed_org"));
+        // A listed login is reported as such even when the org matches too
+        let both = decision(&["octocat"], &["acme"]);
+        assert_eq!(both, (true, "in_allowed_users"));
+    }
+
+    #[test]
+    fn denials_name_the_allowlists_checked() {
+        assert_eq!(decision(&["hubot"], &[]), (false, "not_in_allowed_users"));
+        assert_eq!(decision(&[], &["umbrella"]), (false, "not_in_allowed_orgs"));
+        assert_eq!(
+            decision(&["hubot"], &["umbrella"]),
+            (false, "not_in_allowed_users_or_orgs")
+        );
+    }
+}
@@ -0,0 +1,384 @@
+//! OAuth identity providers users can sign in with
+//!
+//! GitHub is always available. GitLab (gitlab.com or self-hosted via
+//! `GITLAB_URL`) is enabled by setting `GITLAB_CLIENT_ID` and the
+//! `GITLAB_CLIENT_SECRET` secret.
+
+use serde::Deserialize;
+use worker::*;
+
+const GITHUB_AUTHORIZE_URL: &str = "https://github.com/login/oauth/authorize";
+const GITHUB_TOKEN_URL: &str = "https://github.com/login/oauth/access_token";
+const GITHUB_USER_URL: &str = "https://api.github.com/user";
+const GITHUB_ORGS_URL: &str = "https://api.github.com/user/orgs";
+const GITHUB_TEAMS_URL: &str = "https://api.github.com/user/teams?per_page=100";
+
+/// Upper bound on `/user/teams` pages fetched per sign-in (100 teams each)
+const MAX_TEAM_PAGES: usize = 10;
+
+/// GitLab instance used when `GITLAB_URL` is unset
+const DEFAULT_GITLAB_URL: &str = "https://gitlab.com";
+
+/// The signed-in account as reported by a provider
+pub(crate) struct ProviderUser {
+    pub id: i64,
+    pub login: String,
+    pub email: Option<String>,
+}
+
+/// An OAuth provider: where to send the user, and how to read their identity
+pub(crate) trait OAuthProvider {
+    /// Route segment (`/auth/{name}`) and value of the `users.provider` column
+    const NAME: &'static str;
+    /// Secret holding the OAuth app's client secret
+    const CLIENT_SECRET: &'static str;
+
+    fn client_id(&self) -> &str;
+
+    /// Page the browser is sent to for approval
+    fn authorize_url(&self, redirect_uri: &str, state: &str) -> String;
+
+    /// Endpoint exchanging the callback code for an access token
+    fn token_url(&self) -> String;
+
+    /// Endpoint describing the signed-in account
+    fn user_url(&self) -> String;
+
+    async fn fetch_user(&self, token: &str) -> Result<ProviderUser>;
+
+    /// Organizations (GitHub) or groups (GitLab, by full path) the user belongs to
+    async fn fetch_orgs(&self, token: &str) -> Result<Vec<String>>;
+
+    /// Teams as "org/team-slug"; only GitHub has them
+    async fn fetch_teams(&self, _token: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// Value stored in `users.github_id`, which must be unique across providers
+    fn account_id(&self, id: i64) -> i64 {
+        id
+    }
+}
+
+/// A provider selected by the `/auth/:provider` route segment
+pub(crate) enum Provider {
+    GitHub(GitHub),
+    GitLab(GitLab),
+}
+
+impl Provider {
+    /// The named provider, or `None` if it is unknown or not configured
+    pub fn from_name(env: &Env, name: &str) -> Option<Self> {
+        match name {
+            GitHub::NAME => env.var("GITHUB_CLIENT_ID").ok().map(|id| {
+                Provider::GitHub(GitHub {
+                    client_id: id.to_string(),
+                })
+            }),
+            GitLab::NAME => GitLab::from_env(env).map(Provider::GitLab),
+            _ => None,
+        }
+    }
+}
+
+/// Login or org name as written in allowlists: GitHub names as-is, others
+/// prefixed with their provider (e.g. `gitlab:alice`), so the same name on two
+/// providers never matches the wrong account
+pub(crate) fn qualified_name(provider: &str, name: &str) -> String {
+    if provider == GitHub::NAME {
+        name.to_string()
+    } else {
+        format!("{}:{}", provider, name)
+    }
+}
+
+/// Inverse of [`qualified_name`]: the provider and bare name of an allowlist entry
+pub(crate) fn split_qualified_name(name: &str) -> (&'static str, &str) {
+    match name.split_once(':') {
+        Some((provider, bare)) if provider.eq_ignore_ascii_case(GitLab::NAME) => {
+            (GitLab::NAME, bare)
+        }
+        _ => (GitHub::NAME, name),
+    }
+}
+
+/// GET a provider API endpoint with the user's access token
+async fn get_json(url: &str, token: &str) -> Result<Response> {
+    let headers = Headers::new();
+    headers.set("Authorization", &format!("Bearer {}", token))?;
+    headers.set("User-Agent", "AI-Orchestrator")?;
+    headers.set("Accept", "application/json")?;
+
+    let mut init = RequestInit::new();
+    init.with_method(Method::Get);
+    init.with_headers(headers);
+
+    let request = Request::new_with_init(url, &init)?;
+    Fetch::Request(request).send().await
+}
+
+pub(crate) struct GitHub {
+    client_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubUser {
+    id: i64,
+    login: String,
+    email: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubOrg {
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubTeam {
+    slug: String,
+    organization: GitHubOrg,
+}
+
+impl OAuthProvider for GitHub {
+    const NAME: &'static str = "github";
+    const CLIENT_SECRET: &'static str = "GITHUB_CLIENT_SECRET";
+
+    fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    fn authorize_url(&self, redirect_uri: &str, state: &str) -> String {
+        format!(
+            "{}?client_id={}&redirect_uri={}&scope=read:org%20read:user%20user:email&state={}",
+            GITHUB_AUTHORIZE_URL,
+            self.client_id,
+            super::url_encode(redirect_uri),
+            state
+        )
+    }
+
+    fn token_url(&self) -> String {
+        GITHUB_TOKEN_URL.to_string()
+    }
+
+    fn user_url(&self) -> String {
+        GITHUB_USER_URL.to_string()
+    }
+
+    async fn fetch_user(&self, token: &str) -> Result<ProviderUser> {
+        let user: GitHubUser = get_json(&self.user_url(), token).await?.json().await?;
+        Ok(ProviderUser {
+            id: user.id,
+            login: user.login,
+            email: user.email,
+        })
+    }
+
+    async fn fetch_orgs(&self, token: &str) -> Result<Vec<String>> {
+        let orgs: Vec<GitHubOrg> = get_json(GITHUB_ORGS_URL, token).await?.json().await?;
+        Ok(orgs.into_iter().map(|org| org.login).collect())
+    }
+
+    /// Follows the `Link` header through every page of `/user/teams`. The list
+    /// needs the `read:org` scope; without it (or when an org restricts OAuth
+    /// app access) GitHub refuses or omits those teams. That is logged and counts
+    /// as no membership, so the user is denied rather than the sign-in failing.
+    async fn fetch_teams(&self, token: &str) -> Result<Vec<String>> {
+        let mut teams = Vec::new();
+        let mut next_url = Some(GITHUB_TEAMS_URL.to_string());
+        for _ in 0..MAX_TEAM_PAGES {
+            let url = match next_url.take() {
+                Some(url) => url,
+                None => break,
+            };
+
+            let mut response = get_json(&url, token).await?;
+            let scopes = response
+                .headers()
+                .get("X-OAuth-Scopes")?
+                .unwrap_or_default();
+            if !has_org_read_scope(&scopes) {
+                console_warn!(
+                    "GitHub token lacks read:org (scopes: {:?}); team membership may be incomplete",
+                    scopes
+                );
+            }
+            if response.status_code() != 200 {
+                console_warn!(
+                    "GitHub teams lookup failed with status {}",
+                    response.status_code()
+                );
+                break;
+            }
+
+            next_url = response
+                .headers()
+                .get("Link")?
+                .as_deref()
+                .and_then(next_page_url);
+            let page: Vec<GitHubTeam> = response.json().await?;
+            teams.extend(
+                page.into_iter()
+                    .map(|team| format!("{}/{}", team.organization.login, team.slug)),
+            );
+        }
+
+        Ok(teams)
+    }
+}
+
+/// Whether an `X-OAuth-Scopes` value grants reading org and team membership
+fn has_org_read_scope(scopes: &str) -> bool {
+    scopes
+        .split(',')
+        .map(str::trim)
+        .any(|scope| matches!(scope, "read:org" | "write:org" | "admin:org"))
+}
+
+/// URL of the `rel="next"` page in a GitHub `Link` header
+fn next_page_url(link: &str) -> Option<String> {
+    link.split(',').find_map(|part| {
+        let (url, params) = part.split_once(';')?;
+        params
+            .split(';')
+            .any(|param| param.trim() == "rel=\"next\"")
+            .then(|| {
+                url.trim()
+                    .trim_start_matches('<')
+                    .trim_end_matches('>')
+                    .to_string()
+            })
+    })
+}
+
+/// GitLab, signed in through its OpenID Connect endpoints
+///
+/// Only the `openid profile email` scopes are requested: the userinfo
+/// endpoint already lists the user's groups, so no API access is needed.
+pub(crate) struct GitLab {
+    client_id: String,
+    /// Instance root without a trailing slash, e.g. `https://gitlab.example.com`
+    base_url: String,
+}
+
+/// Claims returned by GitLab's `/oauth/userinfo`
+#[derive(Debug, Deserialize)]
+struct GitLabUserInfo {
+    /// Numeric user ID, as a string
+    sub: String,
+    nickname: String,
+    email: Option<String>,
+    #[serde(default)]
+    groups: Vec<String>,
+}
+
+impl GitLab {
+    /// GitLab settings from the environment, if `GITLAB_CLIENT_ID` is set
+    ///
+    /// `GITLAB_URL` must be an `https://` URL; other values disable GitLab
+    /// sign-in rather than sending users and codes over plain HTTP.
+    fn from_env(env: &Env) -> Option<Self> {
+        let client_id = crate::config::var_string(env, "GITLAB_CLIENT_ID")?;
+        let base_url = crate::config::var_string(env, "GITLAB_URL")
+            .unwrap_or_else(|| DEFAULT_GITLAB_URL.to_string());
+        if !base_url.starts_with("https://") {
+            console_error!(
+                "Ignoring GitLab sign-in: GITLAB_URL must use https ({})",
+                base_url
+            );
+            return None;
+        }
+        Some(Self {
+            client_id,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    async fn fetch_user_info(&self, token: &str) -> Result<GitLabUserInfo> {
+        get_json(&self.user_url(), token).await?.json().await
+    }
+}
+
+impl OAuthProvider for GitLab {
+    const NAME: &'static str = "gitlab";
+    const CLIENT_SECRET: &'static str = "GITLAB_CLIENT_SECRET";
+
+    fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    fn authorize_url(&self, redirect_uri: &str, state: &str) -> String {
+        format!(
+            "{}/oauth/authorize?client_id={}&redirect_uri={}&response_type=code&scope=openid%20profile%20email&state={}",
+            self.base_url,
+            self.client_id,
+            super::url_encode(redirect_uri),
+            state
+        )
+    }
+
+    fn token_url(&self) -> String {
+        format!("{}/oauth/token", self.base_url)
+    }
+
+    fn user_url(&self) -> String {
+        format!("{}/oauth/userinfo", self.base_url)
+    }
+
+    async fn fetch_user(&self, token: &str) -> Result<ProviderUser> {
+        let info = self.fetch_user_info(token).await?;
+        let id = info
+            .sub
+            .parse()
+            .map_err(|_| Error::RustError(format!("Unexpected GitLab user ID: {}", info.sub)))?;
+        Ok(ProviderUser {
+            id,
+            login: info.nickname,
+            email: info.email,
+        })
+    }
+
+    async fn fetch_orgs(&self, token: &str) -> Result<Vec<String>> {
+        Ok(self.fetch_user_info(token).await?.groups)
+    }
+
+    /// GitLab IDs are stored negated: the column's UNIQUE constraint predates
+    /// providers, and GitHub IDs are always positive
+    fn account_id(&self, id: i64) -> i64 {
+        -id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn qualified_names_split_back_into_provider_and_name() {
+        for (provider, name) in [("github", "octocat"), ("gitlab", "alice")] {
+            let qualified = qualified_name(provider, name);
+            assert_eq!(split_qualified_name(&qualified), (provider, name));
+        }
+        assert_eq!(split_qualified_name("GitLab:alice"), ("gitlab", "alice"));
+        assert_eq!(
+            split_qualified_name("bitbucket:bob"),
+            ("github", "bitbucket:bob")
+        );
+    }
+
+    #[test]
+    fn next_page_url_follows_rel_next() {
+        let link = r#"<https://api.github.com/user/teams?page=3>; rel="next", <https://api.github.com/user/teams?page=5>; rel="last""#;
+        assert_eq!(
+            next_page_url(link).as_deref(),
+            Some("https://api.github.com/user/teams?page=3")
+        );
+    }
+
+    #[test]
+    fn next_page_url_is_none_on_the_last_page() {
+        let link = r#"<https://api.github.com/user/teams?page=1>; rel="prev", <https://api.github.com/user/teams?page=1>; rel="first""#;
+        assert_eq!(next_page_url(link), None);
+        assert_eq!(next_page_url(""), None);
+    }
+}
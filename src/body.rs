@@ -0,0 +1,98 @@
+//! Proxy body encoding
+//!
+//! Proxied bodies travel inside JSON messages, so they must be strings. Text
+//! is sent as-is; anything else (images, fonts, compressed payloads) is
+//! base64-encoded and flagged with `body_is_base64` so it survives the trip.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+/// Whether a `Content-Type` describes a textual body
+///
+/// A missing type counts as text; `encode` still falls back to base64 when
+/// the bytes are not valid UTF-8.
+pub fn is_text_content_type(content_type: Option<&str>) -> bool {
+    let mime = match content_type {
+        Some(ct) => ct.split(';').next().unwrap_or("").trim().to_lowercase(),
+        None => return true,
+    };
+    mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || matches!(
+            mime.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/x-www-form-urlencoded"
+        )
+}
+
+/// Encode a body for a proxy message, returning it with its `body_is_base64` flag
+pub fn encode(content_type: Option<&str>, bytes: Vec<u8>) -> (String, bool) {
+    if is_text_content_type(content_type) {
+        match String::from_utf8(bytes) {
+            Ok(text) => (text, false),
+            Err(e) => (encode_binary(&e.into_bytes()), true),
+        }
+    } else {
+        (encode_binary(&bytes), true)
+    }
+}
+
+/// Base64 form of binary data carried in a proxy message
+pub fn encode_binary(bytes: &[u8]) -> String {
+    STANDARD.encode(bytes)
+}
+
+/// Raw bytes of a body from a proxy message, `None` if the base64 is malformed
+pub fn decode(body: &str, is_base64: bool) -> Option<Vec<u8>> {
+    if is_base64 {
+        STANDARD.decode(body).ok()
+    } else {
+        Some(body.as_bytes().to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_types_are_recognised_by_mime_only() {
+        assert!(is_text_content_type(None));
+        assert!(is_text_content_type(Some("text/html; charset=utf-8")));
+        assert!(is_text_content_type(Some("Application/JSON")));
+        assert!(is_text_content_type(Some("application/ld+json")));
+        assert!(is_text_content_type(Some("image/svg+xml")));
+        assert!(!is_text_content_type(Some("image/png")));
+        assert!(!is_text_content_type(Some("application/octet-stream")));
+    }
+
+    #[test]
+    fn text_is_sent_as_is_and_binary_as_base64() {
+        assert_eq!(
+            encode(Some("text/plain"), b"hello".to_vec()),
+            ("hello".to_string(), false)
+        );
+        assert_eq!(
+            encode(Some("image/png"), b"hello".to_vec()),
+            ("aGVsbG8=".to_string(), true)
+        );
+        // Invalid UTF-8 falls back to base64 even for text types
+        assert_eq!(encode(None, vec![0xff, 0xfe]), ("//4=".to_string(), true));
+    }
+
+    #[test]
+    fn decode_roundtrips_encode() {
+        for (content_type, bytes) in [
+            (Some("text/html"), "héllo".as_bytes().to_vec()),
+            (Some("font/woff2"), vec![0, 1, 2, 0xff]),
+            (Some("text/plain"), vec![0xc3, 0x28]),
+        ] {
+            let (body, is_base64) = encode(content_type, bytes.clone());
+            assert_eq!(decode(&body, is_base64), Some(bytes));
+        }
+        assert_eq!(decode("not base64!", true), None);
+    }
+}
@@ -0,0 +1,122 @@
+use worker::{Env, Request};
+
+use crate::models::GeoInfo;
+
+/// Header used to read the client IP when `CLIENT_IP_HEADER` is not set
+const DEFAULT_CLIENT_IP_HEADER: &str = "CF-Connecting-IP";
+
+/// Stored User-Agent strings are truncated to this many characters
+const MAX_USER_AGENT_LEN: usize = 256;
+
+/// Name of the header the client IP is read from (`CLIENT_IP_HEADER`)
+pub fn client_ip_header(env: &Env) -> String {
+    crate::config::var_string(env, "CLIENT_IP_HEADER")
+        .unwrap_or_else(|| DEFAULT_CLIENT_IP_HEADER.to_string())
+}
+
+/// Addresses of proxies in front of the worker (`TRUSTED_PROXIES`, comma-separated)
+pub fn trusted_proxies(env: &Env) -> Vec<String> {
+    crate::config::var_string(env, "TRUSTED_PROXIES")
+        .map(|v| {
+            v.split(',')
+                .map(|entry| entry.trim().to_string())
+                .filter(|entry| !entry.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Resolve the originating client IP for a request
+///
+/// Reads the header named by `CLIENT_IP_HEADER` (default `CF-Connecting-IP`,
+/// which Cloudflare always sets). When the worker sits behind another proxy,
+/// set it to `X-Forwarded-For` and list that proxy in `TRUSTED_PROXIES`.
+pub fn client_ip(req: &Request, env: &Env) -> Option<String> {
+    let header = client_ip_header(env);
+    let value = req.headers().get(&header).ok()??;
+    ip_from_header(&header, &value, &trusted_proxies(env))
+}
+
+/// Client IP carried by a `header` with the given value
+fn ip_from_header(header: &str, value: &str, trusted: &[String]) -> Option<String> {
+    if header.eq_ignore_ascii_case("x-forwarded-for") {
+        parse_forwarded_for(value, trusted)
+    } else {
+        Some(value.trim().to_string()).filter(|v| !v.is_empty())
+    }
+}
+
+/// Client IP plus Cloudflare's country/city/region for a request
+pub fn geo_info(req: &Request, env: &Env) -> GeoInfo {
+    let cf = req.cf();
+    GeoInfo {
+        ip: client_ip(req, env),
+        country: cf.and_then(|cf| cf.country()),
+        city: cf.and_then(|cf| cf.city()),
+        region: cf.and_then(|cf| cf.region()),
+    }
+}
+
+/// User-Agent of a request, truncated for storage
+pub fn user_agent(req: &Request) -> Option<String> {
+    let value = req.headers().get("User-Agent").ok()??;
+    Some(value.chars().take(MAX_USER_AGENT_LEN).collect())
+}
+
+/// Return the rightmost address in an `X-Forwarded-For` list not added by a trusted proxy
+///
+/// Each proxy appends the address it received the request from, so entries
+/// left of the first untrusted one may have been sent by the client itself.
+/// If every entry is trusted, the leftmost is used.
+fn parse_forwarded_for(value: &str, trusted: &[String]) -> Option<String> {
+    let entries: Vec<&str> = value
+        .split(',')
+        .map(|entry| entry.trim())
+        .filter(|entry| !entry.is_empty())
+        .collect();
+    entries
+        .iter()
+        .rev()
+        .find(|entry| !trusted.iter().any(|proxy| proxy == *entry))
+        .or_else(|| entries.first())
+        .map(|entry| entry.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trusted(proxies: &[&str]) -> Vec<String> {
+        proxies.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn cf_connecting_ip_is_used_as_is() {
+        let ip = ip_from_header("CF-Connecting-IP", " 203.0.113.7 ", &[]);
+        assert_eq!(ip.as_deref(), Some("203.0.113.7"));
+        assert_eq!(ip_from_header("CF-Connecting-IP", "  ", &[]), None);
+    }
+
+    #[test]
+    fn forwarded_for_ignores_spoofed_leftmost_entries() {
+        let value = "1.1.1.1, 203.0.113.7";
+        let ip = ip_from_header("X-Forwarded-For", value, &[]);
+        assert_eq!(ip.as_deref(), Some("203.0.113.7"));
+    }
+
+    #[test]
+    fn forwarded_for_skips_trusted_proxies() {
+        let proxies = trusted(&["10.0.0.1", "10.0.0.2"]);
+        let value = "1.1.1.1, 203.0.113.7, 10.0.0.1,10.0.0.2";
+        let ip = ip_from_header("x-forwarded-for", value, &proxies);
+        assert_eq!(ip.as_deref(), Some("203.0.113.7"));
+    }
+
+    #[test]
+    fn forwarded_for_of_only_trusted_proxies_uses_leftmost() {
+        let proxies = trusted(&["10.0.0.1"]);
+        let ip = parse_forwarded_for("10.0.0.1, 10.0.0.1", &proxies);
+        assert_eq!(ip.as_deref(), Some("10.0.0.1"));
+        assert_eq!(parse_forwarded_for(" , ", &proxies), None);
+    }
+}
@@ -0,0 +1,252 @@
+use worker::Env;
+
+/// Read an optional environment variable, treating unset and empty as `None`
+pub fn var_string(env: &Env, name: &str) -> Option<String> {
+    env.var(name)
+        .ok()
+        .map(|v| v.to_string().trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Read a boolean environment variable (`true`/`1`/`yes` or `false`/`0`/`no`)
+pub fn var_bool(env: &Env, name: &str, default: bool) -> bool {
+    parse_bool(var_string(env, name).as_deref(), default)
+}
+
+/// [`var_bool`] for a raw value
+pub(crate) fn parse_bool(raw: Option<&str>, default: bool) -> bool {
+    match raw.map(|v| v.trim().to_lowercase()).as_deref() {
+        Some("true") | Some("1") | Some("yes") => true,
+        Some("false") | Some("0") | Some("no") => false,
+        _ => default,
+    }
+}
+
+/// Read a numeric environment variable, falling back to `default` if unset or invalid
+pub fn var_u64(env: &Env, name: &str, default: u64) -> u64 {
+    var_string(env, name)
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Path prefix the app is mounted under (`BASE_PATH`), as `""` or `"/prefix"`
+///
+/// Trailing slashes are dropped. Values with characters other than letters,
+/// digits, `-`, `_`, `.` and `/` are ignored, since the prefix is spliced into
+/// HTML attributes and cookie headers.
+pub fn base_path(env: &Env) -> String {
+    let raw = match var_string(env, "BASE_PATH") {
+        Some(raw) => raw,
+        None => return String::new(),
+    };
+    let valid = raw
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'));
+    if !valid {
+        worker::console_error!("Ignoring BASE_PATH with unsupported characters: {}", raw);
+        return String::new();
+    }
+
+    let trimmed = raw.trim_matches('/');
+    if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", trimmed)
+    }
+}
+
+/// An app-absolute path (e.g. `/dashboard`) under `BASE_PATH`
+pub fn app_path(env: &Env, path: &str) -> String {
+    format!("{}{}", base_path(env), path)
+}
+
+/// Cookie `Path` attribute covering the whole app
+pub fn cookie_path(env: &Env) -> String {
+    let base = base_path(env);
+    if base.is_empty() {
+        "/".to_string()
+    } else {
+        base
+    }
+}
+
+/// `SameSite` attribute for app cookies (`COOKIE_SAMESITE`: `Lax`, `Strict` or `None`)
+///
+/// Defaults to `Lax`. `None` lets the dashboard keep its session inside a
+/// cross-site iframe; pair it with `FRAME_ANCESTORS`. Unknown values are ignored.
+pub fn cookie_same_site(env: &Env) -> &'static str {
+    match parse_same_site(var_string(env, "COOKIE_SAMESITE").as_deref()) {
+        Ok(same_site) => same_site,
+        Err(other) => {
+            worker::console_error!("Ignoring unsupported COOKIE_SAMESITE: {}", other);
+            "Lax"
+        }
+    }
+}
+
+/// `SameSite` attribute for a `COOKIE_SAMESITE` value, or the value if unsupported
+fn parse_same_site(raw: Option<&str>) -> Result<&'static str, &str> {
+    match raw.map(str::to_lowercase).as_deref() {
+        None | Some("lax") => Ok("Lax"),
+        Some("strict") => Ok("Strict"),
+        Some("none") => Ok("None"),
+        Some(_) => Err(raw.unwrap_or_default()),
+    }
+}
+
+/// `SameSite` attribute for [`sign_in_cookie`]: `same_site`, with `Strict` relaxed to `Lax`
+fn sign_in_same_site(same_site: &'static str) -> &'static str {
+    match same_site {
+        "Strict" => "Lax",
+        same_site => same_site,
+    }
+}
+
+/// `Set-Cookie` value for an app cookie (an empty value with `max_age_secs` 0 clears it)
+///
+/// Cookies are always `HttpOnly` and `Secure`; browsers reject `SameSite=None`
+/// without `Secure`.
+pub fn cookie(env: &Env, name: &str, value: &str, max_age_secs: u64) -> String {
+    let same_site = cookie_same_site(env);
+    format_cookie(&cookie_path(env), name, value, max_age_secs, same_site)
+}
+
+/// Like [`cookie`], but never `Strict`: cookies read by the OAuth callback must
+/// survive the cross-site redirect back from the provider
+pub fn sign_in_cookie(env: &Env, name: &str, value: &str, max_age_secs: u64) -> String {
+    let same_site = sign_in_same_site(cookie_same_site(env));
+    format_cookie(&cookie_path(env), name, value, max_age_secs, same_site)
+}
+
+fn format_cookie(
+    path: &str,
+    name: &str,
+    value: &str,
+    max_age_secs: u64,
+    same_site: &str,
+) -> String {
+    format!(
+        "{}={}; Path={}; HttpOnly; Secure; SameSite={}; Max-Age={}",
+        name, value, path, same_site, max_age_secs
+    )
+}
+
+/// `Content-Security-Policy` restricting who may frame the app's pages, if any
+///
+/// `FRAME_ANCESTORS` lists origins allowed to embed the dashboard (comma- or
+/// space-separated, e.g. `https://portal.example.com`); the app itself is
+/// always allowed. Unset, only `SameSite=None` deployments send a policy, which
+/// then allows same-origin framing only. Entries with characters other than
+/// letters, digits, `-`, `.`, `:`, `/` and `*` are ignored.
+pub fn frame_ancestors_policy(env: &Env) -> Option<String> {
+    let raw = var_string(env, "FRAME_ANCESTORS").unwrap_or_default();
+    let (policy, ignored) = build_frame_ancestors_policy(&raw, cookie_same_site(env));
+    for origin in ignored {
+        worker::console_error!("Ignoring FRAME_ANCESTORS entry: {}", origin);
+    }
+    policy
+}
+
+/// [`frame_ancestors_policy`] for a raw `FRAME_ANCESTORS` value and cookie
+/// `SameSite` attribute, along with the entries it ignored
+fn build_frame_ancestors_policy<'a>(
+    raw: &'a str,
+    same_site: &str,
+) -> (Option<String>, Vec<&'a str>) {
+    let (origins, ignored): (Vec<&str>, Vec<&str>) = raw
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|origin| !origin.is_empty())
+        .partition(|origin| {
+            origin
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | ':' | '/' | '*'))
+        });
+
+    if origins.is_empty() && same_site != "None" {
+        return (None, ignored);
+    }
+    let mut policy = String::from("frame-ancestors 'self'");
+    for origin in origins {
+        policy.push(' ');
+        policy.push_str(origin);
+    }
+    (Some(policy), ignored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boolean_settings_fall_back_to_their_default() {
+        for raw in ["true", "TRUE", "1", "yes", " Yes "] {
+            assert!(parse_bool(Some(raw), false), "{raw}");
+        }
+        for raw in ["false", "0", "no", "NO"] {
+            assert!(!parse_bool(Some(raw), true), "{raw}");
+        }
+        for default in [false, true] {
+            assert_eq!(parse_bool(None, default), default);
+            assert_eq!(parse_bool(Some("on"), default), default);
+        }
+    }
+
+    #[test]
+    fn same_site_values_are_case_insensitive_and_default_to_lax() {
+        assert_eq!(parse_same_site(None), Ok("Lax"));
+        assert_eq!(parse_same_site(Some("lax")), Ok("Lax"));
+        assert_eq!(parse_same_site(Some("STRICT")), Ok("Strict"));
+        assert_eq!(parse_same_site(Some("None")), Ok("None"));
+        assert_eq!(parse_same_site(Some("Sometimes")), Err("Sometimes"));
+    }
+
+    #[test]
+    fn cookies_are_always_secure_and_http_only() {
+        for same_site in ["Lax", "Strict", "None"] {
+            let cookie = format_cookie("/app", "session", "abc", 3600, same_site);
+            assert_eq!(
+                cookie,
+                format!(
+                    "session=abc; Path=/app; HttpOnly; Secure; SameSite={}; Max-Age=3600",
+                    same_site
+                )
+            );
+        }
+
+        let cleared = format_cookie("/app", "session", "", 0, "Lax");
+        assert_eq!(
+            cleared,
+            "session=; Path=/app; HttpOnly; Secure; SameSite=Lax; Max-Age=0"
+        );
+    }
+
+    #[test]
+    fn sign_in_cookies_downgrade_strict_to_lax() {
+        assert_eq!(sign_in_same_site("Strict"), "Lax");
+        assert_eq!(sign_in_same_site("Lax"), "Lax");
+        assert_eq!(sign_in_same_site("None"), "None");
+    }
+
+    #[test]
+    fn frame_ancestors_policy_lists_valid_origins() {
+        assert_eq!(build_frame_ancestors_policy("", "Lax"), (None, vec![]));
+        assert_eq!(
+            build_frame_ancestors_policy("", "None"),
+            (Some("frame-ancestors 'self'".to_string()), vec![])
+        );
+
+        let raw = "https://a.example.com, https://*.b.example.com https://c.example.com;x";
+        let (policy, ignored) = build_frame_ancestors_policy(raw, "Strict");
+        assert_eq!(
+            policy.as_deref(),
+            Some("frame-ancestors 'self' https://a.example.com https://*.b.example.com")
+        );
+        assert_eq!(ignored, ["https://c.example.com;x"]);
+
+        // Only invalid entries is the same as none at all
+        assert_eq!(
+            build_frame_ancestors_policy("'none'", "Lax"),
+            (None, vec!["'none'"])
+        );
+    }
+}
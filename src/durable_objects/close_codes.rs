@@ -0,0 +1,18 @@
+//! WebSocket close codes sent to clients, so they can tell disconnect reasons apart
+//!
+//! Codes below 4000 are defined by RFC 6455; 4000-4999 are application-specific.
+
+/// Closed on purpose with nothing wrong (e.g. "Disconnect" in the dashboard)
+pub const NORMAL: u16 = 1000;
+/// The other end of a WebSocket tunnel went away (its client disconnected)
+pub const GOING_AWAY: u16 = 1001;
+/// The client broke a rule: invalid registration or rate limit exceeded
+pub const POLICY_VIOLATION: u16 = 1008;
+/// A tunnelled frame exceeded the relay size limit
+pub const MESSAGE_TOO_BIG: u16 = 1009;
+/// A tunnelled frame could not be delivered to the other end
+pub const INTERNAL_ERROR: u16 = 1011;
+/// The connection reached MAX_WS_LIFETIME_HOURS; reconnect to re-check the token
+pub const LIFETIME_EXCEEDED: u16 = 4002;
+/// A browser socket sent nothing for BROWSER_IDLE_TIMEOUT_MS
+pub const IDLE_TIMEOUT: u16 = 4003;
@@ -1,3 +1,41 @@
+mod close_codes;
 mod user_hub;
 
-pub use user_hub::UserHub;
+pub use user_hub::{UserHub, PROXY_STREAM_HEADER};
+
+use worker::Env;
+
+/// Name of a user's hub Durable Object, prefixed with DEPLOYMENT_NAMESPACE when set
+/// so deployments sharing bindings never route to each other's hubs
+pub fn user_hub_name(env: &Env, user_id: &str) -> String {
+    let namespace = crate::config::var_string(env, "DEPLOYMENT_NAMESPACE");
+    namespaced_hub_name(namespace.as_deref(), user_id)
+}
+
+/// `user_hub_name` for an explicit namespace; without one the legacy name
+/// (the bare user ID) keeps existing hubs reachable
+fn namespaced_hub_name(namespace: Option<&str>, user_id: &str) -> String {
+    match namespace {
+        Some(namespace) => format!("{}:{}", namespace, user_id),
+        None => user_id.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespaces_prefix_hub_names() {
+        assert_eq!(namespaced_hub_name(Some("staging"), "u1"), "staging:u1");
+        assert_ne!(
+            namespaced_hub_name(Some("staging"), "u1"),
+            namespaced_hub_name(Some("prod"), "u1")
+        );
+    }
+
+    #[test]
+    fn unset_namespace_keeps_the_legacy_name() {
+        assert_eq!(namespaced_hub_name(None, "u1"), "u1");
+    }
+}
@@ -1,11 +1,16 @@
-use futures::channel::oneshot;
+use futures::channel::{mpsc, oneshot};
+use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use wasm_bindgen::JsValue;
 use worker::{SqlStorageValue, *};
 
-use crate::models::{Client, ClientMetadata, ClientStatus};
+use super::close_codes;
+use crate::models::{
+    Client, ClientMetadata, ClientStatus, ConnectionQuality, GeoInfo, ProxyHealth, ProxyRequest,
+    ProxyResponse,
+};
 
 /// Row structure for deserializing SQLite client rows
 #[derive(Debug, Deserialize)]
@@ -19,24 +24,55 @@ struct ClientRow {
     connected_at: String,
     last_seen: String,
     callback_url: Option<String>,
+    asset_cache: Option<i64>,
+    activity_summary: Option<String>,
+    ip: Option<String>,
+    country: Option<String>,
+    city: Option<String>,
+    region: Option<String>,
 }
 
-/// HTTP proxy request from the Worker
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ProxyRequest {
-    pub method: String,
-    pub path: String,
-    pub headers: Vec<(String, String)>,
-    pub body: Option<String>,
-    pub query: Option<String>,
+/// Row from the durable `pending_requests` table
+#[derive(Debug, Deserialize)]
+struct PendingRequestRow {
+    request_id: String,
+    client_id: String,
+    browser_id: String,
+    next_seq: i64,
+    started_at: i64,
 }
 
-/// HTTP proxy response to the Worker
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ProxyResponse {
-    pub status: u16,
-    pub headers: Vec<(String, String)>,
-    pub body: String,
+/// Row from the durable `pending_response_parts` table
+#[derive(Debug, Deserialize)]
+struct PendingResponsePartRow {
+    request_id: String,
+    seq: i64,
+    part: String,
+}
+
+/// Row from the durable `counters` table
+#[derive(Debug, Deserialize)]
+struct CounterRow {
+    name: String,
+    value: i64,
+}
+
+/// Response header marking a streamed proxy response
+///
+/// Streamed responses carry the client's status, headers and raw body instead
+/// of a JSON `ProxyResponse`; the Worker checks for this header to tell them apart.
+pub const PROXY_STREAM_HEADER: &str = "X-Orchestrator-Proxy-Stream";
+
+/// Outcome of a proxy request as delivered by claudecodeui
+enum ProxyReply {
+    /// Whole response in a single `http_proxy_response` message
+    Buffered(ProxyResponse),
+    /// Status and headers from `http_proxy_response_start`; the body follows as chunks
+    Streamed {
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: mpsc::UnboundedReceiver<Vec<u8>>,
+    },
 }
 
 /// Message types for WebSocket communication
@@ -48,6 +84,10 @@ pub enum WsMessage {
         client_id: String,
         user_token: String,
         metadata: ClientMetadata,
+        /// Reattach to this client ID's previous connection (keeping `connected_at`
+        /// and its activity summary) if it connected with the same token
+        #[serde(default)]
+        resume: bool,
     },
     /// Registration response
     Registered {
@@ -62,18 +102,49 @@ pub enum WsMessage {
     },
     /// Heartbeat/ping
     Ping { client_id: String },
+    /// What the client is currently doing, shown on its dashboard card
+    Activity { client_id: String, summary: String },
     /// Pong response
     Pong { client_id: String },
     /// Client list request (from browser)
     GetClients,
+    /// Liveness probe sent to dashboards by the idle reaper; browsers echo it back
+    BrowserHeartbeat,
     /// Client list response
     ClientList { clients: Vec<Client> },
     /// Single client update broadcast
     ClientUpdate { client: Client },
+    /// Partial client update carrying only the fields that changed
+    ClientPatch {
+        client_id: String,
+        changed: serde_json::Value,
+    },
+    /// Ask a client to re-send its current metadata (orchestrator -> claudecodeui)
+    RefreshMetadata { client_id: String },
+    /// Current metadata sent by a client, unprompted or after RefreshMetadata
+    MetadataUpdate {
+        client_id: String,
+        metadata: ClientMetadata,
+    },
+    /// Client is shutting down cleanly (claudecodeui -> orchestrator)
+    Shutdown {
+        client_id: String,
+        #[serde(default)]
+        reason: Option<String>,
+    },
     /// Client disconnected
-    ClientDisconnected { client_id: String },
+    ClientDisconnected {
+        client_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
     /// Error message
     Error { message: String },
+    /// Sent to a client just before the orchestrator closes its connection
+    ///
+    /// `reconnect: false` means the disconnect was intentional (dashboard
+    /// disconnect or rejection) and the client should stop rather than retry.
+    Disconnect { reason: String, reconnect: bool },
     /// Connect to client request (from browser)
     ConnectClient { client_id: String },
     /// Connect response (to browser)
@@ -105,12 +176,20 @@ pub enum WsMessage {
     ResponseChunk {
         request_id: String,
         data: serde_json::Value,
+        /// Position of this chunk in the response (starting at 0); unsequenced
+        /// chunks are forwarded as they arrive
+        #[serde(default)]
+        seq: Option<u64>,
     },
     /// Response complete from claudecodeui (claudecodeui -> orchestrator)
     ResponseComplete {
         request_id: String,
         #[serde(default)]
         data: Option<serde_json::Value>,
+        /// Number of chunks sent before completion; completion is held until
+        /// all of them have been forwarded
+        #[serde(default)]
+        seq: Option<u64>,
     },
     /// Forwarded response to browser (orchestrator -> browser)
     ForwardedResponse {
@@ -128,11 +207,18 @@ pub enum WsMessage {
         headers: Vec<(String, String)>,
         #[serde(skip_serializing_if = "Option::is_none")]
         body: Option<String>,
+        /// `body` is base64-encoded binary rather than text
+        #[serde(default)]
+        body_is_base64: bool,
         #[serde(skip_serializing_if = "Option::is_none")]
         query: Option<String>,
         /// Base path for URL rewriting (e.g., "/clients/{client_id}/proxy")
         #[serde(skip_serializing_if = "Option::is_none")]
         proxy_base: Option<String>,
+        /// Set when the browser asked for a stream (`Accept: text/event-stream` or
+        /// `stream=true`); the client should answer with `http_proxy_response_start`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        stream: Option<bool>,
     },
     /// HTTP proxy response (claudecodeui -> orchestrator)
     HttpProxyResponse {
@@ -140,21 +226,405 @@ pub enum WsMessage {
         status: u16,
         headers: Vec<(String, String)>,
         body: String,
+        /// Clients base64-encode non-text bodies and set this flag
+        #[serde(default)]
+        body_is_base64: bool,
+    },
+    /// Start of a streamed HTTP proxy response (claudecodeui -> orchestrator)
+    ///
+    /// Clients may stream any response, and should stream `text/event-stream`
+    /// ones; the body follows as `http_proxy_response_chunk` messages.
+    HttpProxyResponseStart {
+        request_id: String,
+        status: u16,
+        headers: Vec<(String, String)>,
+    },
+    /// Piece of a streamed HTTP proxy response body (claudecodeui -> orchestrator)
+    HttpProxyResponseChunk { request_id: String, data: String },
+    /// End of a streamed HTTP proxy response (claudecodeui -> orchestrator)
+    HttpProxyResponseEnd { request_id: String },
+    // ============ WebSocket Proxy Messages (via WebSocket) ============
+    /// A browser opened a tunnelled WebSocket (orchestrator -> claudecodeui)
+    ///
+    /// The client should open `path` on its local server and relay frames in
+    /// both directions as `ws_proxy_frame` messages carrying `tunnel_id`.
+    WsProxyOpen {
+        tunnel_id: String,
+        path: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        query: Option<String>,
+        headers: Vec<(String, String)>,
+        /// Base path for URL rewriting (e.g., "/clients/{client_id}/ws-proxy")
+        proxy_base: String,
+        /// Subprotocols the browser requested, in order of preference
+        ///
+        /// When non-empty the client must answer with `ws_proxy_accept` once
+        /// the upstream socket is open.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        protocols: Vec<String>,
+    },
+    /// The upstream socket of a tunnel is open (claudecodeui -> orchestrator)
+    WsProxyAccept {
+        tunnel_id: String,
+        /// Subprotocol the upstream selected, if any
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        protocol: Option<String>,
+    },
+    /// One frame of a tunnelled WebSocket (either direction)
+    WsProxyFrame {
+        tunnel_id: String,
+        data: String,
+        /// `data` is a base64-encoded binary frame rather than text
+        #[serde(default)]
+        data_is_base64: bool,
+    },
+    /// One end of a tunnelled WebSocket closed (either direction)
+    ///
+    /// Closes for tunnels that are already gone should be ignored.
+    WsProxyClose {
+        tunnel_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<u16>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
     },
 }
 
 struct ClientConnection {
     websocket: WebSocket,
     client: Client,
+    /// Arrival times (epoch ms) of the most recent pings, oldest first
+    ping_times: Vec<u64>,
+}
+
+/// A dashboard tab subscribed to client updates
+struct BrowserConnection {
+    websocket: WebSocket,
+    /// When the browser last sent a message (epoch ms)
+    last_seen: u64,
+}
+
+/// Registration checked by `/clients/dry-register` without connecting
+#[derive(Debug, Deserialize)]
+struct DryRegistration {
+    client_id: String,
+    metadata: ClientMetadata,
 }
 
 /// Tracks a pending forwarded request
 struct PendingRequest {
     client_id: String,
     browser_ws: WebSocket,
+    /// Reorders sequenced parts before they reach the browser
+    sequence: ResponseSequence,
+    /// When the request was forwarded (epoch ms), used to evict the oldest at capacity
+    started_at: u64,
+}
+
+/// A streamed proxy response whose body is still arriving
+struct ProxyStream {
+    client_id: String,
+    body: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+/// A buffered piece of a forwarded response
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum ResponsePart {
+    Chunk(serde_json::Value),
+    Complete(Option<serde_json::Value>),
+}
+
+/// Sequence state of a forwarded response: parts arriving ahead of `next_seq`
+/// wait in `buffered` until the gap before them is filled
+#[derive(Default)]
+struct ResponseSequence {
+    /// Next part sequence number expected by the browser
+    next_seq: u64,
+    buffered: BTreeMap<u64, ResponsePart>,
+    /// Serialized size of everything in `buffered`
+    buffered_bytes: usize,
+}
+
+/// Why a sequenced part was not buffered
+#[derive(Debug, PartialEq)]
+enum PartRejected {
+    /// Already delivered or already buffered
+    Duplicate,
+    /// More than MAX_RESPONSE_SEQ_AHEAD past `next_seq`
+    TooFarAhead,
+    /// Buffering it would exceed MAX_BUFFERED_RESPONSE_BYTES
+    Overflow,
+}
+
+impl ResponseSequence {
+    /// Buffer a sequenced part, returning its serialized form for persistence
+    fn insert(
+        &mut self,
+        seq: u64,
+        part: ResponsePart,
+    ) -> std::result::Result<String, PartRejected> {
+        if seq < self.next_seq || self.buffered.contains_key(&seq) {
+            return Err(PartRejected::Duplicate);
+        }
+        if seq - self.next_seq > MAX_RESPONSE_SEQ_AHEAD {
+            return Err(PartRejected::TooFarAhead);
+        }
+        let json = serde_json::to_string(&part).unwrap_or_default();
+        if self.buffered_bytes + json.len() > MAX_BUFFERED_RESPONSE_BYTES {
+            return Err(PartRejected::Overflow);
+        }
+        self.buffered_bytes += json.len();
+        self.buffered.insert(seq, part);
+        Ok(json)
+    }
+
+    /// Put back a part persisted by `insert`, skipping unreadable ones
+    fn restore(&mut self, seq: u64, json: &str) {
+        if let Ok(part) = serde_json::from_str(json) {
+            self.buffered_bytes += json.len();
+            self.buffered.insert(seq, part);
+        }
+    }
+
+    /// Remove every buffered part, in sequence order, without advancing `next_seq`
+    fn take_all(&mut self) -> Vec<ResponsePart> {
+        self.buffered_bytes = 0;
+        std::mem::take(&mut self.buffered).into_values().collect()
+    }
+
+    /// Remove the parts now contiguous with what the browser has seen, stopping
+    /// after a completion
+    fn take_ready(&mut self) -> Vec<ResponsePart> {
+        let mut ready = Vec::new();
+        while let Some(part) = self.buffered.remove(&self.next_seq) {
+            self.next_seq += 1;
+            self.buffered_bytes = self
+                .buffered_bytes
+                .saturating_sub(serde_json::to_string(&part).map_or(0, |json| json.len()));
+            let complete = matches!(part, ResponsePart::Complete(_));
+            ready.push(part);
+            if complete {
+                break;
+            }
+        }
+        ready
+    }
+}
+
+/// How far past the next expected part a sequenced part may arrive
+const MAX_RESPONSE_SEQ_AHEAD: u64 = 256;
+/// Maximum serialized size of parts buffered for one request while waiting for a gap
+const MAX_BUFFERED_RESPONSE_BYTES: usize = 4 * 1024 * 1024;
+
+/// Cache path of a proxied request, if it is a plain GET for a file with one
+/// of the comma-separated `extensions`
+fn cacheable_asset_path<'a>(proxy_req: &'a ProxyRequest, extensions: &str) -> Option<&'a str> {
+    if proxy_req.method != "GET" || proxy_req.query.is_some() {
+        return None;
+    }
+
+    let file_name = proxy_req.path.rsplit('/').next()?;
+    let (_, extension) = file_name.rsplit_once('.')?;
+    if !extensions
+        .split(',')
+        .any(|e| e.trim().eq_ignore_ascii_case(extension))
+    {
+        return None;
+    }
+
+    Some(proxy_req.path.trim_start_matches('/'))
+}
+
+/// Proxy response serving an asset read back from the R2 cache
+fn cached_asset_response(content_type: String, bytes: Vec<u8>) -> ProxyResponse {
+    let (body, body_is_base64) = crate::body::encode(Some(&content_type), bytes);
+    ProxyResponse {
+        status: 200,
+        headers: vec![
+            ("Content-Type".to_string(), content_type),
+            ("X-Orchestrator-Cache".to_string(), "HIT".to_string()),
+        ],
+        body,
+        body_is_base64,
+        error: None,
+    }
+}
+
+/// Whether a client in `status` is expected to ping, so going silent marks it disconnected
+///
+/// Only these clients keep the heartbeat alarm armed.
+fn watches_heartbeat(status: ClientStatus) -> bool {
+    !matches!(
+        status,
+        ClientStatus::Disconnected | ClientStatus::PendingApproval
+    )
+}
+
+/// Subprotocols listed in a `Sec-WebSocket-Protocol` request header
+fn requested_protocols(header: Option<&str>) -> Vec<String> {
+    header
+        .unwrap_or_default()
+        .split(',')
+        .map(|protocol| protocol.trim())
+        .filter(|protocol| !protocol.is_empty())
+        .map(|protocol| protocol.to_string())
+        .collect()
+}
+
+/// Subprotocol to answer the browser with: the upstream's choice, if the browser offered it
+///
+/// Browsers fail the connection when the server picks a protocol they didn't
+/// request, so any other choice is left out of the response.
+fn negotiated_protocol(requested: &[String], chosen: Option<String>) -> Option<String> {
+    chosen.filter(|protocol| requested.contains(protocol))
+}
+
+/// Number of recently completed request IDs remembered for late-chunk warnings
+const COMPLETED_REQUESTS_HISTORY: usize = 100;
+
+/// Default rate-limit window for inbound WebSocket messages
+const DEFAULT_WS_RATE_WINDOW_MS: u64 = 10_000;
+/// Default number of pings allowed per socket per window
+const DEFAULT_WS_PING_RATE_LIMIT: u64 = 10;
+/// Default number of non-ping messages allowed per socket per window
+const DEFAULT_WS_MESSAGE_RATE_LIMIT: u64 = 100;
+/// Default number of forward_to_client requests allowed per browser per window
+const DEFAULT_FORWARD_RATE_LIMIT: u64 = 20;
+/// Default maximum number of simultaneously connected clients per user
+const DEFAULT_MAX_CLIENTS_PER_USER: u64 = 50;
+/// Default maximum length of client_id, hostname and project at registration
+const DEFAULT_MAX_CLIENT_FIELD_LENGTH: u64 = 255;
+/// Default timeout for probing a client's callback URL
+const DEFAULT_PROXY_HEALTH_TIMEOUT_MS: u64 = 5_000;
+/// Default maximum number of forwarded requests awaiting a response
+const DEFAULT_MAX_PENDING_REQUESTS: u64 = 1_000;
+/// Default time a forwarded request may wait for its completion before it is failed
+const DEFAULT_FORWARD_TIMEOUT_MS: u64 = 60_000;
+/// Default window during which client listings reuse the last stale-client scan
+const DEFAULT_STALE_CHECK_INTERVAL_MS: u64 = 10_000;
+/// Default number of WebSocket tunnels a single client may have open
+const DEFAULT_MAX_WS_PROXY_TUNNELS: u64 = 20;
+/// Default time a proxied request waits for the client's response
+const DEFAULT_PROXY_TIMEOUT_MS: u64 = 30_000;
+/// Default longest proxied path plus query string, in bytes
+const DEFAULT_PROXY_MAX_URL_LENGTH: u64 = 8_192;
+/// Largest tunnelled frame relayed, leaving room for base64 and JSON framing
+/// within the platform's 1 MiB WebSocket message limit
+const MAX_WS_PROXY_FRAME_BYTES: usize = 512 * 1024;
+/// First tag of browser sockets tunnelled to a client: `["ws-proxy", tunnel_id, client_id]`
+const WS_PROXY_TAG: &str = "ws-proxy";
+/// Number of recent ping arrival times kept per client for connection quality
+const PING_HISTORY: usize = 6;
+/// Default longest gap between pings (or since the last one) still rated "good"
+const DEFAULT_CONNECTION_QUALITY_MAX_GAP_MS: u64 = 60_000;
+/// Default allowed deviation of a ping interval from the mean, in percent
+const DEFAULT_CONNECTION_QUALITY_MAX_JITTER_PCT: u64 = 50;
+/// Default time since a client's last message after which it is shown as disconnected
+const DEFAULT_CLIENT_HEARTBEAT_TIMEOUT_MS: u64 = 90_000;
+/// Default time a browser socket may stay silent before the reaper closes it
+const DEFAULT_BROWSER_IDLE_TIMEOUT_MS: u64 = 300_000;
+/// Maximum length of an activity summary; longer summaries are truncated
+const MAX_ACTIVITY_SUMMARY_LENGTH: usize = 200;
+/// Default time a disconnected client can still be resumed by reconnecting
+const DEFAULT_CLIENT_RESUME_WINDOW_SECONDS: u64 = 300;
+/// File extensions eligible for the R2 asset cache when ASSET_CACHE_EXTENSIONS is unset
+const DEFAULT_ASSET_CACHE_EXTENSIONS: &str = "js,css,svg,png,ico,woff2";
+
+/// Recently disconnected clients a reconnect may resume (see `resume_client`)
+const CREATE_RESUMABLE_CLIENTS: &str = "CREATE TABLE IF NOT EXISTS resumable_clients (
+    client_id TEXT PRIMARY KEY,
+    token_id TEXT NOT NULL,
+    client TEXT NOT NULL,
+    disconnected_at INTEGER NOT NULL
+)";
+/// Remember client `?1` (JSON `?3`) of token `?2`, disconnected at `?4`
+const SAVE_RESUMABLE_CLIENT: &str =
+    "INSERT OR REPLACE INTO resumable_clients (client_id, token_id, client, disconnected_at)
+     VALUES (?, ?, ?, ?)";
+/// Drop resumable clients disconnected before the cutoff bound as `?1`
+const PURGE_RESUMABLE_CLIENTS: &str = "DELETE FROM resumable_clients WHERE disconnected_at < ?";
+/// Remove and return the resumable client `?1`
+const TAKE_RESUMABLE_CLIENT: &str =
+    "DELETE FROM resumable_clients WHERE client_id = ? RETURNING token_id, client";
+/// Drop the resumable clients of the revoked token `?1`
+const FORGET_TOKEN_RESUMABLE_CLIENTS: &str = "DELETE FROM resumable_clients WHERE token_id = ?";
+/// Clients approved in REQUIRE_CLIENT_APPROVAL mode (kept across reconnects)
+///
+/// An approval covers only the token the client connected with, since client
+/// IDs are chosen by the clients themselves.
+const CREATE_APPROVED_CLIENT_TOKENS: &str = "CREATE TABLE IF NOT EXISTS approved_client_tokens (
+    client_id TEXT NOT NULL,
+    token_id TEXT NOT NULL,
+    approved_at TEXT NOT NULL,
+    PRIMARY KEY (client_id, token_id)
+)";
+/// Approve client `?1` with token `?2` at `?3`
+const APPROVE_CLIENT_TOKEN: &str =
+    "INSERT OR REPLACE INTO approved_client_tokens (client_id, token_id, approved_at)
+     VALUES (?, ?, ?)";
+/// Approval of client `?1` with token `?2`, if any
+const CLIENT_TOKEN_APPROVAL: &str =
+    "SELECT client_id FROM approved_client_tokens WHERE client_id = ? AND token_id = ?";
+/// Drop the approvals granted to the revoked token `?1`
+const FORGET_TOKEN_APPROVALS: &str = "DELETE FROM approved_client_tokens WHERE token_id = ?";
+
+/// Which rate-limit bucket an inbound message counts against
+#[derive(Clone, Copy)]
+enum RateKind {
+    Ping,
+    Message,
+    /// `forward_to_client` from a browser, on top of its message budget
+    Forward,
+}
+
+/// Fixed-window message counters for a single WebSocket
+struct MessageRate {
+    websocket: WebSocket,
+    counts: MessageCounts,
+}
+
+/// Messages of each `RateKind` seen in the current window
+#[derive(Default)]
+struct MessageCounts {
+    window_start_ms: u64,
+    pings: u64,
+    messages: u64,
+    forwards: u64,
+}
+
+impl MessageCounts {
+    /// Count a message of `kind` at `now`, returning whether it is within `limit`
+    /// per `window_ms` (either being 0 means unlimited)
+    ///
+    /// All buckets restart together once `window_ms` has passed since the
+    /// window opened.
+    fn record(&mut self, kind: RateKind, now: u64, window_ms: u64, limit: u64) -> bool {
+        if limit == 0 || window_ms == 0 {
+            return true;
+        }
+
+        if now.saturating_sub(self.window_start_ms) >= window_ms {
+            *self = MessageCounts {
+                window_start_ms: now,
+                ..MessageCounts::default()
+            };
+        }
+
+        let count = match kind {
+            RateKind::Ping => &mut self.pings,
+            RateKind::Message => &mut self.messages,
+            RateKind::Forward => &mut self.forwards,
+        };
+        *count += 1;
+        *count <= limit
+    }
 }
 
 /// Per-user Durable Object that manages connected claudecodeui instances
+///
+/// Everything held in a `RefCell` is lost when the object hibernates. Counters
+/// that only guard short windows (rate limits) can live there; totals that must
+/// survive hibernation go through `incr_counter`, which is backed by SQLite.
 #[durable_object]
 pub struct UserHub {
     state: State,
@@ -162,13 +632,23 @@ pub struct UserHub {
     /// Connected claudecodeui clients (using RefCell for interior mutability)
     clients: RefCell<HashMap<String, ClientConnection>>,
     /// Connected browser sessions (for real-time updates)
-    browsers: RefCell<Vec<WebSocket>>,
+    browsers: RefCell<Vec<BrowserConnection>>,
     /// Whether SQLite storage has been initialized
     initialized: RefCell<bool>,
     /// Pending requests: request_id -> (client_id, browser_ws)
     pending_requests: RefCell<HashMap<String, PendingRequest>>,
     /// Pending HTTP proxy requests: request_id -> oneshot sender for response
-    pending_proxy_requests: RefCell<HashMap<String, oneshot::Sender<ProxyResponse>>>,
+    pending_proxy_requests: RefCell<HashMap<String, oneshot::Sender<ProxyReply>>>,
+    /// Streamed proxy responses in flight: request_id -> body sender
+    proxy_streams: RefCell<HashMap<String, ProxyStream>>,
+    /// Tunnels waiting for `ws_proxy_accept`: tunnel_id -> sender for the chosen subprotocol
+    pending_ws_proxy_opens: RefCell<HashMap<String, oneshot::Sender<Option<String>>>>,
+    /// Per-socket inbound message counters (ephemeral, reset on hibernation)
+    message_rates: RefCell<Vec<MessageRate>>,
+    /// Recently completed forwarded request IDs (oldest first)
+    completed_requests: RefCell<Vec<String>>,
+    /// Disconnected clients from the last SQLite reconciliation, with when it ran (epoch ms)
+    stale_clients: RefCell<Option<(u64, Vec<Client>)>>,
 }
 
 impl DurableObject for UserHub {
@@ -180,7 +660,12 @@ impl DurableObject for UserHub {
             browsers: RefCell::new(Vec::new()),
             initialized: RefCell::new(false),
             pending_proxy_requests: RefCell::new(HashMap::new()),
+            proxy_streams: RefCell::new(HashMap::new()),
+            pending_ws_proxy_opens: RefCell::new(HashMap::new()),
             pending_requests: RefCell::new(HashMap::new()),
+            message_rates: RefCell::new(Vec::new()),
+            completed_requests: RefCell::new(Vec::new()),
+            stale_clients: RefCell::new(None),
         }
     }
 
@@ -193,20 +678,91 @@ impl DurableObject for UserHub {
             self.handle_websocket(req).await
         } else if path == "/clients" {
             self.get_clients_json()
+        } else if path == "/counters" {
+            self.get_counters_json()
+        } else if path == "/config" {
+            self.get_config_json()
+        } else if path == "/clients/disconnect-all" {
+            self.disconnect_all_clients().await
+        } else if path == "/clients/dry-register" {
+            self.dry_register(req).await
+        } else if path.starts_with("/clients/") && path.ends_with("/ping-proxy") {
+            // Extract client_id from /clients/{id}/ping-proxy
+            let parts: Vec<&str> = path.split('/').collect();
+            if parts.len() >= 3 {
+                let client_id = parts[2];
+                self.ping_client_proxy(client_id).await
+            } else {
+                Response::error("Invalid path", 400)
+            }
+        } else if path.starts_with("/clients/") && path.ends_with("/approve") {
+            // Extract client_id from /clients/{id}/approve
+            let parts: Vec<&str> = path.split('/').collect();
+            if parts.len() >= 3 {
+                let client_id = parts[2];
+                self.approve_client(client_id)
+            } else {
+                Response::error("Invalid path", 400)
+            }
+        } else if path.starts_with("/clients/") && path.ends_with("/reject") {
+            // Extract client_id from /clients/{id}/reject
+            let parts: Vec<&str> = path.split('/').collect();
+            if parts.len() >= 3 {
+                let client_id = parts[2];
+                self.reject_client(client_id).await
+            } else {
+                Response::error("Invalid path", 400)
+            }
+        } else if path.starts_with("/clients/") && path.ends_with("/refresh") {
+            // Extract client_id from /clients/{id}/refresh
+            let parts: Vec<&str> = path.split('/').collect();
+            if parts.len() >= 3 {
+                let client_id = parts[2];
+                self.request_metadata_refresh(client_id)
+            } else {
+                Response::error("Invalid path", 400)
+            }
+        } else if path.starts_with("/clients/") && path.ends_with("/purge-assets") {
+            // Extract client_id from /clients/{id}/purge-assets
+            let parts: Vec<&str> = path.split('/').collect();
+            if parts.len() >= 3 {
+                let client_id = parts[2];
+                self.purge_cached_assets(client_id).await
+            } else {
+                Response::error("Invalid path", 400)
+            }
+        } else if path.starts_with("/clients/by-token/") && path.ends_with("/disconnect") {
+            // Extract token_id from /clients/by-token/{token_id}/disconnect
+            let parts: Vec<&str> = path.split('/').collect();
+            if parts.len() >= 5 {
+                let token_id = parts[3];
+                self.disconnect_token_clients(token_id).await
+            } else {
+                Response::error("Invalid path", 400)
+            }
         } else if path.starts_with("/clients/") && path.ends_with("/disconnect") {
             // Extract client_id from /clients/{id}/disconnect
             let parts: Vec<&str> = path.split('/').collect();
             if parts.len() >= 3 {
                 let client_id = parts[2];
-                self.disconnect_client(client_id).await
+                self.disconnect_client(client_id, "Disconnected by user")
+                    .await
             } else {
                 Response::error("Invalid path", 400)
             }
+        } else if path.starts_with("/ws-proxy/") {
+            // Extract client_id from /ws-proxy/{client_id}
+            let client_id = path.strip_prefix("/ws-proxy/").unwrap_or("");
+            if client_id.is_empty() {
+                Response::error("Missing client ID", 400)
+            } else {
+                self.handle_ws_proxy(req, client_id).await
+            }
         } else if path.starts_with("/proxy/") {
             // Extract client_id from /proxy/{client_id}
             let client_id = path.strip_prefix("/proxy/").unwrap_or("");
             if client_id.is_empty() {
-                Response::error("Missing client ID", 400)
+                proxy_error(400, "Missing client ID")
             } else {
                 self.handle_proxy(req, client_id).await
             }
@@ -221,6 +777,12 @@ impl DurableObject for UserHub {
         ws: WebSocket,
         message: WebSocketIncomingMessage,
     ) -> Result<()> {
+        // Tunnelled browser sockets carry raw frames for their client, not orchestrator messages
+        if let Some((tunnel_id, client_id)) = self.ws_proxy_tunnel(&ws) {
+            self.relay_to_client(&ws, &tunnel_id, &client_id, message);
+            return Ok(());
+        }
+
         match message {
             WebSocketIncomingMessage::String(text) => {
                 self.handle_message(&ws, &text).await?;
@@ -242,20 +804,69 @@ impl DurableObject for UserHub {
     async fn websocket_close(
         &self,
         ws: WebSocket,
-        _code: usize,
-        _reason: String,
+        code: usize,
+        reason: String,
         _was_clean: bool,
     ) -> Result<()> {
+        if let Some((tunnel_id, client_id)) = self.ws_proxy_tunnel(&ws) {
+            let reason = Some(reason).filter(|r| !r.is_empty());
+            self.send_ws_proxy_close(&tunnel_id, &client_id, Some(code as u16), reason);
+            return Ok(());
+        }
         self.handle_close(&ws).await;
         Ok(())
     }
 
     /// Handle WebSocket errors (hibernation API)
     async fn websocket_error(&self, ws: WebSocket, _error: Error) -> Result<()> {
+        if let Some((tunnel_id, client_id)) = self.ws_proxy_tunnel(&ws) {
+            self.send_ws_proxy_close(&tunnel_id, &client_id, None, None);
+            return Ok(());
+        }
         // Treat errors as disconnections
         self.handle_close(&ws).await;
         Ok(())
     }
+
+    /// Close client sockets that have outlived MAX_WS_LIFETIME_HOURS and
+    /// browser sockets that stopped answering
+    async fn alarm(&self) -> Result<Response> {
+        let _ = self.ensure_state_restored();
+
+        let lifetime_ms = self.max_ws_lifetime_ms();
+        if lifetime_ms > 0 {
+            let now = crate::time::now_ms() as i64;
+            let expired: Vec<WebSocket> = self
+                .clients
+                .borrow()
+                .values()
+                .filter(|conn| lifetime_expired(&conn.client.connected_at, lifetime_ms, now))
+                .map(|conn| conn.websocket.clone())
+                .collect();
+
+            for ws in expired {
+                let disconnect = WsMessage::Disconnect {
+                    reason: "Connection lifetime exceeded, please reconnect".to_string(),
+                    reconnect: true,
+                };
+                if let Ok(json) = serde_json::to_string(&disconnect) {
+                    let _ = ws.send_with_str(&json);
+                }
+                let _ = ws.close(
+                    Some(close_codes::LIFETIME_EXCEEDED),
+                    Some("Lifetime exceeded"),
+                );
+                self.handle_close(&ws).await;
+            }
+        }
+
+        self.expire_forwarded_requests();
+        self.mark_silent_clients();
+        self.reap_browsers().await;
+        self.schedule_alarm().await;
+
+        Response::ok("OK")
+    }
 }
 
 impl UserHub {
@@ -287,18 +898,87 @@ impl UserHub {
             None,
         );
 
+        // Migration: Add asset_cache column if it doesn't exist
+        let _ = sql.exec(
+            "ALTER TABLE clients ADD COLUMN asset_cache INTEGER NOT NULL DEFAULT 0",
+            None,
+        );
+
+        // Migration: Add activity_summary column if it doesn't exist
+        let _ = sql.exec("ALTER TABLE clients ADD COLUMN activity_summary TEXT", None);
+
+        // Migration: Add connection origin columns if they don't exist
+        for column in ["ip", "country", "city", "region"] {
+            let _ = sql.exec(
+                &format!("ALTER TABLE clients ADD COLUMN {} TEXT", column),
+                None,
+            );
+        }
+
+        // Migration: Add token_id column (the token a client registered with)
+        let _ = sql.exec("ALTER TABLE clients ADD COLUMN token_id TEXT", None);
+
+        // Durable counters (see `incr_counter`)
+        sql.exec(
+            "CREATE TABLE IF NOT EXISTS counters (
+                name TEXT PRIMARY KEY,
+                value INTEGER NOT NULL DEFAULT 0
+            )",
+            None,
+        )?;
+
+        // Forwarded requests awaiting completion, so hibernation doesn't drop them.
+        // browser_id is the browser socket's tag (see `handle_websocket`)
+        sql.exec(
+            "CREATE TABLE IF NOT EXISTS pending_requests (
+                request_id TEXT PRIMARY KEY,
+                client_id TEXT NOT NULL,
+                browser_id TEXT NOT NULL,
+                next_seq INTEGER NOT NULL DEFAULT 0,
+                started_at INTEGER NOT NULL
+            )",
+            None,
+        )?;
+
+        // Out-of-order parts of pending requests, one row each so buffering a
+        // part doesn't rewrite everything buffered before it
+        sql.exec(
+            "CREATE TABLE IF NOT EXISTS pending_response_parts (
+                request_id TEXT NOT NULL,
+                seq INTEGER NOT NULL,
+                part TEXT NOT NULL,
+                PRIMARY KEY (request_id, seq)
+            )",
+            None,
+        )?;
+
+        sql.exec(CREATE_RESUMABLE_CLIENTS, None)?;
+
+        sql.exec(CREATE_APPROVED_CLIENT_TOKENS, None)?;
+        // Migration: approvals keyed by client ID alone no longer count
+        let _ = sql.exec("DROP TABLE IF EXISTS approved_clients", None);
+
         *self.initialized.borrow_mut() = true;
         Ok(())
     }
 
     /// Save client to SQLite
+    ///
+    /// The row's `token_id` is kept; it is set at registration by `save_client_token`.
     fn save_client(&self, client: &Client) -> Result<()> {
         self.ensure_initialized()?;
         let sql = self.state.storage().sql();
+        let geo = client.metadata.geo.clone().unwrap_or_default();
+        let geo_value = |value: &Option<String>| {
+            value
+                .clone()
+                .map(SqlStorageValue::String)
+                .unwrap_or(SqlStorageValue::Null)
+        };
 
         sql.exec(
-            "INSERT OR REPLACE INTO clients (client_id, user_id, hostname, project, status, last_activity, connected_at, last_seen, callback_url)
-             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            "INSERT OR REPLACE INTO clients (client_id, user_id, hostname, project, status, last_activity, connected_at, last_seen, callback_url, asset_cache, activity_summary, ip, country, city, region, token_id)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, (SELECT token_id FROM clients WHERE client_id = ?))",
             Some(vec![
                 SqlStorageValue::String(client.id.clone()),
                 SqlStorageValue::String(client.user_id.clone()),
@@ -309,12 +989,129 @@ impl UserHub {
                 SqlStorageValue::String(client.connected_at.clone()),
                 SqlStorageValue::String(client.last_seen.clone()),
                 client.metadata.callback_url.clone().map(SqlStorageValue::String).unwrap_or(SqlStorageValue::Null),
+                SqlStorageValue::Integer(client.metadata.asset_cache as i64),
+                client.metadata.activity_summary.clone().map(SqlStorageValue::String).unwrap_or(SqlStorageValue::Null),
+                geo_value(&geo.ip),
+                geo_value(&geo.country),
+                geo_value(&geo.city),
+                geo_value(&geo.region),
+                SqlStorageValue::String(client.id.clone()),
             ]),
         )?;
 
         Ok(())
     }
 
+    /// Record the token a client registered with (`None` for untagged sockets)
+    fn save_client_token(&self, client_id: &str, token_id: Option<&str>) -> Result<()> {
+        self.ensure_initialized()?;
+        let sql = self.state.storage().sql();
+        sql.exec(
+            "UPDATE clients SET token_id = ? WHERE client_id = ?",
+            Some(vec![
+                token_id
+                    .map(|id| SqlStorageValue::String(id.to_string()))
+                    .unwrap_or(SqlStorageValue::Null),
+                SqlStorageValue::String(client_id.to_string()),
+            ]),
+        )?;
+        Ok(())
+    }
+
+    /// Atomically add `by` to a durable counter and return its new value
+    fn incr_counter(&self, name: &str, by: i64) -> Result<i64> {
+        self.ensure_initialized()?;
+        let sql = self.state.storage().sql();
+
+        let cursor = sql.exec(
+            "INSERT INTO counters (name, value) VALUES (?, ?)
+             ON CONFLICT(name) DO UPDATE SET value = value + excluded.value
+             RETURNING name, value",
+            Some(vec![
+                SqlStorageValue::String(name.to_string()),
+                SqlStorageValue::Integer(by),
+            ]),
+        )?;
+        let rows: Vec<CounterRow> = cursor.to_array()?;
+        Ok(rows.first().map(|row| row.value).unwrap_or(by))
+    }
+
+    /// All durable counters as a JSON object
+    fn get_counters_json(&self) -> Result<Response> {
+        self.ensure_initialized()?;
+        let sql = self.state.storage().sql();
+
+        let cursor = sql.exec("SELECT name, value FROM counters ORDER BY name", None)?;
+        let rows: Vec<CounterRow> = cursor.to_array()?;
+        let counters: serde_json::Map<String, serde_json::Value> = rows
+            .into_iter()
+            .map(|row| (row.name, row.value.into()))
+            .collect();
+
+        Response::from_json(&counters)
+    }
+
+    /// Effective hub settings (env values with defaults applied) for `/api/admin/config`
+    fn get_config_json(&self) -> Result<Response> {
+        let env = &self.env;
+        let setting = |name: &str, default: u64| {
+            (
+                name.to_lowercase(),
+                crate::config::var_u64(env, name, default).into(),
+            )
+        };
+        let mut config: serde_json::Map<String, serde_json::Value> = [
+            setting("WS_RATE_WINDOW_MS", DEFAULT_WS_RATE_WINDOW_MS),
+            setting("WS_PING_RATE_LIMIT", DEFAULT_WS_PING_RATE_LIMIT),
+            setting("WS_MESSAGE_RATE_LIMIT", DEFAULT_WS_MESSAGE_RATE_LIMIT),
+            setting("FORWARD_RATE_LIMIT", DEFAULT_FORWARD_RATE_LIMIT),
+            setting("MAX_CLIENTS_PER_USER", DEFAULT_MAX_CLIENTS_PER_USER),
+            setting("MAX_CLIENT_FIELD_LENGTH", DEFAULT_MAX_CLIENT_FIELD_LENGTH),
+            setting("MAX_PENDING_REQUESTS", DEFAULT_MAX_PENDING_REQUESTS),
+            setting("FORWARD_TIMEOUT_MS", DEFAULT_FORWARD_TIMEOUT_MS),
+            setting("MAX_WS_LIFETIME_HOURS", 0),
+            setting("BROWSER_IDLE_TIMEOUT_MS", DEFAULT_BROWSER_IDLE_TIMEOUT_MS),
+            setting(
+                "CLIENT_HEARTBEAT_TIMEOUT_MS",
+                DEFAULT_CLIENT_HEARTBEAT_TIMEOUT_MS,
+            ),
+            setting("STALE_CHECK_INTERVAL_MS", DEFAULT_STALE_CHECK_INTERVAL_MS),
+            setting("PROXY_HEALTH_TIMEOUT_MS", DEFAULT_PROXY_HEALTH_TIMEOUT_MS),
+            setting("MAX_WS_PROXY_TUNNELS", DEFAULT_MAX_WS_PROXY_TUNNELS),
+            setting("PROXY_MAX_URL_LENGTH", DEFAULT_PROXY_MAX_URL_LENGTH),
+            setting("PROXY_TIMEOUT_MS", DEFAULT_PROXY_TIMEOUT_MS),
+            setting(
+                "CLIENT_RESUME_WINDOW_SECONDS",
+                DEFAULT_CLIENT_RESUME_WINDOW_SECONDS,
+            ),
+            setting(
+                "CONNECTION_QUALITY_MAX_GAP_MS",
+                DEFAULT_CONNECTION_QUALITY_MAX_GAP_MS,
+            ),
+            setting(
+                "CONNECTION_QUALITY_MAX_JITTER_PCT",
+                DEFAULT_CONNECTION_QUALITY_MAX_JITTER_PCT,
+            ),
+        ]
+        .into_iter()
+        .collect();
+
+        let require_approval = crate::config::var_bool(env, "REQUIRE_CLIENT_APPROVAL", false);
+        config.insert(
+            "require_client_approval".to_string(),
+            require_approval.into(),
+        );
+        for flag in ["REQUIRE_HTTPS_CALLBACK", "ALLOW_LOCALHOST_HTTP_CALLBACK"] {
+            let enabled = crate::config::var_bool(env, flag, false);
+            config.insert(flag.to_lowercase(), enabled.into());
+        }
+        let extensions = crate::config::var_string(env, "ASSET_CACHE_EXTENSIONS")
+            .unwrap_or_else(|| DEFAULT_ASSET_CACHE_EXTENSIONS.to_string());
+        config.insert("asset_cache_extensions".to_string(), extensions.into());
+
+        Response::from_json(&config)
+    }
+
     /// Delete client from SQLite
     fn delete_client(&self, client_id: &str) -> Result<()> {
         self.ensure_initialized()?;
@@ -359,7 +1156,7 @@ impl UserHub {
         let sql = self.state.storage().sql();
 
         let cursor = sql.exec(
-            "SELECT client_id, user_id, hostname, project, status, last_activity, connected_at, last_seen, callback_url FROM clients",
+            "SELECT client_id, user_id, hostname, project, status, last_activity, connected_at, last_seen, callback_url, asset_cache, activity_summary, ip, country, city, region FROM clients",
             None,
         )?;
 
@@ -372,9 +1169,17 @@ impl UserHub {
                 "active" => ClientStatus::Active,
                 "busy" => ClientStatus::Busy,
                 "disconnected" => ClientStatus::Disconnected,
+                "pending_approval" => ClientStatus::PendingApproval,
                 _ => ClientStatus::Idle,
             };
 
+            let geo = GeoInfo {
+                ip: row_value.ip,
+                country: row_value.country,
+                city: row_value.city,
+                region: row_value.region,
+            };
+
             clients.push(Client {
                 id: row_value.client_id,
                 user_id: row_value.user_id,
@@ -384,9 +1189,13 @@ impl UserHub {
                     status,
                     last_activity: row_value.last_activity,
                     callback_url: row_value.callback_url,
+                    asset_cache: row_value.asset_cache.unwrap_or(0) != 0,
+                    activity_summary: row_value.activity_summary,
+                    geo: Some(geo).filter(|geo| !geo.is_empty()),
                 },
                 connected_at: row_value.connected_at,
                 last_seen: row_value.last_seen,
+                connection_quality: None,
             });
         }
 
@@ -411,8 +1220,18 @@ impl UserHub {
 
         for ws in websockets {
             let tags = self.state.get_tags(&ws);
-            if tags.iter().any(|t| t == "browser") {
-                browsers.push(ws);
+            if tags.first().is_some_and(|t| t == WS_PROXY_TAG) {
+                // Tunnels are looked up by tag on demand
+                continue;
+            } else if tags.iter().any(|t| t == "browser") {
+                if browsers.iter().any(|b| b.websocket == ws) {
+                    continue;
+                }
+                // Activity from before hibernation is unknown; start the idle clock now
+                browsers.push(BrowserConnection {
+                    websocket: ws,
+                    last_seen: crate::time::now_ms(),
+                });
             } else if let Some(client_id) = tags.first() {
                 if let Some(client) = client_map.get(client_id) {
                     clients.insert(
@@ -420,47 +1239,245 @@ impl UserHub {
                         ClientConnection {
                             websocket: ws,
                             client: client.clone(),
+                            ping_times: Vec::new(),
                         },
                     );
                 }
             }
         }
+        drop(clients);
+        drop(browsers);
 
-        Ok(())
+        self.restore_pending_requests()
     }
 
-    async fn handle_websocket(&self, req: Request) -> Result<Response> {
-        // Get the upgrade header
-        let upgrade = req.headers().get("Upgrade")?;
-        if upgrade.as_deref() != Some("websocket") {
-            return Response::error("Expected websocket", 426);
+    /// Tag identifying a browser socket across hibernation, if it has one
+    fn browser_id(&self, ws: &WebSocket) -> Option<String> {
+        let tags = self.state.get_tags(ws);
+        match tags.as_slice() {
+            [kind, id, ..] if kind == "browser" => Some(id.clone()),
+            _ => None,
         }
+    }
 
-        // Parse query parameters
-        let url = req.url()?;
-        let is_browser = url.query_pairs().any(|(k, v)| k == "type" && v == "browser");
-        let client_id: Option<String> = url
-            .query_pairs()
-            .find(|(k, _)| k == "client_id")
-            .map(|(_, v)| v.to_string());
-
-        let pair = WebSocketPair::new()?;
-        let server = pair.server;
-        let client = pair.client;
-
-        // Use hibernation API for WebSocket acceptance with tags for recovery
-        // Tags allow us to identify WebSockets after hibernation
-        if is_browser {
-            self.state.accept_websocket_with_tags(&server, &["browser"]);
-        } else if let Some(id) = client_id {
-            // Tag client WebSocket with its client_id for hibernation recovery
-            self.state.accept_websocket_with_tags(&server, &[&id]);
-        } else {
-            // Legacy: no client_id provided (shouldn't happen with updated claudecodeui)
-            self.state.accept_web_socket(&server);
+    /// Persist a forwarded request so a response arriving after hibernation still finds it
+    ///
+    /// Browser sockets accepted before they were given an ID tag can't be
+    /// located after waking, so their requests stay in memory only.
+    fn save_pending_request(&self, request_id: &str, req: &PendingRequest) {
+        let browser_id = match self.browser_id(&req.browser_ws) {
+            Some(id) => id,
+            None => return,
+        };
+        let result = self.ensure_initialized().and_then(|_| {
+            self.state.storage().sql().exec(
+                "INSERT OR REPLACE INTO pending_requests (request_id, client_id, browser_id, next_seq, started_at)
+                 VALUES (?, ?, ?, ?, ?)",
+                Some(vec![
+                    SqlStorageValue::String(request_id.to_string()),
+                    SqlStorageValue::String(req.client_id.clone()),
+                    SqlStorageValue::String(browser_id),
+                    SqlStorageValue::Integer(req.sequence.next_seq as i64),
+                    SqlStorageValue::Integer(req.started_at as i64),
+                ]),
+            )
+        });
+        if let Err(e) = result {
+            console_log!("Failed to persist pending request {}: {:?}", request_id, e);
         }
-
-        Response::from_websocket(client)
+    }
+
+    /// Persist one part buffered ahead of the gap before it
+    ///
+    /// Only requests persisted by `save_pending_request` are restored, so parts
+    /// of in-memory-only requests are skipped.
+    fn save_response_part(&self, request_id: &str, seq: u64, json: String) {
+        let result = self.ensure_initialized().and_then(|_| {
+            self.state.storage().sql().exec(
+                "INSERT OR REPLACE INTO pending_response_parts (request_id, seq, part)
+                 SELECT ?1, ?2, ?3 WHERE EXISTS (SELECT 1 FROM pending_requests WHERE request_id = ?1)",
+                Some(vec![
+                    SqlStorageValue::String(request_id.to_string()),
+                    SqlStorageValue::Integer(seq as i64),
+                    SqlStorageValue::String(json),
+                ]),
+            )
+        });
+        if let Err(e) = result {
+            console_log!(
+                "Failed to persist part {} of request {}: {:?}",
+                seq,
+                request_id,
+                e
+            );
+        }
+    }
+
+    /// Record that the browser has received every part before `next_seq`
+    fn save_response_progress(&self, request_id: &str, next_seq: u64) {
+        if self.ensure_initialized().is_err() {
+            return;
+        }
+        let sql = self.state.storage().sql();
+        let params = || {
+            Some(vec![
+                SqlStorageValue::Integer(next_seq as i64),
+                SqlStorageValue::String(request_id.to_string()),
+            ])
+        };
+        let _ = sql.exec(
+            "UPDATE pending_requests SET next_seq = ? WHERE request_id = ?",
+            params(),
+        );
+        let _ = sql.exec(
+            "DELETE FROM pending_response_parts WHERE seq < ? AND request_id = ?",
+            params(),
+        );
+    }
+
+    fn delete_pending_request(&self, request_id: &str) {
+        if self.ensure_initialized().is_err() {
+            return;
+        }
+        let sql = self.state.storage().sql();
+        for query in [
+            "DELETE FROM pending_requests WHERE request_id = ?",
+            "DELETE FROM pending_response_parts WHERE request_id = ?",
+        ] {
+            let _ = sql.exec(
+                query,
+                Some(vec![SqlStorageValue::String(request_id.to_string())]),
+            );
+        }
+    }
+
+    /// Rebuild `pending_requests` from SQLite after hibernation
+    ///
+    /// A response that arrives while the object hibernates wakes it, and
+    /// `handle_message` restores state before routing the message, so the
+    /// request is back in the map by the time its parts are delivered.
+    /// Requests whose browser has since disconnected are dropped.
+    fn restore_pending_requests(&self) -> Result<()> {
+        self.ensure_initialized()?;
+        let rows: Vec<PendingRequestRow> = self
+            .state
+            .storage()
+            .sql()
+            .exec(
+                "SELECT request_id, client_id, browser_id, next_seq, started_at FROM pending_requests",
+                None,
+            )?
+            .to_array()?;
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let parts: Vec<PendingResponsePartRow> = self
+            .state
+            .storage()
+            .sql()
+            .exec(
+                "SELECT request_id, seq, part FROM pending_response_parts",
+                None,
+            )?
+            .to_array()?;
+
+        let browser_sockets: Vec<(String, WebSocket)> = self
+            .state
+            .get_websockets()
+            .into_iter()
+            .filter_map(|ws| self.browser_id(&ws).map(|id| (id, ws)))
+            .collect();
+
+        let mut pending = self.pending_requests.borrow_mut();
+        for row in rows {
+            if pending.contains_key(&row.request_id) {
+                continue;
+            }
+            let browser_ws = browser_sockets
+                .iter()
+                .find(|(id, _)| *id == row.browser_id)
+                .map(|(_, ws)| ws.clone());
+            match browser_ws {
+                Some(browser_ws) => {
+                    let mut sequence = ResponseSequence {
+                        next_seq: row.next_seq as u64,
+                        ..Default::default()
+                    };
+                    for part in parts.iter().filter(|p| p.request_id == row.request_id) {
+                        sequence.restore(part.seq as u64, &part.part);
+                    }
+                    pending.insert(
+                        row.request_id,
+                        PendingRequest {
+                            client_id: row.client_id,
+                            browser_ws,
+                            sequence,
+                            started_at: row.started_at as u64,
+                        },
+                    );
+                }
+                None => self.delete_pending_request(&row.request_id),
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_websocket(&self, req: Request) -> Result<Response> {
+        // Get the upgrade header
+        let upgrade = req.headers().get("Upgrade")?;
+        if upgrade.as_deref() != Some("websocket") {
+            return Response::error("Expected websocket", 426);
+        }
+
+        // Parse query parameters
+        let params = crate::query::Query::from_request(&req)?;
+        let is_browser = params.get_str("type") == Some("browser");
+        let client_id = params.get_str("client_id").map(|id| id.to_string());
+
+        let pair = WebSocketPair::new()?;
+        let server = pair.server;
+        let client = pair.client;
+
+        // Use hibernation API for WebSocket acceptance with tags for recovery
+        // Tags allow us to identify WebSockets after hibernation
+        if is_browser {
+            // The second tag lets forwarded requests find this socket after hibernation
+            let browser_id = generate_request_id()?;
+            self.state
+                .accept_websocket_with_tags(&server, &["browser", &browser_id]);
+        } else if let Some(id) = client_id {
+            // Tag client WebSocket with its client_id for hibernation recovery,
+            // and the token it authenticated with so it can be resumed later
+            match params.get_str("token_id") {
+                Some(token_id) => self
+                    .state
+                    .accept_websocket_with_tags(&server, &[&id, token_id]),
+                None => self.state.accept_websocket_with_tags(&server, &[&id]),
+            }
+
+            // Keep the connection origin on the socket until the client registers
+            let param = |name: &str| {
+                params
+                    .get_str(name)
+                    .filter(|v| !v.is_empty())
+                    .map(|v| v.to_string())
+            };
+            let geo = GeoInfo {
+                ip: param("ip"),
+                country: param("country"),
+                city: param("city"),
+                region: param("region"),
+            };
+            if !geo.is_empty() {
+                let _ = server.serialize_attachment(&geo);
+            }
+        } else {
+            // Legacy: no client_id provided (shouldn't happen with updated claudecodeui)
+            self.state.accept_web_socket(&server);
+        }
+
+        Response::from_websocket(client)
     }
 
     /// Ensure state is restored after hibernation
@@ -479,7 +1496,33 @@ impl UserHub {
         // Restore state if waking from hibernation
         let _ = self.ensure_state_restored();
 
-        let msg: WsMessage = match serde_json::from_str(text) {
+        let parsed = serde_json::from_str::<WsMessage>(text);
+
+        // Any message from a browser counts as a sign of life for the idle reaper
+        if let Some(browser) = self
+            .browsers
+            .borrow_mut()
+            .iter_mut()
+            .find(|b| &b.websocket == ws)
+        {
+            browser.last_seen = crate::time::now_ms();
+        }
+
+        // Pings and everything else (including malformed input) have separate budgets.
+        // Tunnelled frames are bounded by the tunnel limits instead
+        let kind = match parsed {
+            Ok(WsMessage::Ping { .. }) => Some(RateKind::Ping),
+            Ok(WsMessage::WsProxyFrame { .. }) => None,
+            _ => Some(RateKind::Message),
+        };
+        if let Some(kind) = kind {
+            if !self.allow_message(ws, kind) {
+                self.reject_rate_limited(ws).await;
+                return Ok(());
+            }
+        }
+
+        let msg: WsMessage = match parsed {
             Ok(m) => m,
             Err(e) => {
                 let error = WsMessage::Error {
@@ -497,13 +1540,68 @@ impl UserHub {
                 client_id,
                 user_token: _,
                 metadata,
+                resume,
             } => {
-                // Create client
+                // Reject invalid registrations with a clear reason before storing anything
+                if let Err(reason) = self.validate_registration(&client_id, &metadata) {
+                    console_log!("Rejected registration for '{}': {}", client_id, reason);
+                    let registered = WsMessage::Registered {
+                        success: false,
+                        message: Some(reason.clone()),
+                    };
+                    if let Ok(json) = serde_json::to_string(&registered) {
+                        let _ = ws.send_with_str(&json);
+                    }
+                    let _ = ws.close(Some(close_codes::POLICY_VIOLATION), Some(reason));
+                    return Ok(());
+                }
+
+                // Only the token that registered a client may resume it
+                let resumed = if resume {
+                    match self.resume_client(ws, &client_id) {
+                        Ok(resumed) => resumed,
+                        Err(reason) => {
+                            console_log!("Rejected resume for '{}': {}", client_id, reason);
+                            let registered = WsMessage::Registered {
+                                success: false,
+                                message: Some(reason.clone()),
+                            };
+                            if let Ok(json) = serde_json::to_string(&registered) {
+                                let _ = ws.send_with_str(&json);
+                            }
+                            let _ = ws.close(Some(close_codes::POLICY_VIOLATION), Some(reason));
+                            return Ok(());
+                        }
+                    }
+                } else {
+                    None
+                };
+
+                // In approval mode, unapproved clients wait in the pending state
+                let mut metadata = metadata;
+                metadata.geo = ws.deserialize_attachment::<GeoInfo>().ok().flatten();
+                let awaiting_approval =
+                    self.requires_approval(&client_id, self.socket_token_id(ws).as_deref());
+                if awaiting_approval {
+                    metadata.status = ClientStatus::PendingApproval;
+                } else if metadata.status == ClientStatus::PendingApproval {
+                    metadata.status = ClientStatus::Idle;
+                }
+
+                // Create client, carrying over what a resumed connection keeps
                 let user_id = self.state.id().to_string();
-                let client = Client::new(client_id.clone(), user_id, metadata);
+                let mut client = Client::new(client_id.clone(), user_id, metadata);
+                if let Some(previous) = &resumed {
+                    client.connected_at = previous.connected_at.clone();
+                    if client.metadata.activity_summary.is_none() {
+                        client.metadata.activity_summary =
+                            previous.metadata.activity_summary.clone();
+                    }
+                }
 
-                // Save to SQLite for persistence
+                // Save to SQLite for persistence, with the token that authenticated it
                 let _ = self.save_client(&client);
+                let _ = self.save_client_token(&client_id, self.socket_token_id(ws).as_deref());
 
                 // Register in D1 for public path routing (allows anonymous access to manifest.json etc.)
                 if let Err(e) = self.register_client_in_d1(&client).await {
@@ -514,10 +1612,24 @@ impl UserHub {
                 // Note: We need to re-accept with tags, but that's not possible after accept
                 // So we track the mapping in SQLite instead
 
+                let _ = self.incr_counter(
+                    if resumed.is_some() {
+                        "resumes"
+                    } else {
+                        "registrations"
+                    },
+                    1,
+                );
+
                 // Send registration success response
+                let message = if awaiting_approval {
+                    Some("Awaiting approval".to_string())
+                } else {
+                    resumed.is_some().then(|| "Resumed".to_string())
+                };
                 let registered = WsMessage::Registered {
                     success: true,
-                    message: None,
+                    message,
                 };
                 if let Ok(json) = serde_json::to_string(&registered) {
                     let _ = ws.send_with_str(&json);
@@ -530,51 +1642,146 @@ impl UserHub {
                     self.broadcast_to_browsers(&json);
                 }
 
-                // Store connection in memory
-                self.clients.borrow_mut().insert(
+                // Store connection in memory, retiring the socket a resume replaced
+                let replaced = self.clients.borrow_mut().insert(
                     client_id,
                     ClientConnection {
                         websocket: ws.clone(),
                         client,
+                        ping_times: Vec::new(),
                     },
                 );
+                let retired = replaced.filter(|old| resumed.is_some() && &old.websocket != ws);
+                if let Some(old) = retired {
+                    let _ = old.websocket.close(
+                        Some(close_codes::GOING_AWAY),
+                        Some("Resumed on a new connection"),
+                    );
+                }
+                self.invalidate_stale_clients();
+
+                // Make sure the alarm will close this socket when its lifetime runs out
+                self.schedule_alarm().await;
             }
 
             WsMessage::StatusUpdate { client_id, status } => {
                 // Get client, update, and extract data
                 let mut clients = self.clients.borrow_mut();
-                if let Some(conn) = clients.get_mut(&client_id) {
+                if let Some(conn) = clients
+                    .get_mut(&client_id)
+                    .filter(|conn| conn.client.metadata.status != ClientStatus::PendingApproval)
+                {
+                    let previous = conn.client.clone();
                     conn.client.update_status(status);
                     conn.client.update_last_seen();
                     let client_clone = conn.client.clone();
+                    drop(clients); // Release borrow before broadcasting
 
                     // Update SQLite
                     let _ = self.save_client(&client_clone);
 
-                    // Broadcast to browsers
-                    if let Ok(json) =
-                        serde_json::to_string(&WsMessage::ClientUpdate { client: client_clone })
-                    {
-                        drop(clients); // Release borrow before broadcasting
-                        self.broadcast_to_browsers(&json);
+                    // Broadcast only the changed fields to browsers
+                    self.broadcast_client_patch(&previous, &client_clone);
+                }
+            }
+
+            WsMessage::MetadataUpdate {
+                client_id,
+                metadata,
+            } => {
+                // Only the connection that registered a client may update it
+                let registered_here = self
+                    .clients
+                    .borrow()
+                    .get(&client_id)
+                    .is_some_and(|conn| &conn.websocket == ws);
+                if !registered_here {
+                    return Ok(());
+                }
+
+                if let Err(reason) = self.validate_registration(&client_id, &metadata) {
+                    console_log!("Rejected metadata update for '{}': {}", client_id, reason);
+                    let error = WsMessage::Error { message: reason };
+                    if let Ok(json) = serde_json::to_string(&error) {
+                        let _ = ws.send_with_str(&json);
                     }
+                    return Ok(());
+                }
+
+                let updated = {
+                    let mut clients = self.clients.borrow_mut();
+                    clients.get_mut(&client_id).map(|conn| {
+                        let previous = conn.client.clone();
+                        let mut metadata = metadata;
+                        if previous.metadata.status == ClientStatus::PendingApproval {
+                            metadata.status = ClientStatus::PendingApproval;
+                        }
+                        if metadata.activity_summary.is_none() {
+                            metadata.activity_summary = previous.metadata.activity_summary.clone();
+                        }
+                        metadata.geo = previous.metadata.geo.clone();
+                        conn.client.metadata = metadata;
+                        conn.client.update_last_seen();
+                        (previous, conn.client.clone())
+                    })
+                };
+
+                if let Some((previous, client)) = updated {
+                    let _ = self.save_client(&client);
+                    self.broadcast_client_patch(&previous, &client);
+                }
+            }
+
+            WsMessage::Shutdown { client_id, reason } => {
+                // Only the connection that registered a client may shut it down
+                let registered_here = self
+                    .clients
+                    .borrow()
+                    .get(&client_id)
+                    .is_some_and(|conn| &conn.websocket == ws);
+                if !registered_here {
+                    return Ok(());
                 }
+
+                console_log!(
+                    "Client {} shutting down: {}",
+                    client_id,
+                    reason.as_deref().unwrap_or("no reason given")
+                );
+                self.remove_client(&client_id, reason).await;
+                self.message_rates
+                    .borrow_mut()
+                    .retain(|r| &r.websocket != ws);
+                let _ = ws.close(Some(close_codes::NORMAL), Some("Client shutdown"));
             }
 
             WsMessage::Ping { client_id } => {
+                let now = crate::time::now_ms();
                 let client_to_save = {
                     let mut clients = self.clients.borrow_mut();
                     if let Some(conn) = clients.get_mut(&client_id) {
+                        let previous = conn.client.clone();
                         conn.client.update_last_seen();
-                        Some(conn.client.clone())
+                        // A client marked disconnected by the heartbeat check is back
+                        if conn.client.metadata.status == ClientStatus::Disconnected {
+                            conn.client.update_status(ClientStatus::Idle);
+                        }
+                        conn.ping_times.push(now);
+                        if conn.ping_times.len() > PING_HISTORY {
+                            conn.ping_times.remove(0);
+                        }
+                        conn.client.connection_quality =
+                            self.connection_quality(&conn.ping_times, now);
+                        Some((previous, conn.client.clone()))
                     } else {
                         None
                     }
                 };
 
                 // Update last_seen in SQLite periodically (on pings)
-                if let Some(client) = client_to_save {
+                if let Some((previous, client)) = client_to_save {
                     let _ = self.save_client(&client);
+                    self.broadcast_client_patch(&previous, &client);
                 }
 
                 let pong = WsMessage::Pong { client_id };
@@ -583,45 +1790,49 @@ impl UserHub {
                 }
             }
 
+            WsMessage::Activity { client_id, summary } => {
+                let summary: String = summary
+                    .trim()
+                    .chars()
+                    .take(MAX_ACTIVITY_SUMMARY_LENGTH)
+                    .collect();
+
+                // Only the connection that registered a client may report for it
+                let updated = {
+                    let mut clients = self.clients.borrow_mut();
+                    clients
+                        .get_mut(&client_id)
+                        .filter(|conn| &conn.websocket == ws)
+                        .filter(|conn| conn.client.metadata.status != ClientStatus::PendingApproval)
+                        .map(|conn| {
+                            let previous = conn.client.clone();
+                            conn.client.update_activity(summary);
+                            conn.client.update_last_seen();
+                            (previous, conn.client.clone())
+                        })
+                };
+
+                if let Some((previous, client)) = updated {
+                    let _ = self.save_client(&client);
+                    self.broadcast_client_patch(&previous, &client);
+                }
+            }
+
             WsMessage::GetClients => {
                 // This is a browser requesting the client list
                 // Add it to browsers if not already there
                 {
                     let mut browsers = self.browsers.borrow_mut();
-                    if !browsers.contains(ws) {
-                        browsers.push(ws.clone());
+                    if !browsers.iter().any(|b| &b.websocket == ws) {
+                        browsers.push(BrowserConnection {
+                            websocket: ws.clone(),
+                            last_seen: crate::time::now_ms(),
+                        });
                     }
                 }
+                self.schedule_alarm().await;
 
-                // Get clients with active WebSocket connections from memory
-                let active_client_ids: std::collections::HashSet<String> = self
-                    .clients
-                    .borrow()
-                    .keys()
-                    .cloned()
-                    .collect();
-
-                let mut clients: Vec<Client> = self
-                    .clients
-                    .borrow()
-                    .values()
-                    .map(|c| c.client.clone())
-                    .collect();
-
-                // Check SQLite for any clients that might be stale
-                // Mark them as disconnected if their WebSocket is not in memory
-                if let Ok(stored) = self.load_clients_from_sqlite() {
-                    for mut stored_client in stored {
-                        if !active_client_ids.contains(&stored_client.id) {
-                            // Client in SQLite but not in memory - mark as disconnected
-                            if !matches!(stored_client.metadata.status, ClientStatus::Disconnected) {
-                                stored_client.update_status(ClientStatus::Disconnected);
-                                let _ = self.save_client(&stored_client);
-                            }
-                            clients.push(stored_client);
-                        }
-                    }
-                }
+                let clients = self.list_all_clients();
 
                 let response = WsMessage::ClientList { clients };
                 if let Ok(json) = serde_json::to_string(&response) {
@@ -637,7 +1848,18 @@ impl UserHub {
                     clients.get(&client_id).map(|conn| conn.client.clone())
                 };
 
-                let response = if let Some(c) = client_opt {
+                let awaiting_approval = client_opt
+                    .as_ref()
+                    .is_some_and(|c| c.metadata.status == ClientStatus::PendingApproval);
+
+                let response = if awaiting_approval {
+                    WsMessage::ConnectResponse {
+                        success: false,
+                        client_id: client_id.clone(),
+                        url: None,
+                        message: Some("Client is awaiting approval".to_string()),
+                    }
+                } else if client_opt.is_some() {
                     // Client has an active WebSocket connection
                     WsMessage::ConnectResponse {
                         success: true,
@@ -697,20 +1919,47 @@ impl UserHub {
             } => {
                 // Browser wants to forward a request to a claudecodeui client
                 // Find the client's WebSocket
-                let client_ws_opt = {
-                    let clients = self.clients.borrow();
-                    clients.get(&client_id).map(|conn| conn.websocket.clone())
-                };
+                let client_ws_opt = self.routable_client_ws(&client_id);
 
-                if let Some(client_ws) = client_ws_opt {
+                if !self.allow_message(ws, RateKind::Forward) {
+                    // Answer bursts from the dashboard here instead of passing them on
+                    let error = WsMessage::ForwardedResponse {
+                        client_id,
+                        request_id,
+                        data: serde_json::json!({
+                            "error": true,
+                            "message": "Too many requests, slow down"
+                        }),
+                        complete: true,
+                    };
+                    if let Ok(json) = serde_json::to_string(&error) {
+                        let _ = ws.send_with_str(&json);
+                    }
+                } else if self.is_pending_approval(&client_id) {
+                    let error = WsMessage::ForwardedResponse {
+                        client_id,
+                        request_id,
+                        data: serde_json::json!({
+                            "error": true,
+                            "message": "Client is awaiting approval"
+                        }),
+                        complete: true,
+                    };
+                    if let Ok(json) = serde_json::to_string(&error) {
+                        let _ = ws.send_with_str(&json);
+                    }
+                } else if let Some(client_ws) = client_ws_opt {
                     // Track this pending request so we can route responses back
-                    self.pending_requests.borrow_mut().insert(
+                    self.track_pending_request(
                         request_id.clone(),
                         PendingRequest {
                             client_id: client_id.clone(),
                             browser_ws: ws.clone(),
+                            sequence: ResponseSequence::default(),
+                            started_at: crate::time::now_ms(),
                         },
                     );
+                    self.schedule_alarm().await;
 
                     // Forward as user_request to claudecodeui
                     let user_request = WsMessage::UserRequest {
@@ -772,36 +2021,22 @@ impl UserHub {
                 }
             }
 
-            WsMessage::ResponseChunk { request_id, data } => {
-                // Response chunk from claudecodeui - route back to browser
-                let pending = self.pending_requests.borrow();
-                if let Some(req) = pending.get(&request_id) {
-                    let response = WsMessage::ForwardedResponse {
-                        client_id: req.client_id.clone(),
-                        request_id: request_id.clone(),
-                        data,
-                        complete: false,
-                    };
-                    if let Ok(json) = serde_json::to_string(&response) {
-                        let _ = req.browser_ws.send_with_str(&json);
-                    }
-                }
+            WsMessage::ResponseChunk {
+                request_id,
+                data,
+                seq,
+            } => {
+                // Response chunk from claudecodeui - route back to browser in order
+                self.route_response_part(&request_id, seq, ResponsePart::Chunk(data));
             }
 
-            WsMessage::ResponseComplete { request_id, data } => {
+            WsMessage::ResponseComplete {
+                request_id,
+                data,
+                seq,
+            } => {
                 // Response complete from claudecodeui - route back to browser and clean up
-                let pending_req = self.pending_requests.borrow_mut().remove(&request_id);
-                if let Some(req) = pending_req {
-                    let response = WsMessage::ForwardedResponse {
-                        client_id: req.client_id,
-                        request_id,
-                        data: data.unwrap_or(serde_json::json!({"complete": true})),
-                        complete: true,
-                    };
-                    if let Ok(json) = serde_json::to_string(&response) {
-                        let _ = req.browser_ws.send_with_str(&json);
-                    }
-                }
+                self.route_response_part(&request_id, seq, ResponsePart::Complete(data));
             }
 
             WsMessage::HttpProxyResponse {
@@ -809,9 +2044,95 @@ impl UserHub {
                 status,
                 headers,
                 body,
+                body_is_base64,
             } => {
                 // HTTP proxy response from claudecodeui - resolve the pending request
-                self.handle_http_proxy_response(&request_id, status, headers, body);
+                let response = ProxyResponse {
+                    status,
+                    headers,
+                    body,
+                    body_is_base64,
+                    error: None,
+                };
+                self.handle_http_proxy_response(&request_id, response);
+            }
+
+            WsMessage::HttpProxyResponseStart {
+                request_id,
+                status,
+                headers,
+            } => {
+                // Streamed proxy response - hand the body channel to the waiting request
+                self.start_proxy_stream(ws, &request_id, status, headers);
+            }
+
+            WsMessage::HttpProxyResponseChunk { request_id, data } => {
+                let streams = self.proxy_streams.borrow();
+                if let Some(stream) = streams.get(&request_id) {
+                    let _ = stream.body.unbounded_send(data.into_bytes());
+                }
+            }
+
+            WsMessage::HttpProxyResponseEnd { request_id } => {
+                // Dropping the sender ends the response body
+                self.proxy_streams.borrow_mut().remove(&request_id);
+            }
+
+            WsMessage::WsProxyFrame {
+                tunnel_id,
+                data,
+                data_is_base64,
+            } => {
+                // Frames for tunnels this client doesn't own are dropped
+                if let Some((browser_ws, client_id)) = self.owned_ws_proxy_tunnel(ws, &tunnel_id) {
+                    let sent = match crate::body::decode(&data, data_is_base64) {
+                        Some(bytes) if data_is_base64 => browser_ws.send_with_bytes(bytes),
+                        Some(_) => browser_ws.send_with_str(&data),
+                        None => Ok(()),
+                    };
+                    if sent.is_err() {
+                        self.abort_ws_proxy(
+                            &browser_ws,
+                            &tunnel_id,
+                            &client_id,
+                            close_codes::INTERNAL_ERROR,
+                            "Failed to deliver frame",
+                        );
+                    }
+                }
+            }
+
+            WsMessage::WsProxyClose {
+                tunnel_id,
+                code,
+                reason,
+            } => {
+                if let Some((browser_ws, _)) = self.owned_ws_proxy_tunnel(ws, &tunnel_id) {
+                    // A tunnel closed before it was accepted fails the browser's upgrade
+                    self.pending_ws_proxy_opens.borrow_mut().remove(&tunnel_id);
+                    // Reserved codes (1005, 1006, ...) can't be sent; fall back to a plain close
+                    if browser_ws.close(code, reason.as_deref()).is_err() {
+                        let _ = browser_ws.close(Some(close_codes::NORMAL), reason.as_deref());
+                    }
+                }
+            }
+
+            WsMessage::WsProxyAccept {
+                tunnel_id,
+                protocol,
+            } => {
+                // Accepts for tunnels this client doesn't own are dropped
+                let owned = self.owned_ws_proxy_tunnel(ws, &tunnel_id).is_some();
+                let sender = owned
+                    .then(|| self.pending_ws_proxy_opens.borrow_mut().remove(&tunnel_id))
+                    .flatten();
+                if let Some(sender) = sender {
+                    let _ = sender.send(protocol);
+                }
+            }
+
+            WsMessage::BrowserHeartbeat => {
+                // Nothing to do: any inbound message already refreshed last_seen
             }
 
             _ => {
@@ -822,60 +2143,752 @@ impl UserHub {
         Ok(())
     }
 
-    async fn handle_close(&self, ws: &WebSocket) {
-        // Remove from browsers list
-        self.browsers.borrow_mut().retain(|b| b != ws);
-
-        // Remove from clients and broadcast disconnection
-        let disconnected_id = {
-            let clients = self.clients.borrow();
-            clients
-                .iter()
-                .find(|(_, conn)| &conn.websocket == ws)
-                .map(|(id, _)| id.clone())
+    /// Deliver a chunk or completion to the waiting browser, reordering by sequence number
+    fn route_response_part(&self, request_id: &str, seq: Option<u64>, part: ResponsePart) {
+        let mut pending = self.pending_requests.borrow_mut();
+        let req = match pending.get_mut(request_id) {
+            Some(req) => req,
+            None => {
+                if self
+                    .completed_requests
+                    .borrow()
+                    .iter()
+                    .any(|id| id == request_id)
+                {
+                    let kind = match part {
+                        ResponsePart::Chunk(_) => "chunk",
+                        ResponsePart::Complete(_) => "duplicate completion",
+                    };
+                    console_log!(
+                        "Warning: dropping {} for already completed request {}",
+                        kind,
+                        request_id
+                    );
+                }
+                return;
+            }
         };
 
-        if let Some(client_id) = disconnected_id {
-            self.clients.borrow_mut().remove(&client_id);
-
-            // Remove from SQLite
-            let _ = self.delete_client(&client_id);
+        let sequence = &mut req.sequence;
+        let mut accepted = None;
+        match seq {
+            Some(seq) => match sequence.insert(seq, part) {
+                Ok(json) => accepted = Some((seq, json)),
+                Err(PartRejected::Duplicate) => {
+                    console_log!(
+                        "Warning: ignoring duplicate part {} for request {}",
+                        seq,
+                        request_id
+                    );
+                    return;
+                }
+                Err(PartRejected::TooFarAhead) => {
+                    console_log!(
+                        "Warning: ignoring part {} for request {}, expected {}",
+                        seq,
+                        request_id,
+                        sequence.next_seq
+                    );
+                    return;
+                }
+                Err(PartRejected::Overflow) => {
+                    console_log!(
+                        "Failing request {}: too many out-of-order parts buffered",
+                        request_id
+                    );
+                    let error = serde_json::json!({
+                        "error": true,
+                        "message": "Response parts arrived too far out of order"
+                    });
+                    if let Some(req) = pending.remove(request_id) {
+                        Self::send_forwarded(&req, request_id, error, true);
+                    }
+                    self.remember_completed(request_id);
+                    return;
+                }
+            },
+            None => match part {
+                // Unsequenced chunks keep the old pass-through behavior
+                ResponsePart::Chunk(data) => {
+                    Self::send_forwarded(req, request_id, data, false);
+                    return;
+                }
+                // Unsequenced completion flushes whatever is buffered, in order
+                ResponsePart::Complete(data) => {
+                    for part in sequence.take_all() {
+                        if let ResponsePart::Chunk(data) = part {
+                            Self::send_forwarded(req, request_id, data, false);
+                        }
+                    }
+                    let next_seq = req.sequence.next_seq;
+                    req.sequence
+                        .buffered
+                        .insert(next_seq, ResponsePart::Complete(data));
+                }
+            },
+        }
 
-            // Remove from D1 (for public path routing)
-            if let Err(e) = self.unregister_client_from_d1(&client_id).await {
-                console_log!("Failed to unregister client from D1: {:?}", e);
+        // Forward every part that is now contiguous with what the browser has seen
+        let mut completed = false;
+        let ready = req.sequence.take_ready();
+        let delivered = !ready.is_empty();
+        for part in ready {
+            match part {
+                ResponsePart::Chunk(data) => Self::send_forwarded(req, request_id, data, false),
+                ResponsePart::Complete(data) => {
+                    let data = data.unwrap_or(serde_json::json!({"complete": true}));
+                    Self::send_forwarded(req, request_id, data, true);
+                    completed = true;
+                }
             }
+        }
 
-            // Broadcast disconnection to browsers
-            if let Ok(msg) =
-                serde_json::to_string(&WsMessage::ClientDisconnected { client_id })
-            {
-                self.broadcast_to_browsers(&msg);
+        if completed {
+            if !req.sequence.buffered.is_empty() {
+                console_log!(
+                    "Warning: dropping {} parts received after completion of request {}",
+                    req.sequence.buffered.len(),
+                    request_id
+                );
+            }
+            pending.remove(request_id);
+            self.remember_completed(request_id);
+        } else {
+            // Only a part still waiting on a gap needs persisting
+            if let Some((seq, json)) = accepted {
+                if seq >= req.sequence.next_seq {
+                    self.save_response_part(request_id, seq, json);
+                }
+            }
+            if delivered {
+                self.save_response_progress(request_id, req.sequence.next_seq);
             }
         }
     }
 
-    fn get_clients_json(&self) -> Result<Response> {
-        // Restore state if waking from hibernation
-        let _ = self.ensure_state_restored();
+    /// Record a finished request so late parts for it are reported, not silently dropped
+    ///
+    /// Also removes its persisted copy, as every finished request passes through here.
+    fn remember_completed(&self, request_id: &str) {
+        self.delete_pending_request(request_id);
+        let mut history = self.completed_requests.borrow_mut();
+        history.push(request_id.to_string());
+        if history.len() > COMPLETED_REQUESTS_HISTORY {
+            history.remove(0);
+        }
+    }
 
-        // Get clients with active WebSocket connections from memory
-        let active_client_ids: std::collections::HashSet<String> = self
-            .clients
-            .borrow()
+    /// Track a forwarded request, evicting the oldest ones (with an error to their
+    /// browser) so the map never exceeds MAX_PENDING_REQUESTS
+    fn track_pending_request(&self, request_id: String, request: PendingRequest) {
+        let max_pending = crate::config::var_u64(
+            &self.env,
+            "MAX_PENDING_REQUESTS",
+            DEFAULT_MAX_PENDING_REQUESTS,
+        )
+        .max(1) as usize;
+
+        let mut pending = self.pending_requests.borrow_mut();
+        let started_at = pending
+            .iter()
+            .map(|(id, req)| (id.as_str(), req.started_at));
+        for oldest_id in requests_to_evict(started_at, max_pending) {
+            if let Some(evicted) = pending.remove(&oldest_id) {
+                console_log!(
+                    "Warning: evicting pending request {} (limit of {} reached)",
+                    oldest_id,
+                    max_pending
+                );
+                let error = request_error(REQUEST_EVICTED);
+                Self::send_forwarded(&evicted, &oldest_id, error, true);
+                self.remember_completed(&oldest_id);
+            }
+        }
+
+        self.save_pending_request(&request_id, &request);
+        pending.insert(request_id, request);
+    }
+
+    fn forward_timeout_ms(&self) -> u64 {
+        crate::config::var_u64(&self.env, "FORWARD_TIMEOUT_MS", DEFAULT_FORWARD_TIMEOUT_MS)
+    }
+
+    /// Fail forwarded requests that have waited longer than FORWARD_TIMEOUT_MS (0 disables)
+    ///
+    /// The browser gets a final error response so it stops waiting.
+    fn expire_forwarded_requests(&self) {
+        let timeout_ms = self.forward_timeout_ms();
+        if timeout_ms == 0 {
+            return;
+        }
+
+        let now = crate::time::now_ms();
+        let expired: Vec<(String, PendingRequest)> = {
+            let mut pending = self.pending_requests.borrow_mut();
+            let started_at = pending
+                .iter()
+                .map(|(id, req)| (id.as_str(), req.started_at));
+            timed_out_requests(started_at, now, timeout_ms)
+                .into_iter()
+                .filter_map(|id| pending.remove(&id).map(|req| (id, req)))
+                .collect()
+        };
+
+        for (request_id, req) in expired {
+            console_log!("Forwarded request {} timed out", request_id);
+            let error = request_error("Client timed out");
+            Self::send_forwarded(&req, &request_id, error, true);
+            self.remember_completed(&request_id);
+        }
+    }
+
+    /// Send a forwarded response message to the browser awaiting `req`
+    fn send_forwarded(
+        req: &PendingRequest,
+        request_id: &str,
+        data: serde_json::Value,
+        complete: bool,
+    ) {
+        let response = WsMessage::ForwardedResponse {
+            client_id: req.client_id.clone(),
+            request_id: request_id.to_string(),
+            data,
+            complete,
+        };
+        if let Ok(json) = serde_json::to_string(&response) {
+            let _ = req.browser_ws.send_with_str(&json);
+        }
+    }
+
+    /// Check a registration request, returning the rejection reason if invalid
+    fn validate_registration(
+        &self,
+        client_id: &str,
+        metadata: &ClientMetadata,
+    ) -> std::result::Result<(), String> {
+        let limits = RegistrationLimits {
+            max_field_len: crate::config::var_u64(
+                &self.env,
+                "MAX_CLIENT_FIELD_LENGTH",
+                DEFAULT_MAX_CLIENT_FIELD_LENGTH,
+            ) as usize,
+            require_https_callback: crate::config::var_bool(
+                &self.env,
+                "REQUIRE_HTTPS_CALLBACK",
+                false,
+            ),
+            allow_localhost_http_callback: crate::config::var_bool(
+                &self.env,
+                "ALLOW_LOCALHOST_HTTP_CALLBACK",
+                false,
+            ),
+            max_clients: crate::config::var_u64(
+                &self.env,
+                "MAX_CLIENTS_PER_USER",
+                DEFAULT_MAX_CLIENTS_PER_USER,
+            ),
+        };
+
+        // Re-registration of an already-connected client doesn't count against the limit
+        let other_clients = self
+            .clients
+            .borrow()
             .keys()
-            .cloned()
-            .collect();
+            .filter(|id| id.as_str() != client_id)
+            .count() as u64;
+
+        check_registration(client_id, metadata, &limits, other_clients)
+    }
+
+    /// Record an inbound message and return whether the socket is within its rate limit
+    fn allow_message(&self, ws: &WebSocket, kind: RateKind) -> bool {
+        let window_ms =
+            crate::config::var_u64(&self.env, "WS_RATE_WINDOW_MS", DEFAULT_WS_RATE_WINDOW_MS);
+        let limit = match kind {
+            RateKind::Ping => {
+                crate::config::var_u64(&self.env, "WS_PING_RATE_LIMIT", DEFAULT_WS_PING_RATE_LIMIT)
+            }
+            RateKind::Message => crate::config::var_u64(
+                &self.env,
+                "WS_MESSAGE_RATE_LIMIT",
+                DEFAULT_WS_MESSAGE_RATE_LIMIT,
+            ),
+            RateKind::Forward => {
+                crate::config::var_u64(&self.env, "FORWARD_RATE_LIMIT", DEFAULT_FORWARD_RATE_LIMIT)
+            }
+        };
+
+        // A limit of 0 disables rate limiting for this bucket
+        if limit == 0 || window_ms == 0 {
+            return true;
+        }
+
+        let now = crate::time::now_ms();
+        let mut rates = self.message_rates.borrow_mut();
+        let index = match rates.iter().position(|r| &r.websocket == ws) {
+            Some(index) => index,
+            None => {
+                rates.push(MessageRate {
+                    websocket: ws.clone(),
+                    counts: MessageCounts {
+                        window_start_ms: now,
+                        ..MessageCounts::default()
+                    },
+                });
+                rates.len() - 1
+            }
+        };
+        rates[index].counts.record(kind, now, window_ms, limit)
+    }
+
+    /// Time since a client's last message after which it is marked disconnected (0 disables)
+    fn client_heartbeat_timeout_ms(&self) -> i64 {
+        crate::config::var_u64(
+            &self.env,
+            "CLIENT_HEARTBEAT_TIMEOUT_MS",
+            DEFAULT_CLIENT_HEARTBEAT_TIMEOUT_MS,
+        ) as i64
+    }
+
+    /// Mark connected clients that have gone silent as disconnected (0 disables)
+    ///
+    /// Catches clients whose socket never closed cleanly. The socket is left
+    /// open, so a client that pings again returns to idle; until then requests
+    /// aren't routed to it (see `routable_client_ws`).
+    fn mark_silent_clients(&self) {
+        let timeout_ms = self.client_heartbeat_timeout_ms();
+        if timeout_ms == 0 {
+            return;
+        }
+
+        let now = crate::time::now_ms() as i64;
+        let silent: Vec<Client> = {
+            let mut clients = self.clients.borrow_mut();
+            clients
+                .values_mut()
+                .filter(|conn| watches_heartbeat(conn.client.metadata.status))
+                .filter(|conn| {
+                    crate::time::epoch_ms_of(&conn.client.last_seen)
+                        .is_none_or(|seen| seen + timeout_ms <= now)
+                })
+                .map(|conn| {
+                    conn.client.update_status(ClientStatus::Disconnected);
+                    conn.client.clone()
+                })
+                .collect()
+        };
+
+        for client in silent {
+            console_log!("Client {} missed its heartbeat", client.id);
+            let _ = self.save_client(&client);
+            if let Ok(json) = serde_json::to_string(&WsMessage::ClientUpdate { client }) {
+                self.broadcast_to_browsers(&json);
+            }
+        }
+    }
+
+    /// Maximum client connection lifetime in milliseconds (0 means unlimited)
+    fn max_ws_lifetime_ms(&self) -> i64 {
+        crate::config::var_u64(&self.env, "MAX_WS_LIFETIME_HOURS", 0) as i64 * 3_600_000
+    }
+
+    /// How long a browser socket may stay silent before it is closed (0 disables reaping)
+    fn browser_idle_timeout_ms(&self) -> u64 {
+        crate::config::var_u64(
+            &self.env,
+            "BROWSER_IDLE_TIMEOUT_MS",
+            DEFAULT_BROWSER_IDLE_TIMEOUT_MS,
+        )
+    }
+
+    /// Drop browser sockets that are idle or can no longer be written to
+    ///
+    /// Tabs closed without a clean WebSocket close would otherwise stay in
+    /// `browsers` forever. Live dashboards answer each heartbeat, so they are
+    /// never idle for longer than the reaper interval.
+    async fn reap_browsers(&self) {
+        let timeout_ms = self.browser_idle_timeout_ms();
+        if timeout_ms == 0 {
+            return;
+        }
+
+        let now = crate::time::now_ms();
+        let heartbeat = match serde_json::to_string(&WsMessage::BrowserHeartbeat) {
+            Ok(json) => json,
+            Err(_) => return,
+        };
+        let (idle, unreachable): (Vec<WebSocket>, Vec<WebSocket>) = {
+            let browsers = self.browsers.borrow();
+            let idle = browsers
+                .iter()
+                .filter(|b| now.saturating_sub(b.last_seen) > timeout_ms)
+                .map(|b| b.websocket.clone())
+                .collect();
+            let unreachable = browsers
+                .iter()
+                .filter(|b| now.saturating_sub(b.last_seen) <= timeout_ms)
+                .filter(|b| b.websocket.send_with_str(&heartbeat).is_err())
+                .map(|b| b.websocket.clone())
+                .collect();
+            (idle, unreachable)
+        };
+
+        if !idle.is_empty() || !unreachable.is_empty() {
+            console_log!(
+                "Reaping browser sockets: {} idle, {} unreachable",
+                idle.len(),
+                unreachable.len()
+            );
+        }
+        for ws in idle {
+            let _ = ws.close(Some(close_codes::IDLE_TIMEOUT), Some("Idle timeout"));
+            self.handle_close(&ws).await;
+        }
+        for ws in unreachable {
+            self.handle_close(&ws).await;
+        }
+    }
+
+    /// Point the alarm at the next client lifetime deadline, heartbeat check, browser reap
+    /// or forward timeout
+    async fn schedule_alarm(&self) {
+        let lifetime_ms = self.max_ws_lifetime_ms();
+        let lifetime_deadline = if lifetime_ms > 0 {
+            self.clients
+                .borrow()
+                .values()
+                .filter_map(|conn| crate::time::epoch_ms_of(&conn.client.connected_at))
+                .map(|connected| connected + lifetime_ms)
+                .min()
+        } else {
+            None
+        };
+
+        let now = crate::time::now_ms() as i64;
+        // Reap at half the idle timeout so a live tab gets a heartbeat before it times out
+        let idle_timeout_ms = self.browser_idle_timeout_ms() as i64;
+        let reap_deadline = if idle_timeout_ms > 0 && !self.browsers.borrow().is_empty() {
+            Some(now + (idle_timeout_ms / 2).max(1_000))
+        } else {
+            None
+        };
+
+        let heartbeat_timeout_ms = self.client_heartbeat_timeout_ms();
+        let heartbeat_deadline = if heartbeat_timeout_ms > 0 {
+            self.clients
+                .borrow()
+                .values()
+                .filter(|conn| watches_heartbeat(conn.client.metadata.status))
+                .filter_map(|conn| crate::time::epoch_ms_of(&conn.client.last_seen))
+                .map(|seen| seen + heartbeat_timeout_ms)
+                .min()
+        } else {
+            None
+        };
+
+        let forward_timeout_ms = self.forward_timeout_ms() as i64;
+        let forward_deadline = if forward_timeout_ms > 0 {
+            self.pending_requests
+                .borrow()
+                .values()
+                .map(|req| req.started_at as i64 + forward_timeout_ms)
+                .min()
+        } else {
+            None
+        };
+
+        let deadline = lifetime_deadline
+            .into_iter()
+            .chain(reap_deadline)
+            .chain(heartbeat_deadline)
+            .chain(forward_deadline)
+            .min();
+        let deadline = match deadline {
+            Some(deadline) => deadline,
+            None => return,
+        };
+
+        let storage = self.state.storage();
+        // An earlier pending alarm will reschedule itself when it fires
+        if let Ok(Some(existing)) = storage.get_alarm().await {
+            if existing > now && existing <= deadline {
+                return;
+            }
+        }
+        if let Err(e) = storage.set_alarm((deadline - now).max(0)).await {
+            console_log!("Failed to schedule alarm: {:?}", e);
+        }
+    }
+
+    /// Tell a socket it exceeded its rate limit, close it, and clean up its state
+    async fn reject_rate_limited(&self, ws: &WebSocket) {
+        console_log!("Closing WebSocket for exceeding message rate limit");
+
+        let error = WsMessage::Error {
+            message: "Rate limit exceeded".to_string(),
+        };
+        if let Ok(json) = serde_json::to_string(&error) {
+            let _ = ws.send_with_str(&json);
+        }
+        let _ = ws.close(
+            Some(close_codes::POLICY_VIOLATION),
+            Some("Rate limit exceeded"),
+        );
+
+        self.handle_close(ws).await;
+    }
+
+    async fn handle_close(&self, ws: &WebSocket) {
+        // Remove from browsers list
+        self.browsers.borrow_mut().retain(|b| &b.websocket != ws);
+
+        // Drop rate-limit counters for this socket
+        self.message_rates.borrow_mut().retain(|r| &r.websocket != ws);
+
+        // Remove from clients and broadcast disconnection
+        let disconnected = {
+            let clients = self.clients.borrow();
+            clients
+                .iter()
+                .find(|(_, conn)| &conn.websocket == ws)
+                .map(|(id, conn)| (id.clone(), conn.client.clone()))
+        };
+
+        if let Some((client_id, client)) = disconnected {
+            // A client that dropped off (rather than being disconnected) may come back
+            if let Some(token_id) = self.socket_token_id(ws) {
+                self.save_resumable_client(&client, &token_id);
+            }
+            self.remove_client(&client_id, None).await;
+        }
+    }
+
+    /// Token a client socket authenticated with (its second tag), if recorded
+    fn socket_token_id(&self, ws: &WebSocket) -> Option<String> {
+        let tags = self.state.get_tags(ws);
+        match tags.as_slice() {
+            [kind, ..] if kind == "browser" || kind == WS_PROXY_TAG => None,
+            [_, token_id, ..] => Some(token_id.clone()),
+            _ => None,
+        }
+    }
+
+    /// How long a disconnected client stays resumable (CLIENT_RESUME_WINDOW_SECONDS, 0 disables)
+    fn client_resume_window_ms(&self) -> u64 {
+        crate::config::var_u64(
+            &self.env,
+            "CLIENT_RESUME_WINDOW_SECONDS",
+            DEFAULT_CLIENT_RESUME_WINDOW_SECONDS,
+        ) * 1000
+    }
+
+    /// Remember a disconnected client so its next connection can resume it
+    fn save_resumable_client(&self, client: &Client, token_id: &str) {
+        if self.client_resume_window_ms() == 0 {
+            return;
+        }
+        let json = match serde_json::to_string(client) {
+            Ok(json) => json,
+            Err(_) => return,
+        };
+        let result = self.ensure_initialized().and_then(|_| {
+            self.state.storage().sql().exec(
+                SAVE_RESUMABLE_CLIENT,
+                Some(vec![
+                    SqlStorageValue::String(client.id.clone()),
+                    SqlStorageValue::String(token_id.to_string()),
+                    SqlStorageValue::String(json),
+                    SqlStorageValue::Integer(crate::time::now_ms() as i64),
+                ]),
+            )
+        });
+        if let Err(e) = result {
+            console_log!("Failed to save resumable client {}: {:?}", client.id, e);
+        }
+    }
+
+    /// Remove and return a resumable client and the token it connected with
+    ///
+    /// Entries older than the resume window are dropped first.
+    fn take_resumable_client(&self, client_id: &str) -> Result<Option<(String, Client)>> {
+        #[derive(Deserialize)]
+        struct ResumableRow {
+            token_id: String,
+            client: String,
+        }
+
+        self.ensure_initialized()?;
+        let sql = self.state.storage().sql();
+        let cutoff = crate::time::now_ms().saturating_sub(self.client_resume_window_ms());
+        sql.exec(
+            PURGE_RESUMABLE_CLIENTS,
+            Some(vec![SqlStorageValue::Integer(cutoff as i64)]),
+        )?;
+
+        let rows: Vec<ResumableRow> = sql
+            .exec(
+                TAKE_RESUMABLE_CLIENT,
+                Some(vec![SqlStorageValue::String(client_id.to_string())]),
+            )?
+            .to_array()?;
+        Ok(rows.into_iter().next().and_then(|row| {
+            let client = serde_json::from_str(&row.client).ok()?;
+            Some((row.token_id, client))
+        }))
+    }
+
+    /// The earlier connection a `resume` registration on `ws` reattaches to
+    ///
+    /// Looks at a still-open connection with the same client ID first (a
+    /// restart can beat the old socket's close), then at recently
+    /// disconnected clients. Returns `Ok(None)` when there is nothing to
+    /// resume, so the client registers as new, and an error when the client
+    /// ID was registered with a different token. Clients past
+    /// MAX_WS_LIFETIME_HOURS start over rather than keep their old
+    /// `connected_at`.
+    fn resume_client(
+        &self,
+        ws: &WebSocket,
+        client_id: &str,
+    ) -> std::result::Result<Option<Client>, String> {
+        let token_id = match self.socket_token_id(ws) {
+            Some(token_id) => token_id,
+            None => return Ok(None),
+        };
+
+        let live = self
+            .clients
+            .borrow()
+            .get(client_id)
+            .filter(|conn| &conn.websocket != ws)
+            .map(|conn| (self.socket_token_id(&conn.websocket), conn.client.clone()));
+        let (previous_token, previous) = match live {
+            Some(found) => found,
+            None => match self.take_resumable_client(client_id) {
+                Ok(Some((token, client))) => (Some(token), client),
+                Ok(None) => return Ok(None),
+                Err(e) => {
+                    console_log!("Failed to look up resumable client: {:?}", e);
+                    return Ok(None);
+                }
+            },
+        };
+
+        let lifetime_ms = self.max_ws_lifetime_ms();
+        let now = crate::time::now_ms() as i64;
+        resumed_client(
+            &token_id,
+            previous_token.as_deref(),
+            previous,
+            lifetime_ms,
+            now,
+        )
+    }
+
+    /// Forget a client everywhere (memory, SQLite, D1) and tell browsers it left
+    async fn remove_client(
+        &self,
+        client_id: &str,
+        reason: Option<String>,
+    ) -> Option<ClientConnection> {
+        let connection = self.clients.borrow_mut().remove(client_id);
+        self.invalidate_stale_clients();
+
+        // End any responses the client was still streaming
+        self.proxy_streams
+            .borrow_mut()
+            .retain(|_, stream| stream.client_id != client_id);
+
+        // Close browser sockets tunnelled to it
+        for (_, browser_ws) in self.ws_proxy_tunnels(client_id) {
+            let _ = browser_ws.close(Some(close_codes::GOING_AWAY), Some("Client disconnected"));
+        }
+
+        // Remove from SQLite
+        let _ = self.delete_client(client_id);
+
+        // Remove from D1 (for public path routing)
+        if let Err(e) = self.unregister_client_from_d1(client_id).await {
+            console_log!("Failed to unregister client from D1: {:?}", e);
+        }
+
+        // Broadcast disconnection to browsers
+        if let Ok(msg) = serde_json::to_string(&WsMessage::ClientDisconnected {
+            client_id: client_id.to_string(),
+            reason,
+        }) {
+            self.broadcast_to_browsers(&msg);
+        }
+
+        connection
+    }
+
+    fn get_clients_json(&self) -> Result<Response> {
+        // Restore state if waking from hibernation
+        let _ = self.ensure_state_restored();
+
+        Response::from_json(&self.list_all_clients())
+    }
+
+    /// Connected clients from memory followed by stored clients that are no longer connected
+    fn list_all_clients(&self) -> Vec<Client> {
+        // Get clients with active WebSocket connections from memory
+        let active_client_ids: std::collections::HashSet<String> =
+            self.clients.borrow().keys().cloned().collect();
 
+        // Re-rate connection quality so missed pings show up without waiting for the next one
+        let now = crate::time::now_ms();
         let mut clients: Vec<Client> = self
             .clients
             .borrow()
             .values()
-            .map(|c| c.client.clone())
+            .map(|c| {
+                let mut client = c.client.clone();
+                client.connection_quality = self.connection_quality(&c.ping_times, now);
+                client
+            })
             .collect();
 
+        clients.extend(self.stale_clients(&active_client_ids));
+        clients
+    }
+
+    /// Rate a client's connection from its recent ping arrival times
+    ///
+    /// Uses the CONNECTION_QUALITY_MAX_GAP_MS and CONNECTION_QUALITY_MAX_JITTER_PCT
+    /// thresholds; see `rate_connection`.
+    fn connection_quality(&self, ping_times: &[u64], now: u64) -> Option<ConnectionQuality> {
+        let max_gap_ms = crate::config::var_u64(
+            &self.env,
+            "CONNECTION_QUALITY_MAX_GAP_MS",
+            DEFAULT_CONNECTION_QUALITY_MAX_GAP_MS,
+        );
+        let max_jitter_pct = crate::config::var_u64(
+            &self.env,
+            "CONNECTION_QUALITY_MAX_JITTER_PCT",
+            DEFAULT_CONNECTION_QUALITY_MAX_JITTER_PCT,
+        );
+        rate_connection(ping_times, now, max_gap_ms, max_jitter_pct)
+    }
+
+    /// Stored clients without a live WebSocket, marked as disconnected.
+    /// The SQLite scan is reused for STALE_CHECK_INTERVAL_MS unless membership changes.
+    fn stale_clients(&self, active_client_ids: &std::collections::HashSet<String>) -> Vec<Client> {
+        let interval_ms = crate::config::var_u64(
+            &self.env,
+            "STALE_CHECK_INTERVAL_MS",
+            DEFAULT_STALE_CHECK_INTERVAL_MS,
+        );
+        let now = crate::time::now_ms();
+        if let Some((checked_at, cached)) = self.stale_clients.borrow().as_ref() {
+            if now.saturating_sub(*checked_at) < interval_ms {
+                return cached.clone();
+            }
+        }
+
         // Check SQLite for any clients that might be stale
         // Mark them as disconnected if their WebSocket is not in memory
+        let mut stale = Vec::new();
         if let Ok(stored) = self.load_clients_from_sqlite() {
             for mut stored_client in stored {
                 if !active_client_ids.contains(&stored_client.id) {
@@ -884,140 +2897,473 @@ impl UserHub {
                         stored_client.update_status(ClientStatus::Disconnected);
                         let _ = self.save_client(&stored_client);
                     }
-                    clients.push(stored_client);
+                    stale.push(stored_client);
                 }
             }
         }
 
-        Response::from_json(&clients)
+        if interval_ms > 0 {
+            *self.stale_clients.borrow_mut() = Some((now, stale.clone()));
+        }
+        stale
+    }
+
+    /// Force the next client listing to rescan SQLite (call when clients connect or disconnect)
+    fn invalidate_stale_clients(&self) {
+        self.stale_clients.borrow_mut().take();
+    }
+
+    /// Broadcast a `ClientPatch` with the fields that differ between two snapshots
+    fn broadcast_client_patch(&self, previous: &Client, current: &Client) {
+        if let Some(patch) = client_patch(previous, current) {
+            if let Ok(json) = serde_json::to_string(&patch) {
+                self.broadcast_to_browsers(&json);
+            }
+        }
     }
 
     fn broadcast_to_browsers(&self, message: &str) {
         for browser in self.browsers.borrow().iter() {
-            let _ = browser.send_with_str(message);
+            let _ = browser.websocket.send_with_str(message);
         }
     }
 
-    /// Disconnect a specific client by ID
-    async fn disconnect_client(&self, client_id: &str) -> Result<Response> {
+    /// Ask a connected client to re-send its metadata; the reply arrives as a
+    /// MetadataUpdate and is broadcast to browsers like any other change
+    fn request_metadata_refresh(&self, client_id: &str) -> Result<Response> {
         // Restore state if needed
         let _ = self.ensure_state_restored();
 
-        // Find and remove the client
-        let connection = self.clients.borrow_mut().remove(client_id);
+        let websocket = {
+            let clients = self.clients.borrow();
+            clients.get(client_id).map(|conn| conn.websocket.clone())
+        };
+        let websocket = match websocket {
+            Some(ws) => ws,
+            None => return Response::error("Client not connected", 404),
+        };
+
+        let refresh = WsMessage::RefreshMetadata {
+            client_id: client_id.to_string(),
+        };
+        websocket.send_with_str(serde_json::to_string(&refresh)?)?;
+
+        Response::from_json(&serde_json::json!({ "requested": true }))
+    }
 
-        if let Some(conn) = connection {
+    /// Disconnect a specific client by ID, telling it not to reconnect
+    async fn disconnect_client(&self, client_id: &str, reason: &str) -> Result<Response> {
+        // Restore state if needed
+        let _ = self.ensure_state_restored();
+
+        if !self.clients.borrow().contains_key(client_id) {
+            return Response::error("Client not found", 404);
+        }
+
+        if let Some(conn) = self.remove_client(client_id, None).await {
             // Send disconnect command to the client
-            let disconnect_msg = WsMessage::Error {
-                message: "Disconnected by user".to_string(),
+            let disconnect_msg = WsMessage::Disconnect {
+                reason: reason.to_string(),
+                reconnect: false,
             };
             if let Ok(json) = serde_json::to_string(&disconnect_msg) {
                 let _ = conn.websocket.send_with_str(&json);
             }
             // Close the WebSocket
-            let _ = conn.websocket.close(Some(1000), Some("Disconnected by user"));
+            let _ = conn
+                .websocket
+                .close(Some(close_codes::NORMAL), Some(reason));
+        }
 
-            // Delete from SQLite
-            let _ = self.delete_client(client_id);
+        Response::ok("Client disconnected")
+    }
 
-            // Delete from D1 (for public path routing)
-            if let Err(e) = self.unregister_client_from_d1(client_id).await {
-                console_log!("Failed to unregister client from D1: {:?}", e);
+    /// Whether a registering client must wait for approval (REQUIRE_CLIENT_APPROVAL
+    /// is on and this client ID has not been approved with this token before)
+    fn requires_approval(&self, client_id: &str, token_id: Option<&str>) -> bool {
+        let approval_mode = crate::config::var_bool(&self.env, "REQUIRE_CLIENT_APPROVAL", false);
+        needs_approval(approval_mode, token_id, |token_id| {
+            if self.ensure_initialized().is_err() {
+                return false;
             }
+            self.state
+                .storage()
+                .sql()
+                .exec(
+                    CLIENT_TOKEN_APPROVAL,
+                    Some(vec![
+                        SqlStorageValue::String(client_id.to_string()),
+                        SqlStorageValue::String(token_id.to_string()),
+                    ]),
+                )
+                .and_then(|cursor| cursor.to_array::<serde_json::Value>())
+                .map(|rows| !rows.is_empty())
+                .unwrap_or(false)
+        })
+    }
 
-            // Broadcast disconnection to browsers
-            if let Ok(msg) =
-                serde_json::to_string(&WsMessage::ClientDisconnected {
-                    client_id: client_id.to_string(),
-                })
-            {
-                self.broadcast_to_browsers(&msg);
-            }
+    /// Control socket of a client that can take requests
+    ///
+    /// Clients `mark_silent_clients` flagged as disconnected keep their socket
+    /// (a ping revives them), but nothing is routed to them meanwhile.
+    fn routable_client_ws(&self, client_id: &str) -> Option<WebSocket> {
+        self.clients
+            .borrow()
+            .get(client_id)
+            .filter(|conn| conn.client.metadata.status != ClientStatus::Disconnected)
+            .map(|conn| conn.websocket.clone())
+    }
 
-            Response::ok("Client disconnected")
-        } else {
-            Response::error("Client not found", 404)
-        }
+    /// Whether a connected client is still waiting for approval
+    fn is_pending_approval(&self, client_id: &str) -> bool {
+        self.clients
+            .borrow()
+            .get(client_id)
+            .is_some_and(|conn| conn.client.metadata.status == ClientStatus::PendingApproval)
     }
 
-    /// Handle HTTP proxy requests to claudecodeui instances via WebSocket
-    async fn handle_proxy(&self, mut req: Request, client_id: &str) -> Result<Response> {
-        // Restore state if waking from hibernation
+    /// Approve a pending client, making it usable now and on future reconnects
+    fn approve_client(&self, client_id: &str) -> Result<Response> {
+        // Restore state if needed
         let _ = self.ensure_state_restored();
+        self.ensure_initialized()?;
 
-        // Parse the proxy request from the body
-        let body_text = req.text().await?;
-        let proxy_req: ProxyRequest = serde_json::from_str(&body_text)
-            .map_err(|e| Error::RustError(format!("Invalid proxy request: {}", e)))?;
+        let updated = {
+            let mut clients = self.clients.borrow_mut();
+            clients
+                .get_mut(client_id)
+                .filter(|conn| conn.client.metadata.status == ClientStatus::PendingApproval)
+                .map(|conn| {
+                    let previous = conn.client.clone();
+                    conn.client.update_status(ClientStatus::Idle);
+                    (previous, conn.client.clone(), conn.websocket.clone())
+                })
+        };
 
-        // Find the client's WebSocket connection
-        let client_ws = {
-            let clients = self.clients.borrow();
-            clients.get(client_id).map(|conn| conn.websocket.clone())
+        let (previous, client, websocket) = match updated {
+            Some(updated) => updated,
+            None => return Response::error("No pending client with that ID", 404),
         };
 
-        let client_ws = match client_ws {
-            Some(ws) => ws,
-            None => {
-                return Response::from_json(&ProxyResponse {
-                    status: 503,
-                    headers: vec![("Content-Type".to_string(), "application/json".to_string())],
-                    body: r#"{"error": "Client not connected"}"#.to_string(),
-                });
-            }
+        // Remember the approval for this client ID and token only; a socket
+        // without a token tag is approved for this connection alone
+        if let Some(token_id) = self.socket_token_id(&websocket) {
+            let sql = self.state.storage().sql();
+            sql.exec(
+                APPROVE_CLIENT_TOKEN,
+                Some(vec![
+                    SqlStorageValue::String(client_id.to_string()),
+                    SqlStorageValue::String(token_id),
+                    SqlStorageValue::String(crate::time::now_iso()),
+                ]),
+            )?;
+        }
+        let _ = self.save_client(&client);
+
+        // Let the client know it can start serving requests
+        let registered = WsMessage::Registered {
+            success: true,
+            message: Some("Approved".to_string()),
         };
+        if let Ok(json) = serde_json::to_string(&registered) {
+            let _ = websocket.send_with_str(&json);
+        }
 
-        // Generate a unique request ID
-        let request_id = generate_request_id();
+        self.broadcast_client_patch(&previous, &client);
+        Response::ok("Client approved")
+    }
 
-        // Create oneshot channel for response
-        let (sender, receiver) = oneshot::channel::<ProxyResponse>();
+    /// Reject a pending client by disconnecting it
+    async fn reject_client(&self, client_id: &str) -> Result<Response> {
+        // Restore state if needed
+        let _ = self.ensure_state_restored();
 
-        // Store the sender in pending_proxy_requests
-        {
-            let mut pending = self.pending_proxy_requests.borrow_mut();
-            pending.insert(request_id.clone(), sender);
+        if !self.is_pending_approval(client_id) {
+            return Response::error("No pending client with that ID", 404);
         }
 
-        // Build and send the HttpProxyRequest message
-        // Include proxy_base so claudecodeui can rewrite URLs in responses
-        let proxy_base = format!("/clients/{}/proxy", client_id);
-        let proxy_msg = WsMessage::HttpProxyRequest {
-            request_id: request_id.clone(),
-            method: proxy_req.method,
-            path: proxy_req.path,
-            headers: proxy_req.headers,
-            body: proxy_req.body,
-            query: proxy_req.query,
-            proxy_base: Some(proxy_base),
+        self.disconnect_client(client_id, "Rejected by user").await
+    }
+
+    /// Check whether a registration would be accepted without storing anything
+    async fn dry_register(&self, mut req: Request) -> Result<Response> {
+        // Restore state if needed
+        let _ = self.ensure_state_restored();
+
+        let registration: DryRegistration = match req.json().await {
+            Ok(registration) => registration,
+            Err(_) => return Response::error("Invalid registration", 400),
         };
 
-        if let Ok(msg_json) = serde_json::to_string(&proxy_msg) {
-            if client_ws.send_with_str(&msg_json).is_err() {
-                // Remove from pending and return error
-                self.pending_proxy_requests.borrow_mut().remove(&request_id);
-                return Response::from_json(&ProxyResponse {
-                    status: 502,
-                    headers: vec![("Content-Type".to_string(), "application/json".to_string())],
-                    body: r#"{"error": "Failed to send request to client"}"#.to_string(),
+        let reason = self
+            .validate_registration(&registration.client_id, &registration.metadata)
+            .err();
+
+        // Remaining slots, not counting a reconnect of this client (absent when unlimited)
+        let max_clients = crate::config::var_u64(
+            &self.env,
+            "MAX_CLIENTS_PER_USER",
+            DEFAULT_MAX_CLIENTS_PER_USER,
+        );
+        let client_limit_remaining = (max_clients > 0).then(|| {
+            let clients = self.clients.borrow();
+            let connected = clients
+                .keys()
+                .filter(|id| id.as_str() != registration.client_id)
+                .count() as u64;
+            max_clients.saturating_sub(connected)
+        });
+
+        Response::from_json(&serde_json::json!({
+            "would_register": reason.is_none(),
+            "reason": reason,
+            "client_limit_remaining": client_limit_remaining,
+        }))
+    }
+
+    /// Disconnect every connected client
+    async fn disconnect_all_clients(&self) -> Result<Response> {
+        // Restore state if needed
+        let _ = self.ensure_state_restored();
+
+        let client_ids: Vec<String> = self.clients.borrow().keys().cloned().collect();
+        let mut disconnected = 0;
+        for client_id in client_ids {
+            let response = self
+                .disconnect_client(&client_id, "Disconnected by user")
+                .await?;
+            if response.status_code() == 200 {
+                disconnected += 1;
+            }
+        }
+
+        Response::from_json(&serde_json::json!({ "disconnected": disconnected }))
+    }
+
+    /// Disconnect every client that registered with `token_id`, e.g. once it is revoked
+    ///
+    /// Disconnected clients of that token are also made non-resumable, and
+    /// approvals granted to it are dropped.
+    async fn disconnect_token_clients(&self, token_id: &str) -> Result<Response> {
+        // Restore state if needed
+        let _ = self.ensure_state_restored();
+        self.ensure_initialized()?;
+
+        #[derive(Deserialize)]
+        struct ClientIdRow {
+            client_id: String,
+        }
+
+        let sql = self.state.storage().sql();
+        let rows: Vec<ClientIdRow> = sql
+            .exec(
+                "SELECT client_id FROM clients WHERE token_id = ?",
+                Some(vec![SqlStorageValue::String(token_id.to_string())]),
+            )?
+            .to_array()?;
+        sql.exec(
+            FORGET_TOKEN_RESUMABLE_CLIENTS,
+            Some(vec![SqlStorageValue::String(token_id.to_string())]),
+        )?;
+        sql.exec(
+            FORGET_TOKEN_APPROVALS,
+            Some(vec![SqlStorageValue::String(token_id.to_string())]),
+        )?;
+
+        let mut disconnected = 0;
+        for row in rows {
+            if !self.clients.borrow().contains_key(&row.client_id) {
+                continue;
+            }
+            let response = self
+                .disconnect_client(&row.client_id, "Token revoked")
+                .await?;
+            if response.status_code() == 200 {
+                disconnected += 1;
+            }
+        }
+
+        Response::from_json(&serde_json::json!({ "disconnected": disconnected }))
+    }
+
+    /// Probe a client's callback URL to check the orchestrator can reach it
+    async fn ping_client_proxy(&self, client_id: &str) -> Result<Response> {
+        // Restore state if needed
+        let _ = self.ensure_state_restored();
+
+        let client = {
+            let clients = self.clients.borrow();
+            clients.get(client_id).map(|conn| conn.client.clone())
+        };
+        let client = match client {
+            Some(c) => Some(c),
+            None => self
+                .load_clients_from_sqlite()
+                .ok()
+                .and_then(|clients| clients.into_iter().find(|c| c.id == client_id)),
+        };
+
+        let client = match client {
+            Some(c) => c,
+            None => return Response::error("Client not found", 404),
+        };
+
+        let callback_url = match client.metadata.callback_url {
+            Some(url) => url,
+            None => {
+                return Response::from_json(&ProxyHealth {
+                    reachable: false,
+                    status: None,
+                    latency_ms: None,
+                    error: Some("No callback URL configured".to_string()),
+                });
+            }
+        };
+
+        let health_url = format!("{}/health", callback_url.trim_end_matches('/'));
+        let timeout_ms = crate::config::var_u64(
+            &self.env,
+            "PROXY_HEALTH_TIMEOUT_MS",
+            DEFAULT_PROXY_HEALTH_TIMEOUT_MS,
+        );
+
+        let mut init = RequestInit::new();
+        init.with_method(Method::Get);
+        let request = match Request::new_with_init(&health_url, &init) {
+            Ok(r) => r,
+            Err(e) => {
+                return Response::from_json(&ProxyHealth {
+                    reachable: false,
+                    status: None,
+                    latency_ms: None,
+                    error: Some(format!("Invalid callback URL: {}", e)),
                 });
             }
+        };
+
+        use futures::future::{select, Either};
+        use std::pin::pin;
+
+        let started = crate::time::now_ms();
+        let fetch = Fetch::Request(request);
+        let fetch_future = pin!(fetch.send());
+        let timeout_future = pin!(sleep_ms(timeout_ms));
+
+        let health = match select(fetch_future, timeout_future).await {
+            Either::Left((Ok(resp), _)) => ProxyHealth {
+                reachable: true,
+                status: Some(resp.status_code()),
+                latency_ms: Some(crate::time::now_ms().saturating_sub(started)),
+                error: None,
+            },
+            Either::Left((Err(e), _)) => ProxyHealth {
+                reachable: false,
+                status: None,
+                latency_ms: None,
+                error: Some(format!("{}", e)),
+            },
+            Either::Right((_, _)) => ProxyHealth {
+                reachable: false,
+                status: None,
+                latency_ms: None,
+                error: Some(format!("Timed out after {}ms", timeout_ms)),
+            },
+        };
+
+        Response::from_json(&health)
+    }
+
+    /// Handle HTTP proxy requests to claudecodeui instances via WebSocket
+    async fn handle_proxy(&self, mut req: Request, client_id: &str) -> Result<Response> {
+        // Restore state if waking from hibernation
+        let _ = self.ensure_state_restored();
+
+        // Parse the proxy request from the body
+        let body_text = req.text().await?;
+        let mut proxy_req: ProxyRequest = match serde_json::from_str(&body_text) {
+            Ok(proxy_req) => proxy_req,
+            Err(e) => {
+                console_error!("Invalid proxy request: {}", e);
+                return proxy_error(400, "Invalid proxy request");
+            }
+        };
+        let _ = self.incr_counter("proxy_requests", 1);
+
+        drop_empty_query(&mut proxy_req);
+        let max_url_length = crate::config::var_u64(
+            &self.env,
+            "PROXY_MAX_URL_LENGTH",
+            DEFAULT_PROXY_MAX_URL_LENGTH,
+        );
+        if proxy_target_too_long(&proxy_req, max_url_length) {
+            return proxy_error(414, "Request URL too long");
         }
 
-        // Wait for response with timeout
-        // Note: In WASM, we use wasm_bindgen_futures and a JavaScript timeout
-        let timeout_ms = 30000; // 30 seconds
-        let timeout_future = async {
-            let promise = js_sys::Promise::new(&mut |resolve, _| {
-                let window = js_sys::global();
-                let _ = js_sys::Reflect::get(&window, &JsValue::from_str("setTimeout"))
-                    .and_then(|set_timeout| {
-                        let set_timeout_fn: js_sys::Function = set_timeout.into();
-                        set_timeout_fn.call2(&JsValue::NULL, &resolve, &JsValue::from(timeout_ms))
-                    });
-            });
-            wasm_bindgen_futures::JsFuture::from(promise).await
+        // Find the client's WebSocket connection
+        let client_ws = match self.routable_client_ws(client_id) {
+            Some(ws) => ws,
+            None => return proxy_error(503, "Client not connected"),
         };
+        if self.is_pending_approval(client_id) {
+            return proxy_error(403, "Client is awaiting approval");
+        }
+
+        // Serve cacheable static assets from R2 when the client opted in. Only
+        // after the checks above, so the cache never outlives access to the client
+        let cache_key = self.asset_cache_key(client_id, &proxy_req);
+        if let Some(key) = &cache_key {
+            if let Some(cached) = self.read_cached_asset(key).await {
+                return Response::from_json(&cached);
+            }
+        }
+
+        // Generate a unique request ID
+        let request_id = match generate_request_id() {
+            Ok(id) => id,
+            Err(e) => {
+                console_error!("Failed to generate proxy request ID: {:?}", e);
+                return proxy_error(500, "Failed to generate request ID");
+            }
+        };
+
+        // Create oneshot channel for response
+        let (sender, receiver) = oneshot::channel::<ProxyReply>();
+
+        // Store the sender in pending_proxy_requests
+        {
+            let mut pending = self.pending_proxy_requests.borrow_mut();
+            pending.insert(request_id.clone(), sender);
+        }
+
+        // Build and send the HttpProxyRequest message
+        // Include proxy_base so claudecodeui can rewrite URLs in responses
+        let proxy_base =
+            crate::config::app_path(&self.env, &format!("/clients/{}/proxy", client_id));
+        let stream = wants_stream(&proxy_req);
+        let proxy_msg = WsMessage::HttpProxyRequest {
+            request_id: request_id.clone(),
+            method: proxy_req.method,
+            path: proxy_req.path,
+            headers: proxy_req.headers,
+            body: proxy_req.body,
+            body_is_base64: proxy_req.body_is_base64,
+            query: proxy_req.query,
+            proxy_base: Some(proxy_base),
+            stream: stream.then_some(true),
+        };
+
+        if let Ok(msg_json) = serde_json::to_string(&proxy_msg) {
+            if client_ws.send_with_str(&msg_json).is_err() {
+                // Remove from pending and return error
+                self.pending_proxy_requests.borrow_mut().remove(&request_id);
+                return proxy_error(502, "Failed to send request to client");
+            }
+        }
+
+        // Wait for the response (or its first streamed chunk) up to PROXY_TIMEOUT_MS
+        let timeout_ms =
+            crate::config::var_u64(&self.env, "PROXY_TIMEOUT_MS", DEFAULT_PROXY_TIMEOUT_MS);
+        let timeout_future = sleep_ms(timeout_ms);
 
         // Use futures::select to race between response and timeout
         use futures::future::{select, Either};
@@ -1029,48 +3375,1449 @@ impl UserHub {
         let result = select(receiver_future, timeout_future).await;
 
         match result {
-            Either::Left((Ok(proxy_response), _)) => {
+            Either::Left((
+                Ok(ProxyReply::Streamed {
+                    status,
+                    headers,
+                    body,
+                }),
+                _,
+            )) => {
+                // Pass the body through as it arrives; streamed responses are never cached
+                let resp_headers = Headers::new();
+                for (key, value) in &headers {
+                    let _ = resp_headers.append(key, value);
+                }
+                resp_headers.set(PROXY_STREAM_HEADER, "1")?;
+                Ok(Response::from_stream(body.map(Ok::<Vec<u8>, Error>))?
+                    .with_status(status)
+                    .with_headers(resp_headers))
+            }
+            Either::Left((Ok(ProxyReply::Buffered(proxy_response)), _)) => {
                 // Got response from client
+                if let Some(key) = &cache_key {
+                    if proxy_response.status == 200 {
+                        self.store_cached_asset(key, &proxy_response).await;
+                    }
+                }
                 Response::from_json(&proxy_response)
             }
             Either::Left((Err(_), _)) => {
                 // Channel was dropped (client disconnected?)
                 self.pending_proxy_requests.borrow_mut().remove(&request_id);
-                Response::from_json(&ProxyResponse {
-                    status: 502,
-                    headers: vec![("Content-Type".to_string(), "application/json".to_string())],
-                    body: r#"{"error": "Client disconnected before responding"}"#.to_string(),
-                })
+                proxy_error(502, "Client disconnected before responding")
             }
             Either::Right((_, _)) => {
                 // Timeout
                 self.pending_proxy_requests.borrow_mut().remove(&request_id);
-                Response::from_json(&ProxyResponse {
-                    status: 504,
-                    headers: vec![("Content-Type".to_string(), "application/json".to_string())],
-                    body: r#"{"error": "Request timed out"}"#.to_string(),
+                proxy_error(504, "Request timed out")
+            }
+        }
+    }
+
+    /// Accept a browser WebSocket and tunnel it to a client over its control socket
+    ///
+    /// The browser socket is tagged `["ws-proxy", tunnel_id, client_id]`, so
+    /// tunnels survive hibernation without any stored state. Frames are relayed
+    /// as they arrive rather than queued: Workers sockets don't expose how much
+    /// is still buffered, so memory is bounded by MAX_WS_PROXY_FRAME_BYTES per
+    /// frame and MAX_WS_PROXY_TUNNELS per client, and a tunnel whose frames
+    /// can't be delivered is closed.
+    ///
+    /// When the browser requests subprotocols, the upgrade waits (up to
+    /// PROXY_TIMEOUT_MS) for the client's `ws_proxy_accept` so the upstream's
+    /// choice can be returned in `Sec-WebSocket-Protocol`.
+    async fn handle_ws_proxy(&self, req: Request, client_id: &str) -> Result<Response> {
+        let upgrade = req.headers().get("Upgrade")?;
+        if !upgrade.is_some_and(|u| u.eq_ignore_ascii_case("websocket")) {
+            return Response::error("Expected websocket", 426);
+        }
+
+        // Restore state if waking from hibernation
+        let _ = self.ensure_state_restored();
+
+        let client_ws = match self.routable_client_ws(client_id) {
+            Some(ws) => ws,
+            None => return Response::error("Client not connected", 503),
+        };
+        if self.is_pending_approval(client_id) {
+            return Response::error("Client is awaiting approval", 403);
+        }
+
+        let max_tunnels = crate::config::var_u64(
+            &self.env,
+            "MAX_WS_PROXY_TUNNELS",
+            DEFAULT_MAX_WS_PROXY_TUNNELS,
+        );
+        if max_tunnels > 0 && self.ws_proxy_tunnels(client_id).len() as u64 >= max_tunnels {
+            return Response::error("Too many open tunnels for this client", 429);
+        }
+
+        let params = crate::query::Query::from_request(&req)?;
+        let protocols =
+            requested_protocols(req.headers().get("Sec-WebSocket-Protocol")?.as_deref());
+        let tunnel_id = generate_request_id()?;
+        let open = WsMessage::WsProxyOpen {
+            tunnel_id: tunnel_id.clone(),
+            path: params.get_str("path").unwrap_or("/").to_string(),
+            query: params
+                .get_str("query")
+                .filter(|q| !q.is_empty())
+                .map(|q| q.to_string()),
+            headers: req
+                .headers()
+                .into_iter()
+                .filter(|(key, _)| {
+                    !key.eq_ignore_ascii_case("upgrade")
+                        && !key.eq_ignore_ascii_case("sec-websocket-protocol")
                 })
+                .collect(),
+            proxy_base: crate::config::app_path(
+                &self.env,
+                &format!("/clients/{}/ws-proxy", client_id),
+            ),
+            protocols: protocols.clone(),
+        };
+
+        // Accept before announcing, so frames the upstream sends as soon as it
+        // opens aren't dropped while the upgrade waits for `ws_proxy_accept`
+        let pair = WebSocketPair::new()?;
+        self.state
+            .accept_websocket_with_tags(&pair.server, &[WS_PROXY_TAG, &tunnel_id, client_id]);
+        let accepted = if protocols.is_empty() {
+            None
+        } else {
+            let (sender, receiver) = oneshot::channel::<Option<String>>();
+            self.pending_ws_proxy_opens
+                .borrow_mut()
+                .insert(tunnel_id.clone(), sender);
+            Some(receiver)
+        };
+
+        let fail = |status: u16, message: &str| {
+            self.pending_ws_proxy_opens.borrow_mut().remove(&tunnel_id);
+            let _ = pair
+                .server
+                .close(Some(close_codes::INTERNAL_ERROR), Some(message));
+            Response::error(message, status)
+        };
+
+        let json = serde_json::to_string(&open)?;
+        if client_ws.send_with_str(&json).is_err() {
+            return fail(502, "Failed to reach client");
+        }
+
+        let protocol = match accepted {
+            None => None,
+            Some(receiver) => {
+                use futures::future::{select, Either};
+                use std::pin::pin;
+
+                let timeout_ms =
+                    crate::config::var_u64(&self.env, "PROXY_TIMEOUT_MS", DEFAULT_PROXY_TIMEOUT_MS);
+                match select(pin!(receiver), pin!(sleep_ms(timeout_ms))).await {
+                    Either::Left((Ok(chosen), _)) => negotiated_protocol(&protocols, chosen),
+                    Either::Left((Err(_), _)) => {
+                        return fail(502, "Client failed to open the WebSocket")
+                    }
+                    Either::Right(_) => {
+                        return fail(504, "Client did not open the WebSocket in time")
+                    }
+                }
+            }
+        };
+
+        let mut response = Response::from_websocket(pair.client)?;
+        if let Some(protocol) = protocol {
+            response
+                .headers_mut()
+                .set("Sec-WebSocket-Protocol", &protocol)?;
+        }
+        Ok(response)
+    }
+
+    /// Tunnel ID and client ID of a tunnelled browser socket
+    fn ws_proxy_tunnel(&self, ws: &WebSocket) -> Option<(String, String)> {
+        match self.state.get_tags(ws).as_slice() {
+            [kind, tunnel_id, client_id] if kind == WS_PROXY_TAG => {
+                Some((tunnel_id.clone(), client_id.clone()))
             }
+            _ => None,
+        }
+    }
+
+    /// Open tunnels of a client as (tunnel ID, browser socket)
+    fn ws_proxy_tunnels(&self, client_id: &str) -> Vec<(String, WebSocket)> {
+        self.state
+            .get_websockets_with_tag(WS_PROXY_TAG)
+            .into_iter()
+            .filter_map(|ws| match self.ws_proxy_tunnel(&ws) {
+                Some((tunnel_id, owner)) if owner == client_id => Some((tunnel_id, ws)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Browser socket (and owning client ID) of a tunnel, if it belongs to the client on `ws`
+    fn owned_ws_proxy_tunnel(
+        &self,
+        ws: &WebSocket,
+        tunnel_id: &str,
+    ) -> Option<(WebSocket, String)> {
+        let client_id = {
+            let clients = self.clients.borrow();
+            clients
+                .iter()
+                .find(|(_, conn)| &conn.websocket == ws)
+                .map(|(id, _)| id.clone())
+        }?;
+        self.ws_proxy_tunnels(&client_id)
+            .into_iter()
+            .find(|(id, _)| id == tunnel_id)
+            .map(|(_, browser_ws)| (browser_ws, client_id))
+    }
+
+    /// Relay a frame from a tunnelled browser socket to its client
+    fn relay_to_client(
+        &self,
+        ws: &WebSocket,
+        tunnel_id: &str,
+        client_id: &str,
+        message: WebSocketIncomingMessage,
+    ) {
+        let (data, data_is_base64) = match message {
+            WebSocketIncomingMessage::String(text) => (text, false),
+            WebSocketIncomingMessage::Binary(bytes) => (crate::body::encode_binary(&bytes), true),
+        };
+        if data.len() > MAX_WS_PROXY_FRAME_BYTES {
+            self.abort_ws_proxy(
+                ws,
+                tunnel_id,
+                client_id,
+                close_codes::MESSAGE_TOO_BIG,
+                "Frame too large",
+            );
+            return;
+        }
+
+        let client_ws = self.routable_client_ws(client_id);
+        let frame = WsMessage::WsProxyFrame {
+            tunnel_id: tunnel_id.to_string(),
+            data,
+            data_is_base64,
+        };
+        let sent = client_ws.is_some_and(|client_ws| {
+            serde_json::to_string(&frame).is_ok_and(|json| client_ws.send_with_str(&json).is_ok())
+        });
+        if !sent {
+            self.abort_ws_proxy(
+                ws,
+                tunnel_id,
+                client_id,
+                close_codes::INTERNAL_ERROR,
+                "Failed to deliver frame",
+            );
         }
     }
 
+    /// Tell a client one of its tunnels closed
+    fn send_ws_proxy_close(
+        &self,
+        tunnel_id: &str,
+        client_id: &str,
+        code: Option<u16>,
+        reason: Option<String>,
+    ) {
+        let client_ws = {
+            let clients = self.clients.borrow();
+            clients.get(client_id).map(|conn| conn.websocket.clone())
+        };
+        let close = WsMessage::WsProxyClose {
+            tunnel_id: tunnel_id.to_string(),
+            code,
+            reason,
+        };
+        if let (Some(client_ws), Ok(json)) = (client_ws, serde_json::to_string(&close)) {
+            let _ = client_ws.send_with_str(&json);
+        }
+    }
+
+    /// Close a tunnel from the orchestrator's side, telling both ends
+    fn abort_ws_proxy(
+        &self,
+        browser_ws: &WebSocket,
+        tunnel_id: &str,
+        client_id: &str,
+        code: u16,
+        reason: &str,
+    ) {
+        let _ = browser_ws.close(Some(code), Some(reason));
+        self.send_ws_proxy_close(tunnel_id, client_id, Some(code), Some(reason.to_string()));
+    }
+
+    /// R2 key prefix for a client's cached proxy assets
+    fn asset_cache_prefix(&self, client_id: &str) -> String {
+        format!("asset-cache/{}/{}/", self.state.id(), client_id)
+    }
+
+    /// R2 key for a proxied request, if the client opted in and the request is a
+    /// plain GET for a file with one of the ASSET_CACHE_EXTENSIONS
+    fn asset_cache_key(&self, client_id: &str, proxy_req: &ProxyRequest) -> Option<String> {
+        let enabled = self
+            .clients
+            .borrow()
+            .get(client_id)
+            .is_some_and(|conn| conn.client.metadata.asset_cache);
+        if !enabled {
+            return None;
+        }
+
+        let extensions = crate::config::var_string(&self.env, "ASSET_CACHE_EXTENSIONS")
+            .unwrap_or_else(|| DEFAULT_ASSET_CACHE_EXTENSIONS.to_string());
+        let path = cacheable_asset_path(proxy_req, &extensions)?;
+        Some(format!("{}{}", self.asset_cache_prefix(client_id), path))
+    }
+
+    /// Load a cached asset from R2 as a proxy response
+    async fn read_cached_asset(&self, key: &str) -> Option<ProxyResponse> {
+        let bucket = self.env.bucket("ASSETS").ok()?;
+        let object = bucket.get(key).execute().await.ok()??;
+        let content_type = object
+            .http_metadata()
+            .content_type
+            .unwrap_or_else(|| "application/octet-stream".to_string());
+        let bytes = object.body()?.bytes().await.ok()?;
+        Some(cached_asset_response(content_type, bytes))
+    }
+
+    /// Store a successful proxied asset in R2 for later requests
+    async fn store_cached_asset(&self, key: &str, response: &ProxyResponse) {
+        let content_type = response
+            .headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("content-type"))
+            .map(|(_, v)| v.clone());
+        let bytes = match crate::body::decode(&response.body, response.body_is_base64) {
+            Some(bytes) => bytes,
+            None => return,
+        };
+
+        let result = match self.env.bucket("ASSETS") {
+            Ok(bucket) => bucket
+                .put(key, bytes)
+                .http_metadata(HttpMetadata {
+                    content_type,
+                    ..Default::default()
+                })
+                .execute()
+                .await
+                .map(|_| ()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            console_log!("Failed to cache asset {}: {:?}", key, e);
+        }
+    }
+
+    /// Delete every cached asset for a client (used by the explicit cache purge)
+    async fn purge_cached_assets(&self, client_id: &str) -> Result<Response> {
+        let bucket = self.env.bucket("ASSETS")?;
+        let prefix = self.asset_cache_prefix(client_id);
+        let mut deleted = 0;
+        let mut cursor: Option<String> = None;
+
+        loop {
+            let mut list = bucket.list().prefix(prefix.clone());
+            if let Some(cursor) = cursor.take() {
+                list = list.cursor(cursor);
+            }
+            let objects = list.execute().await?;
+
+            let keys: Vec<String> = objects.objects().iter().map(|o| o.key()).collect();
+            if !keys.is_empty() {
+                deleted += keys.len();
+                bucket.delete_multiple(keys).await?;
+            }
+
+            cursor = objects.cursor();
+            if !objects.truncated() || cursor.is_none() {
+                break;
+            }
+        }
+
+        Response::from_json(&serde_json::json!({ "deleted": deleted }))
+    }
+
     /// Handle HttpProxyResponse from claudecodeui
-    fn handle_http_proxy_response(&self, request_id: &str, status: u16, headers: Vec<(String, String)>, body: String) {
+    fn handle_http_proxy_response(&self, request_id: &str, response: ProxyResponse) {
         let mut pending = self.pending_proxy_requests.borrow_mut();
         if let Some(sender) = pending.remove(request_id) {
-            let response = ProxyResponse {
-                status,
-                headers,
-                body,
+            let _ = sender.send(ProxyReply::Buffered(response));
+        }
+    }
+
+    /// Handle HttpProxyResponseStart from claudecodeui
+    ///
+    /// The body channel stays open until `http_proxy_response_end` arrives or
+    /// the client disconnects. Starts from a socket other than the connected
+    /// client's, or for unknown requests, are ignored.
+    fn start_proxy_stream(
+        &self,
+        ws: &WebSocket,
+        request_id: &str,
+        status: u16,
+        headers: Vec<(String, String)>,
+    ) {
+        let client_id = {
+            let clients = self.clients.borrow();
+            clients
+                .iter()
+                .find(|(_, conn)| &conn.websocket == ws)
+                .map(|(id, _)| id.clone())
+        };
+        let client_id = match client_id {
+            Some(id) => id,
+            None => return,
+        };
+
+        let sender = match self.pending_proxy_requests.borrow_mut().remove(request_id) {
+            Some(sender) => sender,
+            None => return,
+        };
+        let (body_sender, body) = mpsc::unbounded();
+        let reply = ProxyReply::Streamed {
+            status,
+            headers,
+            body,
+        };
+        if sender.send(reply).is_ok() {
+            let stream = ProxyStream {
+                client_id,
+                body: body_sender,
             };
-            let _ = sender.send(response);
+            self.proxy_streams
+                .borrow_mut()
+                .insert(request_id.to_string(), stream);
         }
     }
 }
 
+/// Whether the browser asked for a streamed response
+fn wants_stream(proxy_req: &ProxyRequest) -> bool {
+    let accepts_event_stream = proxy_req.headers.iter().any(|(key, value)| {
+        key.eq_ignore_ascii_case("accept") && value.contains("text/event-stream")
+    });
+    let stream_flag = proxy_req
+        .query
+        .as_deref()
+        .and_then(|query| Url::parse(&format!("https://proxy/?{}", query)).ok())
+        .is_some_and(|url| crate::query::Query::from_url(&url).get_bool("stream", false));
+    accepts_event_stream || stream_flag
+}
+
+/// Whether a registration must wait for approval
+///
+/// With approval mode on, only a client ID approved together with the token it
+/// connected with (checked by `is_approved`) skips the queue. Sockets without
+/// a token tag can't be matched to an approval and always wait.
+fn needs_approval(
+    approval_mode: bool,
+    token_id: Option<&str>,
+    is_approved: impl FnOnce(&str) -> bool,
+) -> bool {
+    if !approval_mode {
+        return false;
+    }
+    match token_id {
+        Some(token_id) => !is_approved(token_id),
+        None => true,
+    }
+}
+
+/// Error sent to the browser whose forwarded request was evicted to make room
+const REQUEST_EVICTED: &str = "Request evicted: too many pending requests";
+
+/// `data` of a final `ForwardedResponse` reporting a failed request
+fn request_error(message: &str) -> serde_json::Value {
+    serde_json::json!({
+        "error": true,
+        "message": message
+    })
+}
+
+/// IDs of the oldest forwarded requests to evict so one more fits within `max_pending`
+///
+/// `started_at` lists each tracked request's ID and start time (epoch ms);
+/// requests started at the same time are evicted in ID order.
+fn requests_to_evict<'a>(
+    started_at: impl Iterator<Item = (&'a str, u64)>,
+    max_pending: usize,
+) -> Vec<String> {
+    let mut by_age: Vec<(u64, &str)> = started_at.map(|(id, started)| (started, id)).collect();
+    let excess = (by_age.len() + 1).saturating_sub(max_pending.max(1));
+    by_age.sort_unstable();
+    by_age
+        .into_iter()
+        .take(excess)
+        .map(|(_, id)| id.to_string())
+        .collect()
+}
+
+/// IDs of forwarded requests that have waited `timeout_ms` or longer by `now`
+/// (0 disables the timeout)
+///
+/// `started_at` lists each tracked request's ID and start time (epoch ms).
+fn timed_out_requests<'a>(
+    started_at: impl Iterator<Item = (&'a str, u64)>,
+    now: u64,
+    timeout_ms: u64,
+) -> Vec<String> {
+    if timeout_ms == 0 {
+        return Vec::new();
+    }
+    started_at
+        .filter(|(_, started)| now.saturating_sub(*started) >= timeout_ms)
+        .map(|(id, _)| id.to_string())
+        .collect()
+}
+
+/// Whether a `resume` registration with `token_id` reattaches to `previous`
+///
+/// `previous_token` is the token `previous` connected with, `None` when a
+/// still-open socket's token is unknown (nothing is resumed then). A
+/// different token is an error; a client past `lifetime_ms` starts over.
+fn resumed_client(
+    token_id: &str,
+    previous_token: Option<&str>,
+    previous: Client,
+    lifetime_ms: i64,
+    now: i64,
+) -> std::result::Result<Option<Client>, String> {
+    match previous_token {
+        None => Ok(None),
+        Some(previous_token) if previous_token != token_id => {
+            Err("client_id is registered with a different token".to_string())
+        }
+        Some(_) if lifetime_expired(&previous.connected_at, lifetime_ms, now) => Ok(None),
+        Some(_) => Ok(Some(previous)),
+    }
+}
+
+/// Whether a client connected at `connected_at` has outlived `lifetime_ms` (0 means unlimited)
+///
+/// An unparseable `connected_at` counts as expired, so a corrupt timestamp
+/// can neither keep a socket open forever nor be resumed.
+fn lifetime_expired(connected_at: &str, lifetime_ms: i64, now: i64) -> bool {
+    lifetime_ms > 0
+        && crate::time::epoch_ms_of(connected_at)
+            .is_none_or(|connected| connected + lifetime_ms <= now)
+}
+
+/// Rate a connection from its recent ping arrival times
+///
+/// "degraded" when any gap (including the time since the last ping) exceeds
+/// `max_gap_ms`, or an interval strays from the mean by more than
+/// `max_jitter_pct` percent; unrated until two pings arrive.
+fn rate_connection(
+    ping_times: &[u64],
+    now: u64,
+    max_gap_ms: u64,
+    max_jitter_pct: u64,
+) -> Option<ConnectionQuality> {
+    if ping_times.len() < 2 {
+        return None;
+    }
+
+    let intervals: Vec<u64> = ping_times
+        .windows(2)
+        .map(|pair| pair[1].saturating_sub(pair[0]))
+        .collect();
+    let since_last = now.saturating_sub(ping_times[ping_times.len() - 1]);
+    if since_last > max_gap_ms || intervals.iter().any(|&i| i > max_gap_ms) {
+        return Some(ConnectionQuality::Degraded);
+    }
+
+    let mean = intervals.iter().sum::<u64>() / intervals.len() as u64;
+    let irregular = intervals
+        .iter()
+        .any(|&i| i.abs_diff(mean) * 100 > mean * max_jitter_pct);
+    if irregular {
+        Some(ConnectionQuality::Degraded)
+    } else {
+        Some(ConnectionQuality::Good)
+    }
+}
+
+/// Deployment settings a registration is checked against
+struct RegistrationLimits {
+    /// Longest client_id, hostname and project, in characters
+    max_field_len: usize,
+    /// Refuse `http://` callback URLs (`REQUIRE_HTTPS_CALLBACK`)
+    require_https_callback: bool,
+    /// Still accept `http://` callbacks to localhost (`ALLOW_LOCALHOST_HTTP_CALLBACK`)
+    allow_localhost_http_callback: bool,
+    /// Most connected clients per user; 0 means unlimited
+    max_clients: u64,
+}
+
+/// Check a registration against `limits`, returning the rejection reason if invalid
+///
+/// `other_clients` counts the user's connected clients other than this one.
+fn check_registration(
+    client_id: &str,
+    metadata: &ClientMetadata,
+    limits: &RegistrationLimits,
+    other_clients: u64,
+) -> std::result::Result<(), String> {
+    if client_id.trim().is_empty() {
+        return Err("client_id must not be empty".to_string());
+    }
+    if metadata.hostname.trim().is_empty() {
+        return Err("hostname must not be empty".to_string());
+    }
+    if metadata.project.trim().is_empty() {
+        return Err("project must not be empty".to_string());
+    }
+
+    // Oversized fields would bloat storage and every broadcast
+    let max_len = limits.max_field_len;
+    for (field, value) in [
+        ("client_id", client_id),
+        ("hostname", metadata.hostname.as_str()),
+        ("project", metadata.project.as_str()),
+    ] {
+        if value.chars().count() > max_len {
+            return Err(format!("{} must be at most {} characters", field, max_len));
+        }
+    }
+
+    if let Some(callback_url) = &metadata.callback_url {
+        let url = match Url::parse(callback_url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => url,
+            Ok(_) => return Err("callback_url must use http or https".to_string()),
+            Err(_) => return Err("callback_url is not a valid URL".to_string()),
+        };
+
+        // Production deployments can refuse plaintext callbacks outright;
+        // local development may still opt in to http://localhost
+        if url.scheme() == "http" && limits.require_https_callback {
+            let localhost_allowed = limits.allow_localhost_http_callback && is_localhost(&url);
+            if !localhost_allowed {
+                return Err("callback_url must use https on this deployment".to_string());
+            }
+        }
+    }
+
+    if limits.max_clients > 0 && other_clients >= limits.max_clients {
+        return Err(format!(
+            "Client limit reached ({} connected clients)",
+            limits.max_clients
+        ));
+    }
+
+    Ok(())
+}
+
+/// A bare `?` carries no query; don't forward an empty one
+fn drop_empty_query(proxy_req: &mut ProxyRequest) {
+    proxy_req.query = proxy_req.query.take().filter(|query| !query.is_empty());
+}
+
+/// Length of the path and query string a client will request, as `path?query`
+fn proxy_target_length(proxy_req: &ProxyRequest) -> usize {
+    proxy_req.path.len() + proxy_req.query.as_ref().map_or(0, |query| query.len() + 1)
+}
+
+/// Whether a proxied request's target is longer than `max_url_length` bytes (0 means unlimited)
+fn proxy_target_too_long(proxy_req: &ProxyRequest, max_url_length: u64) -> bool {
+    max_url_length > 0 && proxy_target_length(proxy_req) as u64 > max_url_length
+}
+
+/// Build an orchestrator-level proxy error (as opposed to one relayed from the client)
+///
+/// The HTTP status matches the one inside the `ProxyResponse`, so the error
+/// stays visible to anything that only looks at the status.
+fn proxy_error(status: u16, message: &str) -> Result<Response> {
+    Ok(Response::from_json(&ProxyResponse {
+        status,
+        headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+        body: serde_json::json!({ "error": message }).to_string(),
+        body_is_base64: false,
+        error: Some(message.to_string()),
+    })?
+    .with_status(status))
+}
+
+/// Resolve after `ms` milliseconds
+///
+/// In WASM there is no native timer, so this wraps a JavaScript `setTimeout`
+/// promise. Used to race request futures against a deadline.
+async fn sleep_ms(ms: u64) {
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        let global = js_sys::global();
+        let _ = js_sys::Reflect::get(&global, &JsValue::from_str("setTimeout")).and_then(
+            |set_timeout| {
+                let set_timeout_fn: js_sys::Function = set_timeout.into();
+                set_timeout_fn.call2(&JsValue::NULL, &resolve, &JsValue::from_f64(ms as f64))
+            },
+        );
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
+
+/// `ClientPatch` carrying the fields that differ between two client snapshots,
+/// `None` when nothing changed
+fn client_patch(previous: &Client, current: &Client) -> Option<WsMessage> {
+    let old = serde_json::to_value(previous).ok()?;
+    let new = serde_json::to_value(current).ok()?;
+    let changed = diff_json(&old, &new)?;
+    Some(WsMessage::ClientPatch {
+        client_id: current.id.clone(),
+        changed,
+    })
+}
+
+/// Compute the fields of `new` that differ from `old`
+///
+/// Objects are compared key by key (recursively), so the result mirrors the
+/// shape of the input with unchanged keys omitted. Keys removed in `new` are
+/// reported as `null`. Returns `None` when nothing changed.
+fn diff_json(old: &serde_json::Value, new: &serde_json::Value) -> Option<serde_json::Value> {
+    match (old, new) {
+        (serde_json::Value::Object(old_map), serde_json::Value::Object(new_map)) => {
+            let mut changed = serde_json::Map::new();
+            for (key, new_value) in new_map {
+                match old_map.get(key) {
+                    Some(old_value) => {
+                        if let Some(diff) = diff_json(old_value, new_value) {
+                            changed.insert(key.clone(), diff);
+                        }
+                    }
+                    None => {
+                        changed.insert(key.clone(), new_value.clone());
+                    }
+                }
+            }
+            for key in old_map.keys() {
+                if !new_map.contains_key(key) {
+                    changed.insert(key.clone(), serde_json::Value::Null);
+                }
+            }
+            if changed.is_empty() {
+                None
+            } else {
+                Some(serde_json::Value::Object(changed))
+            }
+        }
+        _ if old == new => None,
+        _ => Some(new.clone()),
+    }
+}
+
+/// Whether a URL points at the local machine (`localhost`, `*.localhost` or a loopback IP)
+fn is_localhost(url: &Url) -> bool {
+    let host = match url.host_str() {
+        Some(host) => host.to_ascii_lowercase(),
+        None => return false,
+    };
+    // IPv6 hosts keep their brackets in `host_str`
+    let ip = host.trim_start_matches('[').trim_end_matches(']');
+    let is_loopback_ip = ip
+        .parse::<std::net::IpAddr>()
+        .is_ok_and(|ip| ip.is_loopback());
+    host == "localhost" || host.ends_with(".localhost") || is_loopback_ip
+}
+
 /// Generate a unique request ID
-fn generate_request_id() -> String {
-    let mut bytes = [0u8; 16];
-    getrandom::getrandom(&mut bytes).unwrap_or_default();
-    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+fn generate_request_id() -> Result<String> {
+    crate::random::random_hex(16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether client "laptop" is held, given approvals as (client_id, token_id)
+    fn held(approval_mode: bool, token_id: Option<&str>, approvals: &[(&str, &str)]) -> bool {
+        needs_approval(approval_mode, token_id, |token_id| {
+            approvals.contains(&("laptop", token_id))
+        })
+    }
+
+    const LIMITS: RegistrationLimits = RegistrationLimits {
+        max_field_len: 8,
+        require_https_callback: false,
+        allow_localhost_http_callback: false,
+        max_clients: 2,
+    };
+
+    fn metadata(hostname: &str, project: &str) -> ClientMetadata {
+        serde_json::from_value(serde_json::json!({
+            "hostname": hostname,
+            "project": project,
+            "last_activity": null
+        }))
+        .unwrap()
+    }
+
+    /// Rejection reason for a registration under `LIMITS` with no other clients
+    fn rejection(client_id: &str, hostname: &str, project: &str) -> Option<String> {
+        check_registration(client_id, &metadata(hostname, project), &LIMITS, 0).err()
+    }
+
+    #[test]
+    fn registrations_need_every_field() {
+        assert_eq!(rejection("laptop", "devbox", "app"), None);
+
+        let empty = |field: &str| Some(format!("{} must not be empty", field));
+        assert_eq!(rejection(" ", "devbox", "app"), empty("client_id"));
+        assert_eq!(rejection("laptop", "", "app"), empty("hostname"));
+        assert_eq!(rejection("laptop", "devbox", " "), empty("project"));
+    }
+
+    #[test]
+    fn registration_fields_are_capped_in_characters() {
+        // Multi-byte characters count once
+        assert_eq!(rejection("12345678", "éééééééé", "/src/app"), None);
+
+        for (client_id, hostname, project, field) in [
+            ("123456789", "devbox", "app", "client_id"),
+            ("laptop", "devbox-01", "app", "hostname"),
+            ("laptop", "devbox", "/src/app2", "project"),
+        ] {
+            let expected = format!("{} must be at most 8 characters", field);
+            assert_eq!(rejection(client_id, hostname, project), Some(expected));
+        }
+    }
+
+    #[test]
+    fn message_counts_enforce_the_limit_per_window() {
+        let mut counts = MessageCounts {
+            window_start_ms: 1_000,
+            ..MessageCounts::default()
+        };
+        let allowed: Vec<bool> = (0..4)
+            .map(|i| counts.record(RateKind::Message, 1_000 + i, 10_000, 3))
+            .collect();
+        assert_eq!(allowed, [true, true, true, false]);
+
+        // The window restarts once it has fully elapsed
+        assert!(!counts.record(RateKind::Message, 10_999, 10_000, 3));
+        assert!(counts.record(RateKind::Message, 11_000, 10_000, 3));
+        assert_eq!(counts.window_start_ms, 11_000);
+        assert_eq!(counts.messages, 1);
+    }
+
+    #[test]
+    fn message_kinds_have_separate_buckets() {
+        let mut counts = MessageCounts::default();
+        assert!(counts.record(RateKind::Ping, 0, 10_000, 1));
+        assert!(!counts.record(RateKind::Ping, 1, 10_000, 1));
+        assert!(counts.record(RateKind::Message, 2, 10_000, 1));
+        assert!(counts.record(RateKind::Forward, 3, 10_000, 1));
+        assert!(!counts.record(RateKind::Forward, 4, 10_000, 1));
+    }
+
+    #[test]
+    fn zero_limit_or_window_is_unlimited() {
+        let mut counts = MessageCounts::default();
+        for now in 0..1_000 {
+            assert!(counts.record(RateKind::Ping, now, 10_000, 0));
+            assert!(counts.record(RateKind::Message, now, 0, 1));
+        }
+    }
+
+    #[test]
+    fn full_pending_maps_evict_the_oldest_requests() {
+        let pending = [("b", 2_000), ("a", 1_000), ("c", 3_000)];
+        let evict = |max_pending| requests_to_evict(pending.into_iter(), max_pending);
+
+        // Room left, or exactly one slot short
+        assert!(evict(4).is_empty());
+        assert_eq!(evict(3), ["a"]);
+        assert_eq!(evict(2), ["a", "b"]);
+        // A limit of 1 keeps only the new request, and 0 is treated as 1
+        assert_eq!(evict(1), ["a", "b", "c"]);
+        assert_eq!(evict(0), ["a", "b", "c"]);
+
+        assert!(requests_to_evict(std::iter::empty(), 1).is_empty());
+        let tied = [("y", 1_000), ("x", 1_000)];
+        assert_eq!(requests_to_evict(tied.into_iter(), 2), ["x"]);
+    }
+
+    #[test]
+    fn forwarded_requests_time_out_after_the_forward_timeout() {
+        let timeout = DEFAULT_FORWARD_TIMEOUT_MS;
+        let pending = [("old", 1_000), ("edge", 5_000), ("new", 9_000)];
+        let timed_out = |now| {
+            let mut ids = timed_out_requests(pending.into_iter(), now, timeout);
+            ids.sort();
+            ids
+        };
+
+        assert!(timed_out(1_000 + timeout - 1).is_empty());
+        assert_eq!(timed_out(5_000 + timeout - 1), ["old"]);
+        assert_eq!(timed_out(5_000 + timeout), ["edge", "old"]);
+        // A clock behind a request's start never times it out
+        assert!(timed_out(0).is_empty());
+    }
+
+    #[test]
+    fn zero_forward_timeout_never_expires_requests() {
+        let pending = [("old", 0)];
+        assert!(timed_out_requests(pending.into_iter(), u64::MAX, 0).is_empty());
+    }
+
+    #[test]
+    fn evicted_browsers_get_a_final_error() {
+        let message = WsMessage::ForwardedResponse {
+            client_id: "laptop".to_string(),
+            request_id: "r1".to_string(),
+            data: request_error(REQUEST_EVICTED),
+            complete: true,
+        };
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            serde_json::json!({
+                "type": "forwarded_response",
+                "client_id": "laptop",
+                "request_id": "r1",
+                "data": {
+                    "error": true,
+                    "message": "Request evicted: too many pending requests"
+                },
+                "complete": true
+            })
+        );
+    }
+
+    #[test]
+    fn connections_expire_at_the_end_of_their_lifetime() {
+        const HOUR: i64 = 3_600_000;
+        // 2024-03-05T06:07:08Z
+        let connected = 1_709_618_828_000;
+        let connected_at = "2024-03-05T06:07:08Z";
+
+        assert!(!lifetime_expired(connected_at, HOUR, connected + HOUR - 1));
+        assert!(lifetime_expired(connected_at, HOUR, connected + HOUR));
+        let later = connected + 2 * HOUR;
+        assert!(lifetime_expired("2024-03-05 06:07:08", HOUR, later));
+
+        // No limit, however old the connection
+        assert!(!lifetime_expired(connected_at, 0, connected + 1_000 * HOUR));
+    }
+
+    #[test]
+    fn unparseable_connection_times_are_expired() {
+        assert!(lifetime_expired("", 3_600_000, 0));
+        assert!(lifetime_expired("yesterday", 3_600_000, 0));
+        assert!(!lifetime_expired("yesterday", 0, 0));
+    }
+
+    /// Client "laptop" connected at 2024-03-05T06:07:08Z
+    fn resumable_client() -> Client {
+        serde_json::from_value(serde_json::json!({
+            "id": "laptop",
+            "user_id": "owner",
+            "metadata": { "hostname": "devbox", "project": "app", "last_activity": null },
+            "connected_at": "2024-03-05T06:07:08Z",
+            "last_seen": "2024-03-05T06:07:08Z"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn resumes_keep_the_earlier_connection() {
+        const HOUR: i64 = 3_600_000;
+        let now = 1_709_618_828_000 + HOUR / 2;
+
+        // Same token: a live socket is taken over, a stored client restored
+        let resumed = resumed_client("t1", Some("t1"), resumable_client(), HOUR, now);
+        let resumed = resumed.unwrap().unwrap();
+        assert_eq!(resumed.connected_at, "2024-03-05T06:07:08Z");
+
+        // A live socket whose token is unknown isn't taken over
+        let unknown = resumed_client("t1", None, resumable_client(), HOUR, now);
+        assert!(unknown.unwrap().is_none());
+    }
+
+    #[test]
+    fn resumes_need_the_same_token_and_a_live_lifetime() {
+        const HOUR: i64 = 3_600_000;
+        let now = 1_709_618_828_000 + HOUR;
+
+        let other = resumed_client("t2", Some("t1"), resumable_client(), HOUR, now);
+        let error = other.err();
+        assert_eq!(
+            error.as_deref(),
+            Some("client_id is registered with a different token")
+        );
+
+        // Past MAX_WS_LIFETIME_HOURS the client registers as new
+        let expired = resumed_client("t1", Some("t1"), resumable_client(), HOUR, now);
+        assert!(expired.unwrap().is_none());
+    }
+
+    #[test]
+    fn resumable_clients_outside_the_window_are_purged() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        db.execute(CREATE_RESUMABLE_CLIENTS, []).unwrap();
+        for (client_id, disconnected_at) in [("old", 1_000), ("recent", 5_000)] {
+            let params = rusqlite::params![client_id, "t1", "{}", disconnected_at];
+            db.execute(SAVE_RESUMABLE_CLIENT, params).unwrap();
+        }
+
+        db.execute(PURGE_RESUMABLE_CLIENTS, [2_000]).unwrap();
+        let take = |client_id: &str| -> Option<(String, String)> {
+            let mut stmt = db.prepare(TAKE_RESUMABLE_CLIENT).unwrap();
+            let mut rows = stmt.query([client_id]).unwrap();
+            let row = rows.next().unwrap()?;
+            Some((row.get(0).unwrap(), row.get(1).unwrap()))
+        };
+        assert_eq!(take("old"), None);
+        assert_eq!(take("recent"), Some(("t1".to_string(), "{}".to_string())));
+        // Taking a client removes it
+        assert_eq!(take("recent"), None);
+    }
+
+    /// Closing the revoked token's live sockets needs the Workers runtime;
+    /// what it leaves behind in storage is checked here
+    #[test]
+    fn revoked_tokens_lose_resumes_and_approvals() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        db.execute(CREATE_RESUMABLE_CLIENTS, []).unwrap();
+        db.execute(CREATE_APPROVED_CLIENT_TOKENS, []).unwrap();
+        for (client_id, token_id) in [("laptop", "t1"), ("desk", "t1"), ("laptop", "t2")] {
+            let resumable = rusqlite::params![client_id, token_id, "{}", 1_000];
+            db.execute(SAVE_RESUMABLE_CLIENT, resumable).unwrap();
+            let approval = rusqlite::params![client_id, token_id, "2024-03-05T06:07:08Z"];
+            db.execute(APPROVE_CLIENT_TOKEN, approval).unwrap();
+        }
+
+        db.execute(FORGET_TOKEN_RESUMABLE_CLIENTS, ["t1"]).unwrap();
+        db.execute(FORGET_TOKEN_APPROVALS, ["t1"]).unwrap();
+
+        let approved = |client_id: &str, token_id: &str| {
+            let mut stmt = db.prepare(CLIENT_TOKEN_APPROVAL).unwrap();
+            stmt.exists([client_id, token_id]).unwrap()
+        };
+        assert!(!approved("laptop", "t1"));
+        assert!(!approved("desk", "t1"));
+        assert!(approved("laptop", "t2"));
+
+        let resumable: Vec<(String, String)> = db
+            .prepare("SELECT client_id, token_id FROM resumable_clients")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(resumable, [("laptop".to_string(), "t2".to_string())]);
+    }
+
+    /// Connection quality with a 30s gap limit and 50% jitter allowance
+    fn quality(ping_times: &[u64], now: u64) -> Option<ConnectionQuality> {
+        rate_connection(ping_times, now, 30_000, 50)
+    }
+
+    #[test]
+    fn connections_are_unrated_until_two_pings() {
+        assert_eq!(quality(&[], 1_000), None);
+        assert_eq!(quality(&[1_000], 1_000), None);
+        assert!(quality(&[1_000, 11_000], 11_000).is_some());
+    }
+
+    #[test]
+    fn regular_pings_are_good() {
+        let pings = [0, 10_000, 20_000, 30_000, 40_000];
+        assert_eq!(quality(&pings, 40_000), Some(ConnectionQuality::Good));
+        // Up to the gap limit since the last ping, and within the jitter allowance
+        assert_eq!(quality(&pings, 70_000), Some(ConnectionQuality::Good));
+        let jittery = [0, 10_000, 16_000, 30_000, 40_000];
+        assert_eq!(quality(&jittery, 40_000), Some(ConnectionQuality::Good));
+    }
+
+    #[test]
+    fn gaps_and_jitter_degrade_the_connection() {
+        let degraded = Some(ConnectionQuality::Degraded);
+        // One interval over the gap limit
+        assert_eq!(quality(&[0, 10_000, 40_001, 50_000], 50_000), degraded);
+        // No ping for longer than the gap limit
+        assert_eq!(quality(&[0, 10_000, 20_000], 50_001), degraded);
+        // 4s and 16s intervals stray 60% from their 10s mean
+        assert_eq!(quality(&[0, 4_000, 20_000], 20_000), degraded);
+
+        // A looser jitter allowance accepts the same pings
+        let pings = [0, 4_000, 20_000];
+        let loose = rate_connection(&pings, 20_000, 30_000, 60);
+        assert_eq!(loose, Some(ConnectionQuality::Good));
+    }
+
+    const HTTPS_ONLY: &str = "callback_url must use https on this deployment";
+
+    /// Rejection reason for a callback URL, given `REQUIRE_HTTPS_CALLBACK`
+    /// and `ALLOW_LOCALHOST_HTTP_CALLBACK`
+    fn callback_rejection(
+        callback_url: &str,
+        require_https: bool,
+        localhost: bool,
+    ) -> Option<String> {
+        let limits = RegistrationLimits {
+            require_https_callback: require_https,
+            allow_localhost_http_callback: localhost,
+            ..LIMITS
+        };
+        let mut meta = metadata("devbox", "app");
+        meta.callback_url = Some(callback_url.to_string());
+        check_registration("laptop", &meta, &limits, 0).err()
+    }
+
+    #[test]
+    fn plaintext_callbacks_are_refused_only_when_https_is_required() {
+        let lan = "http://10.0.0.5:3001";
+        let https = "https://devbox.example.com";
+        for localhost in [false, true] {
+            assert_eq!(callback_rejection(lan, false, localhost), None);
+            assert_eq!(callback_rejection(https, true, localhost), None);
+            let rejected = callback_rejection(lan, true, localhost);
+            assert_eq!(rejected.as_deref(), Some(HTTPS_ONLY));
+        }
+    }
+
+    #[test]
+    fn localhost_callbacks_can_stay_plaintext() {
+        for url in [
+            "http://localhost:3001",
+            "http://127.0.0.1:3001",
+            "http://[::1]:3001",
+        ] {
+            assert_eq!(callback_rejection(url, true, true), None, "{url}");
+            let rejected = callback_rejection(url, true, false);
+            assert_eq!(rejected.as_deref(), Some(HTTPS_ONLY), "{url}");
+        }
+        // Only the host decides, not a lookalike name
+        let lookalike = callback_rejection("http://localhost.example.com", true, true);
+        assert_eq!(lookalike.as_deref(), Some(HTTPS_ONLY));
+    }
+
+    #[test]
+    fn callbacks_must_be_http_urls() {
+        for require_https in [false, true] {
+            for url in ["ws://devbox:3001", "file:///tmp/sock", "devbox:3001"] {
+                let rejected = callback_rejection(url, require_https, true);
+                let expected = "callback_url must use http or https";
+                assert_eq!(rejected.as_deref(), Some(expected), "{url}");
+            }
+            let rejected = callback_rejection("not a url", require_https, true);
+            let expected = "callback_url is not a valid URL";
+            assert_eq!(rejected.as_deref(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn client_limit_counts_other_connected_clients() {
+        let meta = metadata("devbox", "app");
+        assert_eq!(check_registration("laptop", &meta, &LIMITS, 1), Ok(()));
+        assert_eq!(
+            check_registration("laptop", &meta, &LIMITS, 2),
+            Err("Client limit reached (2 connected clients)".to_string())
+        );
+
+        let unlimited = RegistrationLimits {
+            max_clients: 0,
+            ..LIMITS
+        };
+        assert_eq!(check_registration("laptop", &meta, &unlimited, 500), Ok(()));
+    }
+
+    #[test]
+    fn approval_mode_off_never_holds_clients() {
+        assert!(!held(false, Some("tok1"), &[]));
+        assert!(!held(false, None, &[]));
+    }
+
+    #[test]
+    fn approved_client_and_token_skip_the_queue() {
+        assert!(!held(true, Some("tok1"), &[("laptop", "tok1")]));
+    }
+
+    #[test]
+    fn approved_client_id_with_another_token_waits() {
+        assert!(held(true, Some("tok2"), &[("laptop", "tok1")]));
+        assert!(held(true, Some("tok1"), &[("desktop", "tok1")]));
+    }
+
+    #[test]
+    fn rejected_new_and_untagged_clients_wait() {
+        // Rejecting stores nothing, so a rejected client is held again on reconnect
+        assert!(held(true, Some("tok1"), &[]));
+        assert!(held(true, None, &[("laptop", "tok1")]));
+    }
+
+    fn chunk(n: u64) -> ResponsePart {
+        ResponsePart::Chunk(serde_json::json!(n))
+    }
+
+    #[test]
+    fn out_of_order_parts_are_delivered_in_sequence() {
+        let mut sequence = ResponseSequence::default();
+        assert!(sequence.insert(2, ResponsePart::Complete(None)).is_ok());
+        assert!(sequence.insert(1, chunk(1)).is_ok());
+        assert!(sequence.take_ready().is_empty());
+
+        assert!(sequence.insert(0, chunk(0)).is_ok());
+        let complete = ResponsePart::Complete(None);
+        assert_eq!(sequence.take_ready(), vec![chunk(0), chunk(1), complete]);
+        assert_eq!(sequence.next_seq, 3);
+        assert_eq!(sequence.buffered_bytes, 0);
+    }
+
+    #[test]
+    fn duplicate_parts_are_rejected() {
+        let mut sequence = ResponseSequence::default();
+        assert!(sequence.insert(1, chunk(1)).is_ok());
+        assert_eq!(sequence.insert(1, chunk(1)), Err(PartRejected::Duplicate));
+        assert!(sequence.insert(0, chunk(0)).is_ok());
+        sequence.take_ready();
+        assert_eq!(sequence.insert(0, chunk(0)), Err(PartRejected::Duplicate));
+    }
+
+    #[test]
+    fn parts_far_ahead_are_rejected() {
+        let mut sequence = ResponseSequence::default();
+        assert!(sequence.insert(MAX_RESPONSE_SEQ_AHEAD, chunk(0)).is_ok());
+        assert_eq!(
+            sequence.insert(MAX_RESPONSE_SEQ_AHEAD + 1, chunk(0)),
+            Err(PartRejected::TooFarAhead)
+        );
+        assert_eq!(sequence.buffered.len(), 1);
+    }
+
+    #[test]
+    fn buffered_bytes_are_capped() {
+        let mut sequence = ResponseSequence::default();
+        let big = || ResponsePart::Chunk(serde_json::json!("x".repeat(1024 * 1024)));
+        for seq in 1..4 {
+            assert!(sequence.insert(seq, big()).is_ok());
+        }
+        assert_eq!(sequence.insert(4, big()), Err(PartRejected::Overflow));
+        assert!(sequence.buffered_bytes <= MAX_BUFFERED_RESPONSE_BYTES);
+    }
+
+    #[test]
+    fn restored_parts_keep_their_order_and_size() {
+        let mut original = ResponseSequence::default();
+        let json = original.insert(1, chunk(1)).unwrap();
+
+        let mut restored = ResponseSequence::default();
+        restored.restore(1, &json);
+        restored.restore(2, "not json");
+        assert_eq!(restored.buffered_bytes, original.buffered_bytes);
+        assert!(restored.insert(0, chunk(0)).is_ok());
+        assert_eq!(restored.take_ready(), vec![chunk(0), chunk(1)]);
+    }
+
+    #[test]
+    fn requested_subprotocol_is_echoed_back() {
+        let protocols = requested_protocols(Some("chat.v2, chat.v1"));
+        assert_eq!(protocols, vec!["chat.v2", "chat.v1"]);
+
+        // The browser's request reaches the client in ws_proxy_open...
+        let open = WsMessage::WsProxyOpen {
+            tunnel_id: "t1".to_string(),
+            path: "/shell".to_string(),
+            query: None,
+            headers: Vec::new(),
+            proxy_base: "/clients/laptop/ws-proxy".to_string(),
+            protocols: protocols.clone(),
+        };
+        let json = serde_json::to_value(&open).unwrap();
+        assert_eq!(json["type"], "ws_proxy_open");
+        assert_eq!(json["protocols"], serde_json::json!(["chat.v2", "chat.v1"]));
+
+        // ...and the upstream's choice, echoed in ws_proxy_accept, goes back to the browser
+        let accept = r#"{"type":"ws_proxy_accept","tunnel_id":"t1","protocol":"chat.v1"}"#;
+        let chosen = match serde_json::from_str(accept).unwrap() {
+            WsMessage::WsProxyAccept { protocol, .. } => protocol,
+            other => panic!("unexpected message {:?}", other),
+        };
+        let negotiated = negotiated_protocol(&protocols, chosen);
+        assert_eq!(negotiated.as_deref(), Some("chat.v1"));
+    }
+
+    #[test]
+    fn unrequested_or_missing_subprotocols_are_not_sent() {
+        assert!(requested_protocols(None).is_empty());
+        assert!(requested_protocols(Some(" , ")).is_empty());
+
+        let protocols = requested_protocols(Some("chat.v1"));
+        let other = Some("other".to_string());
+        assert_eq!(negotiated_protocol(&protocols, other), None);
+        assert_eq!(negotiated_protocol(&protocols, None), None);
+    }
+
+    #[test]
+    fn open_without_subprotocols_omits_the_field() {
+        let open = WsMessage::WsProxyOpen {
+            tunnel_id: "t1".to_string(),
+            path: "/".to_string(),
+            query: None,
+            headers: Vec::new(),
+            proxy_base: "/clients/laptop/ws-proxy".to_string(),
+            protocols: Vec::new(),
+        };
+        let json = serde_json::to_value(&open).unwrap();
+        assert!(json.get("protocols").is_none());
+    }
+
+    #[test]
+    fn silent_clients_stop_the_heartbeat_alarm() {
+        assert!(watches_heartbeat(ClientStatus::Idle));
+        assert!(watches_heartbeat(ClientStatus::Busy));
+        assert!(!watches_heartbeat(ClientStatus::Disconnected));
+        assert!(!watches_heartbeat(ClientStatus::PendingApproval));
+    }
+
+    fn get(path: &str) -> ProxyRequest {
+        ProxyRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            headers: Vec::new(),
+            body: None,
+            body_is_base64: false,
+            query: None,
+        }
+    }
+
+    #[test]
+    fn empty_queries_are_not_forwarded() {
+        let mut bare = get("/search");
+        bare.query = Some(String::new());
+        drop_empty_query(&mut bare);
+        assert_eq!(bare.query, None);
+        assert_eq!(proxy_target_length(&bare), "/search".len());
+
+        let mut with_query = get("/search");
+        with_query.query = Some("q=1".to_string());
+        drop_empty_query(&mut with_query);
+        assert_eq!(with_query.query.as_deref(), Some("q=1"));
+        assert_eq!(proxy_target_length(&with_query), "/search?q=1".len());
+    }
+
+    #[test]
+    fn proxy_targets_are_capped_at_the_url_length_limit() {
+        let mut req = get("/search");
+        req.query = Some("q=1".to_string());
+        let length = "/search?q=1".len() as u64;
+        assert!(!proxy_target_too_long(&req, length));
+        assert!(proxy_target_too_long(&req, length - 1));
+        // 0 turns the limit off
+        assert!(!proxy_target_too_long(&req, 0));
+
+        let max = DEFAULT_PROXY_MAX_URL_LENGTH;
+        let at_limit = get(&"/".repeat(max as usize));
+        assert!(!proxy_target_too_long(&at_limit, max));
+        let over_limit = get(&"/".repeat(max as usize + 1));
+        assert!(proxy_target_too_long(&over_limit, max));
+    }
+
+    #[test]
+    fn only_plain_gets_for_listed_extensions_are_cached() {
+        let extensions = DEFAULT_ASSET_CACHE_EXTENSIONS;
+        let script = get("/assets/app.JS");
+        let path = cacheable_asset_path(&script, extensions);
+        assert_eq!(path, Some("assets/app.JS"));
+        assert_eq!(cacheable_asset_path(&get("/index.html"), extensions), None);
+        assert_eq!(cacheable_asset_path(&get("/assets/"), extensions), None);
+
+        let mut with_query = get("/app.js");
+        with_query.query = Some("v=2".to_string());
+        assert_eq!(cacheable_asset_path(&with_query, extensions), None);
+
+        let mut post = get("/app.js");
+        post.method = "POST".to_string();
+        assert_eq!(cacheable_asset_path(&post, extensions), None);
+    }
+
+    #[test]
+    fn stored_asset_is_served_unchanged() {
+        for (content_type, original) in [
+            ("text/css", b"body { color: red }".to_vec()),
+            ("image/png", vec![0x89, b'P', b'N', b'G', 0, 0xff]),
+        ] {
+            // What store_cached_asset writes to R2...
+            let (body, body_is_base64) = crate::body::encode(Some(content_type), original.clone());
+            let stored = crate::body::decode(&body, body_is_base64).unwrap();
+
+            // ...is what read_cached_asset serves back
+            let served = cached_asset_response(content_type.to_string(), stored);
+            assert_eq!(served.status, 200);
+            let served_bytes = crate::body::decode(&served.body, served.body_is_base64);
+            assert_eq!(served_bytes, Some(original));
+            assert!(served
+                .headers
+                .contains(&("X-Orchestrator-Cache".to_string(), "HIT".to_string())));
+        }
+    }
+
+    #[test]
+    fn diff_json_reports_only_changed_fields() {
+        let old = serde_json::json!({
+            "a": 1,
+            "nested": { "kept": true, "changed": "old", "removed": 3 },
+            "list": [1, 2]
+        });
+        let new = serde_json::json!({
+            "a": 1,
+            "nested": { "kept": true, "changed": "new", "added": null },
+            "list": [1, 2, 3]
+        });
+        assert_eq!(
+            diff_json(&old, &new),
+            Some(serde_json::json!({
+                "nested": { "changed": "new", "added": null, "removed": null },
+                "list": [1, 2, 3]
+            }))
+        );
+        assert_eq!(diff_json(&old, &old), None);
+        assert_eq!(
+            diff_json(&serde_json::json!(1), &serde_json::json!("1")),
+            Some(serde_json::json!("1"))
+        );
+    }
+
+    #[test]
+    fn client_patch_carries_only_changed_fields() {
+        let previous: Client = serde_json::from_value(serde_json::json!({
+            "id": "laptop",
+            "user_id": "owner",
+            "metadata": {
+                "hostname": "devbox",
+                "project": "/home/me/orchestrator",
+                "status": "idle",
+                "last_activity": null
+            },
+            "connected_at": "2026-01-01T00:00:00Z",
+            "last_seen": "2026-01-01T00:00:00Z"
+        }))
+        .unwrap();
+
+        let mut current = previous.clone();
+        current.metadata.status = ClientStatus::Busy;
+        current.metadata.activity_summary = Some("running tests".to_string());
+        match client_patch(&previous, &current) {
+            Some(WsMessage::ClientPatch { client_id, changed }) => {
+                assert_eq!(client_id, "laptop");
+                assert_eq!(
+                    changed,
+                    serde_json::json!({
+                        "metadata": { "status": "busy", "activity_summary": "running tests" }
+                    })
+                );
+            }
+            other => panic!("expected a ClientPatch, got {:?}", other),
+        }
+
+        assert!(client_patch(&previous, &previous.clone()).is_none());
+    }
 }
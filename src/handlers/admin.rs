@@ -0,0 +1,165 @@
+use worker::*;
+
+use crate::auth::AuthMiddleware;
+use crate::durable_objects::user_hub_name;
+
+/// Disconnect any user's client (admins only, for abuse response)
+pub async fn admin_disconnect_client(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    // Require an admin
+    let admin = match AuthMiddleware::require_admin_json(&req, &ctx.env).await? {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    let user_id = ctx.param("user_id").ok_or("Missing user ID")?;
+    let client_id = ctx.param("client_id").ok_or("Missing client ID")?;
+
+    // Get the target user's Durable Object
+    let namespace = ctx.env.durable_object("USER_HUB")?;
+    let id = namespace.id_from_name(&user_hub_name(&ctx.env, user_id))?;
+    let stub = id.get_stub()?;
+
+    let do_req = Request::new(
+        &format!("https://do/clients/{}/disconnect", client_id),
+        Method::Post,
+    )?;
+    let response = stub.fetch_with_request(do_req).await?;
+    let disconnected = response.status_code() == 200;
+    if disconnected {
+        crate::user_cache::invalidate_client(client_id);
+    }
+
+    // Audit trail for admin actions
+    console_log!(
+        "{}",
+        serde_json::json!({
+            "event": "admin_action",
+            "action": "disconnect_client",
+            "admin": admin.github_login,
+            "target_user_id": user_id,
+            "client_id": client_id,
+            "success": disconnected,
+        })
+    );
+
+    if disconnected {
+        Response::from_json(&serde_json::json!({ "disconnected": true }))
+    } else {
+        Ok(Response::from_json(&serde_json::json!({
+            "error": "client not connected"
+        }))?
+        .with_status(404))
+    }
+}
+
+/// Effective non-secret configuration, for checking what a deployment runs with
+///
+/// Values come from the same env accessors the handlers use, with defaults
+/// applied. Secrets are only reported as configured or not.
+pub async fn admin_config(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let admin = match AuthMiddleware::require_admin_json(&req, &ctx.env).await? {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+    let env = &ctx.env;
+
+    // Hub settings are read inside the Durable Object, where they apply
+    let namespace = env.durable_object("USER_HUB")?;
+    let id = namespace.id_from_name(&user_hub_name(env, &admin.id))?;
+    let stub = id.get_stub()?;
+    let hub: serde_json::Value = match stub.fetch_with_str("https://do/config").await {
+        Ok(mut resp) => resp.json().await.unwrap_or(serde_json::Value::Null),
+        Err(e) => {
+            console_error!("Admin config: hub unreachable: {:?}", e);
+            serde_json::Value::Null
+        }
+    };
+
+    let allowlist_count = |name: &str| crate::auth::allowlist(env, name).len();
+    let secret_set = |name: &str| env.secret(name).is_ok();
+
+    Response::from_json(&serde_json::json!({
+        "auth": {
+            "github_client_id": env.var("GITHUB_CLIENT_ID").ok().map(|v| v.to_string()),
+            "allow_all_users": crate::config::var_bool(env, "ALLOW_ALL_USERS", false),
+            "allowed_users_count": allowlist_count("ALLOWED_USERS"),
+            "allowed_orgs_count": allowlist_count("ALLOWED_ORGS"),
+            "allowed_teams_count": allowlist_count("ALLOWED_TEAMS"),
+            "gh_cache_enabled": env.kv("GH_CACHE").is_ok(),
+            "gitlab_enabled": crate::config::var_string(env, "GITLAB_CLIENT_ID").is_some(),
+            "gitlab_url": crate::config::var_string(env, "GITLAB_URL"),
+            "admin_users_count": allowlist_count("ADMIN_USERS"),
+            "allowed_redirect_hosts": crate::auth::allowed_redirect_hosts(env),
+            "cookie_samesite": crate::config::cookie_same_site(env),
+            "frame_ancestors": crate::config::frame_ancestors_policy(env),
+            "session_idle_timeout_minutes": crate::config::var_u64(env, "SESSION_IDLE_TIMEOUT", 0),
+            "require_typed_confirmation":
+                crate::config::var_bool(env, "REQUIRE_TYPED_CONFIRMATION", true),
+            "callback_rate_limit": crate::config::var_u64(
+                env,
+                "AUTH_CALLBACK_RATE_LIMIT",
+                crate::auth::DEFAULT_AUTH_CALLBACK_RATE_LIMIT,
+            ),
+            "callback_rate_window_seconds": crate::config::var_u64(
+                env,
+                "AUTH_CALLBACK_RATE_WINDOW_SECONDS",
+                crate::auth::DEFAULT_AUTH_CALLBACK_RATE_WINDOW_SECONDS,
+            ),
+            "token_create_rate_limit": crate::config::var_u64(
+                env,
+                "TOKEN_CREATE_RATE_LIMIT",
+                super::tokens::DEFAULT_TOKEN_CREATE_RATE_LIMIT,
+            ),
+            "token_create_rate_window_seconds": crate::config::var_u64(
+                env,
+                "TOKEN_CREATE_RATE_WINDOW_SECONDS",
+                super::tokens::DEFAULT_TOKEN_CREATE_RATE_WINDOW_SECONDS,
+            ),
+            "connect_test_rate_limit": crate::config::var_u64(
+                env,
+                "CONNECT_TEST_RATE_LIMIT",
+                super::websocket::DEFAULT_CONNECT_TEST_RATE_LIMIT,
+            ),
+            "connect_test_rate_window_seconds": crate::config::var_u64(
+                env,
+                "CONNECT_TEST_RATE_WINDOW_SECONDS",
+                super::websocket::DEFAULT_CONNECT_TEST_RATE_WINDOW_SECONDS,
+            ),
+        },
+        "routing": {
+            "base_path": crate::config::base_path(env),
+            "deployment_namespace": crate::config::var_string(env, "DEPLOYMENT_NAMESPACE"),
+            "client_ip_header": crate::client_info::client_ip_header(env),
+            "trusted_proxies": crate::client_info::trusted_proxies(env),
+        },
+        "tokens": {
+            "prefix": crate::models::TOKEN_PREFIX,
+        },
+        "dashboard": {
+            "client_display_limit": crate::config::var_u64(
+                env,
+                "CLIENT_DISPLAY_LIMIT",
+                super::clients::DEFAULT_CLIENT_DISPLAY_LIMIT,
+            ),
+        },
+        "proxy": {
+            "debug_log": crate::config::var_bool(env, "PROXY_DEBUG_LOG", false),
+            "error_details": crate::config::var_bool(env, "PROXY_ERROR_DETAILS", false),
+            "nosniff": crate::config::var_bool(env, "PROXY_NOSNIFF", true),
+            "rewrite_rules": super::rewrite::load_rules(env).len(),
+            "strip_response_headers": super::proxy::stripped_response_headers(env),
+            "upstream_auth_status": crate::config::var_string(env, "PROXY_UPSTREAM_AUTH_STATUS"),
+            "user_cache_ttl_ms": crate::user_cache::ttl_ms(env),
+        },
+        "telemetry": {
+            "enabled": crate::config::var_bool(env, "TELEMETRY_ENABLED", false),
+            "endpoint": crate::telemetry::endpoint(env),
+        },
+        "hub": hub,
+        "secrets_configured": {
+            "GITHUB_CLIENT_SECRET": secret_set("GITHUB_CLIENT_SECRET"),
+            "CLOUDFLARE_API_TOKEN": secret_set("CLOUDFLARE_API_TOKEN"),
+            "CLOUDFLARE_ZONE_ID": secret_set("CLOUDFLARE_ZONE_ID"),
+        },
+    }))
+}
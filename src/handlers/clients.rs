@@ -1,9 +1,31 @@
 use worker::*;
 
 use crate::auth::AuthMiddleware;
-use crate::models::Client;
+use crate::durable_objects::user_hub_name;
+use crate::models::{Client, ClientDetail, ClientStatus, ProxyHealth};
 use crate::templates;
 
+/// Number of client cards shown before the rest collapse behind "Show more"
+pub(super) const DEFAULT_CLIENT_DISPLAY_LIMIT: u64 = 12;
+
+/// Fields accepted by `?fields=` on `GET /api/clients`
+const CLIENT_FIELDS: &[&str] = &["id", "user_id", "metadata", "connected_at", "last_seen"];
+
+/// Values accepted by `?status=` on `GET /api/clients`
+const CLIENT_STATUSES: &[&str] = &["idle", "active", "busy", "disconnected", "pending_approval"];
+
+/// Resolve the card limit from `?limit=` (`all` or 0 shows every client)
+fn client_display_limit(req: &Request, env: &Env) -> Result<Option<usize>> {
+    let params = crate::query::Query::from_request(req)?;
+    if params.get_str("limit") == Some("all") {
+        return Ok(None);
+    }
+
+    let default = crate::config::var_u64(env, "CLIENT_DISPLAY_LIMIT", DEFAULT_CLIENT_DISPLAY_LIMIT);
+    let limit = params.get_u32("limit", default.min(u32::MAX as u64) as u32) as usize;
+    Ok(Some(limit).filter(|&n| n > 0))
+}
+
 /// Get all clients for the current user (returns HTMX partial)
 pub async fn get_clients(req: Request, ctx: RouteContext<()>) -> Result<Response> {
     // Check authentication
@@ -14,7 +36,7 @@ pub async fn get_clients(req: Request, ctx: RouteContext<()>) -> Result<Response
 
     // Get the user's Durable Object
     let namespace = ctx.env.durable_object("USER_HUB")?;
-    let id = namespace.id_from_name(&user.id)?;
+    let id = namespace.id_from_name(&user_hub_name(&ctx.env, &user.id))?;
     let stub = id.get_stub()?;
 
     // Fetch clients from DO
@@ -22,19 +44,106 @@ pub async fn get_clients(req: Request, ctx: RouteContext<()>) -> Result<Response
     let mut response = stub.fetch_with_request(do_req).await?;
 
     let clients: Vec<Client> = response.json().await.unwrap_or_default();
+    let limit = client_display_limit(&req, &ctx.env)?;
 
     // Check if this is an HTMX request
-    let is_htmx = req.headers().get("HX-Request")?.is_some();
+    let is_htmx = super::wants_htmx(&req);
 
     if is_htmx {
         // Return just the client list partial
-        Response::from_html(templates::render_client_list(&clients))
+        Response::from_html(templates::render_client_list(&clients, limit))
     } else {
         // Return full page with client list
-        Response::from_html(templates::render_clients_page(&user, &clients))
+        Response::from_html(templates::render_clients_page(&user, &clients, limit))
     }
 }
 
+/// List the current user's clients as JSON
+///
+/// `?status=active` (or a comma-separated list such as `active,busy`) keeps
+/// only clients in those states; an unknown status returns a 400 JSON error.
+pub async fn list_clients_api(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    // Require authentication
+    let user = match AuthMiddleware::require_auth_json(&req, &ctx.env).await? {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    let params = crate::query::Query::from_request(&req)?;
+    let statuses = match params.get_str("status").map(parse_statuses) {
+        Some(Ok(statuses)) => Some(statuses),
+        Some(Err(unknown)) => {
+            return Ok(Response::from_json(&serde_json::json!({
+                "error": format!("unknown status: {}", unknown),
+                "known_statuses": CLIENT_STATUSES,
+            }))?
+            .with_status(400));
+        }
+        None => None,
+    };
+
+    // Get the user's Durable Object
+    let namespace = ctx.env.durable_object("USER_HUB")?;
+    let id = namespace.id_from_name(&user_hub_name(&ctx.env, &user.id))?;
+    let stub = id.get_stub()?;
+
+    // Fetch clients from DO
+    let do_req = Request::new("https://do/clients", Method::Get)?;
+    let mut response = stub.fetch_with_request(do_req).await?;
+
+    let mut clients: Vec<Client> = response.json().await.unwrap_or_default();
+    if let Some(statuses) = statuses.filter(|s| !s.is_empty()) {
+        clients.retain(|client| statuses.contains(&client.metadata.status));
+    }
+    super::json::list_response(&req, &clients, CLIENT_FIELDS)
+}
+
+/// One of the current user's clients as JSON, with derived connection health
+///
+/// Returns a 404 JSON error when the user has no client with that ID.
+pub async fn get_client_api(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    // Require authentication
+    let user = match AuthMiddleware::require_auth_json(&req, &ctx.env).await? {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    let client_id = ctx.param("id").ok_or("Missing client ID")?;
+
+    // Get the user's Durable Object
+    let namespace = ctx.env.durable_object("USER_HUB")?;
+    let id = namespace.id_from_name(&user_hub_name(&ctx.env, &user.id))?;
+    let stub = id.get_stub()?;
+
+    // Fetch clients from DO
+    let do_req = Request::new("https://do/clients", Method::Get)?;
+    let mut response = stub.fetch_with_request(do_req).await?;
+
+    let clients: Vec<Client> = response.json().await.unwrap_or_default();
+    match clients.into_iter().find(|c| &c.id == client_id) {
+        Some(client) => {
+            Response::from_json(&ClientDetail::new(client, crate::time::now_ms() as i64))
+        }
+        None => Ok(
+            Response::from_json(&serde_json::json!({ "error": "Client not found" }))?
+                .with_status(404),
+        ),
+    }
+}
+
+/// Statuses named in a comma-separated `?status=` value, or the first unknown name
+fn parse_statuses(value: &str) -> std::result::Result<Vec<ClientStatus>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            serde_json::from_value(serde_json::Value::String(name.to_ascii_lowercase()))
+                .map_err(|_| name.to_string())
+        })
+        .collect()
+}
+
 /// Get a single client by ID (returns HTMX partial - collapsed card)
 pub async fn get_client(req: Request, ctx: RouteContext<()>) -> Result<Response> {
     // Check authentication
@@ -47,7 +156,7 @@ pub async fn get_client(req: Request, ctx: RouteContext<()>) -> Result<Response>
 
     // Get the user's Durable Object
     let namespace = ctx.env.durable_object("USER_HUB")?;
-    let id = namespace.id_from_name(&user.id)?;
+    let id = namespace.id_from_name(&user_hub_name(&ctx.env, &user.id))?;
     let stub = id.get_stub()?;
 
     // Fetch clients from DO
@@ -75,7 +184,7 @@ pub async fn get_client_details(req: Request, ctx: RouteContext<()>) -> Result<R
 
     // Get the user's Durable Object
     let namespace = ctx.env.durable_object("USER_HUB")?;
-    let id = namespace.id_from_name(&user.id)?;
+    let id = namespace.id_from_name(&user_hub_name(&ctx.env, &user.id))?;
     let stub = id.get_stub()?;
 
     // Fetch clients from DO
@@ -91,8 +200,13 @@ pub async fn get_client_details(req: Request, ctx: RouteContext<()>) -> Result<R
     }
 }
 
-/// Disconnect a client (sends disconnect command via WebSocket)
-pub async fn disconnect_client(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+/// Run a per-client action on the DO and return the updated client list
+async fn client_list_action(
+    req: Request,
+    ctx: RouteContext<()>,
+    action: &str,
+    failure: &str,
+) -> Result<Response> {
     // Check authentication
     let user = match AuthMiddleware::require_auth(&req, &ctx.env).await? {
         Ok(user) => user,
@@ -103,24 +217,159 @@ pub async fn disconnect_client(req: Request, ctx: RouteContext<()>) -> Result<Re
 
     // Get the user's Durable Object
     let namespace = ctx.env.durable_object("USER_HUB")?;
-    let id = namespace.id_from_name(&user.id)?;
+    let id = namespace.id_from_name(&user_hub_name(&ctx.env, &user.id))?;
     let stub = id.get_stub()?;
 
-    // Send disconnect request to DO
+    // Send the action request to DO
     let do_req = Request::new(
-        &format!("https://do/clients/{}/disconnect", client_id),
+        &format!("https://do/clients/{}/{}", client_id, action),
         Method::Post,
     )?;
     let response = stub.fetch_with_request(do_req).await?;
 
     if response.status_code() == 200 {
+        crate::user_cache::invalidate_client(client_id);
+
         // Return updated client list for HTMX swap
         let clients_req = Request::new("https://do/clients", Method::Get)?;
         let mut clients_response = stub.fetch_with_request(clients_req).await?;
         let clients: Vec<Client> = clients_response.json().await.unwrap_or_default();
+        let limit = client_display_limit(&req, &ctx.env)?;
+
+        Response::from_html(templates::render_client_list(&clients, limit))
+    } else {
+        Response::error(failure, response.status_code())
+    }
+}
+
+/// Disconnect a client (sends disconnect command via WebSocket)
+pub async fn disconnect_client(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    client_list_action(req, ctx, "disconnect", "Failed to disconnect client").await
+}
+
+/// Approve a client waiting in the REQUIRE_CLIENT_APPROVAL queue
+pub async fn approve_client(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    client_list_action(req, ctx, "approve", "Failed to approve client").await
+}
+
+/// Reject (and disconnect) a client waiting in the REQUIRE_CLIENT_APPROVAL queue
+pub async fn reject_client(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    client_list_action(req, ctx, "reject", "Failed to reject client").await
+}
+
+/// Show the typed-confirmation modal for disconnecting every client
+pub async fn confirm_disconnect_all(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    // Check authentication
+    let user = match AuthMiddleware::require_auth(&req, &ctx.env).await? {
+        Ok(user) => user,
+        Err(redirect) => return Ok(redirect),
+    };
+
+    Response::from_html(templates::render_confirm_modal(
+        "Disconnect All",
+        "This closes the connection of every client. They will reconnect on their own if configured to.",
+        "/clients/disconnect-all",
+        &user.github_login,
+    ))
+}
 
-        Response::from_html(templates::render_client_list(&clients))
+/// Disconnect every client (requires typed confirmation)
+pub async fn disconnect_all_clients(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    // Check authentication
+    let user = match AuthMiddleware::require_auth(&req, &ctx.env).await? {
+        Ok(user) => user,
+        Err(redirect) => return Ok(redirect),
+    };
+
+    if let Some(rejection) = super::check_confirmation(&mut req, &ctx.env, &user).await? {
+        return Ok(rejection);
+    }
+
+    // Get the user's Durable Object
+    let namespace = ctx.env.durable_object("USER_HUB")?;
+    let id = namespace.id_from_name(&user_hub_name(&ctx.env, &user.id))?;
+    let stub = id.get_stub()?;
+
+    let do_req = Request::new("https://do/clients/disconnect-all", Method::Post)?;
+    let mut response = stub.fetch_with_request(do_req).await?;
+    let result: serde_json::Value = response.json().await?;
+
+    if super::wants_htmx(&req) {
+        // Close the modal; the form triggers a client list refresh
+        Response::from_html("")
+    } else {
+        Response::from_json(&result)
+    }
+}
+
+/// Ask a client to re-send its metadata (the update reaches the dashboard over WebSocket)
+pub async fn refresh_client_metadata(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    // Check authentication
+    let user = match AuthMiddleware::require_auth(&req, &ctx.env).await? {
+        Ok(user) => user,
+        Err(redirect) => return Ok(redirect),
+    };
+
+    let client_id = ctx.param("id").ok_or("Missing client ID")?;
+
+    // Get the user's Durable Object
+    let namespace = ctx.env.durable_object("USER_HUB")?;
+    let id = namespace.id_from_name(&user_hub_name(&ctx.env, &user.id))?;
+    let stub = id.get_stub()?;
+
+    let do_req = Request::new(
+        &format!("https://do/clients/{}/refresh", client_id),
+        Method::Post,
+    )?;
+    let response = stub.fetch_with_request(do_req).await?;
+    let requested = response.status_code() == 200;
+
+    // Check if this is an HTMX request
+    let is_htmx = super::wants_htmx(&req);
+
+    if is_htmx {
+        Response::from_html(templates::render_refresh_result(requested))
+    } else if requested {
+        Response::from_json(&serde_json::json!({ "requested": true }))
+    } else {
+        Response::error("Client not connected", 404)
+    }
+}
+
+/// Test whether the orchestrator can reach a client's callback URL
+pub async fn ping_client_proxy(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    // Check authentication
+    let user = match AuthMiddleware::require_auth(&req, &ctx.env).await? {
+        Ok(user) => user,
+        Err(redirect) => return Ok(redirect),
+    };
+
+    let client_id = ctx.param("id").ok_or("Missing client ID")?;
+
+    // Get the user's Durable Object
+    let namespace = ctx.env.durable_object("USER_HUB")?;
+    let id = namespace.id_from_name(&user_hub_name(&ctx.env, &user.id))?;
+    let stub = id.get_stub()?;
+
+    // Ask the DO to probe the client's callback URL
+    let do_req = Request::new(
+        &format!("https://do/clients/{}/ping-proxy", client_id),
+        Method::Post,
+    )?;
+    let mut response = stub.fetch_with_request(do_req).await?;
+
+    if response.status_code() != 200 {
+        return Response::error("Client not found", 404);
+    }
+
+    let health: ProxyHealth = response.json().await?;
+
+    // Check if this is an HTMX request
+    let is_htmx = super::wants_htmx(&req);
+
+    if is_htmx {
+        Response::from_html(templates::render_proxy_health(&health))
     } else {
-        Response::error("Failed to disconnect client", 500)
+        Response::from_json(&health)
     }
 }
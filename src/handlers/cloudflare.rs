@@ -2,14 +2,13 @@ use serde::{Deserialize, Serialize};
 use worker::*;
 
 use crate::auth::AuthMiddleware;
+use crate::durable_objects::user_hub_name;
 
 /// Cloudflare API response structure
 #[derive(Debug, Deserialize)]
 struct CloudflareResponse {
     success: bool,
     errors: Vec<CloudflareError>,
-    #[serde(default)]
-    messages: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -18,6 +17,24 @@ struct CloudflareError {
     message: String,
 }
 
+/// Retries after a rate-limited purge before giving up
+const PURGE_MAX_RETRIES: u32 = 2;
+/// Wait before the first retry when Cloudflare sends no Retry-After; doubles each retry
+const PURGE_RETRY_BASE_MS: u64 = 1_000;
+/// Longest wait honored from Retry-After, so a retry can't outlive the request
+const PURGE_MAX_RETRY_WAIT_MS: u64 = 10_000;
+/// Cloudflare API error code for "too many requests" reported with a non-429 status
+const CF_RATE_LIMIT_ERROR_CODE: i32 = 971;
+
+/// Outcome of a single purge request
+struct PurgeAttempt {
+    result: CloudflareResponse,
+    /// Cloudflare rejected the request for exceeding its rate limits
+    rate_limited: bool,
+    /// Wait requested by the `Retry-After` header, if any
+    retry_after_ms: Option<u64>,
+}
+
 /// Request body for cache purge by prefix
 #[derive(Debug, Serialize)]
 struct PurgeCacheByPrefixRequest {
@@ -27,7 +44,7 @@ struct PurgeCacheByPrefixRequest {
 /// Purge Cloudflare cache for a specific client's proxy URLs
 pub async fn purge_client_cache(req: Request, ctx: RouteContext<()>) -> Result<Response> {
     // Check authentication
-    let _user = match AuthMiddleware::require_auth(&req, &ctx.env).await? {
+    let user = match AuthMiddleware::require_auth(&req, &ctx.env).await? {
         Ok(user) => user,
         Err(redirect) => return Ok(redirect),
     };
@@ -35,6 +52,19 @@ pub async fn purge_client_cache(req: Request, ctx: RouteContext<()>) -> Result<R
     // Get client ID from path parameter
     let client_id = ctx.param("id").ok_or("Missing client ID")?.clone();
 
+    // Drop any assets the Durable Object cached in R2 for this client
+    let namespace = ctx.env.durable_object("USER_HUB")?;
+    let stub = namespace
+        .id_from_name(&user_hub_name(&ctx.env, &user.id))?
+        .get_stub()?;
+    let do_req = Request::new(
+        &format!("https://do/clients/{}/purge-assets", client_id),
+        Method::Post,
+    )?;
+    if let Err(e) = stub.fetch_with_request(do_req).await {
+        console_error!("[CLOUDFLARE] Failed to purge R2 asset cache: {:?}", e);
+    }
+
     // Get Cloudflare credentials from environment
     let zone_id = match ctx.env.secret("CLOUDFLARE_ZONE_ID") {
         Ok(secret) => secret.to_string(),
@@ -57,8 +87,8 @@ pub async fn purge_client_cache(req: Request, ctx: RouteContext<()>) -> Result<R
     };
 
     // Build the cache purge request
-    // Purge all URLs under /clients/{client_id}/proxy/
-    let prefix = format!("/clients/{}/proxy/", client_id);
+    // Purge all URLs under /clients/{client_id}/proxy/ (below BASE_PATH)
+    let prefix = crate::config::app_path(&ctx.env, &format!("/clients/{}/proxy/", client_id));
 
     // We need the full URL with hostname for Cloudflare's purge API
     // Get the hostname from the request
@@ -78,31 +108,48 @@ pub async fn purge_client_cache(req: Request, ctx: RouteContext<()>) -> Result<R
         zone_id
     );
 
-    let headers = Headers::new();
-    headers.set("Authorization", &format!("Bearer {}", api_token))?;
-    headers.set("Content-Type", "application/json")?;
-
     let body = serde_json::to_string(&purge_request)?;
 
-    let mut init = RequestInit::new();
-    init.with_method(Method::Post);
-    init.with_headers(headers);
-    init.with_body(Some(wasm_bindgen::JsValue::from_str(&body)));
-
-    let cf_req = Request::new_with_init(&cf_url, &init)?;
+    // The purge API is rate limited; back off and retry a couple of times
+    let mut attempt = 0;
+    let purge = loop {
+        let purge = send_purge_request(&cf_url, &api_token, &body).await?;
+        if !purge.rate_limited || attempt >= PURGE_MAX_RETRIES {
+            break purge;
+        }
 
-    let mut cf_response = Fetch::Request(cf_req).send().await?;
-    let response_text = cf_response.text().await?;
+        let wait_ms = purge
+            .retry_after_ms
+            .unwrap_or(PURGE_RETRY_BASE_MS << attempt)
+            .min(PURGE_MAX_RETRY_WAIT_MS);
+        console_log!(
+            "[CLOUDFLARE] Cache purge rate limited, retrying in {}ms",
+            wait_ms
+        );
+        Delay::from(std::time::Duration::from_millis(wait_ms)).await;
+        attempt += 1;
+    };
 
-    // Parse Cloudflare response
-    let cf_result: CloudflareResponse = serde_json::from_str(&response_text)
-        .map_err(|e| Error::RustError(format!("Failed to parse Cloudflare response: {}", e)))?;
+    if purge.rate_limited {
+        console_error!("[CLOUDFLARE] Cache purge still rate limited after retries");
+        let mut response = Response::error(
+            "Cache purge rate limited by Cloudflare, try again later",
+            429,
+        )?;
+        if let Some(ms) = purge.retry_after_ms {
+            response
+                .headers_mut()
+                .set("Retry-After", &ms.div_ceil(1_000).to_string())?;
+        }
+        return Ok(response);
+    }
 
+    let cf_result = purge.result;
     if cf_result.success {
         console_log!("[CLOUDFLARE] Successfully purged cache for prefix: {}", full_prefix);
 
         // Check if this is an HTMX request
-        let is_htmx = req.headers().get("HX-Request")?.is_some();
+        let is_htmx = super::wants_htmx(&req);
 
         if is_htmx {
             // Return a success notification for HTMX
@@ -130,3 +177,41 @@ pub async fn purge_client_cache(req: Request, ctx: RouteContext<()>) -> Result<R
         Response::error(format!("Cache purge failed: {}", error_msg), 500)
     }
 }
+
+/// Send one purge request to the Cloudflare API
+async fn send_purge_request(cf_url: &str, api_token: &str, body: &str) -> Result<PurgeAttempt> {
+    let headers = Headers::new();
+    headers.set("Authorization", &format!("Bearer {}", api_token))?;
+    headers.set("Content-Type", "application/json")?;
+
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post);
+    init.with_headers(headers);
+    init.with_body(Some(wasm_bindgen::JsValue::from_str(body)));
+
+    let cf_req = Request::new_with_init(cf_url, &init)?;
+
+    let mut cf_response = Fetch::Request(cf_req).send().await?;
+    let retry_after_ms = cf_response
+        .headers()
+        .get("Retry-After")?
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(|seconds| seconds * 1_000);
+    let status = cf_response.status_code();
+    let response_text = cf_response.text().await?;
+
+    // Parse Cloudflare response
+    let cf_result: CloudflareResponse = serde_json::from_str(&response_text)
+        .map_err(|e| Error::RustError(format!("Failed to parse Cloudflare response: {}", e)))?;
+
+    let rate_limited = status == 429
+        || cf_result
+            .errors
+            .iter()
+            .any(|e| e.code == CF_RATE_LIMIT_ERROR_CODE);
+    Ok(PurgeAttempt {
+        result: cf_result,
+        rate_limited,
+        retry_after_ms,
+    })
+}
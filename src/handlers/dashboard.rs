@@ -1,6 +1,7 @@
 use worker::*;
 
-use crate::auth::AuthMiddleware;
+use crate::auth::{AuthMiddleware, WELCOME_TOKEN_COOKIE};
+use crate::models::TOKEN_PREFIX;
 use crate::templates;
 
 /// Dashboard page - requires authentication
@@ -11,6 +12,53 @@ pub async fn dashboard(req: Request, ctx: RouteContext<()>) -> Result<Response>
         Err(redirect) => return Ok(redirect),
     };
 
+    // A token auto-created at first login is shown once, then its cookie is cleared
+    let welcome_token = crate::auth::get_cookie(&req, WELCOME_TOKEN_COOKIE)
+        .ok()
+        .filter(|token| token.starts_with(TOKEN_PREFIX));
+    let modal = match &welcome_token {
+        Some(token) => Some(templates::render_welcome_token(
+            token,
+            &connect_url(&req, &ctx.env)?,
+        )),
+        None => None,
+    };
+
+    // Hubs other users have shared with this one, for the hub switcher
+    let shared_hubs = match super::viewers::shared_hubs(&ctx.env, &user).await {
+        Ok(hubs) => hubs,
+        Err(e) => {
+            console_error!("Failed to load shared hubs: {:?}", e);
+            Vec::new()
+        }
+    };
+
     // Render dashboard with user info
-    Response::from_html(templates::render_dashboard(&user))
+    let mut response = Response::from_html(templates::render_dashboard(
+        &user,
+        &shared_hubs,
+        modal.as_deref(),
+    ))?;
+    if welcome_token.is_some() {
+        response.headers_mut().set(
+            "Set-Cookie",
+            &crate::config::cookie(&ctx.env, WELCOME_TOKEN_COOKIE, "", 0),
+        )?;
+    }
+    Ok(response)
+}
+
+/// WebSocket URL claudecodeui connects to on this deployment
+fn connect_url(req: &Request, env: &Env) -> Result<String> {
+    let url = req.url()?;
+    let scheme = if url.scheme() == "http" { "ws" } else { "wss" };
+    let host = url.host_str().unwrap_or_default();
+    let port = url.port().map(|p| format!(":{}", p)).unwrap_or_default();
+    Ok(format!(
+        "{}://{}{}{}",
+        scheme,
+        host,
+        port,
+        crate::config::app_path(env, "/ws/connect")
+    ))
 }
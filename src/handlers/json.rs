@@ -0,0 +1,124 @@
+use serde::Serialize;
+use worker::*;
+
+use crate::query::Query;
+
+/// Serialize a list for an `/api/*` endpoint
+///
+/// Honours `?pretty=true` (indented output) and `?fields=a,b` (keep only the
+/// named top-level fields of each item). Field names are checked against
+/// `known_fields`; an unknown name returns a 400 JSON error.
+pub(super) fn list_response<T: Serialize>(
+    req: &Request,
+    items: &[T],
+    known_fields: &[&str],
+) -> Result<Response> {
+    let params = Query::from_request(req)?;
+    let body = match list_body(&params, items, known_fields)? {
+        Ok(body) => body,
+        Err(unknown) => {
+            return Ok(Response::from_json(&serde_json::json!({
+                "error": format!("unknown field: {}", unknown),
+                "known_fields": known_fields,
+            }))?
+            .with_status(400));
+        }
+    };
+
+    let headers = Headers::new();
+    headers.set("Content-Type", "application/json")?;
+    Ok(Response::ok(body)?.with_headers(headers))
+}
+
+/// JSON body for [`list_response`], or the first requested field not in `known_fields`
+fn list_body<T: Serialize>(
+    params: &Query,
+    items: &[T],
+    known_fields: &[&str],
+) -> Result<std::result::Result<String, String>> {
+    let pretty = params.get_bool("pretty", false);
+    let fields: Option<Vec<String>> = params.get_str("fields").map(|value| {
+        value
+            .split(',')
+            .map(|f| f.trim().to_string())
+            .filter(|f| !f.is_empty())
+            .collect()
+    });
+
+    let mut value = serde_json::to_value(items)?;
+    if let Some(fields) = fields.filter(|f| !f.is_empty()) {
+        if let Some(unknown) = fields.iter().find(|f| !known_fields.contains(&f.as_str())) {
+            return Ok(Err(unknown.clone()));
+        }
+
+        if let serde_json::Value::Array(entries) = &mut value {
+            for entry in entries {
+                if let serde_json::Value::Object(map) = entry {
+                    map.retain(|key, _| fields.contains(key));
+                }
+            }
+        }
+    }
+
+    let body = if pretty {
+        serde_json::to_string_pretty(&value)?
+    } else {
+        serde_json::to_string(&value)?
+    };
+    Ok(Ok(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIELDS: &[&str] = &["id", "name", "status"];
+
+    fn body(query: &str, items: &[serde_json::Value]) -> std::result::Result<String, String> {
+        let url = Url::parse(&format!("https://example.com/api/clients?{}", query)).unwrap();
+        list_body(&Query::from_url(&url), items, FIELDS).unwrap()
+    }
+
+    fn items() -> Vec<serde_json::Value> {
+        vec![
+            serde_json::json!({ "id": "c1", "name": "laptop", "status": "idle" }),
+            serde_json::json!({ "id": "c2", "name": "desktop", "status": "busy" }),
+        ]
+    }
+
+    #[test]
+    fn fields_keep_only_the_named_keys() {
+        let projected = body("fields=id,%20status", &items()).unwrap();
+        assert_eq!(
+            projected,
+            r#"[{"id":"c1","status":"idle"},{"id":"c2","status":"busy"}]"#
+        );
+
+        // No names at all is the same as no projection
+        let everything = body("", &items()).unwrap();
+        assert_eq!(body("fields=,", &items()).unwrap(), everything);
+        assert!(everything.contains(r#""name":"laptop""#));
+    }
+
+    #[test]
+    fn unknown_fields_are_reported() {
+        let unknown = Err("secret".to_string());
+        assert_eq!(body("fields=id,secret", &items()), unknown);
+        // Even when there is nothing to project
+        assert_eq!(body("fields=secret", &[]), unknown);
+    }
+
+    #[test]
+    fn empty_lists_stay_empty_arrays() {
+        assert_eq!(body("fields=id", &[]).unwrap(), "[]");
+        assert_eq!(body("pretty=true", &[]).unwrap(), "[]");
+    }
+
+    #[test]
+    fn pretty_output_is_indented() {
+        let items = [serde_json::json!({ "id": "c1" })];
+        let pretty = "[\n  {\n    \"id\": \"c1\"\n  }\n]";
+        assert_eq!(body("pretty=true", &items).unwrap(), pretty);
+        assert_eq!(body("pretty=false", &items).unwrap(), r#"[{"id":"c1"}]"#);
+    }
+}
@@ -1,47 +1,186 @@
+mod admin;
 mod clients;
 mod cloudflare;
 mod dashboard;
+mod json;
 mod proxy;
+mod redact;
+mod rewrite;
+mod sessions;
 mod tokens;
+mod viewers;
 mod websocket;
 
-pub use clients::{disconnect_client, get_client, get_client_details, get_clients};
+pub use admin::{admin_config, admin_disconnect_client};
+pub use clients::{
+    approve_client, confirm_disconnect_all, disconnect_all_clients, disconnect_client, get_client,
+    get_client_api, get_client_details, get_clients, list_clients_api, ping_client_proxy,
+    refresh_client_metadata, reject_client,
+};
 pub use cloudflare::purge_client_cache;
 pub use dashboard::dashboard;
-pub use proxy::proxy_to_client;
+pub use proxy::{proxy_to_client, ws_proxy_to_client};
+pub use sessions::{list_sessions, list_sessions_htmx, revoke_session};
 pub use tokens::{
-    close_token_modal, create_token_api, delete_token, list_tokens, list_tokens_htmx,
-    revoke_token_htmx, show_token_modal, validate_token,
+    bulk_tokens, close_token_modal, confirm_revoke_all, create_first_token, create_token_api,
+    delete_token, list_tokens, list_tokens_htmx, prune_token_usage, revoke_all_tokens,
+    revoke_token_htmx, show_token_modal, token_usage,
+};
+pub use viewers::{
+    add_viewer, list_shared_hubs, list_viewers, remove_viewer, shared_hub_clients,
+    shared_hub_clients_api,
 };
-pub use websocket::websocket_upgrade;
+pub use websocket::{connect_test, websocket_upgrade};
 
 use worker::*;
 
 use crate::auth::AuthMiddleware;
+use crate::models::User;
 use crate::templates;
 
 /// Home page - login screen (redirects to dashboard if already logged in)
+///
+/// Protected pages send signed-out users here with `?next=`, which is carried
+/// through the sign-in link so they land back on the page they asked for.
 pub async fn home(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let next = crate::query::Query::from_request(&req)?
+        .get_str("next")
+        .map(|path| path.to_string())
+        .filter(|path| crate::auth::is_safe_redirect_path(path));
+
     // Check if user is already authenticated
     match AuthMiddleware::require_auth(&req, &ctx.env).await? {
         Ok(_user) => {
-            // User is logged in, redirect to dashboard
+            // User is logged in, redirect to the requested page or the dashboard
             let headers = Headers::new();
-            headers.set("Location", "/dashboard")?;
+            let location = match next {
+                Some(next) => next,
+                None => crate::config::app_path(&ctx.env, "/dashboard"),
+            };
+            headers.set("Location", &location)?;
             Ok(Response::empty()?.with_status(302).with_headers(headers))
         }
         Err(_) => {
             // User is not logged in, show login page
-            Response::from_html(templates::render_home())
+            let gitlab_enabled = crate::config::var_string(&ctx.env, "GITLAB_CLIENT_ID").is_some();
+            Response::from_html(templates::render_home(next.as_deref(), gitlab_enabled))
         }
     }
 }
 
+/// Whether the request came from HTMX and should get an HTML partial
+///
+/// A request that also asks for JSON (see [`wants_json`]) is answered with
+/// JSON instead, so HTMX extensions and scripts can opt out of partials.
+pub(crate) fn wants_htmx(req: &Request) -> bool {
+    htmx_partial(
+        header(req, "HX-Request").as_deref(),
+        header(req, "Accept").as_deref(),
+    )
+}
+
+/// Whether the `Accept` header prefers JSON over HTML
+///
+/// `application/json` must be listed with a non-zero quality at least as high
+/// as `text/html`'s; wildcards alone never select JSON.
+pub(crate) fn wants_json(req: &Request) -> bool {
+    prefers_json(header(req, "Accept").as_deref())
+}
+
+/// [`wants_htmx`] for the given `HX-Request` and `Accept` header values
+fn htmx_partial(hx_request: Option<&str>, accept: Option<&str>) -> bool {
+    let htmx = hx_request.is_some_and(|value| value.trim() != "false");
+    htmx && !prefers_json(accept)
+}
+
+/// [`wants_json`] for the given `Accept` header value
+fn prefers_json(accept: Option<&str>) -> bool {
+    let accept = match accept {
+        Some(accept) => accept,
+        None => return false,
+    };
+
+    let quality = |wanted: &str| {
+        accept
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';').map(str::trim);
+                let media_type = parts.next()?;
+                if !media_type.eq_ignore_ascii_case(wanted) {
+                    return None;
+                }
+                let q = parts
+                    .find_map(|param| param.strip_prefix("q="))
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                Some(q)
+            })
+            .reduce(f32::max)
+    };
+
+    match (quality("application/json"), quality("text/html")) {
+        (Some(json), html) => json > 0.0 && json >= html.unwrap_or(0.0),
+        (None, _) => false,
+    }
+}
+
+/// Value of a request header; unreadable headers count as absent
+fn header(req: &Request, name: &str) -> Option<String> {
+    req.headers().get(name).ok().flatten()
+}
+
+/// Check the typed confirmation for a destructive bulk action
+///
+/// Reads `confirm` from the form (HTMX) or JSON body and requires it to match
+/// the user's login (GitHub or GitLab), unless `REQUIRE_TYPED_CONFIRMATION` is disabled.
+/// Returns a 400 response when the confirmation is missing or wrong.
+async fn check_confirmation(req: &mut Request, env: &Env, user: &User) -> Result<Option<Response>> {
+    if !crate::config::var_bool(env, "REQUIRE_TYPED_CONFIRMATION", true) {
+        return Ok(None);
+    }
+
+    let confirm = if wants_htmx(req) {
+        req.form_data()
+            .await
+            .ok()
+            .and_then(|form| match form.get("confirm") {
+                Some(FormEntry::Field(value)) => Some(value),
+                _ => None,
+            })
+    } else {
+        req.json::<serde_json::Value>()
+            .await
+            .ok()
+            .and_then(|body| body["confirm"].as_str().map(|s| s.to_string()))
+    };
+
+    if confirmation_matches(confirm.as_deref(), &user.github_login) {
+        Ok(None)
+    } else {
+        Ok(Some(Response::error(CONFIRMATION_MISMATCH, 400)?))
+    }
+}
+
+/// Error for a typed confirmation that isn't the user's login, whichever provider it's from
+const CONFIRMATION_MISMATCH: &str = "Confirmation does not match your username";
+
+/// Whether a typed confirmation (surrounding whitespace ignored) is exactly `login`
+fn confirmation_matches(confirm: Option<&str>, login: &str) -> bool {
+    confirm.map(str::trim) == Some(login)
+}
+
 /// Health check endpoint
 pub fn health(_req: Request, _ctx: RouteContext<()>) -> Result<Response> {
     Response::ok("OK")
 }
 
+/// Answer OPTIONS probes from load balancers on `/` and `/health`
+pub fn probe_options(_req: Request, _ctx: RouteContext<()>) -> Result<Response> {
+    let headers = Headers::new();
+    headers.set("Allow", "GET, OPTIONS")?;
+    Ok(Response::empty()?.with_status(204).with_headers(headers))
+}
+
 /// Serve static assets from R2
 pub async fn serve_static(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
     let path = ctx.param("path").unwrap_or(&"".to_string()).clone();
@@ -65,6 +204,18 @@ pub async fn serve_static(_req: Request, ctx: RouteContext<()>) -> Result<Respon
     }
 }
 
+/// Current server time, so the dashboard can correct for a skewed browser clock
+pub async fn server_time(_req: Request, _ctx: RouteContext<()>) -> Result<Response> {
+    let headers = Headers::new();
+    headers.set("Cache-Control", "public, max-age=1")?;
+
+    Ok(Response::from_json(&serde_json::json!({
+        "now_ms": crate::time::now_ms(),
+        "now_iso": crate::time::now_iso(),
+    }))?
+    .with_headers(headers))
+}
+
 fn guess_content_type(path: &str) -> &'static str {
     if path.ends_with(".css") {
         "text/css"
@@ -73,12 +224,88 @@ fn guess_content_type(path: &str) -> &'static str {
     } else if path.ends_with(".html") {
         "text/html"
     } else if path.ends_with(".svg") {
-        "image/svg+xml"
+        "image/svg+xml; charset=utf-8"
     } else if path.ends_with(".png") {
         "image/png"
     } else if path.ends_with(".ico") {
         "image/x-icon"
+    } else if path.ends_with(".webp") {
+        "image/webp"
+    } else if path.ends_with(".avif") {
+        "image/avif"
+    } else if path.ends_with(".woff") {
+        "font/woff"
+    } else if path.ends_with(".woff2") {
+        "font/woff2"
+    } else if path.ends_with(".json") {
+        "application/json; charset=utf-8"
+    } else if path.ends_with(".map") {
+        "application/json"
+    } else if path.ends_with(".txt") {
+        "text/plain; charset=utf-8"
     } else {
         "application/octet-stream"
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_needs_a_quality_at_least_as_high_as_html() {
+        assert!(prefers_json(Some("application/json")));
+        assert!(prefers_json(Some("text/html;q=0.8, application/json")));
+        assert!(prefers_json(Some("Application/JSON;q=0.5, text/html;q=.5")));
+        assert!(!prefers_json(Some("text/html, application/json;q=0.9")));
+        assert!(!prefers_json(Some("application/json;q=0")));
+        assert!(!prefers_json(Some("application/json;q=.4, text/html")));
+        assert!(!prefers_json(None));
+    }
+
+    #[test]
+    fn wildcards_never_select_json() {
+        assert!(!prefers_json(Some("*/*")));
+        assert!(!prefers_json(Some("application/*")));
+        assert!(prefers_json(Some("*/*;q=0.1, application/json")));
+    }
+
+    #[test]
+    fn htmx_requests_get_partials_unless_they_ask_for_json() {
+        assert!(htmx_partial(Some("true"), Some("text/html")));
+        assert!(htmx_partial(Some("true"), Some("*/*")));
+        assert!(htmx_partial(Some("true"), None));
+        assert!(!htmx_partial(Some("true"), Some("application/json")));
+        assert!(!htmx_partial(Some("false"), None));
+        assert!(!htmx_partial(None, Some("text/html")));
+    }
+
+    #[test]
+    fn confirmation_must_be_the_login_on_any_provider() {
+        assert!(confirmation_matches(Some("octocat"), "octocat"));
+        assert!(confirmation_matches(Some(" alice \n"), "alice"));
+        assert!(!confirmation_matches(Some("OctoCat"), "octocat"));
+        assert!(!confirmation_matches(Some(""), "octocat"));
+        assert!(!confirmation_matches(None, "octocat"));
+        assert!(!CONFIRMATION_MISMATCH.contains("GitHub"));
+    }
+
+    #[test]
+    fn content_type_follows_the_extension() {
+        for (path, expected) in [
+            ("/static/app.css", "text/css"),
+            ("/static/app.js", "application/javascript"),
+            ("/static/app.js.map", "application/json"),
+            ("/icons/logo.webp", "image/webp"),
+            ("/icons/logo.avif", "image/avif"),
+            ("/fonts/inter.woff", "font/woff"),
+            ("/fonts/inter.woff2", "font/woff2"),
+            ("/manifest.json", "application/json; charset=utf-8"),
+            ("/robots.txt", "text/plain; charset=utf-8"),
+            ("/archive.tar", "application/octet-stream"),
+            ("/no-extension", "application/octet-stream"),
+        ] {
+            assert_eq!(guess_content_type(path), expected, "{}", path);
+        }
+    }
+}
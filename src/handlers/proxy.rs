@@ -1,27 +1,13 @@
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use wasm_bindgen::JsValue;
 use worker::*;
 
 use crate::auth::AuthMiddleware;
-use crate::models::User;
-
-/// Proxy request to send to the Durable Object
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ProxyRequest {
-    pub method: String,
-    pub path: String,
-    pub headers: Vec<(String, String)>,
-    pub body: Option<String>,
-    pub query: Option<String>,
-}
-
-/// Proxy response from the Durable Object
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ProxyResponse {
-    pub status: u16,
-    pub headers: Vec<(String, String)>,
-    pub body: String,
-}
+use crate::durable_objects::{user_hub_name, PROXY_STREAM_HEADER};
+use crate::models::{
+    build_response, is_relayed_response_header, ProxyRequest, ProxyResponse, User,
+    HOP_BY_HOP_HEADERS,
+};
 
 /// Static paths that don't require authentication (PWA resources)
 const PUBLIC_PROXY_PATHS: &[&str] = &[
@@ -55,8 +41,132 @@ fn is_public_path(path: &str) -> bool {
     false
 }
 
-/// Look up user by client_id from D1 database
+/// Row returned from D1 users query
+#[derive(Debug, Deserialize)]
+struct UserRow {
+    id: String,
+    provider: String,
+    github_id: i64,
+    github_login: String,
+    email: Option<String>,
+    created_at: String,
+    last_login: Option<String>,
+}
+
+/// Lowercased response header names from PROXY_STRIP_RESPONSE_HEADERS (comma-separated)
+pub(super) fn stripped_response_headers(env: &Env) -> Vec<String> {
+    crate::config::var_string(env, "PROXY_STRIP_RESPONSE_HEADERS")
+        .map(|list| {
+            list.split(',')
+                .map(|h| h.trim().to_lowercase())
+                .filter(|h| !h.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Header marking a 401/403 that came from claudecodeui rather than the orchestrator
+const UPSTREAM_AUTH_HEADER: &str = "X-Upstream-Auth";
+
+/// Flag claudecodeui's own auth failures on a relayed response
+///
+/// Upstream 401/403 responses get `X-Upstream-Auth: true` so the frontend can
+/// tell them apart from the orchestrator's and skip the login redirect. When
+/// PROXY_UPSTREAM_AUTH_STATUS is set, their status is replaced by it as well.
+fn mark_upstream_auth(env: &Env, mut response: Response) -> Response {
+    let status = response.status_code();
+    if status != 401 && status != 403 {
+        return response;
+    }
+    let _ = response.headers_mut().set(UPSTREAM_AUTH_HEADER, "true");
+    match crate::config::var_u64(env, "PROXY_UPSTREAM_AUTH_STATUS", status as u64) {
+        replacement @ 200..=599 => response.with_status(replacement as u16),
+        _ => response,
+    }
+}
+
+/// `Content-Type` given to relayed responses that don't declare one
+const FALLBACK_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// Stop browsers from sniffing relayed content into something executable
+///
+/// Sets `X-Content-Type-Options: nosniff` and labels responses without a
+/// `Content-Type` as `application/octet-stream`, so they are never rendered
+/// in the orchestrator's origin. The client's own `Content-Type` is kept
+/// as-is. PROXY_NOSNIFF=false turns this off.
+fn protect_content_type(env: &Env, mut response: Response) -> Response {
+    if !crate::config::var_bool(env, "PROXY_NOSNIFF", true) {
+        return response;
+    }
+    let headers = response.headers_mut();
+    let _ = headers.set("X-Content-Type-Options", "nosniff");
+    if !headers.has("Content-Type").unwrap_or(false) {
+        let _ = headers.set("Content-Type", FALLBACK_CONTENT_TYPE);
+    }
+    response
+}
+
+/// Return a JSON error raised by the orchestrator rather than the proxied client
+fn orchestrator_error(status: u16, message: &str) -> Result<Response> {
+    Ok(Response::from_json(&orchestrator_error_body(message, None))?.with_status(status))
+}
+
+/// Body of an orchestrator error, with the upstream details if given
+fn orchestrator_error_body(
+    message: &str,
+    upstream: Option<serde_json::Value>,
+) -> serde_json::Value {
+    let mut body = serde_json::json!({
+        "error": message,
+        "source": "orchestrator"
+    });
+    if let Some(upstream) = upstream {
+        body["upstream"] = upstream;
+    }
+    body
+}
+
+/// Longest upstream body snippet included in errors when `PROXY_ERROR_DETAILS` is on
+const MAX_UPSTREAM_SNIPPET_BYTES: usize = 512;
+
+/// Orchestrator error for a response that arrived but could not be relayed
+///
+/// With `PROXY_ERROR_DETAILS` on, the body also carries the upstream status
+/// and the start of its body under `upstream`. Off by default, since the
+/// snippet is returned unredacted.
+fn upstream_error(
+    env: &Env,
+    status: u16,
+    message: &str,
+    upstream_status: u16,
+    upstream_body: &str,
+) -> Result<Response> {
+    let details = crate::config::var_bool(env, "PROXY_ERROR_DETAILS", false);
+    let upstream = details.then(|| upstream_details(upstream_status, upstream_body));
+    Ok(Response::from_json(&orchestrator_error_body(message, upstream))?.with_status(status))
+}
+
+/// `upstream` part of an [`upstream_error`], keeping at most
+/// `MAX_UPSTREAM_SNIPPET_BYTES` of the body (cut at a character boundary)
+fn upstream_details(upstream_status: u16, upstream_body: &str) -> serde_json::Value {
+    let mut end = upstream_body.len().min(MAX_UPSTREAM_SNIPPET_BYTES);
+    while !upstream_body.is_char_boundary(end) {
+        end -= 1;
+    }
+    serde_json::json!({
+        "status": upstream_status,
+        "body": &upstream_body[..end],
+        "truncated": end < upstream_body.len(),
+    })
+}
+
+/// Look up user by client_id (briefly cached, see `user_cache`)
 async fn lookup_user_by_client(env: &Env, client_id: &str) -> Result<Option<User>> {
+    crate::user_cache::client_user(env, client_id, || load_user_by_client(env, client_id)).await
+}
+
+/// Look up user by client_id from D1 database
+async fn load_user_by_client(env: &Env, client_id: &str) -> Result<Option<User>> {
     let db = env.d1("DB")?;
 
     // Query the clients table to find the user_id for this client
@@ -65,25 +175,50 @@ async fn lookup_user_by_client(env: &Env, client_id: &str) -> Result<Option<User
 
     if let Some(user_id) = result {
         // Look up the full user record
-        let user_stmt = db.prepare("SELECT id, github_id, github_login, email, created_at, last_login FROM users WHERE id = ?1");
-        let user_row = user_stmt.bind(&[user_id.into()])?.first::<serde_json::Value>(None).await?;
-
-        if let Some(row) = user_row {
-            return Ok(Some(User {
-                id: row["id"].as_str().unwrap_or_default().to_string(),
-                github_id: row["github_id"].as_i64().unwrap_or(0),
-                github_login: row["github_login"].as_str().unwrap_or_default().to_string(),
-                email: row["email"].as_str().map(|s| s.to_string()),
-                created_at: row["created_at"].as_str().unwrap_or_default().to_string(),
-                last_login: row["last_login"].as_str().map(|s| s.to_string()),
-            }));
-        }
+        let user_stmt = db.prepare(
+            "SELECT id, provider, github_id, github_login, email, created_at, last_login FROM users WHERE id = ?1",
+        );
+        let user_row = user_stmt.bind(&[user_id.into()])?.first::<UserRow>(None).await?;
+
+        return Ok(user_row.map(|row| User {
+            id: row.id,
+            provider: row.provider,
+            github_id: row.github_id,
+            github_login: row.github_login,
+            email: row.email,
+            created_at: row.created_at,
+            last_login: row.last_login,
+        }));
     }
 
     Ok(None)
 }
 
+/// Resolve the session user, reusing a recent lookup for the same session
+async fn authenticate(req: &Request, env: &Env) -> Result<std::result::Result<User, Response>> {
+    let user = match AuthMiddleware::session_id(req) {
+        Some(id) => {
+            let load = || AuthMiddleware::get_user(req, env);
+            crate::user_cache::session_user(env, &id, load).await?
+        }
+        None => None,
+    };
+    match user {
+        Some(user) => Ok(Ok(user)),
+        None => Ok(Err(AuthMiddleware::sign_in_redirect(req, env)?)),
+    }
+}
+
 /// Proxy HTTP requests to claudecodeui instances
+///
+/// Most responses are buffered: claudecodeui sends the complete body in a
+/// single `http_proxy_response` WebSocket message, so chunked upstream
+/// responses are reassembled before they reach the Worker. Event streams and
+/// requests with `stream=true` are instead relayed chunk by chunk when the
+/// client answers with `http_proxy_response_start`; those bodies bypass the
+/// rewrite rules. Framing headers that describe the upstream encoding
+/// (`Transfer-Encoding`, `Trailer`, `Content-Length`) are dropped and the
+/// runtime re-frames the body. Trailers themselves are not relayed.
 pub async fn proxy_to_client(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
     // Get the proxy path early to check if it's a public resource
     let proxy_path = ctx.param("path").unwrap_or(&"".to_string()).clone();
@@ -96,14 +231,15 @@ pub async fn proxy_to_client(mut req: Request, ctx: RouteContext<()>) -> Result<
 
     let is_public = is_public_path(&proxy_path);
 
-    // Try to authenticate the user
-    let user: Option<User> = match AuthMiddleware::require_auth(&req, &ctx.env).await? {
-        Ok(user) => Some(user),
+    // Try to authenticate the user. `authenticated` is only true when the
+    // session belongs to the requester; the D1 fallback is used for routing only
+    let (user, authenticated) = match authenticate(&req, &ctx.env).await? {
+        Ok(user) => (Some(user), true),
         Err(redirect) => {
             if is_public {
                 // For public paths, try to look up user by client_id in D1
                 match lookup_user_by_client(&ctx.env, &client_id).await {
-                    Ok(Some(user)) => Some(user),
+                    Ok(Some(user)) => (Some(user), false),
                     Ok(None) => {
                         // Client not found in D1, can't route
                         return Response::error("Client not found", 404);
@@ -142,41 +278,42 @@ pub async fn proxy_to_client(mut req: Request, ctx: RouteContext<()>) -> Result<
 
     // Collect headers (filter out hop-by-hop headers)
     let mut headers: Vec<(String, String)> = Vec::new();
-    let hop_by_hop = [
-        "connection",
-        "keep-alive",
-        "proxy-authenticate",
-        "proxy-authorization",
-        "te",
-        "trailers",
-        "transfer-encoding",
-        "upgrade",
-        "host",
-    ];
-
     for (key, value) in req.headers() {
         let key_lower = key.to_lowercase();
-        if !hop_by_hop.contains(&key_lower.as_str()) {
+        // Identity headers are only ever set by the orchestrator, never passed through
+        if !HOP_BY_HOP_HEADERS.contains(&key_lower.as_str())
+            && !key_lower.starts_with("x-orchestrator-")
+        {
             headers.push((key, value));
         }
     }
 
     // Add orchestrator user info headers for auto-authentication
-    // claudecodeui can use these to auto-login the user without requiring separate auth
-    headers.push((
-        "X-Orchestrator-User-Id".to_string(),
-        user.github_id.to_string(),
-    ));
-    headers.push((
-        "X-Orchestrator-Username".to_string(),
-        user.github_login.clone(),
-    ));
-
-    // Get request body if present
-    let body = if req.method() != Method::Get && req.method() != Method::Head {
-        req.text().await.ok()
+    // claudecodeui can use these to auto-login the user without requiring separate auth.
+    // Unauthenticated public-path requests were routed via D1 and must not carry them.
+    if authenticated {
+        headers.push((
+            "X-Orchestrator-User-Id".to_string(),
+            user.github_id.to_string(),
+        ));
+        headers.push((
+            "X-Orchestrator-Username".to_string(),
+            user.github_login.clone(),
+        ));
+    }
+
+    // Get request body if present; binary payloads are sent base64-encoded
+    let (body, body_is_base64) = if req.method() != Method::Get && req.method() != Method::Head {
+        let content_type = req.headers().get("Content-Type").ok().flatten();
+        match req.bytes().await {
+            Ok(bytes) => {
+                let (body, is_base64) = crate::body::encode(content_type.as_deref(), bytes);
+                (Some(body), is_base64)
+            }
+            Err(_) => (None, false),
+        }
     } else {
-        None
+        (None, false)
     };
 
     // Build proxy request
@@ -185,12 +322,30 @@ pub async fn proxy_to_client(mut req: Request, ctx: RouteContext<()>) -> Result<
         path: format!("/{}", proxy_path),
         headers,
         body,
+        body_is_base64,
         query: query_string,
     };
 
+    // Opt-in request/response logging, with secrets masked
+    let debug_log = crate::config::var_bool(&ctx.env, "PROXY_DEBUG_LOG", false);
+    let redactor = super::redact::Redactor::from_env(&ctx.env);
+    if debug_log {
+        console_log!(
+            "{}",
+            serde_json::json!({
+                "event": "proxy_request",
+                "client_id": client_id,
+                "method": proxy_req.method,
+                "path": proxy_req.path,
+                "headers": redactor.headers(&proxy_req.headers),
+                "body": redactor.body(proxy_req.body.as_deref()),
+            })
+        );
+    }
+
     // Forward to user's Durable Object
     let namespace = ctx.env.durable_object("USER_HUB")?;
-    let id = namespace.id_from_name(&user.id)?;
+    let id = namespace.id_from_name(&user_hub_name(&ctx.env, &user.id))?;
     let stub = id.get_stub()?;
 
     // Create request to DO's proxy endpoint
@@ -205,41 +360,223 @@ pub async fn proxy_to_client(mut req: Request, ctx: RouteContext<()>) -> Result<
     init.with_body(Some(JsValue::from_str(&body_json)));
 
     let do_req = Request::new_with_init(&do_url, &init)?;
-    let do_resp = stub.fetch_with_request(do_req).await?;
+    let mut do_resp = match stub.fetch_with_request(do_req).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            console_error!("Proxy request to Durable Object failed: {:?}", e);
+            return orchestrator_error(503, "Orchestrator unavailable, try again");
+        }
+    };
+
+    // Operator-denied response headers, dropped along with hop-by-hop ones
+    let stripped = stripped_response_headers(&ctx.env);
+
+    // Streamed responses arrive as the client's status, headers and raw body
+    if do_resp.headers().has(PROXY_STREAM_HEADER)? {
+        let status = do_resp.status_code();
+        let resp_headers = Headers::new();
+        for (key, value) in do_resp.headers() {
+            if is_relayed_response_header(&key, &stripped)
+                && !key.eq_ignore_ascii_case(PROXY_STREAM_HEADER)
+            {
+                let _ = resp_headers.append(&key, &value);
+            }
+        }
+
+        if debug_log {
+            console_log!(
+                "{}",
+                serde_json::json!({
+                    "event": "proxy_response",
+                    "client_id": client_id,
+                    "status": status,
+                    "streamed": true,
+                })
+            );
+        }
 
-    // Check if we got a successful proxy response
-    if do_resp.status_code() != 200 {
-        return Ok(do_resp);
+        let response = Response::from_stream(do_resp.stream()?)?
+            .with_status(status)
+            .with_headers(resp_headers);
+        let response = protect_content_type(&ctx.env, response);
+        return Ok(mark_upstream_auth(&ctx.env, response));
     }
 
-    // Parse the proxy response
-    let mut do_resp_mut = do_resp;
-    let resp_text = do_resp_mut.text().await?;
-    let proxy_resp: ProxyResponse = serde_json::from_str(&resp_text)
-        .map_err(|e| Error::RustError(format!("Failed to parse proxy response: {}", e)))?;
+    // The DO always answers /proxy/* with a ProxyResponse; anything else means
+    // the DO itself failed before it could build one
+    let resp_text = do_resp.text().await?;
+    let mut proxy_resp: ProxyResponse = match serde_json::from_str(&resp_text) {
+        Ok(proxy_resp) => proxy_resp,
+        Err(_) => {
+            console_error!(
+                "Unexpected proxy response from Durable Object ({}): {}",
+                do_resp.status_code(),
+                resp_text
+            );
+            return upstream_error(
+                &ctx.env,
+                502,
+                "Unexpected response from orchestrator",
+                do_resp.status_code(),
+                &resp_text,
+            );
+        }
+    };
+
+    if debug_log {
+        console_log!(
+            "{}",
+            serde_json::json!({
+                "event": "proxy_response",
+                "client_id": client_id,
+                "status": proxy_resp.status,
+                "error": proxy_resp.error,
+                "headers": redactor.headers(&proxy_resp.headers),
+                "body": redactor.body(Some(&proxy_resp.body)),
+            })
+        );
+    }
+
+    // Errors raised by the orchestrator (client offline, timeout, ...) are
+    // reported as such instead of being passed off as the client's response
+    if let Some(message) = proxy_resp.error {
+        return orchestrator_error(proxy_resp.status, &message);
+    }
+
+    // URL rewriting is handled by claudecodeui (it receives proxy_base in the request);
+    // operator-configured rewrite rules are applied on top for textual bodies
+    if !proxy_resp.body_is_base64 {
+        let content_type = proxy_resp
+            .headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("content-type"))
+            .map(|(_, v)| v.as_str());
+        let rules = super::rewrite::load_rules(&ctx.env);
+        let body = std::mem::take(&mut proxy_resp.body);
+        proxy_resp.body = super::rewrite::apply_rules(&rules, content_type, body);
+    }
 
-    // Build the response to return to the client
-    let mut resp_headers = Headers::new();
-    for (key, value) in &proxy_resp.headers {
+    // Relay the client's status, headers and body
+    let response = match build_response(&proxy_resp, &stripped) {
+        Ok(response) => response,
+        Err(e) => {
+            console_error!("Failed to relay proxy response: {:?}", e);
+            return upstream_error(
+                &ctx.env,
+                502,
+                "Invalid response body from client",
+                proxy_resp.status,
+                &proxy_resp.body,
+            );
+        }
+    };
+    let response = protect_content_type(&ctx.env, response);
+    Ok(mark_upstream_auth(&ctx.env, response))
+}
+
+/// Tunnel a browser WebSocket to a claudecodeui instance
+///
+/// The socket is accepted by the user's Durable Object, which relays frames
+/// over the client's existing control connection (`ws_proxy_*` messages), so
+/// the client needs no publicly reachable callback URL.
+pub async fn ws_proxy_to_client(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let proxy_path = ctx.param("path").cloned().unwrap_or_default();
+    let client_id = ctx.param("id").ok_or("Missing client ID")?.clone();
+
+    let upgrade = req.headers().get("Upgrade")?;
+    if !upgrade.is_some_and(|u| u.eq_ignore_ascii_case("websocket")) {
+        return Response::error("Expected websocket", 426);
+    }
+
+    // Sockets can't follow a login redirect
+    let user = match authenticate(&req, &ctx.env).await? {
+        Ok(user) => user,
+        Err(_) => return Response::error("Unauthorized", 401),
+    };
+
+    // Pass the browser's headers on, minus the ones describing this connection.
+    // Requested subprotocols are kept so the upstream can pick one
+    let headers = Headers::new();
+    for (key, value) in req.headers() {
         let key_lower = key.to_lowercase();
-        // Skip hop-by-hop headers in response too
-        if !hop_by_hop.contains(&key_lower.as_str()) {
-            let _ = resp_headers.set(key, value);
+        if !HOP_BY_HOP_HEADERS.contains(&key_lower.as_str())
+            && (!key_lower.starts_with("sec-websocket-") || key_lower == "sec-websocket-protocol")
+            && !key_lower.starts_with("x-orchestrator-")
+        {
+            headers.set(&key, &value)?;
+        }
+    }
+    headers.set("X-Orchestrator-User-Id", &user.github_id.to_string())?;
+    headers.set("X-Orchestrator-Username", &user.github_login)?;
+    headers.set("Upgrade", "websocket")?;
+
+    let mut do_url = Url::parse(&format!("https://do/ws-proxy/{}", client_id))?;
+    {
+        let mut query = do_url.query_pairs_mut();
+        query.append_pair("path", &format!("/{}", proxy_path));
+        if let Some(original) = req.url()?.query() {
+            query.append_pair("query", original);
         }
     }
 
-    // URL rewriting is handled by claudecodeui (it receives proxy_base in the request)
-    let response_body = proxy_resp.body;
+    let mut init = RequestInit::new();
+    init.with_method(Method::Get);
+    init.with_headers(headers);
 
-    // Create response with the proxied status and body
-    // We need to create a new response with the correct status
-    // worker-rs doesn't have a clean way to set status, so we rebuild it
-    let response = if proxy_resp.status >= 400 {
-        Response::error(&response_body, proxy_resp.status)
-            .map(|r| r.with_headers(resp_headers))?
-    } else {
-        Response::ok(response_body)?.with_headers(resp_headers)
-    };
+    let namespace = ctx.env.durable_object("USER_HUB")?;
+    let stub = namespace
+        .id_from_name(&user_hub_name(&ctx.env, &user.id))?
+        .get_stub()?;
+    let do_req = Request::new_with_init(do_url.as_str(), &init)?;
+    stub.fetch_with_request(do_req).await
+}
 
-    Ok(response)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upstream_details_are_left_out_by_default() {
+        let body = orchestrator_error_body("Invalid upstream response", None);
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "error": "Invalid upstream response",
+                "source": "orchestrator"
+            })
+        );
+    }
+
+    #[test]
+    fn upstream_details_carry_the_status_and_body() {
+        let upstream = upstream_details(502, "Bad gateway");
+        let body = orchestrator_error_body("Invalid upstream response", Some(upstream));
+        assert_eq!(
+            body["upstream"],
+            serde_json::json!({
+                "status": 502,
+                "body": "Bad gateway",
+                "truncated": false
+            })
+        );
+        assert_eq!(body["source"], "orchestrator");
+    }
+
+    #[test]
+    fn long_upstream_bodies_are_truncated() {
+        let exact = "a".repeat(MAX_UPSTREAM_SNIPPET_BYTES);
+        assert_eq!(upstream_details(500, &exact)["truncated"], false);
+
+        let long = format!("{}b", exact);
+        let details = upstream_details(500, &long);
+        assert_eq!(details["body"], exact.as_str());
+        assert_eq!(details["truncated"], true);
+
+        // A multi-byte character straddling the limit is dropped whole
+        let straddling = format!("{}é", "a".repeat(MAX_UPSTREAM_SNIPPET_BYTES - 1));
+        let details = upstream_details(500, &straddling);
+        let kept = "a".repeat(MAX_UPSTREAM_SNIPPET_BYTES - 1);
+        assert_eq!(details["body"], kept.as_str());
+        assert_eq!(details["truncated"], true);
+    }
 }
@@ -0,0 +1,180 @@
+use worker::*;
+
+/// Headers masked in debug logs when `PROXY_REDACT_HEADERS` is unset
+const DEFAULT_REDACT_HEADERS: &str =
+    "authorization,proxy-authorization,cookie,set-cookie,x-api-key";
+
+/// JSON field names masked in debug logs when `PROXY_REDACT_FIELDS` is unset
+const DEFAULT_REDACT_FIELDS: &str =
+    "password,token,access_token,refresh_token,id_token,api_key,apikey,secret,client_secret";
+
+/// Replacement for redacted values
+const REDACTED: &str = "***";
+
+/// Logged bodies are cut off after this many bytes
+const MAX_LOGGED_BODY_BYTES: usize = 4096;
+
+/// Header and JSON field names whose values are masked before logging
+///
+/// Both lists are comma-separated and matched case-insensitively; setting
+/// either env var replaces its defaults.
+pub struct Redactor {
+    headers: Vec<String>,
+    fields: Vec<String>,
+}
+
+impl Redactor {
+    pub fn from_env(env: &Env) -> Self {
+        let var = |name: &str, default: &str| {
+            crate::config::var_string(env, name).unwrap_or_else(|| default.to_string())
+        };
+        Self::from_lists(
+            &var("PROXY_REDACT_HEADERS", DEFAULT_REDACT_HEADERS),
+            &var("PROXY_REDACT_FIELDS", DEFAULT_REDACT_FIELDS),
+        )
+    }
+
+    /// Redactor for comma-separated header and field names
+    fn from_lists(headers: &str, fields: &str) -> Self {
+        let list = |names: &str| {
+            names
+                .split(',')
+                .map(|s| s.trim().to_lowercase())
+                .filter(|s| !s.is_empty())
+                .collect()
+        };
+        Self {
+            headers: list(headers),
+            fields: list(fields),
+        }
+    }
+
+    /// Headers with the values of sensitive ones replaced by `***`
+    pub fn headers(&self, headers: &[(String, String)]) -> Vec<(String, String)> {
+        headers
+            .iter()
+            .map(|(key, value)| {
+                if self.headers.contains(&key.to_lowercase()) {
+                    (key.clone(), REDACTED.to_string())
+                } else {
+                    (key.clone(), value.clone())
+                }
+            })
+            .collect()
+    }
+
+    /// A body safe to log: JSON with sensitive fields masked, otherwise only its size
+    ///
+    /// Non-JSON bodies (forms, HTML, binary) can't be redacted reliably, so they
+    /// are never logged verbatim.
+    pub fn body(&self, body: Option<&str>) -> String {
+        let body = match body {
+            Some(body) if !body.is_empty() => body,
+            _ => return String::new(),
+        };
+
+        match serde_json::from_str::<serde_json::Value>(body) {
+            Ok(mut value) => {
+                self.redact_value(&mut value);
+                let mut logged = value.to_string();
+                if logged.len() > MAX_LOGGED_BODY_BYTES {
+                    let mut end = MAX_LOGGED_BODY_BYTES;
+                    while !logged.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    logged.truncate(end);
+                    logged.push_str("...");
+                }
+                logged
+            }
+            Err(_) => format!("[{} bytes, not JSON]", body.len()),
+        }
+    }
+
+    fn redact_value(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, entry) in map.iter_mut() {
+                    if self.fields.contains(&key.to_lowercase()) {
+                        *entry = serde_json::Value::String(REDACTED.to_string());
+                    } else {
+                        self.redact_value(entry);
+                    }
+                }
+            }
+            serde_json::Value::Array(items) => {
+                for item in items {
+                    self.redact_value(item);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults() -> Redactor {
+        Redactor::from_lists(DEFAULT_REDACT_HEADERS, DEFAULT_REDACT_FIELDS)
+    }
+
+    fn header(key: &str, value: &str) -> (String, String) {
+        (key.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn configured_headers_are_masked() {
+        let redactor = Redactor::from_lists(" X-Session , authorization", "");
+        let headers = redactor.headers(&[
+            header("Authorization", "Bearer abc"),
+            header("x-session", "s1"),
+            header("Accept", "text/html"),
+        ]);
+        assert_eq!(
+            headers,
+            vec![
+                header("Authorization", "***"),
+                header("x-session", "***"),
+                header("Accept", "text/html"),
+            ]
+        );
+
+        // Setting the list replaces the defaults
+        let cookie = redactor.headers(&[header("Cookie", "session=1")]);
+        assert_eq!(cookie, vec![header("Cookie", "session=1")]);
+    }
+
+    #[test]
+    fn nested_json_fields_are_masked() {
+        let body = r#"{"user":{"name":"octo","Password":"hunter2"},"items":[{"token":"t1"},{"id":2}],"api_key":{"nested":"x"}}"#;
+        let logged: serde_json::Value = serde_json::from_str(&defaults().body(Some(body))).unwrap();
+        assert_eq!(
+            logged,
+            serde_json::json!({
+                "user": {"name": "octo", "Password": "***"},
+                "items": [{"token": "***"}, {"id": 2}],
+                "api_key": "***"
+            })
+        );
+    }
+
+    #[test]
+    fn non_json_bodies_are_never_logged_verbatim() {
+        let form = "username=octo&password=hunter2";
+        let logged = defaults().body(Some(form));
+        assert_eq!(logged, format!("[{} bytes, not JSON]", form.len()));
+        assert!(!logged.contains("hunter2"));
+        assert_eq!(defaults().body(Some("")), "");
+        assert_eq!(defaults().body(None), "");
+    }
+
+    #[test]
+    fn long_json_bodies_are_truncated() {
+        let body = serde_json::json!({ "data": "é".repeat(MAX_LOGGED_BODY_BYTES) }).to_string();
+        let logged = defaults().body(Some(&body));
+        assert!(logged.ends_with("..."));
+        assert!(logged.len() <= MAX_LOGGED_BODY_BYTES + 3);
+    }
+}
@@ -0,0 +1,111 @@
+use serde::Deserialize;
+use worker::*;
+
+/// Maximum number of rewrite rules honored (extra rules are ignored)
+const MAX_REWRITE_RULES: usize = 20;
+
+/// Bodies larger than this are passed through untouched
+const MAX_REWRITE_BODY_BYTES: usize = 1024 * 1024;
+
+/// A find/replace rule applied to proxied response bodies
+///
+/// Configured via the `PROXY_REWRITE_RULES` env var as a JSON array, e.g.
+/// `[{"content_type": "text/html", "find": "<head>", "replace": "<head><base href=\"/\">"}]`
+#[derive(Debug, Deserialize)]
+pub struct RewriteRule {
+    /// MIME type the rule applies to, matched against the response `Content-Type`
+    pub content_type: String,
+    pub find: String,
+    pub replace: String,
+}
+
+/// Load rewrite rules from the environment (invalid config is logged and ignored)
+pub fn load_rules(env: &Env) -> Vec<RewriteRule> {
+    let raw = match crate::config::var_string(env, "PROXY_REWRITE_RULES") {
+        Some(raw) => raw,
+        None => return Vec::new(),
+    };
+
+    match serde_json::from_str::<Vec<RewriteRule>>(&raw) {
+        Ok(mut rules) => {
+            if rules.len() > MAX_REWRITE_RULES {
+                console_log!(
+                    "PROXY_REWRITE_RULES has {} rules; only the first {} are applied",
+                    rules.len(),
+                    MAX_REWRITE_RULES
+                );
+                rules.truncate(MAX_REWRITE_RULES);
+            }
+            rules
+        }
+        Err(e) => {
+            console_error!("Invalid PROXY_REWRITE_RULES: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Apply the rules matching `content_type` to a response body
+pub fn apply_rules(rules: &[RewriteRule], content_type: Option<&str>, body: String) -> String {
+    if rules.is_empty() || body.len() > MAX_REWRITE_BODY_BYTES {
+        return body;
+    }
+
+    // Compare only the MIME type, ignoring parameters such as charset
+    let mime = match content_type {
+        Some(ct) => ct.split(';').next().unwrap_or("").trim().to_lowercase(),
+        None => return body,
+    };
+
+    rules
+        .iter()
+        .filter(|rule| {
+            !rule.find.is_empty() && rule.content_type.trim().eq_ignore_ascii_case(&mime)
+        })
+        .fold(body, |acc, rule| acc.replace(&rule.find, &rule.replace))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(content_type: &str, find: &str, replace: &str) -> RewriteRule {
+        RewriteRule {
+            content_type: content_type.to_string(),
+            find: find.to_string(),
+            replace: replace.to_string(),
+        }
+    }
+
+    #[test]
+    fn rules_apply_in_order_to_matching_mime_types() {
+        let rules = [
+            rule("text/html", "<head>", "<head><base href=\"/\">"),
+            rule(" TEXT/HTML ", "/", "/app/"),
+            rule("text/css", "red", "blue"),
+        ];
+        let html = Some("text/html; charset=utf-8");
+        assert_eq!(
+            apply_rules(&rules, html, "<head>".to_string()),
+            "<head><base href=\"/app/\">"
+        );
+        assert_eq!(
+            apply_rules(&rules, Some("text/css"), "a { color: red }".to_string()),
+            "a { color: blue }"
+        );
+    }
+
+    #[test]
+    fn unmatched_empty_and_oversized_bodies_pass_through() {
+        let rules = [rule("text/html", "a", "b"), rule("text/plain", "", "x")];
+        for content_type in [None, Some("text/htmlx"), Some("text/plain")] {
+            assert_eq!(apply_rules(&rules, content_type, "a".to_string()), "a");
+        }
+
+        let oversized = "a".repeat(MAX_REWRITE_BODY_BYTES + 1);
+        assert_eq!(
+            apply_rules(&rules, Some("text/html"), oversized.clone()),
+            oversized
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use serde::Deserialize;
+use worker::*;
+
+use crate::auth::AuthMiddleware;
+use crate::models::SessionInfo;
+use crate::templates;
+
+/// D1 row for sessions
+#[derive(Debug, Deserialize)]
+struct SessionRow {
+    id: String,
+    created_at: String,
+    expires_at: String,
+    last_activity: Option<String>,
+    created_ip: Option<String>,
+    user_agent: Option<String>,
+}
+
+/// Load the user's unexpired sessions, most recently active first
+async fn fetch_user_sessions(
+    env: &Env,
+    user_id: &str,
+    current_session: Option<&str>,
+) -> Result<Vec<SessionInfo>> {
+    let db = env.d1("DB")?;
+    let result = db
+        .prepare(
+            "SELECT id, created_at, expires_at, last_activity, created_ip, user_agent
+             FROM sessions
+             WHERE user_id = ?1
+             ORDER BY COALESCE(last_activity, created_at) DESC",
+        )
+        .bind(&[user_id.into()])?
+        .all()
+        .await?;
+
+    let rows: Vec<SessionRow> = result.results()?;
+    Ok(rows
+        .into_iter()
+        .filter(|row| !crate::time::is_past(&row.expires_at))
+        .map(|row| SessionInfo {
+            is_current: current_session == Some(row.id.as_str()),
+            id: row.id,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            last_activity: row.last_activity,
+            created_ip: row.created_ip,
+            user_agent: row.user_agent,
+        })
+        .collect())
+}
+
+/// List active sessions for the authenticated user (JSON)
+pub async fn list_sessions(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    // Require authentication
+    let user = match AuthMiddleware::require_auth_json(&req, &ctx.env).await? {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    let current = AuthMiddleware::session_id(&req);
+    let sessions = fetch_user_sessions(&ctx.env, &user.id, current.as_deref()).await?;
+
+    Response::from_json(&sessions)
+}
+
+/// List active sessions as HTML (HTMX partial)
+pub async fn list_sessions_htmx(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    // Require authentication
+    let user = match AuthMiddleware::require_auth(&req, &ctx.env).await? {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    let current = AuthMiddleware::session_id(&req);
+    let sessions = fetch_user_sessions(&ctx.env, &user.id, current.as_deref()).await?;
+
+    if super::wants_htmx(&req) {
+        Response::from_html(templates::render_session_list(&sessions))
+    } else {
+        Response::from_html(templates::render_sessions_page(&user, &sessions))
+    }
+}
+
+/// Revoke a single session
+///
+/// Revoking the session making the request signs the user out, so it needs
+/// `?confirm=true`.
+pub async fn revoke_session(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    // Require authentication
+    let user = match AuthMiddleware::require_auth_json(&req, &ctx.env).await? {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    let session_id = ctx.param("id").ok_or("Missing session ID")?;
+    let current = AuthMiddleware::session_id(&req);
+    let is_current = current.as_deref() == Some(session_id.as_str());
+
+    if is_current {
+        let confirmed = crate::query::Query::from_request(&req)?.get_bool("confirm", false);
+        if !confirmed {
+            return Response::error(
+                "Revoking the current session signs you out; pass confirm=true",
+                400,
+            );
+        }
+    }
+
+    let db = ctx.env.d1("DB")?;
+
+    // Delete session (only if owned by user)
+    let result = db
+        .prepare("DELETE FROM sessions WHERE id = ?1 AND user_id = ?2")
+        .bind(&[session_id.into(), user.id.clone().into()])?
+        .run()
+        .await?;
+    let deleted = result.meta()?.and_then(|meta| meta.changes).unwrap_or(0) > 0;
+    if !deleted {
+        return Response::error("Session not found", 404);
+    }
+    crate::user_cache::invalidate_session(session_id);
+
+    let is_htmx = super::wants_htmx(&req);
+
+    if is_current {
+        // The caller is now signed out; send HTMX back to the login page
+        let headers = Headers::new();
+        if is_htmx {
+            headers.set("HX-Redirect", &crate::config::app_path(&ctx.env, "/"))?;
+        }
+        headers.set("Set-Cookie", &crate::auth::clear_session_cookie(&ctx.env))?;
+        return Ok(Response::ok("Session revoked")?.with_headers(headers));
+    }
+
+    if is_htmx {
+        // Return updated session list
+        let sessions = fetch_user_sessions(&ctx.env, &user.id, current.as_deref()).await?;
+        Response::from_html(templates::render_session_list(&sessions))
+    } else {
+        Response::ok("Session revoked")
+    }
+}
@@ -1,110 +1,295 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use worker::*;
 
 use crate::auth::AuthMiddleware;
-#[allow(unused_imports)]
-use crate::models::{hash_token, parse_token, verify_token, ClientToken, TokenCreated, TokenInfo};
+use crate::durable_objects::user_hub_name;
+use crate::models::{hash_token, token_expired, ClientToken, TokenCreated, TokenInfo};
 use crate::templates;
 
 /// Request to create a new token
 #[derive(Debug, Deserialize)]
 pub struct CreateTokenRequest {
     pub name: String,
+    /// Hours until the token expires; omit for a token that never expires
+    #[serde(default)]
+    pub ttl_hours: Option<u64>,
+}
+
+/// Tokens a user may create per window when `TOKEN_CREATE_RATE_LIMIT` is unset
+pub(super) const DEFAULT_TOKEN_CREATE_RATE_LIMIT: u64 = 20;
+
+/// Length of the token creation window when `TOKEN_CREATE_RATE_WINDOW_SECONDS` is unset
+pub(super) const DEFAULT_TOKEN_CREATE_RATE_WINDOW_SECONDS: u64 = 3_600;
+
+/// Longest token lifetime accepted (10 years); larger values overflow date handling
+const MAX_TOKEN_TTL_HOURS: u64 = 87_600;
+
+/// Usage events kept per token; older ones are removed by the scheduled cleanup
+const MAX_TOKEN_USAGE_EVENTS: u32 = 50;
+
+/// Recent uses of a token, joined to the hostname of a client the owner (`?2`) still has
+const TOKEN_USAGE_QUERY: &str = "SELECT u.used_at, u.ip, u.user_agent, u.client_id, c.hostname
+     FROM token_usage u
+     LEFT JOIN clients c ON c.client_id = u.client_id AND c.user_id = ?2
+     WHERE u.token_id = ?1
+     ORDER BY u.id DESC";
+
+/// Delete all but the newest `?1` usage events of every token
+const PRUNE_TOKEN_USAGE: &str = "DELETE FROM token_usage WHERE id IN (
+         SELECT id FROM (
+             SELECT id, ROW_NUMBER() OVER (PARTITION BY token_id ORDER BY id DESC) AS newest
+             FROM token_usage
+         ) WHERE newest > ?1
+     )";
+
+/// Maximum number of token IDs accepted by a single bulk request
+const MAX_BULK_TOKEN_IDS: usize = 100;
+
+/// Request to apply one action to many tokens
+#[derive(Debug, Deserialize)]
+pub struct BulkTokenRequest {
+    /// `revoke` or `delete`
+    pub action: String,
+    pub ids: Vec<String>,
+}
+
+/// Outcome of a bulk action for a single token
+#[derive(Debug, Serialize)]
+pub struct BulkTokenResult {
+    pub id: String,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Fields accepted by `?fields=` on `GET /api/tokens`
+const TOKEN_FIELDS: &[&str] = &[
+    "id",
+    "name",
+    "created_at",
+    "last_used",
+    "last_used_ip",
+    "last_used_ua",
+    "expires_at",
+    "is_revoked",
+    "is_expired",
+];
+
+/// Tokens per page when `?limit=` is absent
+const DEFAULT_TOKEN_PAGE_SIZE: u32 = 50;
+
+/// Largest `?limit=` honoured on token lists
+const MAX_TOKEN_PAGE_SIZE: u32 = 200;
+
+/// Values accepted by `?sort=` on token lists, with the `ORDER BY` each maps to
+///
+/// Only these fixed clauses ever reach the SQL, never the raw parameter.
+const TOKEN_SORTS: &[(&str, &str)] = &[
+    ("created_at", "created_at DESC, id"),
+    ("last_used", "last_used IS NULL, last_used DESC, id"),
+    ("name", "name COLLATE NOCASE, created_at DESC, id"),
+];
+
+/// One page of a token list, from `?limit=`, `?offset=` and `?sort=`
+struct TokenPage {
+    sort: &'static str,
+    limit: u32,
+    offset: u32,
+}
+
+impl TokenPage {
+    /// First page, newest first
+    fn first() -> Self {
+        Self {
+            sort: TOKEN_SORTS[0].0,
+            limit: DEFAULT_TOKEN_PAGE_SIZE,
+            offset: 0,
+        }
+    }
+
+    /// Parse the paging parameters; an unknown sort returns its name
+    fn from_request(req: &Request) -> Result<std::result::Result<Self, String>> {
+        let params = crate::query::Query::from_request(req)?;
+        Ok(Self::from_query(&params))
+    }
+
+    fn from_query(params: &crate::query::Query) -> std::result::Result<Self, String> {
+        let sort = match params.get_str("sort").map(str::trim) {
+            None | Some("") => TOKEN_SORTS[0].0,
+            Some(name) => match TOKEN_SORTS.iter().find(|(known, _)| *known == name) {
+                Some((known, _)) => known,
+                None => return Err(name.to_string()),
+            },
+        };
+        let limit = params
+            .get_u32("limit", DEFAULT_TOKEN_PAGE_SIZE)
+            .clamp(1, MAX_TOKEN_PAGE_SIZE);
+        Ok(Self {
+            sort,
+            limit,
+            offset: params.get_u32("offset", 0),
+        })
+    }
+
+    fn order_by(&self) -> &'static str {
+        TOKEN_SORTS
+            .iter()
+            .find(|(name, _)| *name == self.sort)
+            .map(|(_, clause)| *clause)
+            .unwrap_or(TOKEN_SORTS[0].1)
+    }
+
+    /// Query string of the page after this one
+    fn next_query(&self) -> String {
+        format!(
+            "offset={}&limit={}&sort={}",
+            self.offset.saturating_add(self.limit),
+            self.limit,
+            self.sort
+        )
+    }
+}
+
+/// 400 response naming the accepted `?sort=` values
+fn unknown_sort_response(sort: &str) -> Result<Response> {
+    let known: Vec<&str> = TOKEN_SORTS.iter().map(|(name, _)| *name).collect();
+    Ok(Response::from_json(&serde_json::json!({
+        "error": format!("unknown sort: {}", sort),
+        "known_sorts": known,
+    }))?
+    .with_status(400))
+}
+
+/// The 429 response if `user_id` has created too many tokens recently
+async fn check_token_create_rate(env: &Env, user_id: &str) -> Result<Option<Response>> {
+    let max = crate::config::var_u64(
+        env,
+        "TOKEN_CREATE_RATE_LIMIT",
+        DEFAULT_TOKEN_CREATE_RATE_LIMIT,
+    );
+    let window = crate::config::var_u64(
+        env,
+        "TOKEN_CREATE_RATE_WINDOW_SECONDS",
+        DEFAULT_TOKEN_CREATE_RATE_WINDOW_SECONDS,
+    );
+    let key = format!("token_create:{}", user_id);
+    match crate::rate_limit::check_rate_limit(env, &key, max, window).await {
+        Some(retry_after) => Ok(Some(crate::rate_limit::too_many_requests(retry_after)?)),
+        None => Ok(None),
+    }
+}
+
+/// Disconnect the user's clients that registered with a revoked or deleted token
+///
+/// Failures are only logged: the token is already unusable, so the clients
+/// cannot reconnect with it either way.
+async fn disconnect_token_clients(env: &Env, user_id: &str, token_id: &str) {
+    let result = async {
+        let namespace = env.durable_object("USER_HUB")?;
+        let stub = namespace
+            .id_from_name(&user_hub_name(env, user_id))?
+            .get_stub()?;
+        let do_req = Request::new(
+            &format!("https://do/clients/by-token/{}/disconnect", token_id),
+            Method::Post,
+        )?;
+        stub.fetch_with_request(do_req).await
+    }
+    .await;
+
+    if let Err(e) = result {
+        console_error!("Failed to disconnect token {} clients: {:?}", token_id, e);
+    }
 }
 
 /// D1 row for tokens
 #[derive(Debug, Deserialize)]
-#[allow(dead_code)]
 struct TokenRow {
     id: String,
-    user_id: String,
     name: String,
     created_at: String,
     last_used: Option<String>,
+    last_used_ip: Option<String>,
+    last_used_ua: Option<String>,
     revoked_at: Option<String>,
+    expires_at: Option<String>,
 }
 
-/// List all tokens for the authenticated user
-pub async fn list_tokens(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // Require authentication
-    let user = match AuthMiddleware::require_auth(&req, &ctx.env).await? {
-        Ok(user) => user,
-        Err(response) => return Ok(response),
-    };
-
-    let db = ctx.env.d1("DB")?;
-
+/// Load one page of the user's tokens
+///
+/// Reads one row past the page so callers can tell whether another follows;
+/// the second value is true when it does, and that row is dropped.
+async fn fetch_user_tokens(
+    db: &D1Database,
+    user_id: &str,
+    page: &TokenPage,
+) -> Result<(Vec<TokenInfo>, bool)> {
+    let query = format!(
+        "SELECT id, name, created_at, last_used, last_used_ip, last_used_ua,
+                revoked_at, expires_at
+         FROM client_tokens
+         WHERE user_id = ?1
+         ORDER BY {}
+         LIMIT ?2 OFFSET ?3",
+        page.order_by()
+    );
     let tokens = db
-        .prepare(
-            "SELECT id, user_id, name, created_at, last_used, revoked_at
-             FROM client_tokens
-             WHERE user_id = ?1
-             ORDER BY created_at DESC",
-        )
-        .bind(&[user.id.into()])?
+        .prepare(&query)
+        .bind(&[
+            user_id.into(),
+            ((page.limit + 1) as f64).into(),
+            (page.offset as f64).into(),
+        ])?
         .all()
         .await?;
 
-    let rows: Vec<TokenRow> = tokens.results()?;
-    let token_infos: Vec<TokenInfo> = rows
+    let mut rows: Vec<TokenRow> = tokens.results()?;
+    let has_more = rows.len() > page.limit as usize;
+    rows.truncate(page.limit as usize);
+
+    let infos = rows
         .into_iter()
         .map(|row| TokenInfo {
+            is_expired: token_expired(row.expires_at.as_deref()),
             id: row.id,
             name: row.name,
             created_at: row.created_at,
             last_used: row.last_used,
+            last_used_ip: row.last_used_ip,
+            last_used_ua: row.last_used_ua,
+            expires_at: row.expires_at,
             is_revoked: row.revoked_at.is_some(),
         })
         .collect();
-
-    Response::from_json(&token_infos)
+    Ok((infos, has_more))
 }
 
-/// Create a new token for the authenticated user
-pub async fn create_token(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+/// List the authenticated user's tokens
+///
+/// Paged with `?limit=` (default 50, at most 200) and `?offset=`, ordered by
+/// `?sort=created_at` (newest first, the default), `last_used` (most recent
+/// first, never-used last) or `name`.
+pub async fn list_tokens(req: Request, ctx: RouteContext<()>) -> Result<Response> {
     // Require authentication
-    let user = match AuthMiddleware::require_auth(&req, &ctx.env).await? {
+    let user = match AuthMiddleware::require_auth_json(&req, &ctx.env).await? {
         Ok(user) => user,
         Err(response) => return Ok(response),
     };
 
-    // Parse request body
-    let body: CreateTokenRequest = req.json().await?;
-
-    // Generate token
-    let (token, raw_token) = ClientToken::new(user.id.clone(), body.name.clone());
-    let token_hash = hash_token(&raw_token.split('_').last().unwrap_or(&raw_token));
-
-    // Store in D1
-    let db = ctx.env.d1("DB")?;
-    db.prepare(
-        "INSERT INTO client_tokens (id, user_id, name, token_hash, created_at)
-         VALUES (?1, ?2, ?3, ?4, ?5)",
-    )
-    .bind(&[
-        token.id.clone().into(),
-        user.id.into(),
-        body.name.into(),
-        token_hash.into(),
-        token.created_at.into(),
-    ])?
-    .run()
-    .await?;
-
-    // Return the token (only time it's shown)
-    let response = TokenCreated {
-        id: token.id,
-        name: token.name,
-        token: raw_token,
+    let page = match TokenPage::from_request(&req)? {
+        Ok(page) => page,
+        Err(sort) => return unknown_sort_response(&sort),
     };
+    let db = ctx.env.d1("DB")?;
+    let (token_infos, _) = fetch_user_tokens(&db, &user.id, &page).await?;
 
-    Response::from_json(&response)
+    super::json::list_response(&req, &token_infos, TOKEN_FIELDS)
 }
 
-/// Revoke a token
-pub async fn revoke_token(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+/// Delete a token permanently
+pub async fn delete_token(req: Request, ctx: RouteContext<()>) -> Result<Response> {
     // Require authentication
-    let user = match AuthMiddleware::require_auth(&req, &ctx.env).await? {
+    let user = match AuthMiddleware::require_auth_json(&req, &ctx.env).await? {
         Ok(user) => user,
         Err(response) => return Ok(response),
     };
@@ -113,91 +298,279 @@ pub async fn revoke_token(req: Request, ctx: RouteContext<()>) -> Result<Respons
 
     let db = ctx.env.d1("DB")?;
 
-    // Update token to revoked (only if owned by user)
+    // Delete token (only if owned by user)
     let result = db
-        .prepare(
+        .prepare("DELETE FROM client_tokens WHERE id = ?1 AND user_id = ?2")
+        .bind(&[token_id.into(), user.id.clone().into()])?
+        .run()
+        .await?;
+    crate::user_cache::invalidate_user(&user.id);
+    if result.meta()?.and_then(|meta| meta.changes).unwrap_or(0) > 0 {
+        disconnect_token_clients(&ctx.env, &user.id, token_id).await;
+    }
+
+    Response::ok("Token deleted")
+}
+
+/// Recent uses of a token, newest first
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenUsageEvent {
+    pub used_at: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub client_id: Option<String>,
+    /// Hostname the client registered with, while it is still connected
+    pub hostname: Option<String>,
+}
+
+/// List recent usage events for a token owned by the user
+pub async fn token_usage(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    // Require authentication
+    let user = match AuthMiddleware::require_auth_json(&req, &ctx.env).await? {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    let token_id = ctx.param("id").ok_or("Missing token ID")?.clone();
+    let db = ctx.env.d1("DB")?;
+
+    let owned = db
+        .prepare("SELECT id FROM client_tokens WHERE id = ?1 AND user_id = ?2")
+        .bind(&[token_id.clone().into(), user.id.clone().into()])?
+        .first::<String>(Some("id"))
+        .await?;
+    if owned.is_none() {
+        return Response::error("Token not found", 404);
+    }
+
+    let events = db
+        .prepare(TOKEN_USAGE_QUERY)
+        .bind(&[token_id.into(), user.id.into()])?
+        .all()
+        .await?;
+    let events: Vec<TokenUsageEvent> = events.results()?;
+
+    Response::from_json(&serde_json::json!({ "events": events }))
+}
+
+/// Keep only the newest `MAX_TOKEN_USAGE_EVENTS` per token (run by the cron trigger)
+///
+/// Failures are only logged; the next run catches up.
+pub async fn prune_token_usage(env: &Env) {
+    let result = async {
+        env.d1("DB")?
+            .prepare(PRUNE_TOKEN_USAGE)
+            .bind(&[(MAX_TOKEN_USAGE_EVENTS as f64).into()])?
+            .run()
+            .await
+    }
+    .await;
+
+    if let Err(e) = result {
+        console_error!("Failed to prune token usage: {:?}", e);
+    }
+}
+
+/// Revoke or delete several tokens in one transaction, reporting per-token results
+pub async fn bulk_tokens(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    // Require authentication
+    let user = match AuthMiddleware::require_auth_json(&req, &ctx.env).await? {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    let body: BulkTokenRequest = match req.json().await {
+        Ok(body) => body,
+        Err(_) => return bulk_request_error("Invalid request body"),
+    };
+
+    let (sql, failure) = match body.action.as_str() {
+        "revoke" => (
             "UPDATE client_tokens
              SET revoked_at = CURRENT_TIMESTAMP
              WHERE id = ?1 AND user_id = ?2 AND revoked_at IS NULL",
-        )
-        .bind(&[token_id.into(), user.id.into()])?
-        .run()
-        .await?;
+            "Token not found or already revoked",
+        ),
+        "delete" => (
+            "DELETE FROM client_tokens WHERE id = ?1 AND user_id = ?2",
+            "Token not found",
+        ),
+        _ => return bulk_request_error("Action must be \"revoke\" or \"delete\""),
+    };
 
-    if result.success() {
-        Response::ok("Token revoked")
-    } else {
-        Response::error("Failed to revoke token", 500)
+    // Drop repeated IDs, keeping the first occurrence
+    let mut seen = std::collections::HashSet::new();
+    let mut ids = body.ids;
+    ids.retain(|id| seen.insert(id.clone()));
+    if ids.is_empty() {
+        return bulk_request_error("No token IDs provided");
+    }
+    if ids.len() > MAX_BULK_TOKEN_IDS {
+        return bulk_request_error(&format!(
+            "At most {} token IDs per request",
+            MAX_BULK_TOKEN_IDS
+        ));
+    }
+
+    let db = ctx.env.d1("DB")?;
+
+    // D1 runs a batch as a single transaction; ownership is enforced per
+    // statement, so tokens belonging to other users simply change no rows
+    let statements = ids
+        .iter()
+        .map(|id| {
+            db.prepare(sql)
+                .bind(&[id.clone().into(), user.id.clone().into()])
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let outcomes = db.batch(statements).await?;
+    crate::user_cache::invalidate_user(&user.id);
+
+    let results: Vec<BulkTokenResult> = ids
+        .into_iter()
+        .zip(outcomes)
+        .map(|(id, outcome)| {
+            let changed = outcome
+                .meta()
+                .ok()
+                .flatten()
+                .and_then(|meta| meta.changes)
+                .unwrap_or(0)
+                > 0;
+            BulkTokenResult {
+                id,
+                success: changed,
+                error: (!changed).then(|| failure.to_string()),
+            }
+        })
+        .collect();
+    for result in results.iter().filter(|result| result.success) {
+        disconnect_token_clients(&ctx.env, &user.id, &result.id).await;
     }
+
+    Response::from_json(&serde_json::json!({ "results": results }))
 }
 
-/// Delete a token permanently
-pub async fn delete_token(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+/// Show the typed-confirmation modal for revoking every token
+pub async fn confirm_revoke_all(req: Request, ctx: RouteContext<()>) -> Result<Response> {
     // Require authentication
     let user = match AuthMiddleware::require_auth(&req, &ctx.env).await? {
         Ok(user) => user,
         Err(response) => return Ok(response),
     };
 
-    let token_id = ctx.param("id").ok_or("Missing token ID")?;
+    Response::from_html(templates::render_confirm_modal(
+        "Revoke All",
+        "This revokes every active connection token. Clients will need new tokens to reconnect.",
+        "/api/tokens/revoke-all",
+        &user.github_login,
+    ))
+}
 
-    let db = ctx.env.d1("DB")?;
+/// Revoke every active token for the user (requires typed confirmation)
+pub async fn revoke_all_tokens(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    // Require authentication
+    let user = match AuthMiddleware::require_auth_json(&req, &ctx.env).await? {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
 
-    // Delete token (only if owned by user)
-    db.prepare("DELETE FROM client_tokens WHERE id = ?1 AND user_id = ?2")
-        .bind(&[token_id.into(), user.id.into()])?
+    if let Some(rejection) = super::check_confirmation(&mut req, &ctx.env, &user).await? {
+        return Ok(rejection);
+    }
+
+    let db = ctx.env.d1("DB")?;
+    let result = db
+        .prepare(
+            "UPDATE client_tokens
+             SET revoked_at = CURRENT_TIMESTAMP
+             WHERE user_id = ?1 AND revoked_at IS NULL",
+        )
+        .bind(&[user.id.clone().into()])?
         .run()
         .await?;
+    crate::user_cache::invalidate_user(&user.id);
+    let revoked = result.meta()?.and_then(|meta| meta.changes).unwrap_or(0);
 
-    Response::ok("Token deleted")
+    if super::wants_htmx(&req) {
+        // Close the modal; the form triggers a token list refresh
+        Response::from_html("")
+    } else {
+        Response::from_json(&serde_json::json!({ "revoked": revoked }))
+    }
 }
 
-/// Validate a token and return user info (used internally for WebSocket auth)
-#[allow(dead_code)]
-pub async fn validate_token(env: &Env, full_token: &str) -> Result<Option<String>> {
-    // Parse token
-    let (token_id, raw_token) = match parse_token(full_token) {
-        Some(parts) => parts,
-        None => return Ok(None),
-    };
+/// Return a 400 JSON error for a malformed bulk request
+fn bulk_request_error(message: &str) -> Result<Response> {
+    Ok(Response::from_json(&serde_json::json!({ "error": message }))?.with_status(400))
+}
+
+/// Create a "Default" token for a user who has never had one (AUTO_CREATE_FIRST_TOKEN)
+///
+/// Returns the raw token when one was created. Revoked and expired tokens
+/// count as existing, so this fires at most once per user; the insert itself
+/// re-checks, so concurrent first logins still create a single token.
+pub async fn create_first_token(env: &Env, user_id: &str) -> Result<Option<String>> {
+    if !crate::config::var_bool(env, "AUTO_CREATE_FIRST_TOKEN", false) {
+        return Ok(None);
+    }
 
-    let token_hash = hash_token(&raw_token);
+    let (token, raw_token) = ClientToken::new(user_id.to_string(), "Default".to_string(), None)?;
+    let token_hash = hash_token(raw_token.rsplit('_').next().unwrap_or(&raw_token));
 
     let db = env.d1("DB")?;
-
-    // Look up token
     let result = db
         .prepare(
-            "SELECT user_id, token_hash, revoked_at
-             FROM client_tokens
-             WHERE id = ?1 AND revoked_at IS NULL",
+            "INSERT INTO client_tokens (id, user_id, name, token_hash, created_at)
+             SELECT ?1, ?2, ?3, ?4, ?5
+             WHERE NOT EXISTS (SELECT 1 FROM client_tokens WHERE user_id = ?2)",
         )
-        .bind(&[token_id.into()])?
-        .first::<TokenValidationRow>(None)
+        .bind(&[
+            token.id.into(),
+            user_id.into(),
+            token.name.into(),
+            token_hash.into(),
+            token.created_at.into(),
+        ])?
+        .run()
         .await?;
 
-    match result {
-        Some(row) if verify_token(&raw_token, &row.token_hash) => {
-            // Update last_used timestamp
-            let _ = db
-                .prepare("UPDATE client_tokens SET last_used = CURRENT_TIMESTAMP WHERE id = ?1")
-                .bind(&[row.id.into()])?
-                .run()
-                .await;
+    let changes = result.meta()?.and_then(|meta| meta.changes).unwrap_or(0);
+    Ok(first_token_created(changes, raw_token))
+}
 
-            Ok(Some(row.user_id))
-        }
-        _ => Ok(None),
-    }
+/// The raw token to show, if the guarded insert created a row
+///
+/// The insert changes nothing for a user who has ever had a token.
+fn first_token_created(changes: usize, raw_token: String) -> Option<String> {
+    (changes > 0).then_some(raw_token)
 }
 
-#[derive(Debug, Deserialize)]
-struct TokenValidationRow {
-    id: String,
-    user_id: String,
-    token_hash: String,
-    #[allow(dead_code)]
-    revoked_at: Option<String>,
+/// Whether a requested `ttl_hours` is absent or within 1..=`MAX_TOKEN_TTL_HOURS`
+fn valid_ttl(ttl_hours: Option<u64>) -> bool {
+    ttl_hours.is_none_or(|hours| (1..=MAX_TOKEN_TTL_HOURS).contains(&hours))
+}
+
+/// Return a 400 JSON error for a `ttl_hours` outside 1..=`MAX_TOKEN_TTL_HOURS`
+fn invalid_ttl_error() -> Result<Response> {
+    Ok(Response::from_json(&serde_json::json!({
+        "error": format!(
+            "ttl_hours must be a whole number of hours between 1 and {}",
+            MAX_TOKEN_TTL_HOURS
+        )
+    }))?
+    .with_status(400))
+}
+
+/// Return a 500 JSON error when a token could not be generated
+fn token_generation_error(e: Error) -> Result<Response> {
+    console_error!("Token generation failed: {:?}", e);
+    Ok(Response::from_json(&token_generation_error_body())?.with_status(500))
+}
+
+/// JSON body of the 500 from [`token_generation_error`]; the cause is only logged
+fn token_generation_error_body() -> serde_json::Value {
+    serde_json::json!({ "error": "Failed to generate token" })
 }
 
 // ==================== HTMX Handlers ====================
@@ -210,32 +583,26 @@ pub async fn list_tokens_htmx(req: Request, ctx: RouteContext<()>) -> Result<Res
         Err(response) => return Ok(response),
     };
 
+    let page = match TokenPage::from_request(&req)? {
+        Ok(page) => page,
+        Err(sort) => return Response::error(format!("Unknown sort: {}", sort), 400),
+    };
     let db = ctx.env.d1("DB")?;
-
-    let tokens = db
-        .prepare(
-            "SELECT id, user_id, name, created_at, last_used, revoked_at
-             FROM client_tokens
-             WHERE user_id = ?1
-             ORDER BY created_at DESC",
-        )
-        .bind(&[user.id.into()])?
-        .all()
-        .await?;
-
-    let rows: Vec<TokenRow> = tokens.results()?;
-    let token_infos: Vec<TokenInfo> = rows
-        .into_iter()
-        .map(|row| TokenInfo {
-            id: row.id,
-            name: row.name,
-            created_at: row.created_at,
-            last_used: row.last_used,
-            is_revoked: row.revoked_at.is_some(),
-        })
-        .collect();
-
-    Response::from_html(templates::render_token_list(&token_infos))
+    let (token_infos, has_more) = fetch_user_tokens(&db, &user.id, &page).await?;
+    let next_page = has_more.then(|| page.next_query());
+
+    if super::wants_htmx(&req) {
+        Response::from_html(templates::render_token_list(
+            &token_infos,
+            next_page.as_deref(),
+        ))
+    } else {
+        Response::from_html(templates::render_tokens_page(
+            &user,
+            &token_infos,
+            next_page.as_deref(),
+        ))
+    }
 }
 
 /// Show the token creation modal
@@ -257,38 +624,60 @@ pub async fn close_token_modal(_req: Request, _ctx: RouteContext<()>) -> Result<
 /// Create a new token and return success modal (HTMX)
 pub async fn create_token_api(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
     // Require authentication
-    let user = match AuthMiddleware::require_auth(&req, &ctx.env).await? {
+    let user = match AuthMiddleware::require_auth_json(&req, &ctx.env).await? {
         Ok(user) => user,
         Err(response) => return Ok(response),
     };
+    if let Some(limited) = check_token_create_rate(&ctx.env, &user.id).await? {
+        return Ok(limited);
+    }
 
     // Check if this is an HTMX request or API request
-    let is_htmx = req.headers().get("HX-Request")?.is_some();
+    let is_htmx = super::wants_htmx(&req);
 
     // Try to parse as form data first (HTMX), then JSON (API)
-    let name = if is_htmx {
+    let (name, ttl_hours) = if is_htmx {
         let form_data = req.form_data().await?;
-        form_data
-            .get("name")
-            .and_then(|v| match v {
+        let field = |key: &str| {
+            form_data.get(key).and_then(|v| match v {
                 FormEntry::Field(s) => Some(s),
                 _ => None,
             })
-            .ok_or("Missing name field")?
+        };
+        let name = field("name").ok_or("Missing name field")?;
+        // An empty selection means "never expires"
+        let ttl_hours = match field("ttl_hours").filter(|v| !v.is_empty()) {
+            Some(value) => match value.parse::<u64>() {
+                Ok(hours) => Some(hours),
+                Err(_) => return invalid_ttl_error(),
+            },
+            None => None,
+        };
+        (name, ttl_hours)
     } else {
         let body: CreateTokenRequest = req.json().await?;
-        body.name
+        (body.name, body.ttl_hours)
     };
+    if !valid_ttl(ttl_hours) {
+        return invalid_ttl_error();
+    }
 
     // Generate token
-    let (token, raw_token) = ClientToken::new(user.id.clone(), name.clone());
-    let token_hash = hash_token(&raw_token.split('_').last().unwrap_or(&raw_token));
+    let (token, raw_token) = match ClientToken::new(user.id.clone(), name.clone(), ttl_hours) {
+        Ok(generated) => generated,
+        Err(e) => return token_generation_error(e),
+    };
+    let token_hash = hash_token(raw_token.rsplit('_').next().unwrap_or(&raw_token));
 
     // Store in D1
+    let expires_at = match &token.expires_at {
+        Some(expires_at) => expires_at.into(),
+        None => wasm_bindgen::JsValue::NULL,
+    };
     let db = ctx.env.d1("DB")?;
     db.prepare(
-        "INSERT INTO client_tokens (id, user_id, name, token_hash, created_at)
-         VALUES (?1, ?2, ?3, ?4, ?5)",
+        "INSERT INTO client_tokens (id, user_id, name, token_hash, created_at, expires_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
     )
     .bind(&[
         token.id.clone().into(),
@@ -296,6 +685,7 @@ pub async fn create_token_api(mut req: Request, ctx: RouteContext<()>) -> Result
         name.clone().into(),
         token_hash.into(),
         token.created_at.into(),
+        expires_at,
     ])?
     .run()
     .await?;
@@ -317,7 +707,7 @@ pub async fn create_token_api(mut req: Request, ctx: RouteContext<()>) -> Result
 /// Revoke a token and return updated list (HTMX)
 pub async fn revoke_token_htmx(req: Request, ctx: RouteContext<()>) -> Result<Response> {
     // Require authentication
-    let user = match AuthMiddleware::require_auth(&req, &ctx.env).await? {
+    let user = match AuthMiddleware::require_auth_json(&req, &ctx.env).await? {
         Ok(user) => user,
         Err(response) => return Ok(response),
     };
@@ -327,45 +717,184 @@ pub async fn revoke_token_htmx(req: Request, ctx: RouteContext<()>) -> Result<Re
     let db = ctx.env.d1("DB")?;
 
     // Update token to revoked (only if owned by user)
-    db.prepare(
-        "UPDATE client_tokens
-         SET revoked_at = CURRENT_TIMESTAMP
-         WHERE id = ?1 AND user_id = ?2 AND revoked_at IS NULL",
-    )
-    .bind(&[token_id.into(), user.id.clone().into()])?
-    .run()
-    .await?;
+    let result = db
+        .prepare(
+            "UPDATE client_tokens
+             SET revoked_at = CURRENT_TIMESTAMP
+             WHERE id = ?1 AND user_id = ?2 AND revoked_at IS NULL",
+        )
+        .bind(&[token_id.into(), user.id.clone().into()])?
+        .run()
+        .await?;
+    crate::user_cache::invalidate_user(&user.id);
+
+    // Connected clients using the token are disconnected, as the confirm dialog promises
+    if result.meta()?.and_then(|meta| meta.changes).unwrap_or(0) > 0 {
+        disconnect_token_clients(&ctx.env, &user.id, token_id).await;
+    }
 
     // Check if this is an HTMX request
-    let is_htmx = req.headers().get("HX-Request")?.is_some();
+    let is_htmx = super::wants_htmx(&req);
 
     if is_htmx {
         // Return updated token list
-        let tokens = db
-            .prepare(
-                "SELECT id, user_id, name, created_at, last_used, revoked_at
-                 FROM client_tokens
-                 WHERE user_id = ?1
-                 ORDER BY created_at DESC",
-            )
-            .bind(&[user.id.into()])?
-            .all()
-            .await?;
-
-        let rows: Vec<TokenRow> = tokens.results()?;
-        let token_infos: Vec<TokenInfo> = rows
-            .into_iter()
-            .map(|row| TokenInfo {
-                id: row.id,
-                name: row.name,
-                created_at: row.created_at,
-                last_used: row.last_used,
-                is_revoked: row.revoked_at.is_some(),
-            })
-            .collect();
-
-        Response::from_html(templates::render_token_list(&token_infos))
+        let page = TokenPage::first();
+        let (token_infos, has_more) = fetch_user_tokens(&db, &user.id, &page).await?;
+        let next_page = has_more.then(|| page.next_query());
+
+        Response::from_html(templates::render_token_list(
+            &token_infos,
+            next_page.as_deref(),
+        ))
     } else {
         Response::ok("Token revoked")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(query: &str) -> std::result::Result<TokenPage, String> {
+        let url = Url::parse(&format!("https://example.com/api/tokens?{}", query)).unwrap();
+        TokenPage::from_query(&crate::query::Query::from_url(&url))
+    }
+
+    #[test]
+    fn token_page_rejects_sorts_outside_the_allowlist() {
+        let injected = "name; DROP TABLE client_tokens";
+        let query = format!("sort={}", crate::auth::url_encode(injected));
+        assert_eq!(page(&query).err().as_deref(), Some(injected));
+        assert_eq!(page("sort=token_hash").err().as_deref(), Some("token_hash"));
+
+        for (name, clause) in TOKEN_SORTS {
+            let page = page(&format!("sort={}", name)).unwrap();
+            assert_eq!(page.order_by(), *clause);
+        }
+    }
+
+    #[test]
+    fn token_page_defaults_and_clamps() {
+        let first = page("").unwrap();
+        assert_eq!(first.sort, "created_at");
+        assert_eq!((first.limit, first.offset), (DEFAULT_TOKEN_PAGE_SIZE, 0));
+        assert_eq!(page("sort=").unwrap().sort, "created_at");
+
+        assert_eq!(page("limit=0").unwrap().limit, 1);
+        assert_eq!(page("limit=5000").unwrap().limit, MAX_TOKEN_PAGE_SIZE);
+        assert_eq!(page("limit=abc").unwrap().limit, DEFAULT_TOKEN_PAGE_SIZE);
+        assert_eq!(page("offset=150").unwrap().offset, 150);
+        assert_eq!(page("offset=-1").unwrap().offset, 0);
+    }
+
+    #[test]
+    fn token_page_next_query_advances_by_one_page() {
+        let next = page("offset=20&limit=10&sort=name").unwrap().next_query();
+        assert_eq!(next, "offset=30&limit=10&sort=name");
+        assert_eq!(page(&next).unwrap().offset, 30);
+
+        let last = TokenPage {
+            offset: u32::MAX,
+            ..TokenPage::first()
+        };
+        let saturated = format!("offset={}&", u32::MAX);
+        assert!(last.next_query().starts_with(&saturated));
+    }
+
+    #[test]
+    fn rng_failure_yields_a_clean_json_error() {
+        let generated = crate::random::tests::with_rng_failure(|| {
+            ClientToken::new("u1".into(), "laptop".into(), None)
+        });
+        assert!(generated.is_err());
+
+        // Only a generic message reaches the caller, never the RNG error
+        assert_eq!(
+            token_generation_error_body().to_string(),
+            r#"{"error":"Failed to generate token"}"#
+        );
+    }
+
+    #[test]
+    fn ttl_must_be_positive_and_capped() {
+        assert!(valid_ttl(None));
+        assert!(valid_ttl(Some(1)));
+        assert!(valid_ttl(Some(MAX_TOKEN_TTL_HOURS)));
+        assert!(!valid_ttl(Some(0)));
+        assert!(!valid_ttl(Some(MAX_TOKEN_TTL_HOURS + 1)));
+        assert!(!valid_ttl(Some(3_000_000_000)));
+    }
+
+    /// In-memory SQLite database with `schema.sql` applied and user "u1" owning token "t1"
+    fn usage_db() -> rusqlite::Connection {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        db.execute_batch(include_str!("../../schema.sql")).unwrap();
+        db.execute_batch(
+            "INSERT INTO users (id, github_id, github_login) VALUES ('u1', 1, 'octocat');
+             INSERT INTO users (id, github_id, github_login) VALUES ('u2', 2, 'mallory');
+             INSERT INTO client_tokens (id, user_id, name, token_hash) VALUES ('t1', 'u1', 'CI', 'h1');
+             INSERT INTO client_tokens (id, user_id, name, token_hash) VALUES ('t2', 'u1', 'Laptop', 'h2');",
+        )
+        .unwrap();
+        db
+    }
+
+    #[test]
+    fn usage_only_shows_hostnames_of_the_owners_clients() {
+        let db = usage_db();
+        // "laptop" was used with t1 but is now held by another user's client
+        db.execute_batch(
+            "INSERT INTO clients (client_id, user_id, hostname) VALUES ('laptop', 'u2', 'mallory-box');
+             INSERT INTO clients (client_id, user_id, hostname) VALUES ('ci', 'u1', 'runner-1');
+             INSERT INTO token_usage (token_id, client_id) VALUES ('t1', 'laptop');
+             INSERT INTO token_usage (token_id, client_id) VALUES ('t1', 'ci');",
+        )
+        .unwrap();
+
+        let mut stmt = db.prepare(TOKEN_USAGE_QUERY).unwrap();
+        let rows: Vec<(String, Option<String>)> = stmt
+            .query_map(["t1", "u1"], |row| Ok((row.get(3)?, row.get(4)?)))
+            .unwrap()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            [
+                ("ci".to_string(), Some("runner-1".to_string())),
+                ("laptop".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn pruning_keeps_the_newest_events_of_each_token() {
+        let db = usage_db();
+        for token_id in ["t1", "t1", "t1", "t2"] {
+            db.execute("INSERT INTO token_usage (token_id) VALUES (?1)", [token_id])
+                .unwrap();
+        }
+
+        db.execute(PRUNE_TOKEN_USAGE, [2]).unwrap();
+
+        // Event 1 was t1's oldest; t2's only event stays
+        let mut stmt = db
+            .prepare("SELECT id FROM token_usage ORDER BY id")
+            .unwrap();
+        let ids: Vec<i64> = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        assert_eq!(ids, [2, 3, 4]);
+    }
+
+    #[test]
+    fn first_token_is_created_once_per_user() {
+        // A new user has no tokens, so the insert adds exactly one row
+        let raw = "tok_abc_123".to_string();
+        assert_eq!(first_token_created(1, raw.clone()), Some(raw.clone()));
+
+        // A returning user (even one whose tokens are all revoked) gets none
+        assert_eq!(first_token_created(0, raw), None);
+    }
+}
@@ -0,0 +1,394 @@
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+use worker::*;
+
+use crate::auth::AuthMiddleware;
+use crate::durable_objects::user_hub_name;
+use crate::models::{Client, ClientStatus, HubViewer, SharedHub, User};
+use crate::templates;
+
+/// Longest login accepted as a viewer (GitHub allows 39 characters, GitLab 255)
+const MAX_VIEWER_LOGIN_LEN: usize = 262;
+
+/// Hubs shared with the account `?1`, excluding the viewer's own (`?2`)
+const SHARED_HUBS_QUERY: &str = "SELECT u.id AS owner_id, u.github_login AS owner_login
+     FROM hub_shares v
+     JOIN users u ON u.id = v.owner_id
+     WHERE v.viewer_account_id = ?1 AND v.owner_id != ?2
+     ORDER BY u.github_login";
+
+/// The account currently signed in as login `?2` with provider `?1`
+///
+/// Should a renamed login have been taken over, the most recent sign-in wins.
+const ACCOUNT_BY_LOGIN_QUERY: &str = "SELECT github_id, github_login, provider FROM users
+     WHERE provider = ?1 AND github_login = ?2 COLLATE NOCASE
+     ORDER BY last_login DESC
+     LIMIT 1";
+
+/// Grant `?3` (login `?2`) read-only access to `?1`'s hub, unless it already has it
+const INSERT_HUB_SHARE: &str =
+    "INSERT INTO hub_shares (owner_id, viewer_account_id, viewer_login) VALUES (?1, ?3, ?2)
+     ON CONFLICT(owner_id, viewer_account_id) DO NOTHING";
+
+#[derive(Debug, Deserialize)]
+struct AddViewerRequest {
+    login: String,
+}
+
+/// A user a viewer login resolved to
+#[derive(Debug, Deserialize)]
+struct ViewerAccount {
+    github_id: i64,
+    github_login: String,
+    provider: String,
+}
+
+/// What a viewer may see of a shared client: the fields of its read-only card
+///
+/// Callback URLs, connection origins and tokens stay with the owner.
+#[derive(Debug, Serialize)]
+struct ReadOnlyClient {
+    id: String,
+    display_name: String,
+    status: ClientStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_activity: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    activity_summary: Option<String>,
+}
+
+impl From<&Client> for ReadOnlyClient {
+    fn from(client: &Client) -> Self {
+        Self {
+            id: client.id.clone(),
+            display_name: templates::display_name(client),
+            status: client.metadata.status,
+            last_activity: client.metadata.last_activity.clone(),
+            activity_summary: client.metadata.activity_summary.clone(),
+        }
+    }
+}
+
+/// Hubs whose owners have granted `user`'s account read-only access, by owner login
+pub(crate) async fn shared_hubs(env: &Env, user: &User) -> Result<Vec<SharedHub>> {
+    let db = env.d1("DB")?;
+    let result = db
+        .prepare(SHARED_HUBS_QUERY)
+        .bind(&[
+            // D1 doesn't support bigint
+            JsValue::from_f64(user.github_id as f64),
+            user.id.clone().into(),
+        ])?
+        .all()
+        .await?;
+    result.results()
+}
+
+/// The hub owned by `owner_id` among those shared with a user, if any
+fn find_hub<'a>(hubs: &'a [SharedHub], owner_id: &str) -> Option<&'a SharedHub> {
+    hubs.iter().find(|hub| hub.owner_id == owner_id)
+}
+
+/// Clients currently in `owner_id`'s hub
+async fn fetch_hub_clients(env: &Env, owner_id: &str) -> Result<Vec<Client>> {
+    let namespace = env.durable_object("USER_HUB")?;
+    let id = namespace.id_from_name(&user_hub_name(env, owner_id))?;
+    let stub = id.get_stub()?;
+
+    let do_req = Request::new("https://do/clients", Method::Get)?;
+    let mut response = stub.fetch_with_request(do_req).await?;
+    Ok(response.json().await.unwrap_or_default())
+}
+
+/// List the logins that can view the current user's hub (JSON)
+pub async fn list_viewers(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user = match AuthMiddleware::require_auth_json(&req, &ctx.env).await? {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    let db = ctx.env.d1("DB")?;
+    let result = db
+        .prepare(
+            "SELECT viewer_login, created_at FROM hub_shares
+             WHERE owner_id = ?1
+             ORDER BY viewer_login",
+        )
+        .bind(&[user.id.clone().into()])?
+        .all()
+        .await?;
+    let viewers: Vec<HubViewer> = result.results()?;
+
+    Response::from_json(&viewers)
+}
+
+/// Grant a login read-only access to the current user's hub
+///
+/// Body: `{"login": "octocat"}` (GitLab accounts as `gitlab:name`). The login
+/// must belong to someone who has signed in here; access is tied to that
+/// account, not the login. Granting an existing viewer again is a no-op.
+pub async fn add_viewer(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user = match AuthMiddleware::require_auth_json(&req, &ctx.env).await? {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    let body: AddViewerRequest = match req.json().await {
+        Ok(body) => body,
+        Err(_) => return Response::error("Expected a JSON body with a login", 400),
+    };
+    let login = body.login.trim();
+    if login.is_empty() || login.len() > MAX_VIEWER_LOGIN_LEN {
+        return Response::error("Invalid login", 400);
+    }
+
+    let (provider, bare_login) = crate::auth::split_qualified_name(login);
+    let db = ctx.env.d1("DB")?;
+    let viewer = db
+        .prepare(ACCOUNT_BY_LOGIN_QUERY)
+        .bind(&[provider.into(), bare_login.into()])?
+        .first::<ViewerAccount>(None)
+        .await?;
+    let viewer = match viewer {
+        Some(viewer) => viewer,
+        None => return Response::error("No one with that login has signed in here yet", 404),
+    };
+    if viewer.github_id == user.github_id {
+        return Response::error("You can already view your own hub", 400);
+    }
+
+    let viewer_login = crate::auth::qualified_name(&viewer.provider, &viewer.github_login);
+    db.prepare(INSERT_HUB_SHARE)
+        .bind(&[
+            user.id.clone().into(),
+            viewer_login.clone().into(),
+            JsValue::from_f64(viewer.github_id as f64),
+        ])?
+        .run()
+        .await?;
+    console_log!(
+        "{} shared their hub with {}",
+        user.github_login,
+        viewer_login
+    );
+
+    Ok(Response::from_json(&serde_json::json!({ "viewer_login": viewer_login }))?.with_status(201))
+}
+
+/// Revoke a login's read-only access to the current user's hub
+pub async fn remove_viewer(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user = match AuthMiddleware::require_auth_json(&req, &ctx.env).await? {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    let login = ctx.param("login").ok_or("Missing viewer login")?;
+    let login = crate::auth::url_decode(login).unwrap_or_else(|| login.to_string());
+
+    let db = ctx.env.d1("DB")?;
+    let result = db
+        .prepare("DELETE FROM hub_shares WHERE owner_id = ?1 AND viewer_login = ?2")
+        .bind(&[user.id.clone().into(), login.into()])?
+        .run()
+        .await?;
+    let deleted = result.meta()?.and_then(|meta| meta.changes).unwrap_or(0) > 0;
+    if !deleted {
+        return Response::error("Viewer not found", 404);
+    }
+
+    Response::from_json(&serde_json::json!({ "success": true }))
+}
+
+/// List the hubs shared with the current user (JSON)
+pub async fn list_shared_hubs(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user = match AuthMiddleware::require_auth_json(&req, &ctx.env).await? {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    Response::from_json(&shared_hubs(&ctx.env, &user).await?)
+}
+
+/// List a shared hub's clients as JSON, for its viewers only
+pub async fn shared_hub_clients_api(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user = match AuthMiddleware::require_auth_json(&req, &ctx.env).await? {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    let owner_id = ctx.param("owner_id").ok_or("Missing hub owner")?;
+    let hubs = shared_hubs(&ctx.env, &user).await?;
+    if find_hub(&hubs, owner_id).is_none() {
+        return Response::error("Forbidden: this hub is not shared with you", 403);
+    }
+
+    let clients = fetch_hub_clients(&ctx.env, owner_id).await?;
+    let clients: Vec<ReadOnlyClient> = clients.iter().map(ReadOnlyClient::from).collect();
+    Response::from_json(&clients)
+}
+
+/// Read-only view of a shared hub's clients (HTMX partial or full page)
+///
+/// Viewers see status and activity only; cards have no proxy link or actions.
+pub async fn shared_hub_clients(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user = match AuthMiddleware::require_auth(&req, &ctx.env).await? {
+        Ok(user) => user,
+        Err(redirect) => return Ok(redirect),
+    };
+
+    let owner_id = ctx.param("owner_id").ok_or("Missing hub owner")?;
+    let hubs = shared_hubs(&ctx.env, &user).await?;
+    let hub = match find_hub(&hubs, owner_id) {
+        Some(hub) => hub,
+        None => {
+            return Ok(Response::from_html(templates::render_error(
+                "Hub not shared",
+                "This hub has not been shared with you.",
+            ))?
+            .with_status(403));
+        }
+    };
+
+    let clients = fetch_hub_clients(&ctx.env, &hub.owner_id).await?;
+    if super::wants_htmx(&req) {
+        Response::from_html(templates::render_read_only_client_list(&clients))
+    } else {
+        Response::from_html(templates::render_shared_hub_page(
+            &user, hub, &hubs, &clients,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shared_client() -> Client {
+        serde_json::from_value(serde_json::json!({
+            "id": "laptop",
+            "user_id": "owner",
+            "metadata": {
+                "hostname": "devbox",
+                "project": "/home/me/orchestrator",
+                "status": "busy",
+                "last_activity": "2026-01-01T00:00:00Z",
+                "callback_url": "http://localhost:3010",
+                "activity_summary": "running tests",
+                "geo": { "ip": "203.0.113.7" }
+            },
+            "connected_at": "2026-01-01T00:00:00Z",
+            "last_seen": "2026-01-01T00:00:00Z"
+        }))
+        .unwrap()
+    }
+
+    fn hubs() -> Vec<SharedHub> {
+        vec![SharedHub {
+            owner_id: "owner".to_string(),
+            owner_login: "octocat".to_string(),
+        }]
+    }
+
+    #[test]
+    fn viewer_can_list_shared_hub() {
+        assert!(find_hub(&hubs(), "owner").is_some());
+
+        let client = serde_json::to_value(ReadOnlyClient::from(&shared_client())).unwrap();
+        assert_eq!(
+            client,
+            serde_json::json!({
+                "id": "laptop",
+                "display_name": "devbox · orchestrator",
+                "status": "busy",
+                "last_activity": "2026-01-01T00:00:00Z",
+                "activity_summary": "running tests"
+            })
+        );
+    }
+
+    #[test]
+    fn viewer_cannot_disconnect_shared_clients() {
+        // The read-only card offers no actions, and nothing in the JSON
+        // (callback URL, origin) lets a viewer reach the client
+        let mut client = shared_client();
+        client.metadata.last_activity = None;
+        let html = templates::render_read_only_client_list(&[client]);
+        assert!(!html.contains("hx-post"));
+        assert!(!html.contains("disconnect"));
+
+        let client = serde_json::to_string(&ReadOnlyClient::from(&shared_client())).unwrap();
+        assert!(!client.contains("localhost:3010"));
+        assert!(!client.contains("203.0.113.7"));
+    }
+
+    #[test]
+    fn non_viewer_is_denied() {
+        assert!(find_hub(&hubs(), "someone-else").is_none());
+        assert!(find_hub(&[], "owner").is_none());
+    }
+
+    /// The users signed in as login `?2` on provider `?1`, resolved like `add_viewer`
+    fn resolve(db: &rusqlite::Connection, name: &str) -> Option<(i64, String)> {
+        let (provider, login) = crate::auth::split_qualified_name(name);
+        db.query_row(ACCOUNT_BY_LOGIN_QUERY, [provider, login], |row| {
+            let provider: String = row.get(2)?;
+            let login: String = row.get(1)?;
+            Ok((row.get(0)?, crate::auth::qualified_name(&provider, &login)))
+        })
+        .ok()
+    }
+
+    fn hubs_of(db: &rusqlite::Connection, account_id: i64, user_id: &str) -> Vec<String> {
+        let mut stmt = db.prepare(SHARED_HUBS_QUERY).unwrap();
+        stmt.query_map(rusqlite::params![account_id, user_id], |row| row.get(0))
+            .unwrap()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn access_follows_the_account_not_the_login() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        db.execute_batch(include_str!("../../schema.sql")).unwrap();
+        db.execute_batch(
+            "INSERT INTO users (id, provider, github_id, github_login, last_login)
+                 VALUES ('owner', 'github', 1, 'owner', '2026-01-01');
+             INSERT INTO users (id, provider, github_id, github_login, last_login)
+                 VALUES ('u-octo', 'github', 583231, 'octocat', '2026-01-01');
+             INSERT INTO users (id, provider, github_id, github_login, last_login)
+                 VALUES ('u-gl', 'gitlab', -7, 'octocat', '2026-01-02');",
+        )
+        .unwrap();
+
+        // Granting resolves the login to its provider account
+        let (account_id, login) = resolve(&db, "OctoCat").unwrap();
+        assert_eq!((account_id, login.as_str()), (583231, "octocat"));
+        assert_eq!(resolve(&db, "gitlab:octocat").unwrap().0, -7);
+        assert_eq!(resolve(&db, "nobody"), None);
+        db.execute(
+            INSERT_HUB_SHARE,
+            rusqlite::params!["owner", login, account_id],
+        )
+        .unwrap();
+        // Granting again is a no-op
+        db.execute(
+            INSERT_HUB_SHARE,
+            rusqlite::params!["owner", login, account_id],
+        )
+        .unwrap();
+        assert_eq!(hubs_of(&db, 583231, "u-octo"), ["owner"]);
+
+        // octocat renames and someone else signs in as "octocat": no access for them
+        db.execute_batch(
+            "UPDATE users SET github_login = 'octo-renamed' WHERE id = 'u-octo';
+             INSERT INTO users (id, provider, github_id, github_login, last_login)
+                 VALUES ('u-new', 'github', 999, 'octocat', '2026-02-01');",
+        )
+        .unwrap();
+        assert!(hubs_of(&db, 999, "u-new").is_empty());
+        assert_eq!(hubs_of(&db, 583231, "u-octo"), ["owner"]);
+
+        // Nor does the same login on another provider get it
+        assert!(hubs_of(&db, -7, "u-gl").is_empty());
+    }
+}
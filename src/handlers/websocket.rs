@@ -1,14 +1,196 @@
 use serde::Deserialize;
+use wasm_bindgen::JsValue;
 use worker::*;
 
 use crate::auth::AuthMiddleware;
-use crate::models::{parse_token, verify_token};
+use crate::durable_objects::user_hub_name;
+use crate::models::{parse_token, token_expired_at, verify_token, ClientMetadata};
+
+/// Records one authentication with a token; each use appends a row, and the
+/// scheduled cleanup (`tokens::prune_token_usage`) trims old ones
+const INSERT_TOKEN_USAGE: &str =
+    "INSERT INTO token_usage (token_id, ip, user_agent, client_id) VALUES (?1, ?2, ?3, ?4)";
+
+/// Connect tests allowed per client IP per window when `CONNECT_TEST_RATE_LIMIT` is unset
+pub(super) const DEFAULT_CONNECT_TEST_RATE_LIMIT: u64 = 10;
+
+/// Length of the connect test window when `CONNECT_TEST_RATE_WINDOW_SECONDS` is unset
+pub(super) const DEFAULT_CONNECT_TEST_RATE_WINDOW_SECONDS: u64 = 60;
 
 /// Row for token validation query
 #[derive(Debug, Deserialize)]
 struct TokenRow {
     user_id: String,
     token_hash: String,
+    expires_at: Option<String>,
+}
+
+/// Return a 401 JSON error with a machine-readable code for claudecodeui
+fn auth_error(code: &str) -> Result<Response> {
+    Ok(Response::from_json(&serde_json::json!({
+        "error": { "code": code }
+    }))?
+    .with_status(401))
+}
+
+/// Split a presented client token into its ID and secret, or the auth error code
+fn parse_client_token(
+    full_token: Option<&str>,
+) -> std::result::Result<(String, String), &'static str> {
+    let full_token = full_token.ok_or("missing_token")?;
+    parse_token(full_token).ok_or("invalid_token_format")
+}
+
+/// Check a token's secret against its D1 row (`None` when no live token has its ID)
+fn verify_client_token(
+    raw_token: &str,
+    row: Option<TokenRow>,
+    now_ms: i64,
+) -> std::result::Result<TokenRow, &'static str> {
+    let row = row.ok_or("token_not_found")?;
+    if !verify_token(raw_token, &row.token_hash) {
+        return Err("invalid_token");
+    }
+    if token_expired_at(row.expires_at.as_deref(), now_ms) {
+        return Err("token_expired");
+    }
+    Ok(row)
+}
+
+/// Resolve a `token_id.secret` client token to its D1 row, or the auth error code
+async fn lookup_client_token(
+    env: &Env,
+    full_token: Option<&str>,
+) -> Result<std::result::Result<(String, TokenRow), &'static str>> {
+    let (token_id, raw_token) = match parse_client_token(full_token) {
+        Ok(parts) => parts,
+        Err(code) => return Ok(Err(code)),
+    };
+
+    // Look up token in D1
+    let db = env.d1("DB")?;
+    let token_result = db
+        .prepare(
+            "SELECT user_id, token_hash, expires_at
+             FROM client_tokens
+             WHERE id = ?1 AND revoked_at IS NULL",
+        )
+        .bind(&[token_id.clone().into()])?
+        .first::<TokenRow>(None)
+        .await?;
+
+    let now_ms = crate::time::now_ms() as i64;
+    Ok(verify_client_token(&raw_token, token_result, now_ms).map(|row| (token_id, row)))
+}
+
+/// Body of `POST /api/connect-test`
+#[derive(Debug, Deserialize)]
+struct ConnectTestRequest {
+    token: String,
+    client_id: String,
+    metadata: ClientMetadata,
+}
+
+/// Dry-run a client connection for setup scripts: validates the token, reaches the
+/// user's hub and checks the registration would be accepted, without registering
+///
+/// Unauthenticated, so it is throttled per client IP: its error codes reveal
+/// whether a token ID exists.
+pub async fn connect_test(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if let Some(ip) = crate::client_info::client_ip(&req, &ctx.env) {
+        let max = crate::config::var_u64(
+            &ctx.env,
+            "CONNECT_TEST_RATE_LIMIT",
+            DEFAULT_CONNECT_TEST_RATE_LIMIT,
+        );
+        let window = crate::config::var_u64(
+            &ctx.env,
+            "CONNECT_TEST_RATE_WINDOW_SECONDS",
+            DEFAULT_CONNECT_TEST_RATE_WINDOW_SECONDS,
+        );
+        let key = format!("connect_test:{}", ip);
+        if let Some(retry_after) =
+            crate::rate_limit::check_rate_limit(&ctx.env, &key, max, window).await
+        {
+            console_warn!("Rate limited connect tests from {}", ip);
+            return crate::rate_limit::too_many_requests(retry_after);
+        }
+    }
+
+    let test: ConnectTestRequest = match req.json().await {
+        Ok(test) => test,
+        Err(e) => return Response::error(format!("Invalid request: {}", e), 400),
+    };
+
+    let token_row = match lookup_client_token(&ctx.env, Some(&test.token)).await? {
+        Ok((_, row)) => row,
+        Err(code) => {
+            return Ok(Response::from_json(&connect_test_rejected(code))?.with_status(401));
+        }
+    };
+
+    // Ask the user's Durable Object whether it would accept this registration
+    let namespace = ctx.env.durable_object("USER_HUB")?;
+    let id = namespace.id_from_name(&user_hub_name(&ctx.env, &token_row.user_id))?;
+    let stub = id.get_stub()?;
+
+    let body = serde_json::json!({
+        "client_id": test.client_id,
+        "metadata": test.metadata,
+    });
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post);
+    init.with_body(Some(body.to_string().into()));
+    let do_req = Request::new_with_init("https://do/clients/dry-register", &init)?;
+
+    let hub_result: Option<serde_json::Value> = match stub.fetch_with_request(do_req).await {
+        Ok(mut resp) if resp.status_code() == 200 => resp.json().await.ok(),
+        Ok(resp) => {
+            console_error!("Connect test: hub returned {}", resp.status_code());
+            None
+        }
+        Err(e) => {
+            console_error!("Connect test: hub unreachable: {:?}", e);
+            None
+        }
+    };
+
+    Response::from_json(&connect_test_result(hub_result.as_ref()))
+}
+
+/// Connect test report for a token that failed authentication with `code`
+fn connect_test_rejected(code: &str) -> serde_json::Value {
+    serde_json::json!({
+        "ok": false,
+        "hub_reachable": false,
+        "would_register": false,
+        "client_limit_remaining": null,
+        "error": { "code": code }
+    })
+}
+
+/// Connect test report from the hub's dry-run answer (`None` when it couldn't be reached)
+fn connect_test_result(hub: Option<&serde_json::Value>) -> serde_json::Value {
+    let hub = match hub {
+        Some(hub) => hub,
+        None => {
+            return serde_json::json!({
+                "ok": false,
+                "hub_reachable": false,
+                "would_register": false,
+                "client_limit_remaining": null,
+            });
+        }
+    };
+
+    let would_register = hub["would_register"].as_bool().unwrap_or(false);
+    serde_json::json!({
+        "ok": would_register,
+        "hub_reachable": true,
+        "would_register": would_register,
+        "client_limit_remaining": hub["client_limit_remaining"],
+        "reason": hub["reason"],
+    })
 }
 
 /// WebSocket upgrade handler - routes to appropriate Durable Object
@@ -16,14 +198,10 @@ pub async fn websocket_upgrade(req: Request, ctx: RouteContext<()>) -> Result<Re
     // Note: Cloudflare handles WebSocket upgrade at edge - we just need to forward to DO
     // The DO will handle the actual WebSocket connection via WebSocketPair
 
-    let url = req.url()?;
-    let params: std::collections::HashMap<String, String> = url
-        .query_pairs()
-        .map(|(k, v)| (k.to_string(), v.to_string()))
-        .collect();
+    let params = crate::query::Query::from_request(&req)?;
 
     // Determine connection type
-    let is_browser = params.get("type").map(|t| t == "browser").unwrap_or(false);
+    let is_browser = params.get_str("type") == Some("browser");
 
     // For browser connections, require authentication via session cookie
     if is_browser {
@@ -34,7 +212,7 @@ pub async fn websocket_upgrade(req: Request, ctx: RouteContext<()>) -> Result<Re
 
         // Forward to user's Durable Object
         let namespace = ctx.env.durable_object("USER_HUB")?;
-        let id = namespace.id_from_name(&user.id)?;
+        let id = namespace.id_from_name(&user_hub_name(&ctx.env, &user.id))?;
         let stub = id.get_stub()?;
 
         // Forward with WebSocket upgrade headers for DO
@@ -49,44 +227,39 @@ pub async fn websocket_upgrade(req: Request, ctx: RouteContext<()>) -> Result<Re
         stub.fetch_with_request(do_req).await
     } else {
         // claudecodeui connection - authenticate via token
-        let full_token = params.get("token").ok_or("Missing token parameter")?;
-
-        // Parse and validate token
-        let (token_id, raw_token) = match parse_token(full_token) {
-            Some(parts) => parts,
-            None => return Response::error("Invalid token format", 401),
+        let full_token = params.get_str("token");
+        let (token_id, token_row) = match lookup_client_token(&ctx.env, full_token).await? {
+            Ok(found) => found,
+            Err(code) => return auth_error(code),
         };
 
-        // Look up token in D1
+        // Update last_used and record the use (fire and forget)
+        let client_id = params.get_str("client_id").unwrap_or_default().to_string();
         let db = ctx.env.d1("DB")?;
-        let token_result = db
-            .prepare(
-                "SELECT user_id, token_hash FROM client_tokens WHERE id = ?1 AND revoked_at IS NULL",
+        let optional = |value: Option<String>| value.map(JsValue::from).unwrap_or(JsValue::NULL);
+        let ip = optional(crate::client_info::client_ip(&req, &ctx.env));
+        let user_agent = optional(crate::client_info::user_agent(&req));
+        let usage = vec![
+            db.prepare(
+                "UPDATE client_tokens
+                 SET last_used = CURRENT_TIMESTAMP, last_used_ip = ?2, last_used_ua = ?3
+                 WHERE id = ?1",
             )
-            .bind(&[token_id.clone().into()])?
-            .first::<TokenRow>(None)
-            .await?;
-
-        let token_row = match token_result {
-            Some(row) => row,
-            None => return Response::error("Token not found or revoked", 401),
-        };
-
-        // Verify token hash
-        if !verify_token(&raw_token, &token_row.token_hash) {
-            return Response::error("Invalid token", 401);
+            .bind(&[token_id.clone().into(), ip.clone(), user_agent.clone()])?,
+            db.prepare(INSERT_TOKEN_USAGE).bind(&[
+                token_id.clone().into(),
+                ip,
+                user_agent,
+                optional(Some(client_id.clone()).filter(|id| !id.is_empty())),
+            ])?,
+        ];
+        if let Err(e) = db.batch(usage).await {
+            console_log!("Failed to record token usage: {:?}", e);
         }
 
-        // Update last_used timestamp (fire and forget)
-        let _ = db
-            .prepare("UPDATE client_tokens SET last_used = CURRENT_TIMESTAMP WHERE id = ?1")
-            .bind(&[token_id.into()])?
-            .run()
-            .await;
-
         // Forward to user's Durable Object
         let namespace = ctx.env.durable_object("USER_HUB")?;
-        let id = namespace.id_from_name(&token_row.user_id)?;
+        let id = namespace.id_from_name(&user_hub_name(&ctx.env, &token_row.user_id))?;
         let stub = id.get_stub()?;
 
         // Forward with WebSocket upgrade headers for DO
@@ -97,10 +270,153 @@ pub async fn websocket_upgrade(req: Request, ctx: RouteContext<()>) -> Result<Re
         init.with_method(Method::Get);
         init.with_headers(headers);
 
-        // Include client_id in the DO request URL for hibernation-aware tagging
-        let client_id = params.get("client_id").cloned().unwrap_or_default();
-        let do_url = format!("https://do/ws?client_id={}", client_id);
-        let do_req = Request::new_with_init(&do_url, &init)?;
+        // Include client_id in the DO request URL for hibernation-aware tagging,
+        // plus where the client connected from (only the Worker sees the edge request)
+        let geo = crate::client_info::geo_info(&req, &ctx.env);
+        let mut do_url = Url::parse("https://do/ws")?;
+        {
+            let mut query = do_url.query_pairs_mut();
+            query.append_pair("client_id", &client_id);
+            // Lets the DO tie the client ID to this token for resuming
+            query.append_pair("token_id", &token_id);
+            for (key, value) in [
+                ("ip", &geo.ip),
+                ("country", &geo.country),
+                ("city", &geo.city),
+                ("region", &geo.region),
+            ] {
+                if let Some(value) = value {
+                    query.append_pair(key, value);
+                }
+            }
+        }
+        let do_req = Request::new_with_init(do_url.as_str(), &init)?;
         stub.fetch_with_request(do_req).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::hash_token;
+
+    /// 2024-03-05T06:07:08Z
+    const NOW: i64 = 1_709_618_828_000;
+
+    fn row(secret: &str, expires_at: Option<&str>) -> TokenRow {
+        TokenRow {
+            user_id: "u1".to_string(),
+            token_hash: hash_token(secret),
+            expires_at: expires_at.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected_before_lookup() {
+        assert_eq!(parse_client_token(None), Err("missing_token"));
+        assert_eq!(parse_client_token(Some("")), Err("invalid_token_format"));
+        for malformed in ["ao_abc", "xx_abc_def"] {
+            let code = parse_client_token(Some(malformed));
+            assert_eq!(code, Err("invalid_token_format"), "{malformed}");
+        }
+        assert_eq!(
+            parse_client_token(Some("ao_abc_s3cret")),
+            Ok(("abc".to_string(), "s3cret".to_string()))
+        );
+    }
+
+    #[test]
+    fn each_token_failure_has_its_own_code() {
+        let check = |secret: &str, row: Option<TokenRow>| {
+            verify_client_token(secret, row, NOW).map(|row| row.user_id)
+        };
+        let live = || Some(row("s3cret", None));
+        let expired = || Some(row("s3cret", Some("2024-03-05T06:07:07Z")));
+        let unexpired = || Some(row("s3cret", Some("2024-03-06 00:00:00")));
+
+        assert_eq!(check("s3cret", live()), Ok("u1".to_string()));
+        assert_eq!(check("s3cret", None), Err("token_not_found"));
+        assert_eq!(check("guess", live()), Err("invalid_token"));
+        assert_eq!(check("s3cret", expired()), Err("token_expired"));
+        assert_eq!(check("s3cret", unexpired()), Ok("u1".to_string()));
+
+        // A wrong secret is reported as such even for an expired token
+        assert_eq!(check("guess", expired()), Err("invalid_token"));
+    }
+
+    #[test]
+    fn connect_test_reports_a_registrable_client() {
+        let hub = serde_json::json!({
+            "would_register": true,
+            "client_limit_remaining": 3,
+            "reason": null
+        });
+        assert_eq!(
+            connect_test_result(Some(&hub)),
+            serde_json::json!({
+                "ok": true,
+                "hub_reachable": true,
+                "would_register": true,
+                "client_limit_remaining": 3,
+                "reason": null
+            })
+        );
+
+        let full = serde_json::json!({
+            "would_register": false,
+            "client_limit_remaining": 0,
+            "reason": "Client limit reached"
+        });
+        let report = connect_test_result(Some(&full));
+        assert_eq!(report["ok"], false);
+        assert_eq!(report["hub_reachable"], true);
+        assert_eq!(report["reason"], "Client limit reached");
+
+        assert_eq!(connect_test_result(None)["hub_reachable"], false);
+    }
+
+    #[test]
+    fn connect_test_reports_the_token_error_code() {
+        for code in ["invalid_token_format", "token_not_found", "invalid_token"] {
+            let report = connect_test_rejected(code);
+            assert_eq!(report["ok"], false);
+            assert_eq!(report["hub_reachable"], false);
+            assert_eq!(report["error"]["code"], code);
+        }
+    }
+
+    #[test]
+    fn each_authentication_appends_a_usage_row() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        db.execute_batch(include_str!("../../schema.sql")).unwrap();
+        db.execute_batch(
+            "INSERT INTO users (id, github_id, github_login) VALUES ('u1', 1, 'octocat');
+             INSERT INTO client_tokens (id, user_id, name, token_hash) VALUES ('t1', 'u1', 'CI', 'h');",
+        )
+        .unwrap();
+
+        for (ip, client_id) in [("203.0.113.7", "laptop"), ("203.0.113.8", "ci-runner")] {
+            db.execute(
+                INSERT_TOKEN_USAGE,
+                rusqlite::params!["t1", ip, "claudecodeui", client_id],
+            )
+            .unwrap();
+        }
+
+        let mut stmt = db
+            .prepare("SELECT ip, client_id FROM token_usage WHERE token_id = 't1' ORDER BY id")
+            .unwrap();
+        let rows: Vec<(String, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            [
+                ("203.0.113.7".to_string(), "laptop".to_string()),
+                ("203.0.113.8".to_string(), "ci-runner".to_string()),
+            ]
+        );
+    }
+}
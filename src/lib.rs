@@ -1,64 +1,257 @@
 use worker::*;
 
 mod auth;
+mod body;
+mod client_info;
+mod config;
 mod durable_objects;
 mod handlers;
 mod models;
+mod query;
+mod random;
+mod rate_limit;
+mod schema;
+mod telemetry;
 mod templates;
+mod time;
+mod user_cache;
 
 pub use durable_objects::UserHub;
 
+/// Cron triggers (see `[triggers]` in wrangler.toml)
+#[event(scheduled)]
+async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    console_error_panic_hook::set_once();
+    schema::ensure_schema_once(&env).await;
+    telemetry::report(&env).await;
+    rate_limit::prune(&env).await;
+    handlers::prune_token_usage(&env).await;
+}
+
 #[event(fetch)]
 async fn main(req: Request, env: Env, _ctx: Context) -> Result<Response> {
     console_error_panic_hook::set_once();
 
-    Router::new()
+    // Every route is mounted under BASE_PATH (empty by default)
+    let base = config::base_path(&env);
+    let route = |path: &str| format!("{}{}", base, path);
+    // Health checks never touch D1, so they don't wait on the bootstrap
+    if req.path() != route("/health") {
+        schema::ensure_schema_once(&env).await;
+    }
+    let frame_ancestors = config::frame_ancestors_policy(&env);
+    // Proxied client pages are passed through untouched
+    let is_proxy_path = req.path().strip_prefix(&base).is_some_and(is_proxy_path);
+
+    let router = Router::new()
         // Public routes
-        .get_async("/", handlers::home)
-        .get("/health", handlers::health)
+        .get_async(&route("/"), handlers::home)
+        .options(&route("/"), handlers::probe_options)
+        .get(&route("/health"), handlers::health)
+        .options(&route("/health"), handlers::probe_options)
         // Auth routes
-        .get_async("/auth/github", auth::start_oauth)
-        .get_async("/auth/github/callback", auth::handle_callback)
-        .get_async("/auth/logout", auth::logout)
+        .get_async(&route("/auth/logout"), auth::logout)
+        .get_async(&route("/auth/:provider"), auth::start_oauth)
+        .get_async(&route("/auth/:provider/callback"), auth::handle_callback)
+        .post_async(&route("/auth/logout-all"), auth::logout_all)
         // Protected routes (dashboard)
-        .get_async("/dashboard", handlers::dashboard)
-        .get_async("/clients", handlers::get_clients)
-        .get_async("/clients/:id", handlers::get_client)
-        .get_async("/clients/:id/details", handlers::get_client_details)
-        .post_async("/clients/:id/disconnect", handlers::disconnect_client)
-        .post_async("/clients/:id/purge-cache", handlers::purge_client_cache)
+        .get_async(&route("/dashboard"), handlers::dashboard)
+        .get_async(&route("/clients"), handlers::get_clients)
+        .get_async(
+            &route("/clients/disconnect-all"),
+            handlers::confirm_disconnect_all,
+        )
+        .post_async(
+            &route("/clients/disconnect-all"),
+            handlers::disconnect_all_clients,
+        )
+        .get_async(&route("/clients/:id"), handlers::get_client)
+        .get_async(&route("/clients/:id/details"), handlers::get_client_details)
+        .post_async(
+            &route("/clients/:id/disconnect"),
+            handlers::disconnect_client,
+        )
+        .post_async(&route("/clients/:id/approve"), handlers::approve_client)
+        .post_async(&route("/clients/:id/reject"), handlers::reject_client)
+        .post_async(
+            &route("/clients/:id/purge-cache"),
+            handlers::purge_client_cache,
+        )
+        .post_async(
+            &route("/clients/:id/ping-proxy"),
+            handlers::ping_client_proxy,
+        )
+        .post_async(
+            &route("/clients/:id/refresh"),
+            handlers::refresh_client_metadata,
+        )
+        // Client API (JSON)
+        .get_async(&route("/api/clients"), handlers::list_clients_api)
+        .get_async(&route("/api/clients/:id"), handlers::get_client_api)
+        // Admin API (ADMIN_USERS only)
+        .get_async(&route("/api/admin/config"), handlers::admin_config)
+        .post_async(
+            &route("/api/admin/users/:user_id/clients/:client_id/disconnect"),
+            handlers::admin_disconnect_client,
+        )
         // Token management API (JSON)
-        .get_async("/api/tokens", handlers::list_tokens)
-        .post_async("/api/tokens", handlers::create_token_api)
-        .post_async("/api/tokens/:id/revoke", handlers::revoke_token_htmx)
-        .delete_async("/api/tokens/:id", handlers::delete_token)
+        .get_async(&route("/api/tokens"), handlers::list_tokens)
+        .post_async(&route("/api/tokens"), handlers::create_token_api)
+        .post_async(&route("/api/tokens/bulk"), handlers::bulk_tokens)
+        .post_async(
+            &route("/api/tokens/revoke-all"),
+            handlers::revoke_all_tokens,
+        )
+        .post_async(
+            &route("/api/tokens/:id/revoke"),
+            handlers::revoke_token_htmx,
+        )
+        .get_async(&route("/api/tokens/:id/usage"), handlers::token_usage)
+        .delete_async(&route("/api/tokens/:id"), handlers::delete_token)
+        // Session management
+        .post_async(&route("/api/connect-test"), handlers::connect_test)
+        .get_async(&route("/api/time"), handlers::server_time)
+        .get_async(&route("/api/sessions"), handlers::list_sessions)
+        .delete_async(&route("/api/sessions/:id"), handlers::revoke_session)
+        .get_async(&route("/sessions"), handlers::list_sessions_htmx)
+        // Read-only hub sharing (owners manage viewers; viewers list shared hubs)
+        .get_async(&route("/api/viewers"), handlers::list_viewers)
+        .post_async(&route("/api/viewers"), handlers::add_viewer)
+        .delete_async(&route("/api/viewers/:login"), handlers::remove_viewer)
+        .get_async(&route("/api/hubs"), handlers::list_shared_hubs)
+        .get_async(
+            &route("/api/hubs/:owner_id/clients"),
+            handlers::shared_hub_clients_api,
+        )
+        .get_async(&route("/hubs/:owner_id"), handlers::shared_hub_clients)
         // Token management UI (HTMX)
-        .get_async("/tokens", handlers::list_tokens_htmx)
-        .get_async("/tokens/new", handlers::show_token_modal)
-        .get_async("/tokens/close-modal", handlers::close_token_modal)
+        .get_async(&route("/tokens"), handlers::list_tokens_htmx)
+        .get_async(&route("/tokens/new"), handlers::show_token_modal)
+        .get_async(&route("/tokens/revoke-all"), handlers::confirm_revoke_all)
+        .get_async(&route("/tokens/close-modal"), handlers::close_token_modal)
         // WebSocket upgrade for claudecodeui connections
-        .get_async("/ws/connect", handlers::websocket_upgrade)
+        .get_async(&route("/ws/connect"), handlers::websocket_upgrade)
         // HTTP proxy to claudecodeui instances
         // Root path proxy (no trailing slash)
-        .get_async("/clients/:id/proxy", handlers::proxy_to_client)
-        .post_async("/clients/:id/proxy", handlers::proxy_to_client)
-        .put_async("/clients/:id/proxy", handlers::proxy_to_client)
-        .delete_async("/clients/:id/proxy", handlers::proxy_to_client)
-        .patch_async("/clients/:id/proxy", handlers::proxy_to_client)
+        .get_async(&route("/clients/:id/proxy"), handlers::proxy_to_client)
+        .post_async(&route("/clients/:id/proxy"), handlers::proxy_to_client)
+        .put_async(&route("/clients/:id/proxy"), handlers::proxy_to_client)
+        .delete_async(&route("/clients/:id/proxy"), handlers::proxy_to_client)
+        .patch_async(&route("/clients/:id/proxy"), handlers::proxy_to_client)
         // Root path proxy (with trailing slash)
-        .get_async("/clients/:id/proxy/", handlers::proxy_to_client)
-        .post_async("/clients/:id/proxy/", handlers::proxy_to_client)
-        .put_async("/clients/:id/proxy/", handlers::proxy_to_client)
-        .delete_async("/clients/:id/proxy/", handlers::proxy_to_client)
-        .patch_async("/clients/:id/proxy/", handlers::proxy_to_client)
+        .get_async(&route("/clients/:id/proxy/"), handlers::proxy_to_client)
+        .post_async(&route("/clients/:id/proxy/"), handlers::proxy_to_client)
+        .put_async(&route("/clients/:id/proxy/"), handlers::proxy_to_client)
+        .delete_async(&route("/clients/:id/proxy/"), handlers::proxy_to_client)
+        .patch_async(&route("/clients/:id/proxy/"), handlers::proxy_to_client)
         // Subpath proxy (with path after /proxy/)
-        .get_async("/clients/:id/proxy/*path", handlers::proxy_to_client)
-        .post_async("/clients/:id/proxy/*path", handlers::proxy_to_client)
-        .put_async("/clients/:id/proxy/*path", handlers::proxy_to_client)
-        .delete_async("/clients/:id/proxy/*path", handlers::proxy_to_client)
-        .patch_async("/clients/:id/proxy/*path", handlers::proxy_to_client)
+        .get_async(
+            &route("/clients/:id/proxy/*path"),
+            handlers::proxy_to_client,
+        )
+        .post_async(
+            &route("/clients/:id/proxy/*path"),
+            handlers::proxy_to_client,
+        )
+        .put_async(
+            &route("/clients/:id/proxy/*path"),
+            handlers::proxy_to_client,
+        )
+        .delete_async(
+            &route("/clients/:id/proxy/*path"),
+            handlers::proxy_to_client,
+        )
+        .patch_async(
+            &route("/clients/:id/proxy/*path"),
+            handlers::proxy_to_client,
+        )
+        // WebSocket tunnel to claudecodeui instances
+        .get_async(
+            &route("/clients/:id/ws-proxy"),
+            handlers::ws_proxy_to_client,
+        )
+        .get_async(
+            &route("/clients/:id/ws-proxy/*path"),
+            handlers::ws_proxy_to_client,
+        )
         // Static assets
-        .get_async("/static/*path", handlers::serve_static)
-        .run(req, env)
-        .await
+        .get_async(&route("/static/*path"), handlers::serve_static);
+
+    // Serve the login page at the bare prefix as well as at `{BASE_PATH}/`
+    let router = if base.is_empty() {
+        router
+    } else {
+        router.get_async(&base, handlers::home)
+    };
+
+    let mut response = router.run(req, env).await?;
+    if is_proxy_path || !is_html(&response) {
+        return Ok(response);
+    }
+    if let Some(policy) = &frame_ancestors {
+        response
+            .headers_mut()
+            .set("Content-Security-Policy", policy)?;
+    }
+    if base.is_empty() {
+        return Ok(response);
+    }
+
+    // Templates render links rooted at `/`; mount them under BASE_PATH
+    let status = response.status_code();
+    let headers = response.headers().clone();
+    let html = response.text().await?;
+    Ok(Response::from_html(templates::prefix_links(&html, &base))?
+        .with_status(status)
+        .with_headers(headers))
+}
+
+/// Whether a path (below BASE_PATH) is `/clients/:id/proxy` or a subpath of it
+fn is_proxy_path(path: &str) -> bool {
+    let rest = match path.strip_prefix("/clients/") {
+        Some(rest) => rest,
+        None => return false,
+    };
+    match rest.split_once('/') {
+        Some((id, tail)) => !id.is_empty() && (tail == "proxy" || tail.starts_with("proxy/")),
+        None => false,
+    }
+}
+
+/// Whether a response is an HTML page or fragment
+fn is_html(response: &Response) -> bool {
+    response
+        .headers()
+        .get("Content-Type")
+        .ok()
+        .flatten()
+        .is_some_and(|ct| ct.starts_with("text/html"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proxy_paths_match_the_proxy_routes_only() {
+        for path in [
+            "/clients/laptop/proxy",
+            "/clients/laptop/proxy/",
+            "/clients/laptop/proxy/index.html",
+            "/clients/proxybox/proxy/api",
+        ] {
+            assert!(is_proxy_path(path), "{}", path);
+        }
+        for path in [
+            "/clients/proxybox",
+            "/clients/proxybox/details",
+            "/clients/laptop/ping-proxy",
+            "/clients/laptop/ws-proxy",
+            "/clients/laptop/proxyish",
+            "/clients//proxy",
+            "/dashboard/clients/laptop/proxy",
+        ] {
+            assert!(!is_proxy_path(path), "{}", path);
+        }
+    }
 }
@@ -1,18 +1,18 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+use crate::time::now_iso;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum ClientStatus {
+    #[default]
     Idle,
     Active,
     Busy,
     Disconnected,
-}
-
-impl Default for ClientStatus {
-    fn default() -> Self {
-        Self::Idle
-    }
+    /// Connected with a valid token but waiting for manual approval (REQUIRE_CLIENT_APPROVAL)
+    #[serde(rename = "pending_approval")]
+    PendingApproval,
 }
 
 impl std::fmt::Display for ClientStatus {
@@ -22,6 +22,7 @@ impl std::fmt::Display for ClientStatus {
             Self::Active => write!(f, "active"),
             Self::Busy => write!(f, "busy"),
             Self::Disconnected => write!(f, "disconnected"),
+            Self::PendingApproval => write!(f, "pending_approval"),
         }
     }
 }
@@ -36,6 +37,63 @@ pub struct ClientMetadata {
     /// Optional HTTP callback URL for direct proxying (e.g., http://localhost:3010 or https://tunnel.ngrok.io)
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub callback_url: Option<String>,
+    /// Opt in to caching static proxied assets (JS, CSS, images) in R2
+    #[serde(default)]
+    pub asset_cache: bool,
+    /// Short free-form description of what the client is doing (e.g. "running tests")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub activity_summary: Option<String>,
+    /// Where the client connected from, as observed by the orchestrator (never client-supplied)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geo: Option<GeoInfo>,
+}
+
+/// How regularly a connected client has been pinging
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionQuality {
+    Good,
+    Degraded,
+}
+
+impl std::fmt::Display for ConnectionQuality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Good => write!(f, "good"),
+            Self::Degraded => write!(f, "degraded"),
+        }
+    }
+}
+
+/// Connection origin of a client, taken from the WebSocket upgrade request
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GeoInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+}
+
+impl GeoInfo {
+    pub fn is_empty(&self) -> bool {
+        self.ip.is_none() && self.country.is_none() && self.city.is_none() && self.region.is_none()
+    }
+}
+
+/// Result of probing a client's callback URL from the orchestrator
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxyHealth {
+    pub reachable: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -45,58 +103,65 @@ pub struct Client {
     pub metadata: ClientMetadata,
     pub connected_at: String,
     pub last_seen: String,
+    /// Derived from recent ping intervals; unset until enough pings have arrived
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connection_quality: Option<ConnectionQuality>,
 }
 
 impl Client {
     pub fn new(id: String, user_id: String, metadata: ClientMetadata) -> Self {
-        let now = current_timestamp();
+        let now = now_iso();
         Self {
             id,
             user_id,
             metadata,
             connected_at: now.clone(),
             last_seen: now,
+            connection_quality: None,
         }
     }
 
     pub fn update_last_seen(&mut self) {
-        self.last_seen = current_timestamp();
+        self.last_seen = now_iso();
     }
 
     pub fn update_status(&mut self, status: ClientStatus) {
         self.metadata.status = status;
-        self.metadata.last_activity = Some(current_timestamp());
+        self.metadata.last_activity = Some(now_iso());
     }
-}
-
-fn current_timestamp() -> String {
-    let now = js_sys::Date::now();
-    let date = js_sys::Date::new(&wasm_bindgen::JsValue::from_f64(now));
-    date.to_iso_string().as_string().unwrap_or_default()
-}
-
-mod js_sys {
-    use wasm_bindgen::prelude::*;
-
-    #[wasm_bindgen]
-    extern "C" {
-        pub type Date;
-
-        #[wasm_bindgen(constructor)]
-        pub fn new(value: &JsValue) -> Date;
 
-        #[wasm_bindgen(static_method_of = Date)]
-        pub fn now() -> f64;
-
-        #[wasm_bindgen(method, js_name = toISOString)]
-        pub fn to_iso_string(this: &Date) -> JsString;
+    pub fn update_activity(&mut self, summary: String) {
+        self.metadata.activity_summary = Some(summary);
+        self.metadata.last_activity = Some(now_iso());
     }
+}
 
-    #[wasm_bindgen]
-    extern "C" {
-        pub type JsString;
+/// A client plus connection health derived from it, for `GET /api/clients/:id`
+#[derive(Debug, Serialize)]
+pub struct ClientDetail {
+    #[serde(flatten)]
+    pub client: Client,
+    /// Anything but `disconnected`
+    pub is_connected: bool,
+    /// Whole seconds since `last_seen` (unset if the timestamp can't be parsed)
+    pub seconds_since_last_seen: Option<u64>,
+    pub has_callback_url: bool,
+}
 
-        #[wasm_bindgen(method, js_name = toString)]
-        pub fn as_string(this: &JsString) -> Option<String>;
+impl ClientDetail {
+    /// Derive health fields as of `now_ms` (epoch milliseconds)
+    pub fn new(client: Client, now_ms: i64) -> Self {
+        let seconds_since_last_seen = crate::time::epoch_ms_of(&client.last_seen)
+            .map(|last_seen| (now_ms - last_seen).max(0) as u64 / 1000);
+        Self {
+            is_connected: client.metadata.status != ClientStatus::Disconnected,
+            seconds_since_last_seen,
+            has_callback_url: client
+                .metadata
+                .callback_url
+                .as_deref()
+                .is_some_and(|url| !url.trim().is_empty()),
+            client,
+        }
     }
 }
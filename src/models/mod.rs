@@ -1,7 +1,16 @@
 mod client;
+mod proxy;
 mod token;
 mod user;
 
-pub use client::{Client, ClientMetadata, ClientStatus};
-pub use token::{hash_token, parse_token, verify_token, ClientToken, TokenCreated, TokenInfo};
-pub use user::{Session, User};
+pub use client::{
+    Client, ClientDetail, ClientMetadata, ClientStatus, ConnectionQuality, GeoInfo, ProxyHealth,
+};
+pub use proxy::{
+    build_response, is_relayed_response_header, ProxyRequest, ProxyResponse, HOP_BY_HOP_HEADERS,
+};
+pub use token::{
+    hash_token, parse_token, token_expired, token_expired_at, verify_token, ClientToken,
+    TokenCreated, TokenInfo, TOKEN_PREFIX,
+};
+pub use user::{HubViewer, Session, SessionInfo, SharedHub, User};
@@ -0,0 +1,160 @@
+use serde::{Deserialize, Serialize};
+use worker::{Error, Headers, Response, Result};
+
+/// Hop-by-hop headers, which describe a single connection and are never relayed
+pub const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "trailers",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+];
+
+/// HTTP request proxied from the Worker through the user's Durable Object
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProxyRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>,
+    /// `body` holds base64 of a binary payload (see `crate::body`)
+    #[serde(default)]
+    pub body_is_base64: bool,
+    pub query: Option<String>,
+}
+
+/// Buffered response relayed from a client by the Durable Object
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProxyResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    /// `body` holds base64 of a binary payload (see `crate::body`)
+    #[serde(default)]
+    pub body_is_base64: bool,
+    /// Set when the orchestrator itself failed (client offline, timeout, ...)
+    /// rather than relaying a response from the client
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Whether a client response header is passed on to the browser
+///
+/// Drops hop-by-hop headers, `Content-Length` (the runtime re-frames the
+/// body) and anything in `denylist` (lowercased names).
+pub fn is_relayed_response_header(name: &str, denylist: &[String]) -> bool {
+    let name = name.to_lowercase();
+    !HOP_BY_HOP_HEADERS.contains(&name.as_str())
+        && name != "content-length"
+        && !denylist.contains(&name)
+}
+
+/// Browser-facing response for a relayed `ProxyResponse`
+///
+/// Keeps the client's status as-is (including redirects and 304s) and
+/// decodes base64 bodies back to bytes.
+pub fn build_response(resp: &ProxyResponse, denylist: &[String]) -> Result<Response> {
+    let relayed = relayed_response(resp, denylist)?;
+    let headers = Headers::new();
+    for (key, value) in relayed.headers {
+        headers.append(key, value)?;
+    }
+    Ok(Response::from_bytes(relayed.body)?
+        .with_status(relayed.status)
+        .with_headers(headers))
+}
+
+/// What `build_response` sends: the client's status, relayed headers and decoded body
+#[derive(Debug, PartialEq)]
+struct RelayedResponse<'a> {
+    status: u16,
+    headers: Vec<(&'a str, &'a str)>,
+    body: Vec<u8>,
+}
+
+fn relayed_response<'a>(
+    resp: &'a ProxyResponse,
+    denylist: &[String],
+) -> Result<RelayedResponse<'a>> {
+    let headers = resp
+        .headers
+        .iter()
+        .filter(|(key, _)| is_relayed_response_header(key, denylist))
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    let body = crate::body::decode(&resp.body, resp.body_is_base64)
+        .ok_or_else(|| Error::RustError("Invalid base64 body".to_string()))?;
+    Ok(RelayedResponse {
+        status: resp.status,
+        headers,
+        body,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hop_by_hop_length_and_denied_headers_are_dropped() {
+        let denylist = vec!["set-cookie".to_string()];
+        assert!(is_relayed_response_header("Content-Type", &denylist));
+        assert!(is_relayed_response_header("Cache-Control", &denylist));
+        for name in [
+            "Connection",
+            "Transfer-Encoding",
+            "Content-Length",
+            "Set-Cookie",
+        ] {
+            assert!(!is_relayed_response_header(name, &denylist), "{}", name);
+        }
+        assert!(is_relayed_response_header("Set-Cookie", &[]));
+    }
+
+    fn response(status: u16, body: &str, body_is_base64: bool) -> ProxyResponse {
+        ProxyResponse {
+            status,
+            headers: vec![
+                ("Content-Type".to_string(), "application/json".to_string()),
+                ("Connection".to_string(), "keep-alive".to_string()),
+            ],
+            body: body.to_string(),
+            body_is_base64,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn client_statuses_and_bodies_are_relayed_unchanged() {
+        for (status, body) in [
+            (201, r#"{"id":"s1"}"#),
+            (304, ""),
+            (404, r#"{"error":"not found"}"#),
+            (502, "upstream down"),
+        ] {
+            let resp = response(status, body, false);
+            let relayed = relayed_response(&resp, &[]).unwrap();
+            assert_eq!(
+                relayed,
+                RelayedResponse {
+                    status,
+                    headers: vec![("Content-Type", "application/json")],
+                    body: body.as_bytes().to_vec(),
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn base64_bodies_are_decoded() {
+        let png = response(200, "iVBORw==", true);
+        let relayed = relayed_response(&png, &[]).unwrap();
+        assert_eq!(relayed.body, [0x89, b'P', b'N', b'G']);
+        assert!(relayed_response(&response(200, "not base64!", true), &[]).is_err());
+    }
+}
@@ -1,5 +1,10 @@
 use serde::{Deserialize, Serialize};
 
+use crate::time::{future_iso, is_past_at, now_iso};
+
+/// Prefix identifying orchestrator client tokens (`ao_<id>_<secret>`)
+pub const TOKEN_PREFIX: &str = "ao_";
+
 /// Client connection token for claudecodeui instances
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientToken {
@@ -8,7 +13,13 @@ pub struct ClientToken {
     pub name: String,
     pub created_at: String,
     pub last_used: Option<String>,
+    /// Client IP of the most recent connection made with the token
+    pub last_used_ip: Option<String>,
+    /// User-Agent of the most recent connection made with the token
+    pub last_used_ua: Option<String>,
     pub revoked_at: Option<String>,
+    /// When the token stops authenticating; `None` means it never expires
+    pub expires_at: Option<String>,
 }
 
 /// Token creation response (includes the raw token, shown only once)
@@ -26,70 +37,74 @@ pub struct TokenInfo {
     pub name: String,
     pub created_at: String,
     pub last_used: Option<String>,
+    pub last_used_ip: Option<String>,
+    pub last_used_ua: Option<String>,
+    pub expires_at: Option<String>,
     pub is_revoked: bool,
+    pub is_expired: bool,
 }
 
 impl ClientToken {
-    /// Generate a new token with a random value
-    pub fn new(user_id: String, name: String) -> (Self, String) {
-        let id = generate_id();
-        let raw_token = generate_token();
-        let token_hash = hash_token(&raw_token);
+    /// Generate a new token with a random value, expiring after `ttl_hours` if given
+    ///
+    /// Fails when the RNG is unavailable or the expiry is beyond what a date can hold.
+    pub fn new(
+        user_id: String,
+        name: String,
+        ttl_hours: Option<u64>,
+    ) -> worker::Result<(Self, String)> {
+        let expires_at = match ttl_hours {
+            Some(hours) => Some(
+                future_iso(hours)
+                    .ok_or_else(|| worker::Error::RustError("Token expiry out of range".into()))?,
+            ),
+            None => None,
+        };
+        let id = generate_id()?;
+        let raw_token = generate_token()?;
 
         let token = Self {
             id,
             user_id,
             name,
-            created_at: current_timestamp(),
+            created_at: now_iso(),
             last_used: None,
+            last_used_ip: None,
+            last_used_ua: None,
             revoked_at: None,
+            expires_at,
         };
 
         // Return the full token prefixed with the ID for easy lookup
-        let full_token = format!("ao_{}_{}", token.id, raw_token);
-        (token, full_token)
+        let full_token = format!("{}{}_{}", TOKEN_PREFIX, token.id, raw_token);
+        Ok((token, full_token))
     }
+}
 
-    /// Check if the token is revoked
-    #[allow(dead_code)]
-    pub fn is_revoked(&self) -> bool {
-        self.revoked_at.is_some()
-    }
+/// Whether a stored `expires_at` has passed (tokens without one never expire)
+pub fn token_expired(expires_at: Option<&str>) -> bool {
+    token_expired_at(expires_at, crate::time::now_ms() as i64)
+}
 
-    /// Convert to public token info
-    #[allow(dead_code)]
-    pub fn to_info(&self) -> TokenInfo {
-        TokenInfo {
-            id: self.id.clone(),
-            name: self.name.clone(),
-            created_at: self.created_at.clone(),
-            last_used: self.last_used.clone(),
-            is_revoked: self.is_revoked(),
-        }
-    }
+/// [`token_expired`] relative to `now_ms` rather than the current time
+pub fn token_expired_at(expires_at: Option<&str>, now_ms: i64) -> bool {
+    expires_at.is_some_and(|expires_at| is_past_at(expires_at, now_ms))
 }
 
 /// Generate a random ID
-fn generate_id() -> String {
-    use getrandom::getrandom;
-    let mut bytes = [0u8; 8];
-    getrandom(&mut bytes).expect("Failed to generate random bytes");
-    hex::encode(&bytes)
+fn generate_id() -> worker::Result<String> {
+    crate::random::random_hex(8)
 }
 
 /// Generate a random token value
-fn generate_token() -> String {
-    use getrandom::getrandom;
-    let mut bytes = [0u8; 32];
-    getrandom(&mut bytes).expect("Failed to generate random bytes");
-    hex::encode(&bytes)
+fn generate_token() -> worker::Result<String> {
+    crate::random::random_hex(32)
 }
 
 /// Hash a token for storage (simple SHA-256 simulation using repeated hashing)
 pub fn hash_token(token: &str) -> String {
     // Simple hash for token storage (not cryptographically secure, but acceptable for this use case)
     // In production, use a proper KDF like Argon2 or bcrypt
-    use getrandom::getrandom;
 
     // XOR-based simple hash
     let token_bytes = token.as_bytes();
@@ -111,11 +126,9 @@ pub fn verify_token(token: &str, hash: &str) -> bool {
 /// Parse a full token string into (id, raw_token)
 pub fn parse_token(full_token: &str) -> Option<(String, String)> {
     // Format: ao_<id>_<raw_token>
-    if !full_token.starts_with("ao_") {
-        return None;
-    }
+    let rest = full_token.strip_prefix(TOKEN_PREFIX)?;
 
-    let parts: Vec<&str> = full_token[3..].splitn(2, '_').collect();
+    let parts: Vec<&str> = rest.splitn(2, '_').collect();
     if parts.len() != 2 {
         return None;
     }
@@ -123,40 +136,34 @@ pub fn parse_token(full_token: &str) -> Option<(String, String)> {
     Some((parts[0].to_string(), parts[1].to_string()))
 }
 
-fn current_timestamp() -> String {
-    let now = js_sys::Date::now();
-    let date = js_sys::Date::new(&wasm_bindgen::JsValue::from_f64(now));
-    date.to_iso_string().as_string().unwrap_or_default()
-}
-
 mod hex {
     pub fn encode(bytes: &[u8]) -> String {
         bytes.iter().map(|b| format!("{:02x}", b)).collect()
     }
 }
 
-mod js_sys {
-    use wasm_bindgen::prelude::*;
-
-    #[wasm_bindgen]
-    extern "C" {
-        pub type Date;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::tests::with_rng_failure;
 
-        #[wasm_bindgen(constructor)]
-        pub fn new(value: &JsValue) -> Date;
-
-        #[wasm_bindgen(static_method_of = Date)]
-        pub fn now() -> f64;
-
-        #[wasm_bindgen(method, js_name = toISOString)]
-        pub fn to_iso_string(this: &Date) -> JsString;
+    #[test]
+    fn rng_failure_fails_token_generation_without_panicking() {
+        // Handlers turn this error into a 500 (see `token_generation_error`)
+        let result = with_rng_failure(|| ClientToken::new("u1".into(), "laptop".into(), None));
+        assert!(result.is_err());
     }
 
-    #[wasm_bindgen]
-    extern "C" {
-        pub type JsString;
-
-        #[wasm_bindgen(method, js_name = toString)]
-        pub fn as_string(this: &JsString) -> Option<String>;
+    #[test]
+    fn tokens_expire_at_their_expiry_and_never_without_one() {
+        // 2024-03-05T06:07:08Z
+        let now = 1_709_618_828_000;
+        assert!(token_expired_at(Some("2024-03-05T06:07:07Z"), now));
+        assert!(token_expired_at(Some("2024-03-05 06:07:08"), now));
+        assert!(!token_expired_at(Some("2024-03-05T06:07:09.000Z"), now));
+        assert!(!token_expired_at(None, now));
+
+        // A malformed expiry fails closed
+        assert!(token_expired_at(Some("soon"), now));
     }
 }
@@ -1,8 +1,14 @@
 use serde::{Deserialize, Serialize};
 
+use crate::time::{future_iso, now_iso};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub id: String,
+    /// OAuth provider the account signed in with ("github" or "gitlab")
+    #[serde(default = "default_provider")]
+    pub provider: String,
+    /// Provider account ID; negated for GitLab so it stays unique across providers
     pub github_id: i64,
     pub github_login: String,
     pub email: Option<String>,
@@ -18,27 +24,62 @@ pub struct Session {
     pub created_at: String,
 }
 
+/// Session for listing in the dashboard (without the user reference)
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub created_at: String,
+    pub expires_at: String,
+    pub last_activity: Option<String>,
+    pub created_ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// Whether this is the session making the request
+    pub is_current: bool,
+}
+
+/// Someone else's hub the signed-in user may view (see `hub_shares`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SharedHub {
+    pub owner_id: String,
+    pub owner_login: String,
+}
+
+/// An account granted read-only access to the signed-in user's hub, by its login when granted
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HubViewer {
+    pub viewer_login: String,
+    pub created_at: String,
+}
+
 impl User {
-    pub fn new(github_id: i64, github_login: String, email: Option<String>) -> Self {
-        Self {
-            id: generate_id(),
+    pub fn new(
+        provider: String,
+        github_id: i64,
+        github_login: String,
+        email: Option<String>,
+    ) -> worker::Result<Self> {
+        Ok(Self {
+            id: generate_id()?,
+            provider,
             github_id,
             github_login,
             email,
-            created_at: current_timestamp(),
-            last_login: Some(current_timestamp()),
-        }
+            created_at: now_iso(),
+            last_login: Some(now_iso()),
+        })
     }
 
     /// Create user from D1 database row
     pub fn from_db(
         id: String,
+        provider: String,
         github_id: i64,
         github_login: String,
         email: Option<String>,
     ) -> Self {
         Self {
             id,
+            provider,
             github_id,
             github_login,
             email,
@@ -49,70 +90,22 @@ impl User {
 }
 
 impl Session {
-    pub fn new(user_id: String, duration_hours: u64) -> Self {
-        Self {
-            id: generate_id(),
+    pub fn new(user_id: String, duration_hours: u64) -> worker::Result<Self> {
+        Ok(Self {
+            id: generate_id()?,
             user_id,
-            expires_at: future_timestamp(duration_hours),
-            created_at: current_timestamp(),
-        }
-    }
-
-    pub fn is_expired(&self) -> bool {
-        // Simple string comparison works for ISO 8601 timestamps
-        self.expires_at < current_timestamp()
+            expires_at: future_iso(duration_hours)
+                .ok_or_else(|| worker::Error::RustError("Session expiry out of range".into()))?,
+            created_at: now_iso(),
+        })
     }
 }
 
-fn generate_id() -> String {
-    use getrandom::getrandom;
-    let mut bytes = [0u8; 16];
-    getrandom(&mut bytes).expect("Failed to generate random bytes");
-    hex::encode(&bytes)
-}
-
-fn current_timestamp() -> String {
-    // In WASM, we use js_sys for time
-    let now = js_sys::Date::now();
-    let date = js_sys::Date::new(&wasm_bindgen::JsValue::from_f64(now));
-    date.to_iso_string().as_string().unwrap_or_default()
-}
-
-fn future_timestamp(hours: u64) -> String {
-    let now = js_sys::Date::now();
-    let future = now + (hours as f64 * 3600.0 * 1000.0);
-    let date = js_sys::Date::new(&wasm_bindgen::JsValue::from_f64(future));
-    date.to_iso_string().as_string().unwrap_or_default()
-}
-
-mod hex {
-    pub fn encode(bytes: &[u8]) -> String {
-        bytes.iter().map(|b| format!("{:02x}", b)).collect()
-    }
+/// Provider of users cached before the column existed
+fn default_provider() -> String {
+    "github".to_string()
 }
 
-mod js_sys {
-    use wasm_bindgen::prelude::*;
-
-    #[wasm_bindgen]
-    extern "C" {
-        pub type Date;
-
-        #[wasm_bindgen(constructor)]
-        pub fn new(value: &JsValue) -> Date;
-
-        #[wasm_bindgen(static_method_of = Date)]
-        pub fn now() -> f64;
-
-        #[wasm_bindgen(method, js_name = toISOString)]
-        pub fn to_iso_string(this: &Date) -> JsString;
-    }
-
-    #[wasm_bindgen]
-    extern "C" {
-        pub type JsString;
-
-        #[wasm_bindgen(method, js_name = toString)]
-        pub fn as_string(this: &JsString) -> Option<String>;
-    }
+fn generate_id() -> worker::Result<String> {
+    crate::random::random_hex(16)
 }
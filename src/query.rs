@@ -0,0 +1,94 @@
+//! Typed access to URL query parameters
+//!
+//! Parse once per request with `Query::from_url` and read values through the
+//! getters. When a key repeats, the first occurrence wins, so a later
+//! `&next=...` cannot override an earlier one.
+
+use worker::{Request, Result, Url};
+
+/// Decoded query string of a request URL
+pub struct Query {
+    pairs: Vec<(String, String)>,
+}
+
+impl Query {
+    pub fn from_url(url: &Url) -> Self {
+        Self {
+            pairs: url
+                .query_pairs()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    pub fn from_request(req: &Request) -> Result<Self> {
+        Ok(Self::from_url(&req.url()?))
+    }
+
+    /// Raw value of a parameter, if present (possibly empty)
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Boolean parameter (`true`/`1`/`yes` or `false`/`0`/`no`), `default` if absent or malformed
+    pub fn get_bool(&self, key: &str, default: bool) -> bool {
+        let value = self.get_str(key).map(|v| v.trim().to_lowercase());
+        match value.as_deref() {
+            Some("true") | Some("1") | Some("yes") => true,
+            Some("false") | Some("0") | Some("no") => false,
+            _ => default,
+        }
+    }
+
+    /// Numeric parameter, `default` if absent or not a valid `u32`
+    pub fn get_u32(&self, key: &str, default: u32) -> u32 {
+        self.get_str(key)
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(query: &str) -> Query {
+        Query::from_url(&Url::parse(&format!("https://example.com/?{}", query)).unwrap())
+    }
+
+    #[test]
+    fn first_occurrence_wins_and_values_are_decoded() {
+        let q = query("next=%2Fdashboard&next=https://evil.example&empty=");
+        assert_eq!(q.get_str("next"), Some("/dashboard"));
+        assert_eq!(q.get_str("empty"), Some(""));
+        assert_eq!(q.get_str("missing"), None);
+    }
+
+    #[test]
+    fn booleans_fall_back_to_default_when_malformed() {
+        let q = query("a=true&b=1&c=YES&d=false&e=0&f=no&g=maybe&h=");
+        for key in ["a", "b", "c"] {
+            assert!(q.get_bool(key, false), "{}", key);
+        }
+        for key in ["d", "e", "f"] {
+            assert!(!q.get_bool(key, true), "{}", key);
+        }
+        assert!(q.get_bool("g", true));
+        assert!(!q.get_bool("h", false));
+        assert!(q.get_bool("missing", true));
+    }
+
+    #[test]
+    fn numbers_fall_back_to_default_when_malformed() {
+        let q = query("limit=25&offset=-1&page=abc&big=4294967296&padded=%207");
+        assert_eq!(q.get_u32("limit", 10), 25);
+        assert_eq!(q.get_u32("offset", 0), 0);
+        assert_eq!(q.get_u32("page", 1), 1);
+        assert_eq!(q.get_u32("big", 5), 5);
+        assert_eq!(q.get_u32("padded", 0), 7);
+        assert_eq!(q.get_u32("missing", 3), 3);
+    }
+}
@@ -0,0 +1,58 @@
+use worker::{Error, Result};
+
+/// Fill a buffer with cryptographically secure random bytes
+///
+/// `getrandom` is backed by `crypto.getRandomValues` in Workers and should not
+/// fail, but if it does we surface an error instead of panicking the isolate.
+pub fn fill_bytes(bytes: &mut [u8]) -> Result<()> {
+    #[cfg(test)]
+    if tests::FAIL.get() {
+        return Err(Error::RustError(
+            "Failed to generate random bytes: injected failure".to_string(),
+        ));
+    }
+
+    getrandom::getrandom(bytes)
+        .map_err(|e| Error::RustError(format!("Failed to generate random bytes: {}", e)))
+}
+
+/// Generate `len` random bytes encoded as a lowercase hex string
+pub fn random_hex(len: usize) -> Result<String> {
+    let mut bytes = vec![0u8; len];
+    fill_bytes(&mut bytes)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    thread_local! {
+        /// Makes `fill_bytes` fail, standing in for a broken `crypto.getRandomValues`
+        pub(crate) static FAIL: Cell<bool> = const { Cell::new(false) };
+    }
+
+    /// Run `f` with random byte generation failing
+    pub(crate) fn with_rng_failure<T>(f: impl FnOnce() -> T) -> T {
+        FAIL.set(true);
+        let result = f();
+        FAIL.set(false);
+        result
+    }
+
+    #[test]
+    fn random_hex_encodes_requested_length() {
+        let hex = random_hex(16).unwrap();
+        assert_eq!(hex.len(), 32);
+        assert!(hex.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f')));
+    }
+
+    #[test]
+    fn rng_failure_is_an_error_not_a_panic() {
+        let result = with_rng_failure(|| random_hex(16));
+        assert!(matches!(result, Err(Error::RustError(message)) if message.contains("random")));
+        assert!(random_hex(16).is_ok());
+    }
+}
@@ -0,0 +1,139 @@
+//! Fixed-window rate limiting for abuse-prone endpoints
+//!
+//! Counts live in the D1 `rate_limits` table, one row per key, so every
+//! isolate sees the same totals. A key is allowed `max` requests per window
+//! of `window_secs`; the window restarts with the first request after it ends.
+
+use serde::Deserialize;
+use worker::*;
+
+/// Rows whose window started longer ago than this are pruned by the cron
+const RATE_LIMIT_RETENTION_SECS: u64 = 86_400;
+
+/// Count a hit for key `?1` in the window starting at `?2`, restarting the
+/// count when the stored window is an older one
+const RECORD_HIT: &str = "INSERT INTO rate_limits (key, window_start, hits) VALUES (?1, ?2, 1)
+     ON CONFLICT(key) DO UPDATE SET
+         hits = CASE WHEN window_start = excluded.window_start THEN hits + 1 ELSE 1 END,
+         window_start = excluded.window_start
+     RETURNING window_start, hits";
+
+/// Count a request against `key`, returning the seconds until its window ends
+/// once more than `max` requests were made in it
+///
+/// A `max` or `window_secs` of 0 disables the limit. D1 failures are logged and
+/// let the request through, so an outage doesn't lock everyone out.
+pub async fn check_rate_limit(env: &Env, key: &str, max: u64, window_secs: u64) -> Option<u64> {
+    if max == 0 || window_secs == 0 {
+        return None;
+    }
+
+    #[derive(Deserialize)]
+    struct WindowRow {
+        window_start: u64,
+        hits: u64,
+    }
+
+    let now = crate::time::now_ms() / 1000;
+    let window_start = window_start(now, window_secs);
+    let result = async {
+        let db = env.d1("DB")?;
+        db.prepare(RECORD_HIT)
+            .bind(&[key.into(), (window_start as f64).into()])?
+            .first::<WindowRow>(None)
+            .await
+    }
+    .await;
+
+    match result {
+        Ok(Some(row)) => retry_after(row.window_start, row.hits, max, window_secs, now),
+        Ok(None) => None,
+        Err(e) => {
+            console_error!("Rate limit check for {} failed: {:?}", key, e);
+            None
+        }
+    }
+}
+
+/// Start (epoch seconds) of the fixed window containing `now`
+fn window_start(now: u64, window_secs: u64) -> u64 {
+    now - now % window_secs
+}
+
+/// Seconds until the window starting at `window_start` ends, once `hits`
+/// exceeds `max` (at least 1, so callers never retry immediately)
+fn retry_after(window_start: u64, hits: u64, max: u64, window_secs: u64, now: u64) -> Option<u64> {
+    (hits > max).then(|| (window_start + window_secs).saturating_sub(now).max(1))
+}
+
+/// 429 response telling the caller when to retry
+pub fn too_many_requests(retry_after_secs: u64) -> Result<Response> {
+    let headers = Headers::new();
+    headers.set("Retry-After", &retry_after_secs.to_string())?;
+    Ok(Response::error("Too many requests, try again later", 429)?.with_headers(headers))
+}
+
+/// Delete counters for windows that ended long ago; failures are only logged
+pub async fn prune(env: &Env) {
+    let cutoff = (crate::time::now_ms() / 1000).saturating_sub(RATE_LIMIT_RETENTION_SECS);
+    let result = async {
+        env.d1("DB")?
+            .prepare("DELETE FROM rate_limits WHERE window_start < ?1")
+            .bind(&[(cutoff as f64).into()])?
+            .run()
+            .await
+    }
+    .await;
+
+    if let Err(e) = result {
+        console_error!("Failed to prune rate limit counters: {:?}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_are_aligned_to_their_length() {
+        assert_eq!(window_start(0, 60), 0);
+        assert_eq!(window_start(59, 60), 0);
+        assert_eq!(window_start(60, 60), 60);
+        assert_eq!(window_start(1_709_618_828, 3_600), 1_709_618_400);
+        assert_eq!(window_start(1_709_618_828, 1), 1_709_618_828);
+    }
+
+    #[test]
+    fn retry_after_counts_down_to_the_window_end() {
+        // Within the limit
+        assert_eq!(retry_after(60, 5, 5, 60, 61), None);
+        // Over it, at the start and in the middle of the window
+        assert_eq!(retry_after(60, 6, 5, 60, 60), Some(60));
+        assert_eq!(retry_after(60, 6, 5, 60, 100), Some(20));
+        // Never less than a second, even at (or past) the window's end
+        assert_eq!(retry_after(60, 6, 5, 60, 119), Some(1));
+        assert_eq!(retry_after(60, 6, 5, 60, 120), Some(1));
+        assert_eq!(retry_after(60, 6, 5, 60, 500), Some(1));
+    }
+
+    /// `RECORD_HIT` against the real schema, returning `(window_start, hits)`
+    fn record_hit(db: &rusqlite::Connection, key: &str, window_start: u64) -> (u64, u64) {
+        db.query_row(RECORD_HIT, rusqlite::params![key, window_start], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn hits_are_counted_per_key_and_window() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        db.execute_batch(include_str!("../schema.sql")).unwrap();
+
+        assert_eq!(record_hit(&db, "login:1.2.3.4", 60), (60, 1));
+        assert_eq!(record_hit(&db, "login:1.2.3.4", 60), (60, 2));
+        assert_eq!(record_hit(&db, "login:5.6.7.8", 60), (60, 1));
+
+        // The next window starts over
+        assert_eq!(record_hit(&db, "login:1.2.3.4", 120), (120, 1));
+    }
+}
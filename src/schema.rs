@@ -0,0 +1,269 @@
+//! D1 schema bootstrapping
+//!
+//! `schema.sql` is compiled in and applied once per isolate (and by the cron
+//! trigger), so a fresh deployment works without running `wrangler d1 execute`
+//! first. Every statement is `CREATE ... IF NOT EXISTS`, making repeated runs
+//! no-ops, and column additions only run when `pragma_table_info` shows the
+//! column is missing.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use worker::*;
+
+const SCHEMA_SQL: &str = include_str!("../schema.sql");
+
+/// Comment prefix marking column additions for databases created before them
+const MIGRATION_PREFIX: &str = "-- Existing databases:";
+
+/// Column names of the table bound as `?1`
+const TABLE_COLUMNS_QUERY: &str = "SELECT name FROM pragma_table_info(?1)";
+
+thread_local! {
+    static SCHEMA_READY: Cell<bool> = const { Cell::new(false) };
+}
+
+/// A column addition listed as an "Existing databases" comment in `schema.sql`
+#[derive(Debug, PartialEq)]
+struct Migration {
+    table: String,
+    column: String,
+    statement: String,
+}
+
+/// `CREATE` statements from `schema.sql`, with comments stripped
+fn create_statements() -> Vec<String> {
+    let sql: String = SCHEMA_SQL
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("--"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    sql.split(';')
+        .map(|statement| statement.trim().to_string())
+        .filter(|statement| !statement.is_empty())
+        .collect()
+}
+
+/// `ALTER TABLE` statements listed as "Existing databases" comments in `schema.sql`
+fn migration_statements() -> Vec<String> {
+    SCHEMA_SQL
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix(MIGRATION_PREFIX))
+        .map(|statement| statement.trim().trim_end_matches(';').to_string())
+        .collect()
+}
+
+/// Migrations with the table and column each one adds
+///
+/// Statements not shaped like `ALTER TABLE <table> ADD COLUMN <column> ...`
+/// are skipped.
+fn migrations() -> Vec<Migration> {
+    migration_statements()
+        .into_iter()
+        .filter_map(|statement| {
+            let words: Vec<&str> = statement.split_whitespace().collect();
+            match words.as_slice() {
+                [alter, table_kw, table, add, column_kw, column, ..]
+                    if alter.eq_ignore_ascii_case("ALTER")
+                        && table_kw.eq_ignore_ascii_case("TABLE")
+                        && add.eq_ignore_ascii_case("ADD")
+                        && column_kw.eq_ignore_ascii_case("COLUMN") =>
+                {
+                    Some(Migration {
+                        table: table.to_string(),
+                        column: column.to_string(),
+                        statement: statement.clone(),
+                    })
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Migrations whose column is missing, given each table's current columns
+fn pending_migrations<'a>(
+    migrations: &'a [Migration],
+    columns: &HashMap<String, Vec<String>>,
+) -> Vec<&'a Migration> {
+    migrations
+        .iter()
+        .filter(|migration| {
+            !columns.get(&migration.table).is_some_and(|existing| {
+                existing
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(&migration.column))
+            })
+        })
+        .collect()
+}
+
+/// Create any missing tables and indexes, and add columns missing from older databases
+///
+/// Safe to run any number of times: one batch creates the schema, one batch
+/// reads the migrated tables' columns, and only missing columns are added.
+pub async fn ensure_schema(db: &D1Database) -> Result<()> {
+    #[derive(Deserialize)]
+    struct Column {
+        name: String,
+    }
+
+    let statements = create_statements()
+        .into_iter()
+        .map(|statement| db.prepare(statement))
+        .collect();
+    db.batch(statements).await?;
+
+    let migrations = migrations();
+    let mut tables: Vec<&str> = migrations.iter().map(|m| m.table.as_str()).collect();
+    tables.sort_unstable();
+    tables.dedup();
+    let queries = tables
+        .iter()
+        .map(|table| db.prepare(TABLE_COLUMNS_QUERY).bind(&[(*table).into()]))
+        .collect::<Result<Vec<_>>>()?;
+    let mut columns = HashMap::new();
+    for (table, result) in tables.iter().zip(db.batch(queries).await?) {
+        let names: Vec<Column> = result.results()?;
+        let names = names.into_iter().map(|column| column.name).collect();
+        columns.insert(table.to_string(), names);
+    }
+
+    for migration in pending_migrations(&migrations, &columns) {
+        console_log!("Adding column {}.{}", migration.table, migration.column);
+        if let Err(e) = db.prepare(&migration.statement).run().await {
+            console_error!(
+                "Failed to add column {}.{}: {:?}",
+                migration.table,
+                migration.column,
+                e
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Run `ensure_schema` on the first request handled by this isolate
+///
+/// Failures are logged and retried on the next request rather than failing
+/// the current one; queries against a missing table will report it anyway.
+pub async fn ensure_schema_once(env: &Env) {
+    if SCHEMA_READY.with(|ready| ready.get()) {
+        return;
+    }
+
+    let result = match env.d1("DB") {
+        Ok(db) => ensure_schema(&db).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => SCHEMA_READY.with(|ready| ready.set(true)),
+        Err(e) => console_error!("Schema bootstrap failed: {:?}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Current columns of every migrated table, as `ensure_schema` reads them
+    fn table_columns(db: &rusqlite::Connection) -> HashMap<String, Vec<String>> {
+        migrations()
+            .iter()
+            .map(|migration| {
+                let mut stmt = db.prepare(TABLE_COLUMNS_QUERY).unwrap();
+                let names = stmt
+                    .query_map([&migration.table], |row| row.get(0))
+                    .unwrap()
+                    .collect::<std::result::Result<Vec<String>, _>>()
+                    .unwrap();
+                (migration.table.clone(), names)
+            })
+            .collect()
+    }
+
+    /// `ensure_schema` against SQLite, returning the columns it added
+    fn apply_schema(db: &rusqlite::Connection) -> Vec<String> {
+        for statement in create_statements() {
+            db.execute(&statement, []).unwrap();
+        }
+        let migrations = migrations();
+        let pending = pending_migrations(&migrations, &table_columns(db));
+        for migration in &pending {
+            db.execute(&migration.statement, []).unwrap();
+        }
+        pending
+            .iter()
+            .map(|m| format!("{}.{}", m.table, m.column))
+            .collect()
+    }
+
+    #[test]
+    fn create_statements_cover_schema_sql() {
+        let statements = create_statements();
+        assert!(statements.iter().all(|s| s.starts_with("CREATE ")));
+        assert!(statements.iter().all(|s| s.contains("IF NOT EXISTS")));
+        assert!(statements.iter().all(|s| !s.contains("--")));
+
+        let created = |kind: &str| {
+            SCHEMA_SQL
+                .lines()
+                .filter(|line| line.starts_with(kind))
+                .count()
+        };
+        let expected = created("CREATE TABLE") + created("CREATE INDEX");
+        assert_eq!(statements.len(), expected);
+    }
+
+    #[test]
+    fn migrations_are_parsed_from_schema_sql() {
+        let statements = migration_statements();
+        assert!(!statements.is_empty());
+        assert!(statements.iter().all(|s| !s.ends_with(';')));
+
+        // Every listed statement parses, and names a column of its table
+        let migrations = migrations();
+        assert_eq!(migrations.len(), statements.len());
+        assert!(migrations.contains(&Migration {
+            table: "users".to_string(),
+            column: "provider".to_string(),
+            statement: "ALTER TABLE users ADD COLUMN provider TEXT NOT NULL DEFAULT 'github'"
+                .to_string(),
+        }));
+    }
+
+    #[test]
+    fn fresh_databases_need_no_migrations() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        assert!(apply_schema(&db).is_empty());
+    }
+
+    #[test]
+    fn older_databases_gain_missing_columns_once() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE TABLE users (
+                 id TEXT PRIMARY KEY,
+                 github_id INTEGER UNIQUE NOT NULL,
+                 github_login TEXT NOT NULL,
+                 email TEXT,
+                 created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                 last_login DATETIME
+             );
+             INSERT INTO users (id, github_id, github_login) VALUES ('u1', 1, 'octocat');",
+        )
+        .unwrap();
+
+        assert_eq!(apply_schema(&db), ["users.provider"]);
+        let provider: String = db
+            .query_row("SELECT provider FROM users WHERE id = 'u1'", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(provider, "github");
+
+        // A second run changes nothing and doesn't fail
+        assert!(apply_schema(&db).is_empty());
+    }
+}
@@ -0,0 +1,94 @@
+//! Opt-in anonymized usage telemetry
+//!
+//! Off unless `TELEMETRY_ENABLED` is true and `TELEMETRY_ENDPOINT` is set.
+//! When on, the scheduled handler POSTs a single JSON object of deployment-wide
+//! totals to the endpoint:
+//!
+//! ```json
+//! { "version": "0.1.0", "users": 12, "clients": 5, "tokens": 20 }
+//! ```
+//!
+//! `clients` counts currently connected clients and `tokens` counts unrevoked
+//! tokens. Nothing identifying is sent: no user or client IDs, names,
+//! hostnames, projects, IPs or deployment URLs.
+
+use serde::{Deserialize, Serialize};
+use worker::*;
+
+/// Aggregate counts sent to `TELEMETRY_ENDPOINT`
+#[derive(Debug, Serialize)]
+pub struct TelemetryReport {
+    pub version: String,
+    pub users: u64,
+    pub clients: u64,
+    pub tokens: u64,
+}
+
+/// Endpoint to report to, or `None` when telemetry is off
+pub fn endpoint(env: &Env) -> Option<String> {
+    if !crate::config::var_bool(env, "TELEMETRY_ENABLED", false) {
+        return None;
+    }
+    crate::config::var_string(env, "TELEMETRY_ENDPOINT")
+}
+
+/// Count users, connected clients and active tokens across the deployment
+async fn collect(env: &Env) -> Result<TelemetryReport> {
+    #[derive(Deserialize)]
+    struct CountsRow {
+        users: u64,
+        clients: u64,
+        tokens: u64,
+    }
+
+    let db = env.d1("DB")?;
+    let counts = db
+        .prepare(
+            "SELECT (SELECT COUNT(*) FROM users) AS users,
+                    (SELECT COUNT(*) FROM clients) AS clients,
+                    (SELECT COUNT(*) FROM client_tokens WHERE revoked_at IS NULL) AS tokens",
+        )
+        .first::<CountsRow>(None)
+        .await?
+        .ok_or("No telemetry counts returned")?;
+
+    Ok(TelemetryReport {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        users: counts.users,
+        clients: counts.clients,
+        tokens: counts.tokens,
+    })
+}
+
+/// Send one report if telemetry is enabled; failures are logged, never retried
+pub async fn report(env: &Env) {
+    let endpoint = match endpoint(env) {
+        Some(endpoint) => endpoint,
+        None => return,
+    };
+
+    let result = async {
+        let report = collect(env).await?;
+        let headers = Headers::new();
+        headers.set("Content-Type", "application/json")?;
+
+        let mut init = RequestInit::new();
+        init.with_method(Method::Post);
+        init.with_headers(headers);
+        init.with_body(Some(wasm_bindgen::JsValue::from_str(
+            &serde_json::to_string(&report)?,
+        )));
+
+        let response = Fetch::Request(Request::new_with_init(&endpoint, &init)?)
+            .send()
+            .await?;
+        Ok::<u16, Error>(response.status_code())
+    }
+    .await;
+
+    match result {
+        Ok(status) if (200..300).contains(&status) => {}
+        Ok(status) => console_error!("Telemetry endpoint returned {}", status),
+        Err(e) => console_error!("Failed to send telemetry: {:?}", e),
+    }
+}
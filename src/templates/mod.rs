@@ -1,27 +1,101 @@
-use crate::models::{Client, ClientStatus, TokenInfo, User};
+use crate::models::{Client, ClientStatus, ProxyHealth, SessionInfo, SharedHub, TokenInfo, User};
+
+/// Attributes holding app-absolute URLs that need the `BASE_PATH` prefix
+const LINK_ATTRIBUTES: &[&str] = &["href", "src", "action", "hx-get", "hx-post", "hx-delete"];
+
+/// Prefix every app-absolute link in rendered HTML with `base` (`BASE_PATH`)
+///
+/// Templates always render links rooted at `/`; this rewrites the attributes
+/// in `LINK_ATTRIBUTES` (leaving protocol-relative `//host` URLs alone) and
+/// fills in `data-base-path` for the dashboard script. Text content is
+/// escaped, so only real attributes match.
+pub fn prefix_links(html: &str, base: &str) -> String {
+    if base.is_empty() {
+        return html.to_string();
+    }
 
-/// Render the home/login page
-pub fn render_home() -> String {
-    layout(
-        "AI Orchestrator",
+    let mut html = html.replace(
+        "data-base-path=\"\"",
+        &["data-base-path=\"", base, "\""].concat(),
+    );
+    for attribute in LINK_ATTRIBUTES {
+        let rooted = [attribute, "=\"/"].concat();
+        let prefixed = [attribute, "=\"", base, "/"].concat();
+        let protocol_relative = [attribute, "=\"", base, "//"].concat();
+        html = html
+            .replace(&rooted, &prefixed)
+            .replace(&protocol_relative, &[attribute, "=\"//"].concat());
+    }
+    html
+}
+
+/// Render the home/login page, returning to `next` after sign-in
+///
+/// The GitLab button is only shown when GitLab sign-in is configured.
+pub fn render_home(next: Option<&str>, gitlab_enabled: bool) -> String {
+    let sign_in_url = |provider: &str| match next {
+        Some(next) => format!("/auth/{}?next={}", provider, crate::auth::url_encode(next)),
+        None => format!("/auth/{}", provider),
+    };
+    let gitlab_button = if gitlab_enabled {
+        [
+            r#"
+            <a href=""#,
+            &escape_html(&sign_in_url("gitlab")),
+            r#"" class="btn btn-secondary">Sign in with GitLab</a>"#,
+        ]
+        .concat()
+    } else {
+        String::new()
+    };
+
+    let content = [
         r#"
         <div class="login-container">
             <h1>AI Orchestrator</h1>
             <p>Manage your Claude Code instances from a unified interface.</p>
-            <a href="/auth/github" class="btn btn-primary">
+            <a href=""#,
+        &escape_html(&sign_in_url("github")),
+        r#"" class="btn btn-primary">
                 <svg class="icon" viewBox="0 0 16 16" fill="currentColor">
                     <path d="M8 0C3.58 0 0 3.58 0 8c0 3.54 2.29 6.53 5.47 7.59.4.07.55-.17.55-.38 0-.19-.01-.82-.01-1.49-2.01.37-2.53-.49-2.69-.94-.09-.23-.48-.94-.82-1.13-.28-.15-.68-.52-.01-.53.63-.01 1.08.58 1.23.82.72 1.21 1.87.87 2.33.66.07-.52.28-.87.51-1.07-1.78-.2-3.64-.89-3.64-3.95 0-.87.31-1.59.82-2.15-.08-.2-.36-1.02.08-2.12 0 0 .67-.21 2.2.82.64-.18 1.32-.27 2-.27.68 0 1.36.09 2 .27 1.53-1.04 2.2-.82 2.2-.82.44 1.1.16 1.92.08 2.12.51.56.82 1.27.82 2.15 0 3.07-1.87 3.75-3.65 3.95.29.25.54.73.54 1.48 0 1.07-.01 1.93-.01 2.2 0 .21.15.46.55.38A8.013 8.013 0 0016 8c0-4.42-3.58-8-8-8z"/>
                 </svg>
                 Sign in with GitHub
-            </a>
+            </a>"#,
+        &gitlab_button,
+        r#"
         </div>
         "#,
-    )
+    ]
+    .concat();
+
+    layout("AI Orchestrator", &content)
+}
+
+/// Render a standalone error page with a link back home
+pub fn render_error(title: &str, message: &str) -> String {
+    let title_escaped = escape_html(title);
+    let message_escaped = escape_html(message);
+
+    let content = [
+        "<div class=\"login-container\">",
+        "<h1>", &title_escaped, "</h1>",
+        "<p>", &message_escaped, "</p>",
+        "<a href=\"/\" class=\"btn btn-secondary\">Back to home</a>",
+        "</div>",
+    ].concat();
+
+    layout(&[title, " - AI Orchestrator"].concat(), &content)
 }
 
 /// Render the main dashboard
-pub fn render_dashboard(user: &User) -> String {
+/// Dashboard page; `modal` is pre-rendered into the modal slot (e.g. the welcome token)
+///
+/// `shared_hubs` are other users' hubs this user may view; when there are any,
+/// a switcher above the client list links to them.
+pub fn render_dashboard(user: &User, shared_hubs: &[SharedHub], modal: Option<&str>) -> String {
     let username = escape_html(&user.github_login);
+    let switcher = render_hub_switcher(shared_hubs, None);
 
     let content = [
         "<header class=\"dashboard-header\">",
@@ -29,26 +103,43 @@ pub fn render_dashboard(user: &User) -> String {
         "<div class=\"user-info\">",
         "<span>", &username, "</span>",
         "<a href=\"/auth/logout\" class=\"btn btn-secondary\">Logout</a>",
+        "<form method=\"post\" action=\"/auth/logout-all\" ",
+        "onsubmit=\"return confirm('Sign out of every device, including this one?')\">",
+        "<button type=\"submit\" class=\"btn btn-secondary\">Log out all devices</button>",
+        "</form>",
         "</div></header>",
         "<main class=\"dashboard-main\">",
         "<section class=\"clients-section\">",
+        &switcher,
         "<div class=\"section-header\">",
         "<h2>Connected Clients</h2>",
+        "<div class=\"header-actions\">",
         "<span id=\"client-count-badge\" class=\"count-badge\">0</span>",
-        "</div>",
-        "<div id=\"clients-list\" hx-get=\"/clients\" hx-trigger=\"load, every 30s\" hx-swap=\"innerHTML\">",
-        "<div class=\"loading\">Loading clients...</div>",
+        "<button class=\"btn btn-danger btn-sm\" hx-get=\"/clients/disconnect-all\" hx-target=\"#token-modal\" hx-swap=\"innerHTML\">Disconnect All</button>",
+        "</div></div>",
+        "<div id=\"clients-list\" hx-get=\"/clients\" hx-trigger=\"load, every 30s\" hx-vals=\"js:{limit: clientListLimit}\" hx-swap=\"innerHTML\">",
+        "<div class=\"loading\">Loading clients... <a href=\"/clients\">Open client list</a></div>",
         "</div></section>",
         "<section class=\"tokens-section\">",
         "<div class=\"section-header\">",
         "<h2>Connection Tokens</h2>",
+        "<div class=\"header-actions\">",
+        "<button class=\"btn btn-danger btn-sm\" hx-get=\"/tokens/revoke-all\" hx-target=\"#token-modal\" hx-swap=\"innerHTML\">Revoke All</button>",
         "<button class=\"btn btn-primary btn-sm\" hx-get=\"/tokens/new\" hx-target=\"#token-modal\" hx-swap=\"innerHTML\">+ New Token</button>",
-        "</div>",
+        "</div></div>",
         "<p class=\"section-desc\">Generate tokens for your Claude Code instances to connect.</p>",
         "<div id=\"tokens-list\" hx-get=\"/tokens\" hx-trigger=\"load\" hx-swap=\"innerHTML\">",
-        "<div class=\"loading\">Loading tokens...</div>",
+        "<div class=\"loading\">Loading tokens... <a href=\"/tokens\">Open token list</a></div>",
+        "</div></section>",
+        "<section class=\"sessions-section\">",
+        "<div class=\"section-header\">",
+        "<h2>Active Sessions</h2>",
+        "</div>",
+        "<p class=\"section-desc\">Browsers currently signed in to your account.</p>",
+        "<div id=\"sessions-list\" hx-get=\"/sessions\" hx-trigger=\"load\" hx-swap=\"innerHTML\">",
+        "<div class=\"loading\">Loading sessions... <a href=\"/sessions\">Open session list</a></div>",
         "</div></section></main>",
-        "<div id=\"token-modal\"></div>",
+        "<div id=\"token-modal\">", modal.unwrap_or(""), "</div>",
         DASHBOARD_SCRIPT,
     ].concat();
 
@@ -61,6 +152,54 @@ let reconnectAttempts = 0;
 const maxReconnectAttempts = 5;
 let activeClientId = null;
 let pendingRequests = {};
+// Empty uses the server's default card limit; 'all' once "Show more" is clicked
+let clientListLimit = '';
+// Server clock minus browser clock, so relative times survive a wrong local clock
+let clockSkewMs = 0;
+// Path prefix the app is mounted under (BASE_PATH), filled in by the server
+const basePath = document.documentElement.dataset.basePath || '';
+
+function serverNow() {
+    return Date.now() + clockSkewMs;
+}
+
+// Estimate the skew from /api/time, assuming the response was produced mid-flight
+async function syncServerClock() {
+    try {
+        const sent = Date.now();
+        const resp = await fetch(basePath + '/api/time', { cache: 'no-store' });
+        const data = await resp.json();
+        const received = Date.now();
+        clockSkewMs = data.now_ms - (sent + received) / 2;
+    } catch (e) {
+        clockSkewMs = 0;
+    }
+    updateRelativeTimes();
+}
+
+function formatAgo(ms) {
+    const seconds = Math.max(0, Math.round(ms / 1000));
+    if (seconds < 60) return 'just now';
+    const minutes = Math.floor(seconds / 60);
+    if (minutes < 60) return minutes + (minutes === 1 ? ' minute ago' : ' minutes ago');
+    const hours = Math.floor(minutes / 60);
+    if (hours < 24) return hours + (hours === 1 ? ' hour ago' : ' hours ago');
+    const days = Math.floor(hours / 24);
+    return days + (days === 1 ? ' day ago' : ' days ago');
+}
+
+function updateRelativeTimes() {
+    document.querySelectorAll('time[data-epoch-ms]').forEach(el => {
+        const at = Number(el.dataset.epochMs);
+        if (!Number.isNaN(at)) {
+            el.title = new Date(at).toLocaleString();
+            el.textContent = formatAgo(serverNow() - at);
+        }
+    });
+}
+
+document.body.addEventListener('htmx:afterSwap', updateRelativeTimes);
+setInterval(updateRelativeTimes, 30000);
 
 function generateRequestId() {
     return 'req_' + Date.now() + '_' + Math.random().toString(36).substr(2, 9);
@@ -77,7 +216,7 @@ function escapeHtml(str) {
 function connectWebSocket() {
     ws = new WebSocket(
         (location.protocol === 'https:' ? 'wss:' : 'ws:') +
-        '//' + location.host + '/ws/connect?type=browser'
+        '//' + location.host + basePath + '/ws/connect?type=browser'
     );
 
     ws.onopen = () => {
@@ -100,8 +239,26 @@ function connectWebSocket() {
                 htmx.trigger('#clients-list', 'load');
             }
             updateClientCount(Object.values(clientsMap));
+        } else if (msg.type === 'client_patch') {
+            const client = clientsMap[msg.client_id];
+            if (client) {
+                applyClientPatch(client, msg.changed);
+                // Only re-render the card when displayed fields changed
+                if (msg.changed.metadata || msg.changed.connection_quality) {
+                    const clientCard = document.getElementById('client-' + msg.client_id);
+                    if (clientCard) {
+                        htmx.trigger(clientCard, 'refresh');
+                    }
+                }
+                updateClientCount(Object.values(clientsMap));
+            } else {
+                htmx.trigger('#clients-list', 'load');
+            }
         } else if (msg.type === 'client_disconnected') {
             delete clientsMap[msg.client_id];
+            if (msg.reason) {
+                showNotification('Client ' + msg.client_id + ' shut down: ' + msg.reason);
+            }
             const clientCard = document.getElementById('client-' + msg.client_id);
             if (clientCard) {
                 clientCard.style.opacity = '0.5';
@@ -130,6 +287,9 @@ function connectWebSocket() {
             }
         } else if (msg.type === 'forwarded_response') {
             handleForwardedResponse(msg);
+        } else if (msg.type === 'browser_heartbeat') {
+            // Answer so the hub knows this tab is still open
+            ws.send(JSON.stringify({ type: 'browser_heartbeat' }));
         }
     };
 
@@ -178,6 +338,19 @@ function handleForwardedResponse(msg) {
     }
 }
 
+// Merge a client_patch into the cached client object (nested objects merge key by key)
+function applyClientPatch(target, changed) {
+    Object.keys(changed).forEach(key => {
+        const value = changed[key];
+        if (value && typeof value === 'object' && !Array.isArray(value) &&
+            target[key] && typeof target[key] === 'object') {
+            applyClientPatch(target[key], value);
+        } else {
+            target[key] = value;
+        }
+    });
+}
+
 function updateClientCount(clients) {
     const badge = document.getElementById('client-count-badge');
     if (badge && clients) {
@@ -366,38 +539,154 @@ function showNotification(message) {
 }
 
 connectWebSocket();
+syncServerClock();
 </script>"#;
 
-/// Render the clients page (full page, used for non-HTMX requests)
-pub fn render_clients_page(user: &User, clients: &[Client]) -> String {
+/// Render a full page around a single list section
+///
+/// Used for non-HTMX requests so the dashboard's list sections still work as
+/// plain links when htmx fails to load.
+fn render_list_page(user: &User, title: &str, section: &str, list_id: &str, list: &str) -> String {
     layout(
-        "Clients - AI Orchestrator",
+        &[title, " - AI Orchestrator"].concat(),
         &format!(
             r#"
             <header class="dashboard-header">
                 <h1>AI Orchestrator</h1>
                 <div class="user-info">
                     <span>{}</span>
+                    <a href="/dashboard" class="btn btn-secondary">Dashboard</a>
                     <a href="/auth/logout" class="btn btn-secondary">Logout</a>
                 </div>
             </header>
             <main class="dashboard-main">
-                <section class="clients-section">
-                    <h2>Connected Clients</h2>
-                    <div id="clients-list">
+                <section class="{}">
+                    <h2>{}</h2>
+                    <div id="{}">
                         {}
                     </div>
                 </section>
             </main>
             "#,
             escape_html(&user.github_login),
-            render_client_list(clients)
+            section,
+            title,
+            list_id,
+            list
         ),
     )
 }
 
+/// Render the clients page (full page, used for non-HTMX requests)
+pub fn render_clients_page(user: &User, clients: &[Client], limit: Option<usize>) -> String {
+    render_list_page(
+        user,
+        "Connected Clients",
+        "clients-section",
+        "clients-list",
+        &render_client_list(clients, limit),
+    )
+}
+
+/// Links between the user's own hub and the hubs shared with them
+///
+/// `current` is the owner ID of the shared hub being viewed (`None` for the
+/// user's own dashboard). Renders nothing when no hubs are shared.
+fn render_hub_switcher(shared_hubs: &[SharedHub], current: Option<&str>) -> String {
+    if shared_hubs.is_empty() {
+        return String::new();
+    }
+
+    let link = |href: &str, label: &str, active: bool| {
+        let class = if active {
+            "btn-primary"
+        } else {
+            "btn-secondary"
+        };
+        [
+            "<a href=\"",
+            &escape_html(href),
+            "\" class=\"btn btn-sm ",
+            class,
+            "\">",
+            &escape_html(label),
+            "</a>",
+        ]
+        .concat()
+    };
+
+    let mut links = vec![link("/dashboard", "My clients", current.is_none())];
+    links.extend(shared_hubs.iter().map(|hub| {
+        link(
+            &["/hubs/", &hub.owner_id].concat(),
+            &hub.owner_login,
+            current == Some(hub.owner_id.as_str()),
+        )
+    }));
+
+    ["<nav class=\"hub-switcher\">", &links.concat(), "</nav>"].concat()
+}
+
+/// Read-only page of another user's clients, for viewers of their hub
+pub fn render_shared_hub_page(
+    user: &User,
+    hub: &SharedHub,
+    shared_hubs: &[SharedHub],
+    clients: &[Client],
+) -> String {
+    let title = [&hub.owner_login, "'s Clients"].concat();
+    let owner_id = escape_html(&hub.owner_id);
+    let list = [
+        render_hub_switcher(shared_hubs, Some(&hub.owner_id)),
+        "<p class=\"section-desc\">Read-only: shared with you by ".to_string(),
+        escape_html(&hub.owner_login),
+        ".</p>".to_string(),
+        "<div hx-get=\"/hubs/".to_string(),
+        owner_id,
+        "\" hx-trigger=\"every 30s\" hx-swap=\"innerHTML\">".to_string(),
+        render_read_only_client_list(clients),
+        "</div>".to_string(),
+    ]
+    .concat();
+
+    render_list_page(
+        user,
+        &escape_html(&title),
+        "clients-section",
+        "clients-list",
+        &list,
+    )
+}
+
+/// Render the tokens page (full page, used for non-HTMX requests)
+pub fn render_tokens_page(user: &User, tokens: &[TokenInfo], next_page: Option<&str>) -> String {
+    render_list_page(
+        user,
+        "Connection Tokens",
+        "tokens-section",
+        "tokens-list",
+        &render_token_list(tokens, next_page),
+    )
+}
+
+/// Render the sessions page (full page, used for non-HTMX requests)
+pub fn render_sessions_page(user: &User, sessions: &[SessionInfo]) -> String {
+    render_list_page(
+        user,
+        "Active Sessions",
+        "sessions-section",
+        "sessions-list",
+        &render_session_list(sessions),
+    )
+}
+
 /// Render the client list (HTMX partial)
-pub fn render_client_list(clients: &[Client]) -> String {
+///
+/// Clients are ordered by relevance (awaiting approval, active/busy, then idle,
+/// then disconnected).
+/// With a `limit`, only that many cards are shown, followed by a control that
+/// reloads the list with every client.
+pub fn render_client_list(clients: &[Client], limit: Option<usize>) -> String {
     if clients.is_empty() {
         return r#"
             <div class="empty-state">
@@ -408,10 +697,135 @@ pub fn render_client_list(clients: &[Client]) -> String {
         .to_string();
     }
 
-    let cards: Vec<String> = clients.iter().map(render_client_card).collect();
+    let mut ordered: Vec<&Client> = clients.iter().collect();
+    ordered.sort_by_key(|c| status_rank(c.metadata.status));
+
+    let shown = limit.unwrap_or(ordered.len()).min(ordered.len());
+    let cards: Vec<String> = ordered[..shown]
+        .iter()
+        .map(|c| render_client_card(c))
+        .collect();
+
+    let hidden = ordered.len() - shown;
+    let show_more = if hidden > 0 {
+        [
+            "<div class=\"show-more\">",
+            "<button class=\"btn btn-secondary btn-sm\" onclick=\"clientListLimit = 'all'; htmx.trigger('#clients-list', 'load')\">",
+            "Show ",
+            &hidden.to_string(),
+            " more</button></div>",
+        ]
+        .concat()
+    } else {
+        String::new()
+    };
+
+    format!(
+        r#"<div class="clients-grid">{}</div>{}"#,
+        cards.join("\n"),
+        show_more
+    )
+}
+
+/// Position of a status in client lists (most relevant first)
+fn status_rank(status: ClientStatus) -> u8 {
+    match status {
+        ClientStatus::PendingApproval => 0,
+        ClientStatus::Active | ClientStatus::Busy => 1,
+        ClientStatus::Idle => 2,
+        ClientStatus::Disconnected => 3,
+    }
+}
+
+/// Client list for a hub viewer: status and activity only, no links or actions
+pub fn render_read_only_client_list(clients: &[Client]) -> String {
+    if clients.is_empty() {
+        return r#"<div class="empty-state"><p>No clients connected.</p></div>"#.to_string();
+    }
+
+    let mut ordered: Vec<&Client> = clients.iter().collect();
+    ordered.sort_by_key(|c| status_rank(c.metadata.status));
+
+    let cards: Vec<String> = ordered
+        .iter()
+        .map(|client| {
+            let status = client.metadata.status.to_string();
+            let last_activity = client
+                .metadata
+                .last_activity
+                .as_ref()
+                .map(|t| format_relative_time(t))
+                .unwrap_or_else(|| "No activity".to_string());
+            let activity_summary = client
+                .metadata
+                .activity_summary
+                .as_ref()
+                .map(|summary| {
+                    [
+                        "<div class=\"activity-summary\">",
+                        &escape_html(summary),
+                        "</div>",
+                    ]
+                    .concat()
+                })
+                .unwrap_or_default();
+            [
+                "<div class=\"client-card\">",
+                "<div class=\"client-header\">",
+                "<span class=\"client-title\">",
+                &escape_html(&display_name(client)),
+                "</span>",
+                "<div class=\"header-right\">",
+                "<span class=\"status-badge status-",
+                if client.metadata.status == ClientStatus::PendingApproval {
+                    "pending"
+                } else {
+                    &status
+                },
+                "\">",
+                &status,
+                "</span>",
+                "</div></div>",
+                &activity_summary,
+                "<div class=\"client-footer\">",
+                "<span class=\"last-activity\">",
+                &last_activity,
+                "</span>",
+                "</div></div>",
+            ]
+            .concat()
+        })
+        .collect();
+
     format!(r#"<div class="clients-grid">{}</div>"#, cards.join("\n"))
 }
 
+/// Longest project basename shown in a derived client name before it is shortened
+const MAX_DISPLAY_PROJECT_LEN: usize = 24;
+
+/// Card title for a client: "hostname · project", falling back to the client ID
+///
+/// Only the last segment of the project path is used, shortened with
+/// `truncate_path`. Either half may be missing; with neither, the ID is shown.
+pub fn display_name(client: &Client) -> String {
+    let hostname = client.metadata.hostname.trim();
+    let project = client
+        .metadata
+        .project
+        .trim()
+        .rsplit(['/', '\\'])
+        .find(|segment| !segment.is_empty())
+        .map(|basename| truncate_path(basename, MAX_DISPLAY_PROJECT_LEN))
+        .unwrap_or_default();
+
+    match (hostname.is_empty(), project.is_empty()) {
+        (false, false) => format!("{} · {}", hostname, project),
+        (false, true) => hostname.to_string(),
+        (true, false) => project,
+        (true, true) => client.id.clone(),
+    }
+}
+
 /// Render a single client card (collapsed view)
 pub fn render_client_card(client: &Client) -> String {
     let status_class = match client.metadata.status {
@@ -419,9 +833,11 @@ pub fn render_client_card(client: &Client) -> String {
         ClientStatus::Active => "status-active",
         ClientStatus::Busy => "status-busy",
         ClientStatus::Disconnected => "status-disconnected",
+        ClientStatus::PendingApproval => "status-pending",
     };
 
     let id = escape_html(&client.id);
+    let title = escape_html(&display_name(client));
     let last_activity = client
         .metadata
         .last_activity
@@ -429,9 +845,51 @@ pub fn render_client_card(client: &Client) -> String {
         .map(|t| format_relative_time(t))
         .unwrap_or_else(|| "No activity".to_string());
     let status = client.metadata.status.to_string();
+    let quality_dot = match client.connection_quality {
+        Some(quality) => [
+            "<span class=\"quality-dot quality-",
+            &quality.to_string(),
+            "\" title=\"Connection: ",
+            &quality.to_string(),
+            "\"></span>",
+        ]
+        .concat(),
+        None => String::new(),
+    };
+    let activity_summary = client
+        .metadata
+        .activity_summary
+        .as_ref()
+        .map(|summary| {
+            [
+                "<div class=\"activity-summary\">",
+                &escape_html(summary),
+                "</div>",
+            ]
+            .concat()
+        })
+        .unwrap_or_default();
     let is_connected = !matches!(client.metadata.status, ClientStatus::Disconnected);
     let connect_class = if is_connected { "clickable" } else { "" };
 
+    // Approve/Reject buttons for clients held by REQUIRE_CLIENT_APPROVAL
+    let approval_actions = if client.metadata.status == ClientStatus::PendingApproval {
+        [
+            "<div class=\"client-actions\">",
+            "<button class=\"btn btn-primary btn-sm\" hx-post=\"/clients/",
+            &id,
+            "/approve\" hx-target=\"#clients-list\" hx-swap=\"innerHTML\">Approve</button>",
+            "<button class=\"btn btn-danger btn-sm\" hx-post=\"/clients/",
+            &id,
+            "/reject\" hx-target=\"#clients-list\" hx-swap=\"innerHTML\" ",
+            "hx-confirm=\"Reject and disconnect this client?\">Reject</button>",
+            "</div>",
+        ]
+        .concat()
+    } else {
+        String::new()
+    };
+
     // Build HTML - header area is clickable to open proxy interface
     [
         "<div class=\"client-card\" id=\"client-",
@@ -447,17 +905,22 @@ pub fn render_client_card(client: &Client) -> String {
         "/proxy/\" class=\"client-title-link ",
         connect_class,
         "\" target=\"_blank\">",
-        "<span class=\"client-title\">",
+        "<span class=\"client-title\" title=\"",
         &id,
+        "\">",
+        &title,
         "</span>",
         "</a>",
         "<div class=\"header-right\">",
+        &quality_dot,
         "<span class=\"status-badge ",
         status_class,
         "\">",
         &status,
         "</span>",
         "</div></div>",
+        &activity_summary,
+        &approval_actions,
         "<div class=\"client-footer\">",
         "<span class=\"last-activity\">",
         &last_activity,
@@ -481,6 +944,7 @@ pub fn render_client_details(client: &Client) -> String {
         ClientStatus::Active => "status-active",
         ClientStatus::Busy => "status-busy",
         ClientStatus::Disconnected => "status-disconnected",
+        ClientStatus::PendingApproval => "status-pending",
     };
 
     let last_activity_str = client
@@ -492,14 +956,40 @@ pub fn render_client_details(client: &Client) -> String {
 
     let is_connected = !matches!(client.metadata.status, ClientStatus::Disconnected);
     let id = escape_html(&client.id);
+    let title = escape_html(&display_name(client));
     let hostname = escape_html(&client.metadata.hostname);
     let project = escape_html(&client.metadata.project);
     let connected_at = format_relative_time(&client.connected_at);
     let last_seen = format_relative_time(&client.last_seen);
-    let last_activity = escape_html(&last_activity_str);
+    let last_activity = last_activity_str;
     let status = client.metadata.status.to_string();
     let connect_class = if is_connected { "clickable" } else { "" };
 
+    // Where the client connected from, e.g. "Berlin, BE, DE (203.0.113.7)"
+    let location_row = match &client.metadata.geo {
+        Some(geo) => {
+            let place = [&geo.city, &geo.region, &geo.country]
+                .into_iter()
+                .flatten()
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(", ");
+            let location = match (&geo.ip, place.is_empty()) {
+                (Some(ip), true) => ip.clone(),
+                (Some(ip), false) => format!("{} ({})", place, ip),
+                (None, _) => place,
+            };
+            [
+                "<div class=\"detail-row\"><span class=\"detail-label\">Location</span>",
+                "<span class=\"detail-value\">",
+                &escape_html(&location),
+                "</span></div>",
+            ]
+            .concat()
+        }
+        None => String::new(),
+    };
+
     let disconnect_btn = if is_connected {
         [
             "<button class=\"btn btn-danger btn-sm\" hx-post=\"/clients/",
@@ -523,6 +1013,34 @@ pub fn render_client_details(client: &Client) -> String {
     ]
     .concat();
 
+    // Connection test button (only useful when the client advertises a callback URL)
+    let ping_proxy_btn = if client.metadata.callback_url.is_some() {
+        [
+            "<button class=\"btn btn-secondary btn-sm\" hx-post=\"/clients/",
+            &id,
+            "/ping-proxy\" hx-target=\"#purge-notification-",
+            &id,
+            "\" hx-swap=\"innerHTML\">Test Connection</button>",
+        ]
+        .concat()
+    } else {
+        String::new()
+    };
+
+    // Metadata refresh button (asks the connected client to re-send its metadata)
+    let refresh_btn = if is_connected {
+        [
+            "<button class=\"btn btn-secondary btn-sm\" hx-post=\"/clients/",
+            &id,
+            "/refresh\" hx-target=\"#purge-notification-",
+            &id,
+            "\" hx-swap=\"innerHTML\">Refresh Metadata</button>",
+        ]
+        .concat()
+    } else {
+        String::new()
+    };
+
     // WebSocket Debugging button (only shown when connected)
     let ws_debug_btn = if is_connected {
         [
@@ -550,8 +1068,10 @@ pub fn render_client_details(client: &Client) -> String {
         "/proxy/\" class=\"client-title-link ",
         connect_class,
         "\" target=\"_blank\">",
-        "<span class=\"client-title\">",
+        "<span class=\"client-title\" title=\"",
         &id,
+        "\">",
+        &title,
         "</span>",
         "</a>",
         "<div class=\"header-right\">",
@@ -584,9 +1104,12 @@ pub fn render_client_details(client: &Client) -> String {
         "<span class=\"detail-value\">",
         &last_activity,
         "</span></div>",
+        &location_row,
         "</div>",
         "<div class=\"client-actions\">",
         &ws_debug_btn,
+        &ping_proxy_btn,
+        &refresh_btn,
         &purge_cache_btn,
         &disconnect_btn,
         "</div>",
@@ -606,8 +1129,42 @@ pub fn render_client_details(client: &Client) -> String {
     .concat()
 }
 
+/// Render the result of a callback URL reachability test (HTMX partial)
+pub fn render_proxy_health(health: &ProxyHealth) -> String {
+    let (class, message) = if health.reachable {
+        let status = health.status.map(|s| s.to_string()).unwrap_or_default();
+        let latency = health.latency_ms.map(|ms| ms.to_string()).unwrap_or_default();
+        (
+            "toast-success",
+            ["Reachable: HTTP ", &status, " in ", &latency, "ms"].concat(),
+        )
+    } else {
+        let error = health.error.as_deref().unwrap_or("Unknown error");
+        ("toast-error", ["Unreachable: ", error].concat())
+    };
+
+    [
+        "<div class=\"", class, "\">",
+        &escape_html(&message),
+        "</div>",
+    ]
+    .concat()
+}
+
+/// Render the outcome of a metadata refresh request
+pub fn render_refresh_result(requested: bool) -> String {
+    if requested {
+        "<div class=\"toast-success\">Metadata refresh requested</div>".to_string()
+    } else {
+        "<div class=\"toast-error\">Client is not connected</div>".to_string()
+    }
+}
+
 /// Render the token list (HTMX partial)
-pub fn render_token_list(tokens: &[TokenInfo]) -> String {
+///
+/// `next_page` is the query string of the following page, if any; its
+/// "Load more" button replaces itself with that page's cards.
+pub fn render_token_list(tokens: &[TokenInfo], next_page: Option<&str>) -> String {
     if tokens.is_empty() {
         return r#"
             <div class="empty-state small">
@@ -619,7 +1176,23 @@ pub fn render_token_list(tokens: &[TokenInfo]) -> String {
     }
 
     let cards: Vec<String> = tokens.iter().map(render_token_card).collect();
-    ["<div class=\"tokens-grid\">", &cards.join("\n"), "</div>"].concat()
+    let load_more = match next_page {
+        Some(query) => [
+            "<div class=\"show-more\">",
+            "<button class=\"btn btn-secondary btn-sm\" hx-get=\"/tokens?",
+            &escape_html(query),
+            "\" hx-target=\"closest .show-more\" hx-swap=\"outerHTML\">Load more</button></div>",
+        ]
+        .concat(),
+        None => String::new(),
+    };
+    [
+        "<div class=\"tokens-grid\">",
+        &cards.join("\n"),
+        "</div>",
+        &load_more,
+    ]
+    .concat()
 }
 
 /// Render a single token card
@@ -632,15 +1205,47 @@ pub fn render_token_card(token: &TokenInfo) -> String {
         .as_ref()
         .map(|t| format_relative_time(t))
         .unwrap_or_else(|| "Never".to_string());
+    // Where the token was last used from, with the User-Agent on hover
+    let last_used_from = match (&token.last_used_ip, &token.last_used_ua) {
+        (None, None) => String::new(),
+        (ip, user_agent) => [
+            "<span title=\"",
+            &escape_html(user_agent.as_deref().unwrap_or("Unknown client")),
+            "\">From: ",
+            &escape_html(ip.as_deref().unwrap_or("unknown IP")),
+            "</span>",
+        ]
+        .concat(),
+    };
 
     let status_class = if token.is_revoked {
         "token-revoked"
+    } else if token.is_expired {
+        "token-expired"
     } else {
         "token-active"
     };
 
+    let expires_label = if token.is_expired {
+        "Expired: "
+    } else {
+        "Expires: "
+    };
+    let expires = match &token.expires_at {
+        Some(expires_at) => [
+            "<span>",
+            expires_label,
+            &format_relative_time(expires_at),
+            "</span>",
+        ]
+        .concat(),
+        None => String::new(),
+    };
+
     let actions = if token.is_revoked {
         "<span class=\"text-muted\">Revoked</span>".to_string()
+    } else if token.is_expired {
+        "<span class=\"text-muted\">Expired</span>".to_string()
     } else {
         [
             "<button class=\"btn btn-secondary btn-sm\" hx-post=\"/api/tokens/",
@@ -671,6 +1276,8 @@ pub fn render_token_card(token: &TokenInfo) -> String {
         "<span>Last used: ",
         &last_used,
         "</span>",
+        &last_used_from,
+        &expires,
         "</div>",
         "<div class=\"token-actions\">",
         &actions,
@@ -680,6 +1287,93 @@ pub fn render_token_card(token: &TokenInfo) -> String {
     .concat()
 }
 
+/// Render the session list (HTMX partial)
+pub fn render_session_list(sessions: &[SessionInfo]) -> String {
+    if sessions.is_empty() {
+        return r#"
+            <div class="empty-state small">
+                <p>No active sessions.</p>
+            </div>
+        "#
+        .to_string();
+    }
+
+    let cards: Vec<String> = sessions.iter().map(render_session_card).collect();
+    ["<div class=\"tokens-grid\">", &cards.join("\n"), "</div>"].concat()
+}
+
+/// Render a single session card
+pub fn render_session_card(session: &SessionInfo) -> String {
+    let id = escape_html(&session.id);
+    let device = session
+        .user_agent
+        .as_deref()
+        .map(escape_html)
+        .unwrap_or_else(|| "Unknown device".to_string());
+    let ip = session
+        .created_ip
+        .as_deref()
+        .map(escape_html)
+        .unwrap_or_else(|| "unknown".to_string());
+    let created_at = format_relative_time(&session.created_at);
+    let last_active = session
+        .last_activity
+        .as_ref()
+        .map(|t| format_relative_time(t))
+        .unwrap_or_else(|| created_at.clone());
+
+    let (label, action) = if session.is_current {
+        (
+            "<span class=\"count-badge has-active\">This browser</span>",
+            [
+                "<button class=\"btn btn-secondary btn-sm\" hx-delete=\"/api/sessions/",
+                &id,
+                "?confirm=true\" hx-confirm=\"This will sign you out of this browser. Continue?\">Sign out</button>",
+            ]
+            .concat(),
+        )
+    } else {
+        (
+            "",
+            [
+                "<button class=\"btn btn-secondary btn-sm\" hx-delete=\"/api/sessions/",
+                &id,
+                "\" hx-target=\"#sessions-list\" hx-swap=\"innerHTML\" hx-confirm=\"Revoke this session?\">Revoke</button>",
+            ]
+            .concat(),
+        )
+    };
+
+    [
+        "<div class=\"token-card token-active\" id=\"session-",
+        &id[..8.min(id.len())],
+        "\">",
+        "<div class=\"token-header\">",
+        "<span class=\"token-name\">",
+        &device,
+        "</span>",
+        label,
+        "</div>",
+        "<div class=\"token-body\">",
+        "<div class=\"token-meta\">",
+        "<span>IP: ",
+        &ip,
+        "</span>",
+        "<span>Signed in: ",
+        &created_at,
+        "</span>",
+        "<span>Last active: ",
+        &last_active,
+        "</span>",
+        "</div>",
+        "<div class=\"token-actions\">",
+        &action,
+        "</div>",
+        "</div></div>",
+    ]
+    .concat()
+}
+
 /// Render the token creation modal
 pub fn render_token_modal() -> String {
     r##"
@@ -696,6 +1390,17 @@ pub fn render_token_modal() -> String {
                         <input type="text" id="token-name" name="name" placeholder="e.g., Work Laptop" required autofocus>
                         <p class="form-hint">A friendly name to identify this token.</p>
                     </div>
+                    <div class="form-group">
+                        <label for="token-ttl">Expires</label>
+                        <select id="token-ttl" name="ttl_hours">
+                            <option value="">Never</option>
+                            <option value="24">In 24 hours</option>
+                            <option value="168">In 7 days</option>
+                            <option value="720">In 30 days</option>
+                            <option value="2160">In 90 days</option>
+                        </select>
+                        <p class="form-hint">Use an expiry for shared machines and CI runners.</p>
+                    </div>
                 </div>
                 <div class="modal-footer">
                     <button type="button" class="btn btn-secondary" hx-get="/tokens/close-modal" hx-target="#token-modal" hx-swap="innerHTML">Cancel</button>
@@ -708,6 +1413,38 @@ pub fn render_token_modal() -> String {
     .to_string()
 }
 
+/// Render a typed-confirmation modal for a destructive bulk action
+///
+/// The form posts `confirm` to `action`; the server rejects it unless the
+/// value matches `expected` (the user's login on whichever provider they signed in with).
+pub fn render_confirm_modal(title: &str, message: &str, action: &str, expected: &str) -> String {
+    let expected = escape_html(expected);
+
+    [
+        "<div class=\"modal-backdrop\" id=\"modal-backdrop\">",
+        "<div class=\"modal\">",
+        "<div class=\"modal-header\">",
+        "<h3>", &escape_html(title), "</h3>",
+        "<button class=\"modal-close\" hx-get=\"/tokens/close-modal\" hx-target=\"#token-modal\" hx-swap=\"innerHTML\">&times;</button>",
+        "</div>",
+        "<form hx-post=\"", &escape_html(action), "\" hx-target=\"#token-modal\" hx-swap=\"innerHTML\" ",
+        "hx-on::after-request=\"if (event.detail.successful) { htmx.trigger('#clients-list', 'load'); htmx.trigger('#tokens-list', 'load'); } ",
+        "else { this.querySelector('.form-error').textContent = event.detail.xhr.responseText; }\">",
+        "<div class=\"modal-body\">",
+        "<p class=\"warning-text\">", &escape_html(message), "</p>",
+        "<div class=\"form-group\">",
+        "<label for=\"confirm-input\">Type your username, <strong>", &expected, "</strong>, to confirm</label>",
+        "<input type=\"text\" id=\"confirm-input\" name=\"confirm\" placeholder=\"", &expected, "\" autocomplete=\"off\" required autofocus>",
+        "<p class=\"form-error\"></p>",
+        "</div></div>",
+        "<div class=\"modal-footer\">",
+        "<button type=\"button\" class=\"btn btn-secondary\" hx-get=\"/tokens/close-modal\" hx-target=\"#token-modal\" hx-swap=\"innerHTML\">Cancel</button>",
+        "<button type=\"submit\" class=\"btn btn-danger\">", &escape_html(title), "</button>",
+        "</div></form>",
+        "</div></div>",
+    ].concat()
+}
+
 /// Render the token created success modal (shows the token once)
 pub fn render_token_created(token_value: &str, name: &str) -> String {
     let token = escape_html(token_value);
@@ -743,11 +1480,48 @@ pub fn render_token_created(token_value: &str, name: &str) -> String {
     ].concat()
 }
 
+/// Modal showing the token created automatically at first login (AUTO_CREATE_FIRST_TOKEN)
+pub fn render_welcome_token(token_value: &str, connect_url: &str) -> String {
+    let snippet = escape_html(&format!(
+        "export ORCHESTRATOR_URL=\"{}\"\nexport ORCHESTRATOR_TOKEN=\"{}\"",
+        connect_url, token_value
+    ));
+
+    [
+        "<div class=\"modal-backdrop\" id=\"modal-backdrop\">",
+        "<div class=\"modal\">",
+        "<div class=\"modal-header\">",
+        "<h3>Welcome! Your First Token</h3>",
+        "<button class=\"modal-close\" hx-get=\"/tokens/close-modal\" hx-target=\"#token-modal\" hx-swap=\"innerHTML\">&times;</button>",
+        "</div>",
+        "<div class=\"modal-body\">",
+        "<p>A token named <strong>Default</strong> was created for you. ",
+        "Set these variables where claudecodeui runs to connect it:</p>",
+        "<div class=\"token-display\">",
+        "<pre><code id=\"welcome-snippet\">", &snippet, "</code></pre>",
+        "<button class=\"btn btn-sm btn-secondary copy-btn\">Copy</button>",
+        "</div>",
+        "<p class=\"warning-text\">⚠️ This token will only be shown once. Save it now!</p>",
+        "</div>",
+        "<div class=\"modal-footer\">",
+        "<button class=\"btn btn-primary\" hx-get=\"/tokens/close-modal\" hx-target=\"#token-modal\" hx-swap=\"innerHTML\">Done</button>",
+        "</div>",
+        "</div></div>",
+        "<script>",
+        "document.querySelector('#token-modal .copy-btn').addEventListener('click', function() {",
+        "  navigator.clipboard.writeText(document.getElementById('welcome-snippet').textContent);",
+        "  this.textContent = 'Copied!';",
+        "  setTimeout(() => this.textContent = 'Copy', 2000);",
+        "});",
+        "</script>",
+    ].concat()
+}
+
 /// Wrap content in the base layout
 fn layout(title: &str, content: &str) -> String {
     format!(
         r#"<!DOCTYPE html>
-<html lang="en">
+<html lang="en" data-base-path="">
 <head>
     <meta charset="UTF-8">
     <meta name="viewport" content="width=device-width, initial-scale=1.0">
@@ -1001,6 +1775,17 @@ fn layout(title: &str, content: &str) -> String {
         .status-active {{ background: rgba(63, 185, 80, 0.2); color: var(--success); }}
         .status-busy {{ background: rgba(210, 153, 34, 0.2); color: var(--warning); }}
         .status-disconnected {{ background: rgba(248, 81, 73, 0.2); color: var(--error); }}
+        .status-pending {{ background: rgba(88, 166, 255, 0.2); color: var(--accent); }}
+
+        .quality-dot {{
+            display: inline-block;
+            width: 8px;
+            height: 8px;
+            border-radius: 50%;
+            margin-right: 0.5rem;
+        }}
+        .quality-good {{ background: var(--success); }}
+        .quality-degraded {{ background: var(--warning); }}
 
         .client-body {{
             padding: 1rem;
@@ -1089,6 +1874,11 @@ fn layout(title: &str, content: &str) -> String {
             font-size: 0.875rem;
         }}
 
+        .show-more {{
+            text-align: center;
+            margin-top: 1rem;
+        }}
+
         .empty-state {{
             text-align: center;
             padding: 3rem;
@@ -1124,6 +1914,12 @@ fn layout(title: &str, content: &str) -> String {
             margin: 0;
         }}
 
+        .header-actions {{
+            display: flex;
+            align-items: center;
+            gap: 0.5rem;
+        }}
+
         .section-desc {{
             color: var(--text-secondary);
             font-size: 0.875rem;
@@ -1138,13 +1934,21 @@ fn layout(title: &str, content: &str) -> String {
             color: var(--text-secondary);
         }}
 
+        .hub-switcher {{
+            display: flex;
+            flex-wrap: wrap;
+            gap: 0.5rem;
+            margin-bottom: 1rem;
+        }}
+
         .count-badge.has-active {{
             background: rgba(63, 185, 80, 0.2);
             color: var(--success);
         }}
 
         /* Tokens section */
-        .tokens-section {{
+        .tokens-section,
+        .sessions-section {{
             margin-top: 2rem;
             padding-top: 2rem;
             border-top: 1px solid var(--border);
@@ -1163,10 +1967,15 @@ fn layout(title: &str, content: &str) -> String {
             padding: 1rem;
         }}
 
-        .token-card.token-revoked {{
+        .token-card.token-revoked,
+        .token-card.token-expired {{
             opacity: 0.6;
         }}
 
+        .token-card.token-expired {{
+            border-style: dashed;
+        }}
+
         .token-header {{
             display: flex;
             justify-content: space-between;
@@ -1271,7 +2080,8 @@ fn layout(title: &str, content: &str) -> String {
             font-weight: 500;
         }}
 
-        .form-group input {{
+        .form-group input,
+        .form-group select {{
             width: 100%;
             padding: 0.75rem;
             background: var(--bg-primary);
@@ -1281,7 +2091,8 @@ fn layout(title: &str, content: &str) -> String {
             font-size: 1rem;
         }}
 
-        .form-group input:focus {{
+        .form-group input:focus,
+        .form-group select:focus {{
             outline: none;
             border-color: var(--accent);
         }}
@@ -1292,6 +2103,12 @@ fn layout(title: &str, content: &str) -> String {
             color: var(--text-secondary);
         }}
 
+        .form-error {{
+            margin-top: 0.5rem;
+            font-size: 0.75rem;
+            color: var(--error);
+        }}
+
         .success-icon {{
             font-size: 3rem;
             color: var(--success);
@@ -1487,6 +2304,17 @@ fn layout(title: &str, content: &str) -> String {
             animation: fadeIn 0.3s ease;
         }}
 
+        .toast-error {{
+            padding: 0.5rem 1rem;
+            background: rgba(248, 81, 73, 0.15);
+            border: 1px solid rgba(248, 81, 73, 0.4);
+            border-radius: 6px;
+            color: var(--error);
+            font-size: 0.875rem;
+            margin-top: 0.75rem;
+            animation: fadeIn 0.3s ease;
+        }}
+
         @keyframes fadeIn {{
             from {{ opacity: 0; transform: translateY(-10px); }}
             to {{ opacity: 1; transform: translateY(0); }}
@@ -1501,6 +2329,15 @@ fn layout(title: &str, content: &str) -> String {
             color: var(--text-secondary);
         }}
 
+        .activity-summary {{
+            font-size: 0.8125rem;
+            color: var(--text-secondary);
+            margin-top: 0.25rem;
+            overflow: hidden;
+            text-overflow: ellipsis;
+            white-space: nowrap;
+        }}
+
         /* Command Panel */
         .command-panel {{
             position: fixed;
@@ -1671,29 +2508,51 @@ fn escape_html(s: &str) -> String {
         .replace('\'', "&#39;")
 }
 
-/// Format a timestamp for display (ISO string to human-readable)
-fn format_timestamp(ts: &str) -> String {
-    // For now, just return the timestamp as-is
-    // In production, you'd format this nicely
-    ts.to_string()
-}
-
 /// Format timestamp as relative time (e.g., "2 minutes ago")
+///
+/// The dashboard script keeps `<time data-epoch-ms>` elements current (and
+/// corrects for the browser's clock); the server-rendered text is what shows
+/// until then, or without JavaScript.
 fn format_relative_time(ts: &str) -> String {
-    // Parse ISO timestamp and calculate relative time
-    // For now, return a simplified version
-    if ts.is_empty() {
-        return "Unknown".to_string();
+    format_relative_time_at(ts, crate::time::now_ms() as i64)
+}
+
+/// `format_relative_time` against an explicit "now" (epoch ms)
+fn format_relative_time_at(ts: &str, now_ms: i64) -> String {
+    match crate::time::epoch_ms_of(ts) {
+        Some(ms) => format!(
+            "<time data-epoch-ms=\"{}\">{}</time>",
+            ms,
+            format_ago(now_ms - ms)
+        ),
+        None => "Unknown".to_string(),
     }
+}
 
-    // Try to extract just the time portion for display
-    if let Some(time_part) = ts.split('T').nth(1) {
-        if let Some(time) = time_part.split('.').next() {
-            return format!("at {}", time);
+/// Human-readable age of a timestamp `elapsed_ms` in the past, matching the
+/// dashboard script's `formatAgo`
+fn format_ago(elapsed_ms: i64) -> String {
+    let plural = |n: i64, unit: &str| {
+        if n == 1 {
+            format!("1 {} ago", unit)
+        } else {
+            format!("{} {}s ago", n, unit)
         }
-    }
+    };
 
-    ts.to_string()
+    let seconds = (elapsed_ms.max(0) + 500) / 1000;
+    if seconds < 60 {
+        return "just now".to_string();
+    }
+    let minutes = seconds / 60;
+    if minutes < 60 {
+        return plural(minutes, "minute");
+    }
+    let hours = minutes / 60;
+    if hours < 24 {
+        return plural(hours, "hour");
+    }
+    plural(hours / 24, "day")
 }
 
 /// Truncate a file path to fit in a given width
@@ -1705,7 +2564,11 @@ fn truncate_path(path: &str, max_len: usize) -> String {
     // Try to keep the last part of the path visible
     let parts: Vec<&str> = path.split('/').collect();
     if parts.len() <= 2 {
-        return format!("...{}", &path[path.len().saturating_sub(max_len - 3)..]);
+        let mut start = path.len().saturating_sub(max_len - 3);
+        while !path.is_char_boundary(start) {
+            start += 1;
+        }
+        return format!("...{}", &path[start..]);
     }
 
     // Keep first and last parts
@@ -1718,3 +2581,95 @@ fn truncate_path(path: &str, max_len: usize) -> String {
         format!(".../{}", last)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"<body data-base-path=""><a href="/dashboard">Home</a><img src="//cdn.example.com/x.png"><button hx-post="/clients/c1/disconnect">x</button><p>href="/not-a-link"</p></body>"#;
+
+    #[test]
+    fn prefix_links_rewrites_app_links_under_base_path() {
+        let html = prefix_links(PAGE, "/orchestrator");
+        assert!(html.contains(r#"data-base-path="/orchestrator""#));
+        assert!(html.contains(r#"href="/orchestrator/dashboard""#));
+        assert!(html.contains(r#"hx-post="/orchestrator/clients/c1/disconnect""#));
+        // Protocol-relative URLs point elsewhere and stay as they were
+        assert!(html.contains(r#"src="//cdn.example.com/x.png""#));
+    }
+
+    #[test]
+    fn prefix_links_leaves_html_alone_without_base_path() {
+        assert_eq!(prefix_links(PAGE, ""), PAGE);
+    }
+
+    fn client(hostname: &str, project: &str) -> Client {
+        serde_json::from_value(serde_json::json!({
+            "id": "c-1234",
+            "user_id": "owner",
+            "metadata": {
+                "hostname": hostname,
+                "project": project,
+                "last_activity": null
+            },
+            "connected_at": "2026-01-01T00:00:00Z",
+            "last_seen": "2026-01-01T00:00:00Z"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn display_names_combine_hostname_and_project_basename() {
+        let cases = [
+            ("devbox", "/home/me/orchestrator", "devbox · orchestrator"),
+            ("devbox", "/home/me/orchestrator/", "devbox · orchestrator"),
+            ("devbox", "", "devbox"),
+            ("", "/home/me/orchestrator", "orchestrator"),
+            (" ", " / ", "c-1234"),
+            ("", "", "c-1234"),
+            ("pc", r"C:\Users\me\orchestrator", "pc · orchestrator"),
+            ("", "very-long-project-name-xy", "...-long-project-name-xy"),
+        ];
+        for (hostname, project, expected) in cases {
+            assert_eq!(display_name(&client(hostname, project)), expected);
+        }
+    }
+
+    #[test]
+    fn relative_times_use_the_largest_whole_unit() {
+        const MINUTE: i64 = 60_000;
+        const HOUR: i64 = 60 * MINUTE;
+        let cases = [
+            (0, "just now"),
+            (59_499, "just now"),
+            (59_500, "1 minute ago"),
+            (2 * MINUTE, "2 minutes ago"),
+            (59 * MINUTE, "59 minutes ago"),
+            (HOUR, "1 hour ago"),
+            (23 * HOUR + 59 * MINUTE, "23 hours ago"),
+            (24 * HOUR, "1 day ago"),
+            (3 * 24 * HOUR, "3 days ago"),
+            // Timestamps ahead of the server clock
+            (-5 * MINUTE, "just now"),
+        ];
+        for (elapsed_ms, expected) in cases {
+            assert_eq!(format_ago(elapsed_ms), expected, "{elapsed_ms}ms");
+        }
+    }
+
+    #[test]
+    fn relative_times_are_live_time_elements() {
+        // 2024-03-05T06:07:08Z
+        let now = 1_709_618_828_000;
+        assert_eq!(
+            format_relative_time_at("2024-03-05 05:07:08", now),
+            r#"<time data-epoch-ms="1709615228000">1 hour ago</time>"#
+        );
+        assert_eq!(
+            format_relative_time_at("2024-03-05T06:10:00Z", now),
+            r#"<time data-epoch-ms="1709619000000">just now</time>"#
+        );
+        assert_eq!(format_relative_time_at("yesterday", now), "Unknown");
+        assert_eq!(format_relative_time_at("", now), "Unknown");
+    }
+}
@@ -0,0 +1,239 @@
+//! Timestamp helpers shared by models, middleware and Durable Objects
+//!
+//! Timestamps are stored as ISO 8601 strings. D1's `CURRENT_TIMESTAMP` yields
+//! `YYYY-MM-DD HH:MM:SS` (UTC, no zone) while JS `toISOString` yields
+//! `YYYY-MM-DDTHH:MM:SS.sssZ`; `epoch_ms_of` accepts both.
+
+/// Current time in milliseconds since the Unix epoch
+pub fn now_ms() -> u64 {
+    js::Date::now() as u64
+}
+
+/// Largest distance from the epoch a JS `Date` can represent (100,000,000 days)
+const MAX_DATE_MS: f64 = 8.64e15;
+
+/// Current time as an ISO 8601 string
+pub fn now_iso() -> String {
+    iso_of(js::Date::now()).unwrap_or_default()
+}
+
+/// Time `hours` from now as an ISO 8601 string, `None` past the last representable date
+pub fn future_iso(hours: u64) -> Option<String> {
+    iso_of(js::Date::now() + hours as f64 * 3_600_000.0)
+}
+
+/// ISO 8601 form of an epoch time, `None` when JS `Date` can't represent it
+fn iso_of(epoch_ms: f64) -> Option<String> {
+    if !representable(epoch_ms) {
+        return None;
+    }
+    let date = js::Date::new(&wasm_bindgen::JsValue::from_f64(epoch_ms));
+    date.to_iso_string().ok()?.as_string()
+}
+
+/// Whether `epoch_ms` lies within JS `Date`'s range (`toISOString` throws outside it)
+fn representable(epoch_ms: f64) -> bool {
+    epoch_ms.is_finite() && epoch_ms.abs() <= MAX_DATE_MS
+}
+
+/// Whether a stored timestamp is at or before the current time
+///
+/// Unparseable timestamps count as past so that malformed expiries fail closed.
+pub fn is_past(iso: &str) -> bool {
+    is_past_at(iso, now_ms() as i64)
+}
+
+/// [`is_past`] relative to `now_ms` rather than the current time
+pub fn is_past_at(iso: &str, now_ms: i64) -> bool {
+    epoch_ms_of(iso).is_none_or(|ms| ms <= now_ms)
+}
+
+/// Parse an ISO 8601 / SQLite timestamp into milliseconds since the Unix epoch
+///
+/// Accepts `T` or space as the date/time separator, optional fractional
+/// seconds, and an optional `Z` or `±HH:MM` offset (no offset means UTC).
+pub fn epoch_ms_of(iso: &str) -> Option<i64> {
+    let iso = iso.trim();
+    let (date, rest) = iso.split_at(iso.find(['T', 't', ' '])?);
+    let rest = &rest[1..];
+
+    let mut date_parts = date.splitn(3, '-');
+    let year: i64 = date_parts.next()?.parse().ok()?;
+    let month: u32 = date_parts.next()?.parse().ok()?;
+    let day: u32 = date_parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return None;
+    }
+
+    // Split the zone designator off the time of day
+    let (time, offset_ms) = if let Some(time) = rest.strip_suffix(['Z', 'z']) {
+        (time, 0)
+    } else if let Some(pos) = rest.rfind(['+', '-']) {
+        let (time, zone) = rest.split_at(pos);
+        let sign = if zone.starts_with('-') { -1 } else { 1 };
+        let (hours, minutes) = zone[1..].split_once(':').unwrap_or((&zone[1..], "0"));
+        let hours: i64 = hours.parse().ok()?;
+        let minutes: i64 = minutes.parse().ok()?;
+        if hours > 23 || minutes > 59 {
+            return None;
+        }
+        (time, sign * (hours * 60 + minutes) * 60_000)
+    } else {
+        (rest, 0)
+    };
+
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut time_parts = time.splitn(3, ':');
+    let hour: i64 = time_parts.next()?.parse().ok()?;
+    let minute: i64 = time_parts.next()?.parse().ok()?;
+    let second: i64 = time_parts.next().unwrap_or("0").parse().ok()?;
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    // Only millisecond precision is kept
+    let millis: i64 = if fraction.is_empty() {
+        0
+    } else {
+        if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        format!("{:0<3}", &fraction[..fraction.len().min(3)])
+            .parse()
+            .ok()?
+    };
+
+    let days = days_from_civil(year, month, day);
+    let seconds = days * 86_400 + hour * 3_600 + minute * 60 + second;
+    Some(seconds * 1_000 + millis - offset_ms)
+}
+
+/// Number of days in `month` (1-12) of `year`
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+mod js {
+    use wasm_bindgen::prelude::*;
+
+    #[wasm_bindgen]
+    extern "C" {
+        pub type Date;
+
+        #[wasm_bindgen(constructor)]
+        pub fn new(value: &JsValue) -> Date;
+
+        #[wasm_bindgen(static_method_of = Date)]
+        pub fn now() -> f64;
+
+        #[wasm_bindgen(method, catch, js_name = toISOString)]
+        pub fn to_iso_string(this: &Date) -> Result<JsString, JsValue>;
+    }
+
+    #[wasm_bindgen]
+    extern "C" {
+        pub type JsString;
+
+        #[wasm_bindgen(method, js_name = toString)]
+        pub fn as_string(this: &JsString) -> Option<String>;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-03-05T06:07:08Z
+    const MS: i64 = 1_709_618_828_000;
+
+    #[test]
+    fn parses_sqlite_and_js_formats() {
+        assert_eq!(epoch_ms_of("2024-03-05 06:07:08"), Some(MS));
+        assert_eq!(epoch_ms_of("2024-03-05T06:07:08Z"), Some(MS));
+        assert_eq!(epoch_ms_of("2024-03-05t06:07:08z"), Some(MS));
+        assert_eq!(epoch_ms_of(" 2024-03-05T06:07 "), Some(MS - 8_000));
+        assert_eq!(epoch_ms_of("1970-01-01T00:00:00Z"), Some(0));
+    }
+
+    #[test]
+    fn applies_offsets() {
+        assert_eq!(epoch_ms_of("2024-03-05T08:07:08+02:00"), Some(MS));
+        assert_eq!(epoch_ms_of("2024-03-05T01:07:08-05:00"), Some(MS));
+        assert_eq!(epoch_ms_of("2024-03-05T11:37:08+0530"), None);
+        assert_eq!(epoch_ms_of("2024-03-05T11:07:08+05"), Some(MS));
+    }
+
+    #[test]
+    fn keeps_millisecond_fractions() {
+        assert_eq!(epoch_ms_of("2024-03-05T06:07:08.5Z"), Some(MS + 500));
+        assert_eq!(epoch_ms_of("2024-03-05T06:07:08.123Z"), Some(MS + 123));
+        assert_eq!(epoch_ms_of("2024-03-05 06:07:08.123456"), Some(MS + 123));
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        for invalid in [
+            "",
+            "not a date",
+            "2024-03-05",
+            "2024-13-01 00:00:00",
+            "2024-00-01 00:00:00",
+            "2024-03-32 00:00:00",
+            "2023-02-29 00:00:00",
+            "2024-02-31 00:00:00",
+            "2024-04-31 00:00:00",
+            "2024-03-05 24:00:00",
+            "2024-03-05 06:60:00",
+            "2024-03-05 06:07:08.1x",
+            "2024-03-05T06:07:08+24:00",
+        ] {
+            assert_eq!(epoch_ms_of(invalid), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn only_dates_within_js_range_are_representable() {
+        assert!(representable(0.0));
+        assert!(representable(MAX_DATE_MS));
+        assert!(representable(-MAX_DATE_MS));
+        assert!(!representable(MAX_DATE_MS + 1.0));
+        assert!(!representable(f64::NAN));
+        assert!(!representable(f64::INFINITY));
+
+        // A three-billion-hour TTL lands far outside the range
+        let now = MS as f64;
+        assert!(!representable(now + 3_000_000_000.0 * 3_600_000.0));
+    }
+
+    #[test]
+    fn past_is_relative_to_now_and_fails_closed() {
+        assert!(is_past_at("2024-03-05T06:07:07Z", MS));
+        assert!(is_past_at("2024-03-05T06:07:08Z", MS));
+        assert!(!is_past_at("2024-03-05T06:07:09Z", MS));
+        assert!(is_past_at("garbage", MS));
+    }
+
+    #[test]
+    fn accepts_leap_days() {
+        assert!(epoch_ms_of("2024-02-29 00:00:00").is_some());
+        assert!(epoch_ms_of("2000-02-29 00:00:00").is_some());
+        assert!(epoch_ms_of("1900-02-29 00:00:00").is_none());
+    }
+}
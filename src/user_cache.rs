@@ -0,0 +1,239 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::thread::LocalKey;
+
+use worker::{Env, Result};
+
+use crate::models::User;
+
+/// How long a cached user stays valid when `USER_CACHE_TTL_MS` is unset
+const DEFAULT_USER_CACHE_TTL_MS: u64 = 5_000;
+
+/// Maximum entries per cache; the oldest entry is evicted beyond this
+const MAX_USER_CACHE_ENTRIES: usize = 1_000;
+
+/// Short-lived, per-isolate cache of resolved users for the proxy hot path
+///
+/// Proxied requests would otherwise hit D1 once per request to resolve the
+/// session (or, for public paths, the client) to a user. Entries live for
+/// `USER_CACHE_TTL_MS` (0 disables caching). Each Worker isolate has its own
+/// cache, so a revoked session or removed client can keep resolving in other
+/// isolates until the TTL runs out; keep the TTL short.
+struct UserCache {
+    entries: HashMap<String, (u64, User)>,
+}
+
+impl UserCache {
+    fn get(&mut self, key: &str, ttl_ms: u64, now: u64) -> Option<User> {
+        match self.entries.get(key) {
+            Some((stored_at, user)) if now.saturating_sub(*stored_at) < ttl_ms => {
+                Some(user.clone())
+            }
+            Some(_) => {
+                self.entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn put(&mut self, key: String, user: User, now: u64) {
+        if self.entries.len() >= MAX_USER_CACHE_ENTRIES && !self.entries.contains_key(&key) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (stored_at, _))| *stored_at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(key, (now, user));
+    }
+}
+
+thread_local! {
+    static SESSION_USERS: RefCell<UserCache> = RefCell::new(UserCache { entries: HashMap::new() });
+    static CLIENT_USERS: RefCell<UserCache> = RefCell::new(UserCache { entries: HashMap::new() });
+}
+
+/// Effective cache lifetime in milliseconds (0 means caching is off)
+pub fn ttl_ms(env: &Env) -> u64 {
+    crate::config::var_u64(env, "USER_CACHE_TTL_MS", DEFAULT_USER_CACHE_TTL_MS)
+}
+
+/// User for `key` from `cache`, or from `load` when missing or stale
+///
+/// Users `load` finds are remembered as of `now`; misses are not. With
+/// `ttl_ms` 0, `load` runs every time.
+async fn get_or_load<Fut>(
+    cache: &'static LocalKey<RefCell<UserCache>>,
+    key: &str,
+    ttl_ms: u64,
+    now: u64,
+    load: impl FnOnce() -> Fut,
+) -> Result<Option<User>>
+where
+    Fut: Future<Output = Result<Option<User>>>,
+{
+    if ttl_ms == 0 {
+        return load().await;
+    }
+    if let Some(user) = cache.with(|cache| cache.borrow_mut().get(key, ttl_ms, now)) {
+        return Ok(Some(user));
+    }
+
+    let user = load().await?;
+    if let Some(user) = &user {
+        cache.with(|cache| cache.borrow_mut().put(key.to_string(), user.clone(), now));
+    }
+    Ok(user)
+}
+
+/// User a session resolves to, using `load` (a D1 lookup) on a cache miss
+pub async fn session_user<Fut>(
+    env: &Env,
+    session_id: &str,
+    load: impl FnOnce() -> Fut,
+) -> Result<Option<User>>
+where
+    Fut: Future<Output = Result<Option<User>>>,
+{
+    let now = crate::time::now_ms();
+    get_or_load(&SESSION_USERS, session_id, ttl_ms(env), now, load).await
+}
+
+/// Drop a session from this isolate's cache (logout, revocation)
+pub fn invalidate_session(session_id: &str) {
+    SESSION_USERS.with(|cache| cache.borrow_mut().entries.remove(session_id));
+}
+
+/// Owner of a client ID, using `load` (a D1 lookup) on a cache miss
+pub async fn client_user<Fut>(
+    env: &Env,
+    client_id: &str,
+    load: impl FnOnce() -> Fut,
+) -> Result<Option<User>>
+where
+    Fut: Future<Output = Result<Option<User>>>,
+{
+    let now = crate::time::now_ms();
+    get_or_load(&CLIENT_USERS, client_id, ttl_ms(env), now, load).await
+}
+
+/// Drop a client from this isolate's cache (disconnect, rejection)
+pub fn invalidate_client(client_id: &str) {
+    CLIENT_USERS.with(|cache| cache.borrow_mut().entries.remove(client_id));
+}
+
+/// Drop every cached entry belonging to a user (token revocation)
+pub fn invalidate_user(user_id: &str) {
+    for cache in [&SESSION_USERS, &CLIENT_USERS] {
+        cache.with(|cache| {
+            cache
+                .borrow_mut()
+                .entries
+                .retain(|_, (_, user)| user.id != user_id)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use futures::executor::block_on;
+
+    use super::*;
+
+    const TTL: u64 = 5_000;
+
+    fn user(id: &str) -> User {
+        User::from_db(
+            id.to_string(),
+            "github".to_string(),
+            1,
+            format!("{}-login", id),
+            None,
+        )
+    }
+
+    /// `get_or_load` on the session cache with a loader counting D1 lookups
+    fn lookup(key: &str, ttl_ms: u64, now: u64, lookups: &Cell<u32>) -> String {
+        let load = || async {
+            lookups.set(lookups.get() + 1);
+            Ok(Some(user("u1")))
+        };
+        let user = block_on(get_or_load(&SESSION_USERS, key, ttl_ms, now, load)).unwrap();
+        user.unwrap().id
+    }
+
+    #[test]
+    fn lookups_within_the_ttl_skip_the_loader() {
+        let lookups = Cell::new(0);
+        assert_eq!(lookup("s1", TTL, 1_000, &lookups), "u1");
+        assert_eq!(lookup("s1", TTL, 1_000 + TTL - 1, &lookups), "u1");
+        assert_eq!(lookups.get(), 1);
+
+        // Expired entries are reloaded, and the reload restarts the TTL
+        assert_eq!(lookup("s1", TTL, 1_000 + TTL, &lookups), "u1");
+        assert_eq!(lookups.get(), 2);
+        assert_eq!(lookup("s1", TTL, 1_000 + TTL + 1, &lookups), "u1");
+        assert_eq!(lookups.get(), 2);
+    }
+
+    #[test]
+    fn zero_ttl_and_misses_are_never_cached() {
+        let lookups = Cell::new(0);
+        lookup("s1", 0, 1_000, &lookups);
+        lookup("s1", 0, 1_000, &lookups);
+        assert_eq!(lookups.get(), 2);
+        assert!(SESSION_USERS.with(|cache| cache.borrow().entries.is_empty()));
+
+        let missing = || async {
+            lookups.set(lookups.get() + 1);
+            Ok(None)
+        };
+        let user = block_on(get_or_load(&CLIENT_USERS, "c1", TTL, 1_000, missing)).unwrap();
+        assert!(user.is_none());
+        assert!(CLIENT_USERS.with(|cache| cache.borrow().entries.is_empty()));
+    }
+
+    #[test]
+    fn full_caches_evict_the_oldest_entry() {
+        let mut cache = UserCache {
+            entries: HashMap::new(),
+        };
+        for i in 0..MAX_USER_CACHE_ENTRIES {
+            cache.put(format!("s{}", i), user("u1"), 1_000 + i as u64);
+        }
+        // Refreshing an existing key never evicts, and makes it the newest
+        cache.put("s0".to_string(), user("u1"), 2_000);
+        assert_eq!(cache.entries.len(), MAX_USER_CACHE_ENTRIES);
+
+        cache.put("new".to_string(), user("u2"), 5_000);
+        assert_eq!(cache.entries.len(), MAX_USER_CACHE_ENTRIES);
+        assert!(!cache.entries.contains_key("s1"));
+        assert!(cache.entries.contains_key("s0"));
+        assert!(cache.get("new", TTL, 5_000).is_some());
+    }
+
+    #[test]
+    fn invalidating_a_user_clears_their_sessions_and_clients() {
+        for (cache, key, id) in [
+            (&SESSION_USERS, "s1", "u1"),
+            (&SESSION_USERS, "s2", "u2"),
+            (&CLIENT_USERS, "c1", "u1"),
+        ] {
+            cache.with(|cache| cache.borrow_mut().put(key.to_string(), user(id), 1_000));
+        }
+
+        invalidate_user("u1");
+        let keys = |cache: &'static LocalKey<RefCell<UserCache>>| {
+            cache.with(|cache| cache.borrow().entries.keys().cloned().collect::<Vec<_>>())
+        };
+        assert_eq!(keys(&SESSION_USERS), ["s2"]);
+        assert!(keys(&CLIENT_USERS).is_empty());
+    }
+}